@@ -3,13 +3,16 @@ use std::env;
 use crate::core::operations;
 use crate::core::operations::OpType;
 use crate::core::io;
+use crate::core::filters;
+use crate::core::threads;
+use crate::core::error::CoreError;
 
-use super::ppm::PpmImage;
+use super::ppm::{PpmImage, Padding};
 
 // TODO: Should really have the result be a "read image" instead of just one
 // that's in memory. There is a bunch of UI stuff that works differently if the
 // image has been loaded directly from disk.... I think.
-type ArgumentResult = Result<Option<PpmImage>, String>;
+type ArgumentResult = Result<Option<PpmImage>, CoreError>;
 
 // Type for checking flags (index of argument, expected str, error message)
 type FlagCheck = (&'static usize, &'static str, &'static str);
@@ -29,30 +32,62 @@ pub fn parse_arguments(arguments: Option<Vec<String>>) -> ArgumentResult {
 
   // TODO: https://doc.rust-lang.org/rust-by-example/flow_control/match.html
 
-  let args = arguments.unwrap_or(env::args().collect());
+  let mut args = arguments.unwrap_or(env::args().collect());
+
+  // the thread count can be given along with any of the commands
+  if let Some(index) = args.iter().position(|arg| arg == "-threads") {
+    let threads = match args.get(index + 1).map(|arg| arg.parse::<usize>()) {
+      Some(Ok(threads)) => threads,
+      _ => return Err(CoreError::InvalidCommand(
+        "-threads must be followed by a number of threads".to_string()
+      )),
+    };
+
+    threads::set_thread_count(threads)?;
+    args.drain(index..(index + 2));
+  }
 
   match args.len() {
     // this is here so that it will work if there are no arguments
     1 => { return Ok(None) },
+    // noise only reports a measurement, so it has no output file
+    4 => {
+      match get_operation!(args) {
+        "noise" => return parse_noise_command(&args),
+        _ => return Err(CoreError::InvalidCommand(
+          format!("Unknown command: {}", get_operation!(args))
+        )),
+      }
+    }
     // can only be invert, or histeq
     6 => {
       match get_operation!(args) {
         "inv" => return parse_invert_command(&args),
         "histeq" => return parse_histeq_command(&args),
         "sobel" => return parse_sobel_command(&args),
-        _ => return Err(
+        _ => return Err(CoreError::InvalidCommand(
           format!("Unknown command: {}", get_operation!(args))
-        ),
+        )),
       }
     }
-    // handles add, sub, mult, and histmatch
+    // handles add, sub, mult, div, lighten, and darken
     7 => {
       match get_operation!(args) {
-        "add" | "sub" | "mult" => return parse_image_operation_command(&args),
+        "add" | "sub" | "mult" | "div" | "lighten" | "darken" => {
+          return parse_image_operation_command(&args)
+        },
+        _ => return Err(CoreError::InvalidCommand(
+          format!("Unknown command: {}", get_operation!(args))
+        )),
+      }
+    }
+    // can only be histmatch
+    8 => {
+      match get_operation!(args) {
         "histmatch" => return parse_histmatch_command(&args),
-        _ => return Err(
+        _ => return Err(CoreError::InvalidCommand(
           format!("Unknown command: {}", get_operation!(args))
-        ),
+        )),
       }
     }
     // handles log, pow, and gblur
@@ -61,13 +96,36 @@ pub fn parse_arguments(arguments: Option<Vec<String>>) -> ArgumentResult {
         "log" => return parse_log_command(&args),
         "pow" => return parse_pow_command(&args),
         "gblur" => return parse_gblur_command(&args),
-        _ => return Err(
+        _ => return Err(CoreError::InvalidCommand(
           format!("Unknown command {}", get_operation!(args))
-        ),
+        )),
       }
     }
-    _ => return Err("Wrong number of arguments".to_string())
+    _ => return Err(CoreError::InvalidCommand(
+      "Wrong number of arguments".to_string()
+    ))
+  }
+}
+
+/**
+ * Parse the noise command, which prints an estimate of the noise in an image
+ *   noise -i <input file>
+ */
+fn parse_noise_command(args: &Vec<String>) -> ArgumentResult {
+  const INPUT_FILE:usize = 3;
+
+  if args[2].as_str() != "-i" {
+    return Err(CoreError::InvalidCommand(
+      "input flag is in the wrong place".to_string()
+    ))
   }
+
+  let image = io::open_image(args[INPUT_FILE].as_str())?;
+  let noise = filters::estimate_noise(&image, filters::NOISE_TILE_SIZE)?;
+
+  println!("Estimated noise (standard deviation): {:.3}", noise);
+
+  Ok(None)
 }
 
 /**
@@ -75,6 +133,9 @@ pub fn parse_arguments(arguments: Option<Vec<String>>) -> ArgumentResult {
  * - Add
  * - Subtract
  * - Multiply
+ * - Divide
+ * - Lighten
+ * - Darken
  */
 fn parse_image_operation_command(args: &Vec<String>) -> ArgumentResult {
 
@@ -87,16 +148,23 @@ fn parse_image_operation_command(args: &Vec<String>) -> ArgumentResult {
     "add" => optype = OpType::Add,
     "sub" => optype = OpType::Subtract,
     "mul" => optype = OpType::Multiply,
+    "div" => optype = OpType::Divide,
+    "lighten" => optype = OpType::Lighten,
+    "darken" => optype = OpType::Darken,
     _ => {}
   }
 
   // make sure input / output flags are in the right spots
   // TODO: Move this to the check_flag pattern
   if args[2].as_str() != "-i" {
-    return Err("input flag is in the wrong place".to_string())
+    return Err(CoreError::InvalidCommand(
+      "input flag is in the wrong place".to_string()
+    ))
   } 
   if args[5].as_str() != "-o" {
-    return Err("output flag is in the wrong place".to_string())
+    return Err(CoreError::InvalidCommand(
+      "output flag is in the wrong place".to_string()
+    ))
   }
 
   // load the two input images
@@ -115,7 +183,7 @@ fn parse_image_operation_command(args: &Vec<String>) -> ArgumentResult {
   match op_result {
     Ok(img) => {
       match io::write_image(&img, args[6].as_str()) {
-        Err(why) => return Err(why.to_string()),
+        Err(why) => return Err(why),
         Ok(_) => return Ok(Some(img)),
       }
     }
@@ -170,14 +238,14 @@ fn parse_log_command(args: &Vec<String>) -> ArgumentResult {
           match io::write_image(
             &mut image, args[OUTPUT_FILE].as_str()
           ) {
-            Err(why) => return Err(why.to_string()),
+            Err(why) => return Err(why),
             Ok(_) => return Ok(Some(image)),
           }
         },
-        Err(why) => return Err(why.to_string()),
+        Err(why) => return Err(why),
       }
     },
-    Err(why) => Err(why.to_string()),
+    Err(why) => Err(why),
   }
 }
 
@@ -228,14 +296,14 @@ fn parse_pow_command(args: &Vec<String>) -> ArgumentResult {
         match io::write_image(
           &mut image, args[OUTPUT_FILE].as_str()
         ) {
-          Err(why) => Err(why.to_string()),
+          Err(why) => Err(why),
           Ok(_) => Ok(Some(image)),
         }
       },
-      Err(why) => Err(why.to_string()),
+      Err(why) => Err(why),
     }
   },
-  Err(why) => Err(why.to_string()),
+  Err(why) => Err(why),
 }
 }
 
@@ -256,7 +324,6 @@ fn parse_invert_command(args: &Vec<String>) -> ArgumentResult {
   const INPUT_FILE:usize = 3;
   const OUTPUT_FILE:usize = 5;
 
-  use crate::core::operations::negate;
   use crate::core::io::open_image;
   use crate::core::io::write_image;
 
@@ -277,33 +344,55 @@ fn parse_invert_command(args: &Vec<String>) -> ArgumentResult {
           // TODO: Handle the scenario where the image does not open
           if let Ok(mut input) = open_image(input_file){
 
-            let negate_result = negate(
-              &mut input
-            );
-
-            match negate_result {
-              Ok(result) => {
-                match write_image(&result, output_file) {
-                  Ok(_) => return Ok(Some(result)),
-                  Err(why) => return Err(why.to_string()),
-                };
-              },
-              Err(why) => return Err(why.to_string()),
-            }
+            // the input is thrown away, so there is no need to keep a copy
+            input.negate_in_place();
+
+            match write_image(&input, output_file) {
+              Ok(_) => return Ok(Some(input)),
+              Err(why) => return Err(why),
+            };
         } else {
-          Err("something went wrong opening the image file.".to_string())
+          Err(CoreError::Io(
+            "something went wrong opening the image file.".to_string()
+          ))
         }
       },
-      Err(why) => return Err(why.to_string()),
+      Err(why) => return Err(why),
     }
   }
-  _ => return Err("unknown command".to_string())
+  _ => return Err(CoreError::InvalidCommand("unknown command".to_string()))
 }
 }
 
-fn parse_histmatch_command(_args: &Vec<String>) -> ArgumentResult {
-  // TODO: obviously this needs to be implemented....
-  Ok(None)
+/**
+ * Parse the histmatch command, which matches the histogram of the source 
+ * image to that of the reference image
+ *   histmatch -i <source file> -ref <reference file> -o <output file>
+ */
+fn parse_histmatch_command(args: &Vec<String>) -> ArgumentResult {
+  const SOURCE_FILE:usize = 3;
+  const REFERENCE_FILE:usize = 5;
+  const OUTPUT_FILE:usize = 7;
+
+  use crate::core::io::{open_image, write_image};
+  use crate::core::operations::{histogram_matching, Histogram};
+
+  do_flag_position_check(
+    args, &[
+      (&2, "-i", "-i flag in the wrong place"),
+      (&4, "-ref", "-ref flag in the wrong place"),
+      (&6, "-o", "-o flag in the wrong place"),
+  ])?;
+
+  let source = open_image(args[SOURCE_FILE].as_str())?;
+  let reference = Histogram::from_image(
+    &open_image(args[REFERENCE_FILE].as_str())?
+  );
+
+  let matched = histogram_matching(&source, &reference)?;
+  write_image(&matched, args[OUTPUT_FILE].as_str())?;
+
+  Ok(Some(matched))
 }
 
 fn parse_histeq_command(args: &Vec<String>) -> ArgumentResult {
@@ -339,21 +428,21 @@ fn parse_histeq_command(args: &Vec<String>) -> ArgumentResult {
                 Ok(eq_image) => {
                   match write_image(&eq_image, output_file) {
                     Ok(_) => Ok(Some(eq_image)),
-                    Err(why) => Err(why.to_string()),
+                    Err(why) => Err(why),
                   }
                 },
-                Err(why) => return Err(why.to_string())
+                Err(why) => return Err(why)
               }            
             },
-            Err(why) => return Err(why.to_string())
+            Err(why) => return Err(why)
           }
         },
-        Err(why) => return Err(why.to_string()),
+        Err(why) => return Err(why),
       }
     },
-    _ => return Err(
+    _ => return Err(CoreError::InvalidCommand(
       format!("unknown command: {}", args[1].to_lowercase().as_str())
-    ),
+    )),
   }
 }
 
@@ -369,10 +458,182 @@ fn do_flag_position_check(
   conditions: &[FlagCheck]) -> ArgumentResult {
   for flag_check in conditions {
     if args[*flag_check.0].to_lowercase().as_str() != flag_check.1 {
-      return Err(flag_check.2.to_string());
+      return Err(CoreError::InvalidCommand(flag_check.2.to_string()));
     }
   }
 
   Ok(None)
 }
-/* #endregion */
\ No newline at end of file
+/* #endregion */
+
+/* #region Chained commands */
+
+/**
+ * Splits a chained command into its sub-commands. Either of these forms can
+ * be used to chain commands:
+ * 
+ *   chain "gblur -s 1 -k 3" "histeq"
+ *   gblur -s 1 -k 3 | histeq
+ * 
+ * Returns None if the command is not a chain.
+ */
+pub fn split_chain(command: &str) -> Option<Vec<String>> {
+  let trimmed = command.trim();
+
+  let pieces: Vec<String> = if let Some(rest) = trimmed.strip_prefix("chain ") {
+    // sub-commands are wrapped in double quotes, so every other piece between
+    // the quotes is a sub-command
+    rest.split('"')
+      .skip(1)
+      .step_by(2)
+      .map(|piece| piece.trim().to_string())
+      .collect()
+  } else if trimmed.contains('|') {
+    trimmed.split('|')
+      .map(|piece| piece.trim().to_string())
+      .collect()
+  } else {
+    return None
+  };
+
+  Some(pieces.into_iter().filter(|piece| !piece.is_empty()).collect())
+}
+
+/**
+ * Runs each command in the chain in order, feeding the result of each step
+ * into the next one. The first step is given the current image. All of the
+ * intermediate images are returned (in order), so that each step can be 
+ * undone on its own.
+ */
+pub fn run_chain(
+  commands: &Vec<String>, 
+  image: Option<&PpmImage>
+) -> Result<Vec<PpmImage>, CoreError> {
+  if commands.is_empty() {
+    return Err(CoreError::InvalidCommand(
+      "The chain does not contain any commands".to_string()
+    ))
+  }
+
+  let mut steps = Vec::<PpmImage>::with_capacity(commands.len());
+
+  for (step, command) in commands.iter().enumerate() {
+    let input = if let Some(last) = steps.last() { Some(last) } else { image };
+
+    match apply_command(command, input) {
+      Ok(Some(output)) => steps.push(output),
+      Ok(None) => return Err(CoreError::InvalidCommand(
+        format!("Step {} (\"{}\") did not produce an image", step + 1, command)
+      )),
+      Err(why) => return Err(
+        why.context(&format!("Step {} (\"{}\") failed", step + 1, command))
+      ),
+    }
+  }
+
+  Ok(steps)
+}
+
+/**
+ * Runs a single command of a chain. Commands that name their own input file
+ * (with -i) are handed to parse_arguments, while the short forms below are
+ * applied directly to the given image:
+ * 
+ *   inv
+ *   histeq
+ *   sobel
+ *   gray [-linear]
+ *   gblur -s <sigma> -k <kernel size>
+ *   log -c <c> -b <base>
+ *   pow -gamma <gamma>
+ *   conv -m <comma separated kernel, row by row>
+ */
+pub fn apply_command(command: &str, image: Option<&PpmImage>) -> ArgumentResult {
+  let mut args: Vec<String> = vec!["arglist".to_string()];
+  args.extend(command.split_whitespace().map(|arg| arg.to_string()));
+
+  if args.len() < 2 {
+    return Err(CoreError::InvalidCommand("Empty command".to_string()))
+  }
+
+  // the full form of the command reads and writes files on its own
+  if args.iter().any(|arg| arg.as_str() == "-i") {
+    return parse_arguments(Some(args))
+  }
+
+  let image = match image {
+    Some(image) => image,
+    None => return Err(CoreError::InvalidCommand(
+      "There is no image to apply the command to".to_string()
+    )),
+  };
+
+  let result = match get_operation!(args) {
+    "inv" => operations::negate(image),
+    "histeq" => operations::histogram_equalization(image, None),
+    "sobel" => filters::edge_detect(image),
+    "gray" => operations::to_grayscale(
+      image,
+      match args.iter().any(|arg| arg.as_str() == "-linear") {
+        true => operations::GrayscaleMode::LinearLight,
+        false => operations::GrayscaleMode::Srgb,
+      }
+    ),
+    "gblur" => filters::gaussian_blur(
+      image,
+      parse_float(get_flag_value(&args, "-s").unwrap_or(&"1".to_string())),
+      get_flag_value(&args, "-k")
+        .unwrap_or(&"3".to_string())
+        .parse::<i32>()
+        .unwrap_or(0),
+      Padding::Zero
+    ),
+    "log" => operations::log_transform(
+      image,
+      get_flag_value(&args, "-c").map(parse_float),
+      get_flag_value(&args, "-b").map(parse_float)
+    ),
+    "pow" => operations::gamma_transform(
+      image,
+      parse_float(get_flag_value(&args, "-gamma").unwrap_or(&"1".to_string())),
+      None
+    ),
+    "conv" => {
+      let kernel = match get_flag_value(&args, "-m") {
+        Some(kernel) => parse_kernel(kernel)?,
+        None => return Err(CoreError::InvalidCommand(
+          "conv needs a kernel, given with -m".to_string()
+        )),
+      };
+
+      // the kernel is square, so its size follows from the number of values
+      let kernel_size = (kernel.len() as f32).sqrt().round() as usize;
+      filters::convolve(image, &kernel, kernel_size, Padding::Repeat)
+    },
+    _ => return Err(CoreError::InvalidCommand(
+      format!("Unknown command: {}", get_operation!(args))
+    )),
+  };
+
+  result.map(|output| Some(output))
+}
+
+/// Parses a comma separated list of kernel values, like "0,-1,0,-1,5,-1,0,-1,0"
+fn parse_kernel(kernel: &str) -> Result<Vec<f32>, CoreError> {
+  kernel.split(',')
+    .map(|value| value.trim().parse::<f32>().map_err(
+      |_| CoreError::InvalidParameter(
+        format!("\"{}\" is not a valid kernel value", value)
+      )
+    ))
+    .collect()
+}
+
+/// Gets the value that follows the given flag, if the flag is present
+fn get_flag_value<'a>(args: &'a Vec<String>, flag: &str) -> Option<&'a String> {
+  args.iter()
+    .position(|arg| arg.to_lowercase().as_str() == flag)
+    .and_then(|index| args.get(index + 1))
+}
+
+/* #endregion */
@@ -0,0 +1,225 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::core::args::apply_command;
+use crate::core::error::CoreError;
+use crate::core::io;
+
+/// The name a batch writes each result under, unless it is given another
+pub const DEFAULT_BATCH_TEMPLATE: &str = "{name}.{ext}";
+
+/// The name a stack writes each of its master frames under
+pub const DEFAULT_MASTER_TEMPLATE: &str = "master.{algo}.{ext}";
+
+/* #region Output Names */
+
+/// What fills in each of the placeholders of an output file name template
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputName<'a> {
+  /// {name}: the name of the input file without its extension (or of the 
+  /// folder, for a stack)
+  pub name: &'a str,
+  /// {algo}: the command or stacking algorithm, like "gblur" or "median"
+  pub algo: &'a str,
+  /// {params}: the parameters it was run with, like "s_2_k_7"
+  pub params: &'a str,
+  /// {ext}: the extension of the output file, which picks its format
+  pub ext: &'a str,
+}
+
+/**
+ * Fills in the placeholders of an output file name template, so that 
+ * "{name}_{algo}.{ext}" becomes "m31_gblur.ppm". Anything in braces that isn't
+ * one of the placeholders is left as it is.
+ */
+pub fn expand_template(template: &str, output: &OutputName) -> String {
+  template
+    .replace("{name}", output.name)
+    .replace("{algo}", output.algo)
+    .replace("{params}", output.params)
+    .replace("{ext}", output.ext)
+}
+
+/// Splits a command like "gblur -s 2 -k 7" into its name ("gblur") and its
+/// parameters in a form that can go in a file name ("s_2_k_7")
+pub fn command_name_parts(command: &str) -> (String, String) {
+  let mut words = command.split_whitespace();
+  let algo = words.next().unwrap_or_default().to_string();
+  let params: Vec<&str> = words
+    .map(|word| word.trim_start_matches('-'))
+    .filter(|word| !word.is_empty())
+    .collect();
+
+  (algo, params.join("_"))
+}
+
+/* #endregion */
+
+/* #region Batch Processing */
+
+/// How far along a batch is, given to the progress callback after each file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchProgress {
+  pub completed: usize,
+  pub total: usize,
+}
+
+impl BatchProgress {
+  /// How much of the batch is done, from 0 to 1
+  pub fn fraction(&self) -> f32 {
+    if 0 == self.total {
+      return 1.;
+    }
+
+    self.completed as f32 / self.total as f32
+  }
+
+  pub fn remaining(&self) -> usize {
+    self.total - self.completed
+  }
+}
+
+/// What happened to each file of a batch. A file that fails is skipped, so
+/// that one bad file doesn't stop the rest of the batch.
+#[derive(Debug)]
+pub struct BatchSummary {
+  pub total: usize,
+  pub failures: Vec<(PathBuf, CoreError)>,
+}
+
+impl BatchSummary {
+  pub fn succeeded(&self) -> usize {
+    self.total - self.failures.len()
+  }
+
+  /// A description of the batch, listing why each failed file failed
+  pub fn report(&self) -> String {
+    let mut report = format!(
+      "Processed {} of {} images", self.succeeded(), self.total
+    );
+
+    for (path, why) in self.failures.iter() {
+      let name = path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+      report.push_str(&format!("\n{}: {}", name, why));
+    }
+
+    report
+  }
+}
+
+/// The images in a folder that a batch would process, in order of their file
+/// names
+pub fn batch_images(directory: &Path) -> Result<Vec<PathBuf>, CoreError> {
+  let entries = match std::fs::read_dir(directory) {
+    Ok(entries) => entries,
+    Err(why) => return Err(CoreError::Io(format!(
+      "Could not read folder \"{}\": {}", directory.display(), why
+    ))),
+  };
+
+  let mut paths: Vec<PathBuf> = entries
+    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+    .filter(|path| path.is_file() && io::is_image_file(path))
+    .collect();
+  paths.sort();
+
+  Ok(paths)
+}
+
+/**
+ * Applies a command (one of the short forms taken by args::apply_command,
+ * like "gblur -s 2 -k 7") to each of the given images, writing the results
+ * into the output folder under the names the template expands to (see 
+ * expand_template). {ext} is "ppm", but the template can give another 
+ * extension to write PNGs or JPEGs instead. A file whose result would go to
+ * the same place as that of an earlier file (like a.png and a.jpg under the
+ * default template) fails rather than overwriting it. The progress callback
+ * is called after every file, whether or not it succeeded.
+ */
+pub fn run_batch<F: FnMut(BatchProgress)>(
+  paths: &[PathBuf],
+  output_directory: &Path,
+  command: &str,
+  template: &str,
+  mut progress: F
+) -> Result<BatchSummary, CoreError> {
+  if command.trim().is_empty() {
+    return Err(CoreError::InvalidCommand("Empty command".to_string()));
+  }
+
+  // without the name of the input, every result would overwrite the last
+  if !template.contains("{name}") {
+    return Err(CoreError::InvalidParameter(format!(
+      "The file name template \"{}\" has to include {{name}}", template
+    )));
+  }
+
+  // the full form of a command reads and writes its own files
+  if command.split_whitespace().any(|arg| "-i" == arg || "-o" == arg) {
+    return Err(CoreError::InvalidCommand(
+      "A batch command can't name its own input or output files".to_string()
+    ));
+  }
+
+  let mut summary = BatchSummary {
+    total: paths.len(),
+    failures: Vec::<(PathBuf, CoreError)>::new(),
+  };
+
+  let mut output_paths = HashSet::<PathBuf>::new();
+  for (index, path) in paths.iter().enumerate() {
+    let output_path = output_path(path, output_directory, command, template);
+    let result = if output_paths.insert(output_path.clone()) {
+      process_file(path, &output_path, command)
+    } else {
+      Err(CoreError::InvalidParameter(format!(
+        "\"{}\" would overwrite the result of another image",
+        output_path.display()
+      )))
+    };
+
+    if let Err(why) = result {
+      summary.failures.push((path.clone(), why));
+    }
+
+    progress(BatchProgress { completed: index + 1, total: paths.len() });
+  }
+
+  Ok(summary)
+}
+
+/// Where the result of the command on the image goes
+fn output_path(
+  path: &Path,
+  output_directory: &Path,
+  command: &str,
+  template: &str
+) -> PathBuf {
+  let name = path.file_stem().unwrap_or_default().to_string_lossy();
+  let (algo, params) = command_name_parts(command);
+  output_directory.join(expand_template(
+    template, 
+    &OutputName { name: &name, algo: &algo, params: &params, ext: "ppm" }
+  ))
+}
+
+fn process_file(
+  path: &Path,
+  output_path: &Path,
+  command: &str
+) -> Result<(), CoreError> {
+  let image = io::open_image(&path.to_string_lossy())?;
+
+  let output = match apply_command(command, Some(&image))? {
+    Some(output) => output,
+    None => return Err(CoreError::InvalidCommand(
+      format!("\"{}\" did not produce an image", command)
+    )),
+  };
+
+  io::write_image(&output, &output_path.to_string_lossy())
+}
+
+/* #endregion */
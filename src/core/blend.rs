@@ -0,0 +1,334 @@
+use crate::core::ppm::PpmImage;
+use crate::core::operations::{clamp_to_range, OperationResult};
+use crate::core::error::CoreError;
+use crate::core::mask::BinaryMask;
+use crate::to_1d;
+
+use super::PIXEL_SIZE;
+
+/// The most sweeps the Poisson solver makes before settling for what it has
+const POISSON_MAX_ITERATIONS: usize = 5000;
+
+/// The solver stops once no pixel moves by more than this in a sweep
+const POISSON_TOLERANCE: f32 = 0.01;
+
+/// How far past the Gauss-Seidel update each sweep steps (successive over
+/// relaxation). Close to 2 converges fastest on a grid.
+const OVER_RELAXATION: f32 = 1.9;
+
+/// The four neighbors that the discrete laplacian is taken over
+const NEIGHBORS: [(i64, i64); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+
+/* #region Poisson Blending */
+
+/// One of the pixels being solved for, with everything about its equation
+/// that doesn't change between sweeps
+struct PoissonPixel {
+  // where the pixel is in the destination
+  dest_index: usize,
+  // the neighbors that are being solved for as well
+  unknown_neighbors: Vec<usize>,
+  // the fixed neighbors (the border of the region) plus the gradients of
+  // the source
+  known_sum: [f32; PIXEL_SIZE],
+  // how many neighbors the pixel has inside of the destination
+  neighbor_count: f32,
+}
+
+/**
+ * Pastes the masked part of src onto dest with its top left corner at (x, y)
+ * without a visible seam (seamless cloning). Rather than copying the pixels,
+ * it solves the Poisson equation for the pasted region so that its gradients
+ * match those of the source, while its border matches the destination. The
+ * colors of the patch shift to fit in with their new surroundings, but its
+ * detail is kept.
+ *
+ * The mask is the size of the source. Masked pixels next to the edge of the
+ * source take their border from the destination, so an all on mask clones
+ * the whole source. Anything that lands outside of the destination is left
+ * out.
+ */
+pub fn poisson_blend(
+  dest: &PpmImage,
+  src: &PpmImage,
+  mask: &BinaryMask,
+  x: u32,
+  y: u32
+) -> OperationResult {
+  if mask.width() != src.width() || mask.height() != src.height() {
+    return Err(CoreError::InvalidDimensions(format!(
+      "The mask ({}x{}) has to be the same size as the source ({}x{})",
+      mask.width(), mask.height(), src.width(), src.height()
+    )));
+  }
+
+  let (dest_width, dest_height) = (dest.width() as i64, dest.height() as i64);
+  let src_data = src.get_data();
+  let dest_data = dest.get_data();
+
+  let src_sample = |sx: i64, sy: i64, ch: usize| {
+    src_data[to_1d!(sx, sy, src.width()) * PIXEL_SIZE + ch] as f32
+  };
+  let in_dest = |dx: i64, dy: i64| {
+    dx >= 0 && dy >= 0 && dx < dest_width && dy < dest_height
+  };
+
+  // number the masked pixels that land on the destination
+  let mut unknown_index = vec![
+    usize::MAX; (src.width() * src.height()) as usize
+  ];
+  let mut unknowns = Vec::<(i64, i64)>::new();
+  for sy in 0..src.height() {
+    for sx in 0..src.width() {
+      let (dx, dy) = (x as i64 + sx as i64, y as i64 + sy as i64);
+      if mask.get(sx, sy) && in_dest(dx, dy) {
+        unknown_index[to_1d!(sx, sy, src.width())] = unknowns.len();
+        unknowns.push((sx as i64, sy as i64));
+      }
+    }
+  }
+
+  if unknowns.is_empty() {
+    return Ok(dest.clone());
+  }
+
+  let pixels: Vec<PoissonPixel> = unknowns.iter().map(|&(sx, sy)| {
+    let (dx, dy) = (x as i64 + sx, y as i64 + sy);
+    let mut pixel = PoissonPixel {
+      dest_index: to_1d!(dx, dy, dest_width),
+      unknown_neighbors: Vec::<usize>::with_capacity(NEIGHBORS.len()),
+      known_sum: [0.; PIXEL_SIZE],
+      neighbor_count: 0.,
+    };
+
+    for (offset_x, offset_y) in NEIGHBORS {
+      let (nx, ny) = (sx + offset_x, sy + offset_y);
+      let (ndx, ndy) = (dx + offset_x, dy + offset_y);
+      if !in_dest(ndx, ndy) {
+        continue;
+      }
+      pixel.neighbor_count += 1.;
+
+      // the gradient of the source is only known inside of it
+      let in_src = nx >= 0 && ny >= 0 &&
+        nx < src.width() as i64 && ny < src.height() as i64;
+      if in_src {
+        for ch in 0..PIXEL_SIZE {
+          pixel.known_sum[ch] +=
+            src_sample(sx, sy, ch) - src_sample(nx, ny, ch);
+        }
+      }
+
+      let neighbor_index = match in_src {
+        true => unknown_index[to_1d!(nx, ny, src.width())],
+        false => usize::MAX,
+      };
+
+      if usize::MAX != neighbor_index {
+        pixel.unknown_neighbors.push(neighbor_index);
+      } else {
+        let dest_index = to_1d!(ndx, ndy, dest_width) * PIXEL_SIZE;
+        for ch in 0..PIXEL_SIZE {
+          pixel.known_sum[ch] += dest_data[dest_index + ch] as f32;
+        }
+      }
+    }
+
+    pixel
+  }).collect();
+
+  // start from the source as it is, which is usually close in shape
+  let mut values: Vec<[f32; PIXEL_SIZE]> = unknowns.iter()
+    .map(|&(sx, sy)| [0, 1, 2].map(|ch| src_sample(sx, sy, ch)))
+    .collect();
+
+  solve(&pixels, &mut values);
+
+  Ok(write_solved(dest, &pixels, &values))
+}
+
+/* #endregion */
+
+/* #region Masked Compositing */
+
+/**
+ * Takes the pixels that are on in the mask from foreground, and the rest from
+ * background. This is how an edit is limited to part of an image: the edit is
+ * made to the whole image, and composited back over the original.
+ */
+pub fn composite_through_mask(
+  background: &PpmImage,
+  foreground: &PpmImage,
+  mask: &BinaryMask
+) -> OperationResult {
+  let (width, height) = (background.width(), background.height());
+  if foreground.width() != width || foreground.height() != height ||
+     mask.width() != width || mask.height() != height {
+    return Err(CoreError::InvalidDimensions(format!(
+      "The images ({}x{} and {}x{}) and the mask ({}x{}) have to be the \
+       same size",
+      width, height, foreground.width(), foreground.height(),
+      mask.width(), mask.height()
+    )));
+  }
+
+  let mut composited = background.clone();
+  for y in 0..height {
+    for x in 0..width {
+      if mask.get(x, y) {
+        let pixel = foreground.get_pixel_by_coord_ref(x, y);
+        composited.set_pixel_by_coord(x, y, pixel);
+      }
+    }
+  }
+
+  Ok(composited)
+}
+
+/* #endregion */
+
+/* #region Inpainting */
+
+/**
+ * Fills in the masked pixels of the image from the pixels around them (content
+ * aware fill), for removing dust spots, satellite trails and the like. The
+ * filled region is as smooth as it can be while meeting its border, by solving
+ * the Laplace equation over it (diffusion). This works well for gradients and
+ * smooth backgrounds such as the sky, but a large hole in busy texture will
+ * come out blurry.
+ *
+ * The mask has to be the size of the image. Pixels that are not masked are
+ * left as they are.
+ */
+pub fn inpaint(image: &PpmImage, mask: &BinaryMask) -> OperationResult {
+  if mask.width() != image.width() || mask.height() != image.height() {
+    return Err(CoreError::InvalidDimensions(format!(
+      "The mask ({}x{}) has to be the same size as the image ({}x{})",
+      mask.width(), mask.height(), image.width(), image.height()
+    )));
+  }
+
+  if mask.count() == (image.width() * image.height()) as usize {
+    return Err(CoreError::InvalidParameter(
+      "There is nothing left around the mask to fill it in from".to_string()
+    ));
+  }
+
+  let (width, height) = (image.width() as i64, image.height() as i64);
+  let data = image.get_data();
+
+  // number the masked pixels
+  let mut unknown_index = vec![usize::MAX; (width * height) as usize];
+  let mut unknowns = Vec::<(i64, i64)>::new();
+  for y in 0..image.height() {
+    for x in 0..image.width() {
+      if mask.get(x, y) {
+        unknown_index[to_1d!(x, y, width)] = unknowns.len();
+        unknowns.push((x as i64, y as i64));
+      }
+    }
+  }
+
+  if unknowns.is_empty() {
+    return Ok(image.clone());
+  }
+
+  // the average of the border is where the solver starts from
+  let mut border_sum = [0.; PIXEL_SIZE];
+  let mut border_count: f32 = 0.;
+
+  let pixels: Vec<PoissonPixel> = unknowns.iter().map(|&(x, y)| {
+    let mut pixel = PoissonPixel {
+      dest_index: to_1d!(x, y, width),
+      unknown_neighbors: Vec::<usize>::with_capacity(NEIGHBORS.len()),
+      known_sum: [0.; PIXEL_SIZE],
+      neighbor_count: 0.,
+    };
+
+    for (offset_x, offset_y) in NEIGHBORS {
+      let (nx, ny) = (x + offset_x, y + offset_y);
+      if nx < 0 || ny < 0 || nx >= width || ny >= height {
+        continue;
+      }
+      pixel.neighbor_count += 1.;
+
+      let neighbor_index = unknown_index[to_1d!(nx, ny, width)];
+      if usize::MAX != neighbor_index {
+        pixel.unknown_neighbors.push(neighbor_index);
+      } else {
+        let index = to_1d!(nx, ny, width) * PIXEL_SIZE;
+        for ch in 0..PIXEL_SIZE {
+          pixel.known_sum[ch] += data[index + ch] as f32;
+          border_sum[ch] += data[index + ch] as f32;
+        }
+        border_count += 1.;
+      }
+    }
+
+    pixel
+  }).collect();
+
+  let start = border_sum.map(|sum| sum / border_count.max(1.));
+  let mut values = vec![start; pixels.len()];
+
+  solve(&pixels, &mut values);
+
+  Ok(write_solved(image, &pixels, &values))
+}
+
+/* #endregion */
+
+/* #region Solver */
+
+/// Solves the system of equations that the pixels make up in place with
+/// successive over relaxation, starting from the given values
+fn solve(pixels: &[PoissonPixel], values: &mut [[f32; PIXEL_SIZE]]) {
+  for _ in 0..POISSON_MAX_ITERATIONS {
+    let mut largest_change: f32 = 0.;
+
+    for (index, pixel) in pixels.iter().enumerate() {
+      if pixel.neighbor_count <= 0. {
+        continue;
+      }
+
+      for ch in 0..PIXEL_SIZE {
+        let neighbor_sum: f32 = pixel.unknown_neighbors.iter()
+          .map(|neighbor| values[*neighbor][ch])
+          .sum();
+        let solved =
+          (pixel.known_sum[ch] + neighbor_sum) / pixel.neighbor_count;
+
+        let change = OVER_RELAXATION * (solved - values[index][ch]);
+        values[index][ch] += change;
+        largest_change = largest_change.max(change.abs());
+      }
+    }
+
+    if largest_change < POISSON_TOLERANCE {
+      break;
+    }
+  }
+}
+
+/// Copies the image with the solved pixels written into it
+fn write_solved(
+  image: &PpmImage,
+  pixels: &[PoissonPixel],
+  values: &[[f32; PIXEL_SIZE]]
+) -> PpmImage {
+  let mut solved = image.clone();
+  let width = image.width() as usize;
+  for (pixel, value) in pixels.iter().zip(values.iter()) {
+    let new_pixel = value
+      .map(|sample| clamp_to_range(sample.round(), u8::MAX));
+    solved.set_pixel_by_coord(
+      (pixel.dest_index % width) as u32,
+      (pixel.dest_index / width) as u32,
+      &new_pixel
+    );
+  }
+
+  solved
+}
+
+/* #endregion */
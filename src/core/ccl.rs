@@ -1,9 +1,12 @@
-use crate::core::ppm::{PpmImage};
+use crate::core::ppm::{PpmImage, Padding, neighborhood_coords};
+use crate::core::operations::OperationResult;
+use crate::core::error::CoreError;
 use crate::to_1d;
-use std::collections::{HashMap, BTreeSet};
+use std::collections::{HashMap, HashSet, BTreeSet};
 use rand::Rng;
 
-use super::PixelBytes;
+use super::{PixelBytes, PIXEL_SIZE, V_CH, color, mask::BinaryMask, max, min};
+use super::color::ColorMetric;
 
 /// Any pixels that are either unlabeled, or are the background color 
 const UNLABELED:u64 = 0;
@@ -14,18 +17,104 @@ pub enum Connectivity {
   EIGHT,
   FOUR,
   NOS, // "Not Otherwise Specified" Experimental connectivity option
+  DIAGONAL, // only the four diagonal neighbors (an X), for textures
+}
+
+/// How the regions found by ccl are numbered
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LabelNumbering {
+  /// The labels are left as they were found, so there are gaps where labels
+  /// were merged into others
+  AsFound,
+  /// The labels are renumbered to run from 1 up to the number of regions, in
+  /// the order that the regions first appear
+  Sequential,
+}
+
+/**
+ * The sets of labels that have been found to belong to the same region (a
+ * union-find). Each label points at a parent label, and the label at the
+ * root of a set is the one that the whole set is known by, which is always
+ * the smallest label in the set.
+ */
+struct LabelSets {
+  // the parent of each label, indexed by label. UNLABELED is never used.
+  parents: Vec<u64>,
+}
+
+impl LabelSets {
+  fn new() -> Self {
+    LabelSets {
+      parents: vec![UNLABELED],
+    }
+  }
+
+  /// Hands out a new label, in a set of its own
+  fn make_set(&mut self) -> u64 {
+    let label = self.parents.len() as u64;
+    self.parents.push(label);
+    label
+  }
+
+  /// The highest label that was handed out
+  fn max_label(&self) -> u64 {
+    self.parents.len() as u64 - 1
+  }
+
+  /// Finds the label that a label's set is known by. Every label passed on
+  /// the way is pointed straight at it (path compression), so that the next
+  /// lookup is quick.
+  fn find(&mut self, label: u64) -> u64 {
+    let mut root = label;
+    while self.parents[root as usize] != root {
+      root = self.parents[root as usize];
+    }
+
+    let mut current = label;
+    while current != root {
+      let parent = self.parents[current as usize];
+      self.parents[current as usize] = root;
+      current = parent;
+    }
+
+    root
+  }
+
+  /// Joins the sets of two labels, keeping the smaller root
+  fn union(&mut self, a: u64, b: u64) {
+    let root_a = self.find(a);
+    let root_b = self.find(b);
+
+    if root_a < root_b {
+      self.parents[root_b as usize] = root_a;
+    } else if root_b < root_a {
+      self.parents[root_a as usize] = root_b;
+    }
+  }
 }
 
 // uses the cll to create a new image that serves as a mask to illustrate things
-// as an overlay on the loaded image
+// as an overlay on the loaded image. Regions with fewer than min_area pixels
+// (like hot pixels) are painted with the background color, so that they don't
+// crowd out the regions of interest. A min_area of 0 shows every region.
 pub fn make_ccl_mask(
-  image: &PpmImage, c_type: Connectivity, tolerance:f32
+  image: &PpmImage, 
+  c_type: Connectivity, 
+  tolerance:f32, 
+  metric: ColorMetric,
+  min_area: usize
 ) -> PpmImage {
 
-  let (pixel_labels, label_count) = ccl(
-    image, c_type, tolerance
+  let (pixel_labels, label_count) = ccl_with_metric(
+    image, c_type, tolerance, metric, LabelNumbering::Sequential
   );
 
+  // the labels are sequential, so they index straight into the areas
+  let mut areas = vec![0usize; label_count + 1];
+  for label in pixel_labels.iter() {
+    areas[*label as usize] += 1;
+  }
+
   let mut new_image = PpmImage::new(image.width(), image.height());
   // keeping the histogram updated can be very demanding from a performance
   // perspective - so we turn it off here since a CCL mask doesn't need it.
@@ -38,7 +127,12 @@ pub fn make_ccl_mask(
 
   label_colors.push(bg_color);
 
-  for _ in 0..label_count {
+  for label in 1..=label_count {
+    if areas[label] < min_area {
+      label_colors.push(bg_color);
+      continue;
+    }
+
     label_colors.push([
       rand::thread_rng().gen_range(u8::MIN..u8::MAX),
       rand::thread_rng().gen_range(u8::MIN..u8::MAX),
@@ -59,18 +153,33 @@ pub fn make_ccl_mask(
   new_image
 }
 
-// Creates a vector of labels, and a count of how many of them are unique
-fn ccl(
-  image: &PpmImage, c_type: Connectivity, tolerance:f32
+// Creates a vector of labels, and a count of how many of them are unique. With
+// sequential numbering the count is exactly the number of regions, otherwise
+// it is the highest label that was handed out.
+pub fn ccl(
+  image: &PpmImage, 
+  c_type: Connectivity, 
+  tolerance:f32, 
+  numbering: LabelNumbering
+) -> (Vec<u64>, usize) {
+  ccl_with_metric(image, c_type, tolerance, ColorMetric::Redmean, numbering)
+}
+
+// The same as ccl, with the tolerance measured by the given color metric
+pub fn ccl_with_metric(
+  image: &PpmImage, 
+  c_type: Connectivity, 
+  tolerance:f32, 
+  metric: ColorMetric,
+  numbering: LabelNumbering
 ) -> (Vec<u64>, usize) {
 
   // will store the labels that are linked together
-  let mut linked_labels: HashMap<u64, BTreeSet<u64>> = HashMap::new();
+  let mut label_sets = LabelSets::new();
   let mut labels = vec![
     UNLABELED; (image.width() * image.height()) as usize
   ];
 
-  let mut cur_label = UNLABELED + 1;
   let bg_color = image.get_background();
 
   // first pass
@@ -80,9 +189,7 @@ fn ccl(
       // is the pixel a background color
       if pixel != bg_color {
         
-        let possible_neighbors = get_valid_neighbors(
-          x as i32, y as i32, image, c_type
-        );
+        let possible_neighbors = get_valid_neighbors(x, y, image, c_type);
 
         let mut valid_neighbors: Vec<(u32, u32)> = Vec::new();
         let mut neighbor_labels = BTreeSet::<u64>::new();
@@ -91,7 +198,7 @@ fn ccl(
             possible_neighbor.0, possible_neighbor.1
           ).unwrap();
           // does the pixel have the same value as the current one?
-          if is_neighbor_equivalent(pixel, neighbor_pixel, tolerance) {
+          if is_neighbor_equivalent(pixel, neighbor_pixel, tolerance, metric) {
             let neighbor_label = labels[
               to_1d!(possible_neighbor.0, possible_neighbor.1, image.width())
             ];
@@ -106,21 +213,14 @@ fn ccl(
         }
 
         if valid_neighbors.is_empty() {
-          cur_label += 1;
-          linked_labels.insert(cur_label, BTreeSet::from([cur_label]));
-          labels[to_1d!(x, y, image.width())] = cur_label;
+          labels[to_1d!(x, y, image.width())] = label_sets.make_set();
         } else {
-          labels[to_1d!(x, y, image.width())] = *neighbor_labels
-            .iter()
-            .next()
-            .unwrap();
+          let smallest = *neighbor_labels.iter().next().unwrap();
+          labels[to_1d!(x, y, image.width())] = smallest;
 
+          // every neighbor is part of the same region as this pixel
           for label in neighbor_labels.iter() {
-            if let Some(linked) = linked_labels.get_mut(
-              label
-            ) {
-              linked.extend(neighbor_labels.iter());
-            }
+            label_sets.union(smallest, *label);
           }
         }
 
@@ -140,12 +240,7 @@ fn ccl(
       let current_label = labels[to_1d!(x, y, image.width())];
       if current_label != UNLABELED {
         // use label equivalency data structure to use smallest equivalent label
-        let label = *linked_labels
-          .get(&current_label)
-          .unwrap()
-          .iter()
-          .next()
-          .unwrap();
+        let label = label_sets.find(current_label);
 
         labels[to_1d!(x, y, image.width())] = label;
 
@@ -158,25 +253,299 @@ fn ccl(
     }
   }
 
-  (labels, cur_label as usize)
+  if LabelNumbering::Sequential == numbering {
+    let region_count = number_sequentially(&mut labels);
+    return (labels, region_count);
+  }
+
+  (labels, label_sets.max_label() as usize)
+}
+
+/// Renumbers the labels to run from 1 up to the number of distinct labels, in
+/// the order that they first appear. Unlabeled pixels are left alone. Returns
+/// the number of distinct labels.
+fn number_sequentially(labels: &mut [u64]) -> usize {
+  let mut new_labels = HashMap::<u64, u64>::new();
+
+  for label in labels.iter_mut().filter(|label| UNLABELED != **label) {
+    let next_label = new_labels.len() as u64 + 1;
+    *label = *new_labels.entry(*label).or_insert(next_label);
+  }
+
+  new_labels.len()
 }
 
+/* #region Region Statistics */
+
+/// The size, extent, and center of one of the regions found by ccl
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LabelStats {
+  /// How many pixels the region has
+  pub area: usize,
+  /// The bounding box of the region, with both corners inside of it
+  pub min_x: u32,
+  pub min_y: u32,
+  pub max_x: u32,
+  pub max_y: u32,
+  /// The mean (x, y) of the pixels in the region
+  pub centroid: (f32, f32),
+}
+
+/**
+ * Labels the image like ccl (numbered sequentially), and also measures each
+ * region: its area, bounding box, and centroid. This is what's needed to 
+ * sort through detected stars, for instance to throw out hot pixels by 
+ * rejecting any region with an area below a threshold.
+ */
+pub fn ccl_with_stats(
+  image: &PpmImage, 
+  c_type: Connectivity, 
+  tolerance: f32
+) -> (Vec<u64>, HashMap<u64, LabelStats>) {
+  let (labels, _) = ccl(image, c_type, tolerance, LabelNumbering::Sequential);
+
+  // the sums of the coordinates, for the centroids
+  let mut stats = HashMap::<u64, (LabelStats, f64, f64)>::new();
+  for y in 0..image.height() {
+    for x in 0..image.width() {
+      let label = labels[to_1d!(x, y, image.width())];
+      if UNLABELED == label {
+        continue;
+      }
+
+      let (region, sum_x, sum_y) = stats.entry(label).or_insert((
+        LabelStats {
+          area: 0,
+          min_x: x,
+          min_y: y,
+          max_x: x,
+          max_y: y,
+          centroid: (0., 0.),
+        }, 
+        0., 
+        0.
+      ));
+
+      region.area += 1;
+      region.min_x = min(region.min_x, x);
+      region.min_y = min(region.min_y, y);
+      region.max_x = max(region.max_x, x);
+      region.max_y = max(region.max_y, y);
+      *sum_x += x as f64;
+      *sum_y += y as f64;
+    }
+  }
+
+  let stats = stats.into_iter().map(|(label, (mut region, sum_x, sum_y))| {
+    region.centroid = (
+      (sum_x / region.area as f64) as f32, 
+      (sum_y / region.area as f64) as f32
+    );
+    (label, region)
+  }).collect();
+
+  (labels, stats)
+}
+
+/* #endregion */
+
+/* #region Star Removal */
+
+/**
+ * Creates a "starless" version of the image. Stars are found as connected
+ * regions of bright pixels that are no bigger than size_threshold pixels. 
+ * Each star is grown by a pixel (to catch its halo) and then filled with the
+ * median color of the ring of pixels surrounding it.
+ * 
+ * A pixel counts as bright if its value is more than halfway between the 
+ * median value of the image (roughly the sky background) and the brightest
+ * value in the image.
+ */
+pub fn remove_stars(image: &PpmImage, size_threshold: usize) -> OperationResult {
+  if 0 == size_threshold {
+    return Err(CoreError::InvalidParameter(
+      "The star size threshold must be at least one pixel".to_string()
+    ));
+  }
+
+  let width = image.width();
+  let height = image.height();
+
+  let values: Vec<f32> = image.get_data()
+    .chunks_exact(PIXEL_SIZE)
+    .map(|p| color::rgb_to_hsv([p[0], p[1], p[2]])[V_CH])
+    .collect();
+
+  if values.is_empty() {
+    return Err(CoreError::InvalidDimensions(
+      "Cannot remove stars from an empty image".to_string()
+    ));
+  }
+
+  let mut sorted_values = values.clone();
+  sorted_values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+  let median_value = sorted_values[sorted_values.len() / 2];
+  let max_value = sorted_values[sorted_values.len() - 1];
+  let bright_threshold = median_value + (max_value - median_value) / 2.;
+
+  // build a black and white mask of the bright pixels so that CCL can find
+  // the blobs
+  let bright_mask = BinaryMask::from_threshold(image, bright_threshold);
+
+  let (labels, _) = ccl(
+    &bright_mask.to_ppm(), Connectivity::EIGHT, 0., LabelNumbering::AsFound
+  );
+
+  // gather the pixels belonging to each bright region
+  let mut regions = HashMap::<u64, Vec<(u32, u32)>>::new();
+  for y in 0..height {
+    for x in 0..width {
+      let label = labels[to_1d!(x, y, width)];
+      if label != UNLABELED && bright_mask.get(x, y) {
+        regions.entry(label).or_insert_with(Vec::new).push((x, y));
+      }
+    }
+  }
+
+  let mut starless = image.clone();
+
+  for region in regions.values() {
+    if region.len() > size_threshold {
+      continue;
+    }
+
+    // grow the star by a pixel to cover its halo, then use the ring around
+    // that as the surrounding background
+    let star: HashSet<(u32, u32)> = region.iter().cloned().collect();
+    let fill = dilate(&star, width, height);
+    let ring: Vec<(u32, u32)> = dilate(&fill, width, height)
+      .difference(&fill)
+      .cloned()
+      .filter(|(x, y)| !bright_mask.get(*x, *y))
+      .collect();
+
+    if ring.is_empty() {
+      continue;
+    }
+
+    let mut background: PixelBytes<u8> = [0; PIXEL_SIZE];
+    for ch in 0..PIXEL_SIZE {
+      let mut channel: Vec<u8> = ring
+        .iter()
+        .map(|(x, y)| image.get_pixel_by_coord_ref(*x, *y)[ch])
+        .collect();
+      channel.sort();
+      background[ch] = channel[channel.len() / 2];
+    }
+
+    for (x, y) in fill {
+      starless.set_pixel_by_coord(x, y, &background);
+    }
+  }
+
+  Ok(starless)
+}
+
+/// Grows a set of pixel coordinates by one pixel in all eight directions 
+/// (morphological dilation with a 3x3 square)
+fn dilate(
+  pixels: &HashSet<(u32, u32)>, width: u32, height: u32
+) -> HashSet<(u32, u32)> {
+  let mut dilated = pixels.clone();
+
+  for (x, y) in pixels {
+    dilated.extend(
+      neighborhood_coords(*x, *y, 1, width, height, Padding::Zero)
+        .filter_map(|(_, position)| position)
+    );
+  }
+
+  dilated
+}
+
+/* #endregion */
+
+/* #region Star Detection */
+
+/**
+ * Finds the peaks of the image: pixels whose value is the highest within the
+ * window (a square window x window pixels, centered on the pixel) and greater
+ * than or equal to min_value (0..255).
+ * 
+ * Unlike the CCL centroids, stars that have bled into each other still have
+ * separate peaks, so this works as a simple star detector for registration.
+ * When several pixels of a flat peak share the highest value, only the first
+ * one (in reading order) is returned.
+ */
+pub fn find_local_maxima(
+  image: &PpmImage, window: u32, min_value: f32
+) -> Vec<(u32, u32)> {
+  let width = image.width();
+  let height = image.height();
+  let radius = window / 2;
+
+  let values: Vec<f32> = image.get_data()
+    .chunks_exact(PIXEL_SIZE)
+    .map(|p| color::rgb_to_hsv([p[0], p[1], p[2]])[V_CH])
+    .collect();
+
+  let mut maxima = Vec::<(u32, u32)>::new();
+
+  for y in 0..height {
+    for x in 0..width {
+      let value = values[to_1d!(x, y, width)];
+      if value < min_value {
+        continue;
+      }
+
+      let is_peak = !neighborhood_coords(
+        x, y, radius, width, height, Padding::Zero
+      ).any(|(_, position)| {
+        let (wx, wy) = match position {
+          Some(position) => position,
+          None => return false,
+        };
+
+        let other = values[to_1d!(wx, wy, width)];
+
+        // ties go to whichever pixel comes first, so that a flat peak is 
+        // only reported once
+        let comes_first = (wy, wx) < (y, x);
+        other > value || (comes_first && other == value)
+      });
+
+      if is_peak {
+        maxima.push((x, y));
+      }
+    }
+  }
+
+  maxima
+}
+
+/* #endregion */
+
+/// Decides whether two neighboring pixels belong to the same region. The 
+/// tolerance is the largest (normalized, 0..1) distance between two colors,
+/// by the given metric, that still connects them:
+/// 
+/// * 0.0 only connects pixels with exactly the same color
+/// * 1.0 connects any two pixels, no matter how different they are
+/// 
+/// Anything in between connects colors that are at most that far apart, so 
+/// raising the tolerance can only ever merge regions together.
 fn is_neighbor_equivalent(
   pixel:PixelBytes<u8>, 
   neighbor_pixel:PixelBytes<u8>, 
-  tolerance:f32
+  tolerance:f32,
+  metric: ColorMetric
 ) -> bool {
-  if 1. <= tolerance {
+  if tolerance <= 0. {
     pixel == neighbor_pixel
+  } else if tolerance >= 1. {
+    true
   } else {
-    use crate::core::color::redmean_distance;
-    let redmean_dist = redmean_distance(
-      pixel,
-      neighbor_pixel
-    );
-    let is_close = redmean_dist <= 1. - tolerance;
-
-    is_close
+    metric.distance(pixel, neighbor_pixel) <= tolerance
   }
 }
 
@@ -199,7 +568,7 @@ fn is_neighbor_equivalent(
   const NORTH_WEST:(i8, i8) = (-1, -1);
 
 fn get_valid_neighbors(
-  x:i32, y:i32, image: &PpmImage, c_type: Connectivity
+  x:u32, y:u32, image: &PpmImage, c_type: Connectivity
 ) -> Vec<(u32, u32)> {
   let mut shifts: Vec<(i8, i8)> = Vec::new();
   match c_type {
@@ -213,6 +582,10 @@ fn get_valid_neighbors(
       shifts.push(WEST);
       shifts.push(NORTH);
     }
+    Connectivity::DIAGONAL => {
+      shifts.push(NORTH_WEST);
+      shifts.push(NORTH_EAST);
+    }
     Connectivity::NOS => {
       shifts.push(NORTH);
       shifts.push(NORTH_EAST);
@@ -225,16 +598,10 @@ fn get_valid_neighbors(
     }
   }
 
-  let mut valid_neighbors: Vec<(u32, u32)> = Vec::new();
-
-  for shift in shifts {
-    let n_index = (x + shift.0 as i32, y + shift.1 as i32);
-    if n_index.0 >= 0 && (n_index.0 as u32) < image.width() &&
-       n_index.1 >= 1 && (n_index.1 as u32) < image.height() {
-        valid_neighbors.push((n_index.0 as u32, n_index.1 as u32));
-       }
-  }
-
-  valid_neighbors
+  // the neighbors that are off of the image are left out
+  neighborhood_coords(x, y, 1, image.width(), image.height(), Padding::Zero)
+    .filter(|((dx, dy), _)| shifts.contains(&(*dx as i8, *dy as i8)))
+    .filter_map(|(_, position)| position)
+    .collect()
 }
 
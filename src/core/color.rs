@@ -1,7 +1,13 @@
+use std::sync::OnceLock;
+
 use image::Primitive;
+use strum_macros::{EnumIter, Display};
 
 use super::{max, min, PixelBytes, R_CH, G_CH, B_CH};
+use super::operations::clamp_to_range;
 pub type HSVPixel = [f32; 3];
+/// A color in CIELAB: lightness (0 to 100), then the a and b axes
+pub type LabPixel = [f32; 3];
 
 pub const BLACK:PixelBytes<u8> = [0, 0, 0];
 pub const V_MULT:u16 = 10000;
@@ -51,7 +57,8 @@ pub fn rgb_to_hsv<T: Primitive>(pixels: PixelBytes<T>) -> HSVPixel {
   if delta == 0. {
     hue = 0.;
   } else if c_max == r {
-    hue = (g - b) / delta % 6.;
+    // rem_euclid keeps the hue positive, which hsv_to_rgb depends on
+    hue = ((g - b) / delta).rem_euclid(6.);
   } else if c_max == g {
     hue = (b - r) / delta + 2.;
   } else if c_max == b {
@@ -84,4 +91,167 @@ pub fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> PixelBytes<u8> {
     4 => [t, p, v],
     _ => [v, p, q],
   }
-}
\ No newline at end of file
+}
+
+/* #region sRGB Transfer Curve */
+
+/// Decodes an sRGB value (from 0 to 1) to linear light, with the piecewise
+/// sRGB curve (linear below 0.04045, and a 2.4 power above)
+pub fn decode_srgb(value: f32) -> f32 {
+  if value <= 0.04045 {
+    value / 12.92
+  } else {
+    ((value + 0.055) / 1.055).powf(2.4)
+  }
+}
+
+/// Encodes a linear light value (from 0 to 1) back to sRGB
+pub fn encode_srgb(value: f32) -> f32 {
+  if value <= 0.0031308 {
+    value * 12.92
+  } else {
+    1.055 * value.powf(1. / 2.4) - 0.055
+  }
+}
+
+/// Every 8 bit sRGB value decoded to linear light, so that decoding a pixel
+/// is a lookup rather than a power. It is filled in the first time it is used.
+fn srgb_to_linear_lut() -> &'static [f32; 256] {
+  static LUT: OnceLock<[f32; 256]> = OnceLock::new();
+  LUT.get_or_init(|| std::array::from_fn(
+    |value| decode_srgb(value as f32 / u8::MAX as f32)
+  ))
+}
+
+/// Converts an 8 bit sRGB value to linear light (from 0 to 1)
+pub fn srgb_to_linear(value: u8) -> f32 {
+  srgb_to_linear_lut()[value as usize]
+}
+
+/// Converts a linear light value (from 0 to 1) to the nearest 8 bit sRGB 
+/// value. Values outside of 0 to 1 are clamped.
+pub fn linear_to_srgb(value: f32) -> u8 {
+  let encoded = encode_srgb(value.clamp(0., 1.)) * u8::MAX as f32;
+  clamp_to_range(encoded.round(), u8::MAX)
+}
+
+/* #endregion */
+
+/* #region Luminance */
+
+/// The Rec.709 (and sRGB) weights of the red, green, and blue channels
+pub const REC709_WEIGHTS: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// The Rec.709 weighted sum of the sRGB values of a pixel. This is fast, but 
+/// as the values are gamma encoded it isn't the true luminance.
+pub fn srgb_luma(pixel: PixelBytes<u8>) -> u8 {
+  let luma: f32 = pixel.iter()
+    .zip(REC709_WEIGHTS.iter())
+    .map(|(&ch, weight)| ch as f32 * weight)
+    .sum();
+
+  clamp_to_range(luma.round(), u8::MAX)
+}
+
+/// The luminance of a pixel, weighted in linear light and encoded back to
+/// sRGB. Saturated colors come out brighter than with srgb_luma.
+pub fn linear_luminance(pixel: PixelBytes<u8>) -> u8 {
+  let luminance: f32 = pixel.iter()
+    .zip(REC709_WEIGHTS.iter())
+    .map(|(&ch, weight)| srgb_to_linear(ch) * weight)
+    .sum();
+
+  linear_to_srgb(luminance)
+}
+
+/* #endregion */
+
+/* #region Color Difference */
+
+/// The reference white (D65) that sRGB is defined against, in XYZ
+pub const D65_WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+/// The CIE76 distance between black and white, which is used to bring it 
+/// into the same range as the redmean distance
+pub const CIE76_BLACK_TO_WHITE: f32 = 100.;
+
+/// The ways that the difference between two colors can be measured
+#[derive(PartialEq, EnumIter, Display, Clone, Copy, Debug)]
+pub enum ColorMetric {
+  /// A weighted RGB distance, which is quick and close to perceptual
+  #[strum(serialize = "Redmean")]
+  Redmean,
+  /// The distance in CIELAB, which is slower but more accurate
+  #[strum(serialize = "CIE76")]
+  Cie76,
+}
+
+impl ColorMetric {
+  /// The difference between two colors, where black and white are 1 apart. 
+  /// (The most different colors are a little more than 1 apart in CIE76.)
+  pub fn distance(
+    &self, 
+    pixel_one: PixelBytes<u8>, 
+    pixel_two: PixelBytes<u8>
+  ) -> f32 {
+    match self {
+      ColorMetric::Redmean => redmean_distance(pixel_one, pixel_two),
+      ColorMetric::Cie76 => {
+        delta_e_cie76(pixel_one, pixel_two) / CIE76_BLACK_TO_WHITE
+      },
+    }
+  }
+}
+
+/// Converts an sRGB pixel to CIE XYZ (relative to D65, so white has a Y of 1)
+pub fn rgb_to_xyz(pixel: PixelBytes<u8>) -> [f32; 3] {
+  let r = srgb_to_linear(pixel[R_CH]);
+  let g = srgb_to_linear(pixel[G_CH]);
+  let b = srgb_to_linear(pixel[B_CH]);
+
+  [
+    0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+    0.2126729 * r + 0.7151522 * g + 0.0721750 * b,
+    0.0193339 * r + 0.1191920 * g + 0.9503041 * b,
+  ]
+}
+
+/// Converts a color from CIE XYZ to CIELAB, against the D65 white
+pub fn xyz_to_lab(xyz: [f32; 3]) -> LabPixel {
+  // the cube root, with a linear segment near black so that it has no 
+  // infinite slope at zero
+  let f = |t: f32| {
+    const EPSILON: f32 = 216. / 24389.;
+    const KAPPA: f32 = 24389. / 27.;
+
+    if t > EPSILON { t.cbrt() } else { (KAPPA * t + 16.) / 116. }
+  };
+
+  let fx = f(xyz[0] / D65_WHITE[0]);
+  let fy = f(xyz[1] / D65_WHITE[1]);
+  let fz = f(xyz[2] / D65_WHITE[2]);
+
+  [116. * fy - 16., 500. * (fx - fy), 200. * (fy - fz)]
+}
+
+pub fn rgb_to_lab(pixel: PixelBytes<u8>) -> LabPixel {
+  xyz_to_lab(rgb_to_xyz(pixel))
+}
+
+/// The CIE76 color difference: the straight line distance between two colors
+/// in CIELAB. A difference of about 2.3 is just noticeable.
+pub fn delta_e_cie76(
+  pixel_one: PixelBytes<u8>, 
+  pixel_two: PixelBytes<u8>
+) -> f32 {
+  let lab_one = rgb_to_lab(pixel_one);
+  let lab_two = rgb_to_lab(pixel_two);
+
+  lab_one.iter()
+    .zip(lab_two.iter())
+    .map(|(one, two)| (one - two).powi(2))
+    .sum::<f32>()
+    .sqrt()
+}
+
+/* #endregion */
@@ -5,6 +5,7 @@ use byteorder::{ByteOrder, ReadBytesExt};
 use crate::core::ppm::PpmImage;
 
 use super::io::IOResult;
+use super::error::CoreError;
 
 /* #region Constants */
 /// This is the byte order as defined in the CR2 spec that indicates little
@@ -33,6 +34,15 @@ const IMAGE_WIDTH:u16 = 48256;
 /// TIFF tag id for the offset of image data within a TIFF file
 const IMAGE_DATA_OFFSET:u16 = 273;
 
+/// TIFF tag id for the manufacturer of the camera
+pub const MAKE:u16 = 271;
+
+/// TIFF tag id for the model of the camera
+pub const MODEL:u16 = 272;
+
+/// TIFF tag id for the date and time the image was created
+pub const DATE_TIME:u16 = 306;
+
 /* #endregion */
 
 /* #region Data Structures */
@@ -74,6 +84,22 @@ impl CR2Image {
       images: Vec::new(), 
     }
   }
+
+  /// Gets the first entry with the given tag id, searching every image file
+  /// directory in the order they appear in the file
+  pub fn get_tag(&self, tag: u16) -> Option<&IFDEntry> {
+    self.images
+      .iter()
+      .flat_map(|ifd| ifd.entries.iter())
+      .find(|entry| entry.tag_id == tag)
+  }
+
+  /// Gets the value of the given tag as a string. ASCII values are stored with
+  /// their null terminator, which is stripped here.
+  pub fn get_tag_string(&self, tag: u16) -> Option<&str> {
+    self.get_tag(tag)
+      .map(|entry| entry.tag_string.trim_end_matches('\0'))
+  }
 }
 
 /* #endregion */
@@ -245,12 +271,19 @@ impl ImageData {
 
 /* #region Functions to Read CR2 Files */
 pub fn read_cr2(path: &str) -> IOResult {
+  // TODO: the raw image data is not decoded yet, so only the structure of the
+  // file is read, and a placeholder image is returned
+  parse_cr2(path)?;
+
+  Ok(PpmImage::new(10, 10))
+}
+
+/// Reads the header and all of the image file directories of a CR2 file
+pub fn parse_cr2(path: &str) -> Result<CR2Image, CoreError> {
 
   println!("--- Reading \"{}\" ---", path);
   use byteorder::{BigEndian, LittleEndian};
 
-  let temp = PpmImage::new(10, 10);
-
   if let Ok(mut file) = File::open(path) {
 
     /* #region Header */
@@ -346,9 +379,11 @@ pub fn read_cr2(path: &str) -> IOResult {
     /* #endregion */
 
     println!("Finished parsing the CR2 file.");
-  }
 
-  Ok(temp)
+    Ok(cr2_image)
+  } else {
+    Err(CoreError::Io(format!("Could not open file: \"{path}\"")))
+  }
 }
 
 fn read_all_ifd<T: ByteOrder>(
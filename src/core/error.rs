@@ -0,0 +1,65 @@
+use std::fmt;
+
+/**
+ * The errors returned by the core. Each variant carries the message shown to
+ * the user, so that the GUI and command line can print them as they are,
+ * while code that needs to can match on what kind of error it was.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoreError {
+  /// A file couldn't be found, opened, read, or written
+  Io(String),
+  /// A file was read, but its contents aren't a valid image
+  Decode(String),
+  /// An image is empty, or doesn't have the size that an operation needs
+  InvalidDimensions(String),
+  /// A file is in a format that can't be read or written
+  UnsupportedFormat(String),
+  /// An operation was given a parameter that it can't work with
+  InvalidParameter(String),
+  /// A command (from the command line or command box) couldn't be parsed
+  InvalidCommand(String),
+}
+
+impl CoreError {
+  /// The message describing the error, as shown to the user
+  pub fn message(&self) -> &str {
+    match self {
+      CoreError::Io(message) |
+      CoreError::Decode(message) |
+      CoreError::InvalidDimensions(message) |
+      CoreError::UnsupportedFormat(message) |
+      CoreError::InvalidParameter(message) |
+      CoreError::InvalidCommand(message) => message,
+    }
+  }
+
+  /// The same kind of error, with its message prefixed by where it happened
+  pub fn context(self, context: &str) -> CoreError {
+    let message = format!("{}: {}", context, self.message());
+    match self {
+      CoreError::Io(_) => CoreError::Io(message),
+      CoreError::Decode(_) => CoreError::Decode(message),
+      CoreError::InvalidDimensions(_) => CoreError::InvalidDimensions(message),
+      CoreError::UnsupportedFormat(_) => CoreError::UnsupportedFormat(message),
+      CoreError::InvalidParameter(_) => CoreError::InvalidParameter(message),
+      CoreError::InvalidCommand(_) => CoreError::InvalidCommand(message),
+    }
+  }
+}
+
+impl fmt::Display for CoreError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.message())
+  }
+}
+
+impl std::error::Error for CoreError {}
+
+/// Lets code that still reports errors as strings (the GUI) use ? on core
+/// functions
+impl From<CoreError> for String {
+  fn from(error: CoreError) -> Self {
+    error.to_string()
+  }
+}
@@ -1,7 +1,15 @@
-use crate::core::{operations::{perform_operation, OpType}, float_pixel_to_rgb};
+use crate::core::{
+  operations::{perform_operation, clamp_to_range, OpType}, float_pixel_to_rgb
+};
 use std::f32::consts::PI;
 use super::{ppm::{PpmImage, Padding}, operations::OperationResult};
-use crate::core::{EULER, R_CH, B_CH, G_CH, COLOR_CHANNELS, PIXEL_SIZE};
+use crate::core::{EULER, R_CH, B_CH, G_CH, COLOR_CHANNELS, PIXEL_SIZE, PixelBytes};
+use crate::core::{color, H_CH, S_CH, V_CH};
+use crate::core::threads;
+use crate::core::error::CoreError;
+use crate::core::stats::RunningStats;
+use rayon::prelude::*;
+use crate::to_1d;
 
 pub const SOBEL_H: [i32;9] = [
    1,  2,  1, 
@@ -27,6 +35,77 @@ pub const SOBEL_V_REV: [i32;9] = [
   -1,  0, 1
 ];
 
+/// The smallest kernel size that the gaussian filters accept
+pub const MIN_KERNEL_SIZE: i32 = 3;
+
+/// The smallest sigma that the gaussian filters accept. Below this the
+/// neighbours get less than 1% of the center weight, and the filter doesn't
+/// do anything.
+pub const MIN_GAUSSIAN_SIGMA: f32 = 0.33;
+
+/// The value that signed results (such as band-pass filters) are centered on,
+/// so that negative differences show up darker than gray instead of clipping
+pub const MID_GRAY: u8 = 128;
+
+/// The largest kernel size that makes sense for an image of the given size. A
+/// kernel wider than half of the shortest side is mostly padding, so the cap is
+/// the largest odd number below that (but never less than MIN_KERNEL_SIZE)
+pub fn max_kernel_size(width: u32, height: u32) -> i32 {
+  let half = (width.min(height) / 2) as i32;
+  let max = if half % 2 == 0 { half - 1 } else { half };
+  max.max(MIN_KERNEL_SIZE)
+}
+
+/// Rounds an even kernel size up to the next odd value, and keeps the result
+/// within MIN_KERNEL_SIZE..=max
+pub fn odd_kernel_size(kernel_size: i32, max: i32) -> i32 {
+  let odd = if kernel_size % 2 == 0 { kernel_size + 1 } else { kernel_size };
+  let max = if max % 2 == 0 { max - 1 } else { max };
+  odd.clamp(MIN_KERNEL_SIZE, max.max(MIN_KERNEL_SIZE))
+}
+
+/// Checks that a sigma and kernel size make for a usable gaussian
+pub fn validate_gaussian(
+  sigma: f32, kernel_size: i32
+) -> Result<(), CoreError> {
+  if sigma <= 0. {
+    return Err(CoreError::InvalidParameter(format!(
+      "Sigma value must be greater than 0, cannot be: {:.3}", sigma
+    )));
+  }
+
+  if kernel_size % 2 == 0 {
+    return Err(CoreError::InvalidParameter(format!(
+      "Cannot have a blur filter with an even kernel size of {}. Kernel size \
+      must be odd.", kernel_size
+    )));
+  }
+
+  if kernel_size < MIN_KERNEL_SIZE {
+    return Err(CoreError::InvalidParameter(
+      "Cannot have a kernel size that is less than three".to_string()
+    ));
+  }
+
+  if sigma < MIN_GAUSSIAN_SIGMA {
+    return Err(CoreError::InvalidParameter(format!(
+      "Sigma of {:.3} is too small to have any effect, it should be at least \
+      0.33", sigma
+    )));
+  }
+
+  // past this the kernel only covers a small slice of the bell curve, and the
+  // result is just a box blur
+  if sigma > kernel_size as f32 {
+    return Err(CoreError::InvalidParameter(format!(
+      "Sigma of {:.3} is too large for a kernel size of {}, use a sigma of at \
+      most {} or a bigger kernel", sigma, kernel_size, kernel_size
+    )));
+  }
+
+  Ok(())
+}
+
 pub fn gaussian_blur(
   image: &PpmImage, 
   sigma:f32, 
@@ -34,23 +113,190 @@ pub fn gaussian_blur(
   padding:Padding
 ) -> OperationResult {
 
-  /* #region Error Handling */
-  if sigma <= 0. {
-    return Err(format!("Sigma value must be greater than 0, cannot be: {:.3}", sigma))
+  validate_gaussian(sigma, kernel_size)?;
+
+  let blur_mask = get_gaussian_weight_matrix(kernel_size, sigma);
+
+  apply_mask(image, blur_mask, padding)
+}
+
+/// The smallest odd kernel size that covers three standard deviations to
+/// either side of the center, which holds nearly all of a gaussian's weight
+pub fn gaussian_kernel_size(sigma: f32) -> i32 {
+  let radius = (3. * sigma).ceil() as i32;
+  (2 * radius + 1).max(MIN_KERNEL_SIZE)
+}
+
+/**
+ * Difference of gaussians: blurs the image at two scales and subtracts the 
+ * coarser blur from the finer one. This is a band-pass filter, it keeps 
+ * structure that is larger than sigma1 but smaller than sigma2 (nebulosity,
+ * texture) and drops both fine noise and smooth gradients.
+ *
+ * The result is signed, so it is centered on MID_GRAY. The kernel sizes are
+ * picked to fit each sigma.
+ */
+pub fn difference_of_gaussians(
+  image: &PpmImage,
+  sigma1: f32,
+  sigma2: f32,
+  padding: Padding
+) -> OperationResult {
+  let fine = gaussian_blur(
+    image, sigma1, gaussian_kernel_size(sigma1), padding
+  )?;
+  let coarse = gaussian_blur(
+    image, sigma2, gaussian_kernel_size(sigma2), padding
+  )?;
+
+  signed_difference(&fine, &coarse)
+}
+
+/// Subtracts one image from another channel by channel, shifting the result
+/// up by MID_GRAY so that negative differences aren't clipped to black
+pub fn signed_difference(lhs: &PpmImage, rhs: &PpmImage) -> OperationResult {
+  if lhs.width() != rhs.width() || lhs.height() != rhs.height() {
+    return Err(CoreError::InvalidDimensions(format!(
+      "Cannot subtract a {}x{} image from a {}x{} image",
+      rhs.width(), rhs.height(), lhs.width(), lhs.height()
+    )));
   }
 
-  if kernel_size % 2 == 0 {
-    return Err(format!("Cannot have a blur filter with an even kernel size of {}. Kernel size must be odd.", kernel_size))
+  let mut new_image = PpmImage::new(lhs.width(), lhs.height());
+
+  let mut pixel_index:usize = 0;
+  for (lhs_pixel, rhs_pixel) in lhs.get_data().chunks_exact(PIXEL_SIZE)
+    .zip(rhs.get_data().chunks_exact(PIXEL_SIZE)) {
+    let difference = COLOR_CHANNELS.map(|ch| {
+      let delta = lhs_pixel[ch] as f32 - rhs_pixel[ch] as f32;
+      clamp_to_range(delta + MID_GRAY as f32, u8::MAX)
+    });
+
+    new_image.set_pixel(&mut pixel_index, &difference);
+  }
+
+  Ok(new_image)
+}
+
+/**
+ * High-pass filter: the image minus a gaussian blur of itself, which leaves
+ * only the detail finer than sigma. Like difference_of_gaussians, the result
+ * is signed and centered on MID_GRAY.
+ */
+pub fn high_pass(
+  image: &PpmImage, 
+  sigma: f32, 
+  padding: Padding
+) -> OperationResult {
+  let blurred = gaussian_blur(
+    image, sigma, gaussian_kernel_size(sigma), padding
+  )?;
+
+  signed_difference(image, &blurred)
+}
+
+/**
+ * Sharpens an image by blending its high-pass back on top of it with a soft
+ * light blend. Mid-gray in the high-pass leaves a pixel alone, while lighter
+ * or darker detail lightens or darkens it. Unlike unsharp_mask this never
+ * pushes a pixel past black or white, so it doesn't leave hard halos around
+ * stars.
+ *
+ * The strength (0 to 1) fades between the image and the fully blended result.
+ */
+pub fn high_pass_sharpen(
+  image: &PpmImage, 
+  sigma: f32, 
+  strength: f32, 
+  padding: Padding
+) -> OperationResult {
+  if !(0. ..=1.).contains(&strength) {
+    return Err(CoreError::InvalidParameter(format!(
+      "Strength must be between 0 and 1, cannot be: {:.3}", strength
+    )));
   }
 
-  if kernel_size < 3 {
-    return Err(format!("Cannot have a kernel size that is less than three"));
+  let detail = high_pass(image, sigma, padding)?;
+
+  let mut new_image = PpmImage::new(image.width(), image.height());
+
+  let mut pixel_index:usize = 0;
+  for (pixel, detail_pixel) in image.get_data().chunks_exact(PIXEL_SIZE)
+    .zip(detail.get_data().chunks_exact(PIXEL_SIZE)) {
+    let sharpened = COLOR_CHANNELS.map(|ch| {
+      let base = pixel[ch] as f32 / 255.;
+      let blend = soft_light(base, detail_pixel[ch] as f32 / 255.);
+      clamp_to_range(((base + (blend - base) * strength) * 255.).round(), u8::MAX)
+    });
+
+    new_image.set_pixel(&mut pixel_index, &sharpened);
   }
-  /* #endregion */
 
-  let blur_mask = get_gaussian_weight_matrix(kernel_size, sigma);
+  Ok(new_image)
+}
 
-  apply_mask(image, blur_mask, padding)
+/**
+ * Clarity: an unsharp mask with a large radius, applied to the value channel
+ * only. Rather than sharpening fine detail, this boosts the contrast between
+ * an area and its surroundings, and as the hue and saturation are left alone
+ * it doesn't shift or oversaturate colors.
+ *
+ * The radius is the sigma of the blur (usually tens of pixels), and the 
+ * strength is how much of the difference from the blur is added back in. A
+ * negative strength lowers the local contrast instead.
+ */
+pub fn clarity(image: &PpmImage, radius: f32, strength: f32) -> OperationResult {
+  if !strength.is_finite() {
+    return Err(CoreError::InvalidParameter(
+      format!("Strength must be a number, cannot be: {}", strength)
+    ));
+  }
+
+  // the value channel as a gray image, so that it can be blurred on its own
+  let mut values = PpmImage::new(image.width(), image.height());
+  values.keep_histogram_updated = false;
+  let mut pixel_index:usize = 0;
+  for pixel in image.get_data().chunks_exact(PIXEL_SIZE) {
+    let value = pixel[R_CH].max(pixel[G_CH]).max(pixel[B_CH]);
+    values.set_pixel(&mut pixel_index, &[value; PIXEL_SIZE]);
+  }
+
+  let blurred = gaussian_blur(
+    &values, radius, gaussian_kernel_size(radius), Padding::Repeat
+  )?;
+
+  let mut new_image = PpmImage::new(image.width(), image.height());
+
+  let mut pixel_index:usize = 0;
+  for (pixel, blurred_pixel) in image.get_data().chunks_exact(PIXEL_SIZE)
+    .zip(blurred.get_data().chunks_exact(PIXEL_SIZE)) {
+    let hsv_pixel = color::rgb_to_hsv([pixel[R_CH], pixel[G_CH], pixel[B_CH]]);
+    let value = hsv_pixel[V_CH];
+    let new_value = clamp_to_range(
+      (value + strength * (value - blurred_pixel[R_CH] as f32)).round(), 
+      u8::MAX
+    );
+
+    // pixels that keep their value are copied, rather than going through HSV 
+    // and back
+    if new_value == value as u8 {
+      new_image.set_pixel(&mut pixel_index, pixel);
+      continue;
+    }
+
+    let clarified = color::hsv_to_rgb(
+      hsv_pixel[H_CH], hsv_pixel[S_CH], new_value as f32 / u8::MAX as f32
+    );
+    new_image.set_pixel(&mut pixel_index, &clarified);
+  }
+
+  Ok(new_image)
+}
+
+/// The soft light blend of two values between 0 and 1. A blend value of one
+/// half leaves the base as it is.
+fn soft_light(base: f32, blend: f32) -> f32 {
+  (1. - 2. * blend) * base * base + 2. * blend * base
 }
 
 pub fn unsharp_mask(
@@ -61,6 +307,8 @@ pub fn unsharp_mask(
   padding:Padding
 ) -> OperationResult {
   
+    validate_gaussian(sigma, kernel_size)?;
+
     // Unsharp Mask: OriginalImage + Blurred(Negated(OriginalImage))
 
     let blur_filter = get_gaussian_weight_matrix(kernel_size, sigma);
@@ -76,6 +324,45 @@ pub fn unsharp_mask(
     apply_mask(image, sharpen_mask, padding)
 }
 
+/**
+ * Convolves an image with an arbitrary kernel of floats, for experimenting 
+ * with custom sharpen, edge, or blur matrices.
+ * 
+ * The kernel is given row by row, and is laid over each pixel as written 
+ * (it is not flipped first). It must be square, with an odd side length of 
+ * kernel_size.
+ */
+pub fn convolve(
+  image: &PpmImage, 
+  kernel: &[f32], 
+  kernel_size: usize, 
+  padding: Padding
+) -> OperationResult {
+  if 0 == kernel_size % 2 {
+    return Err(CoreError::InvalidParameter(format!(
+      "The kernel size must be odd, but is {}", kernel_size
+    )));
+  }
+
+  if kernel.len() != kernel_size * kernel_size {
+    return Err(CoreError::InvalidParameter(format!(
+      "A {}x{} kernel needs {} values, but {} were given", 
+      kernel_size, kernel_size, kernel_size * kernel_size, kernel.len()
+    )));
+  }
+
+  // get_matrix_at gives the neighborhood column by column, so the kernel is
+  // transposed to match
+  let mut mask = vec![0.; kernel.len()];
+  for row in 0..kernel_size {
+    for column in 0..kernel_size {
+      mask[column * kernel_size + row] = kernel[row * kernel_size + column];
+    }
+  }
+
+  apply_mask(image, mask, padding)
+}
+
 fn apply_mask(
   image:&PpmImage, 
   mask:Vec<f32>, 
@@ -87,28 +374,156 @@ fn apply_mask(
 
   let kernel_size = (mask.len() as f32).sqrt() as usize;
 
-  for y in 0..image.height() {
-    for x in 0..image.width() {
-      let matrix = image.get_matrix_at(
-        x, y, kernel_size as usize, padding
-      );
-      let mut new_pixel_value: [f32; PIXEL_SIZE] = [0.; PIXEL_SIZE];
-      for i in 0..matrix.len() {
-        for ch in COLOR_CHANNELS {
-          new_pixel_value[ch] += matrix[i][ch] as f32 * mask[i];
+  // the rows are filtered in parallel, and then copied into the new image 
+  // (which keeps track of its histogram, so it is written to on one thread)
+  let rows: Vec<Vec<PixelBytes<u8>>> = threads::install(|| {
+    (0..image.height()).into_par_iter().map(|y| {
+      (0..image.width()).map(|x| {
+        let matrix = image.get_matrix_at(
+          x, y, kernel_size as usize, padding
+        );
+        let mut new_pixel_value: [f32; PIXEL_SIZE] = [0.; PIXEL_SIZE];
+        for i in 0..matrix.len() {
+          for ch in COLOR_CHANNELS {
+            new_pixel_value[ch] += matrix[i][ch] as f32 * mask[i];
+          }
         }
+
+        float_pixel_to_rgb(new_pixel_value)
+      }).collect()
+    }).collect()
+  });
+
+  let mut pixel_index:usize = 0;
+  for row in rows {
+    for pixel in row {
+      new_image.set_pixel(&mut pixel_index, &pixel);
+    }
+  }
+
+  Ok(new_image)
+}
+
+/* #endregion */
+
+/* #region Deconvolution */
+
+/**
+ * Sharpens an image by Richardson-Lucy deconvolution, given the point spread
+ * function that blurred it (for instance, atmospheric seeing on a stacked
+ * astrophoto). A gaussian made with get_gaussian_weight_matrix is a sensible
+ * default for the psf. Each iteration refines the estimate of the original 
+ * image, so more iterations sharpen more (but also amplify noise).
+ */
+pub fn richardson_lucy(
+  image: &PpmImage,
+  psf: &Vec<f32>,
+  iterations: u32
+) -> OperationResult {
+
+  /* #region Error Handling */
+  let kernel_size = (psf.len() as f32).sqrt() as usize;
+
+  if kernel_size * kernel_size != psf.len() || kernel_size % 2 == 0 {
+    return Err(CoreError::InvalidParameter(format!(
+      "The point spread function must be a square matrix with an odd size, \
+      cannot have {} values", psf.len()
+    )));
+  }
+
+  if 0 == iterations {
+    return Err(CoreError::InvalidParameter(
+      "Deconvolution needs at least one iteration".to_string()
+    ));
+  }
+  /* #endregion */
+
+  // don't let the ratio between the observed and blurred values blow up
+  const EPSILON: f32 = 1e-6;
+
+  let width = image.width() as usize;
+  let height = image.height() as usize;
+
+  let observed: Vec<[f32; PIXEL_SIZE]> = image.get_data()
+    .chunks_exact(PIXEL_SIZE)
+    .map(|p| [p[R_CH] as f32, p[G_CH] as f32, p[B_CH] as f32])
+    .collect();
+
+  // the mirrored psf is the psf rotated by 180 degrees
+  let mut psf_mirror = psf.clone();
+  psf_mirror.reverse();
+
+  // the first estimate is the observed image itself
+  let mut estimate = observed.clone();
+
+  for _ in 0..iterations {
+    let blurred = convolve_float(&estimate, width, height, psf);
+
+    let mut ratio = vec![[0.; PIXEL_SIZE]; observed.len()];
+    for i in 0..observed.len() {
+      for ch in COLOR_CHANNELS {
+        ratio[i][ch] = observed[i][ch] / blurred[i][ch].max(EPSILON);
       }
+    }
 
-      new_image.set_pixel_by_coord(
-        x, y,
-        &float_pixel_to_rgb(new_pixel_value)
-      );
+    let correction = convolve_float(&ratio, width, height, &psf_mirror);
+
+    for i in 0..estimate.len() {
+      for ch in COLOR_CHANNELS {
+        estimate[i][ch] *= correction[i][ch];
+      }
     }
   }
 
+  let mut new_image = PpmImage::new(image.width(), image.height());
+
+  let mut pixel_index:usize = 0;
+  for pixel in estimate {
+    new_image.set_pixel(&mut pixel_index, &float_pixel_to_rgb(pixel));
+  }
+
   Ok(new_image)
 }
 
+/// Convolves floating point image data with the given (square) kernel. Edge
+/// pixels are repeated past the border of the image. The kernel is laid out
+/// the same way as the matrices returned by PpmImage::get_matrix_at.
+fn convolve_float(
+  data: &Vec<[f32; PIXEL_SIZE]>,
+  width: usize,
+  height: usize,
+  kernel: &Vec<f32>
+) -> Vec<[f32; PIXEL_SIZE]> {
+  let kernel_size = (kernel.len() as f32).sqrt() as i32;
+  let start_delta = -1 * (kernel_size - 1) / 2;
+
+  let mut result = vec![[0.; PIXEL_SIZE]; data.len()];
+
+  for y in 0..height as i32 {
+    for x in 0..width as i32 {
+      let mut new_pixel_value: [f32; PIXEL_SIZE] = [0.; PIXEL_SIZE];
+
+      for kx in 0..kernel_size {
+        for ky in 0..kernel_size {
+          let sx = (x + start_delta + kx).clamp(0, width as i32 - 1);
+          let sy = (y + start_delta + ky).clamp(0, height as i32 - 1);
+
+          let weight = kernel[(kx * kernel_size + ky) as usize];
+          let source = data[to_1d!(sx, sy, width)];
+
+          for ch in COLOR_CHANNELS {
+            new_pixel_value[ch] += source[ch] * weight;
+          }
+        }
+      }
+
+      result[to_1d!(x, y, width)] = new_pixel_value;
+    }
+  }
+
+  result
+}
+
 /* #endregion */
 
 pub fn apply_sobel(
@@ -130,19 +545,11 @@ pub fn apply_sobel(
         }
       }
 
-      for i in 0..PIXEL_SIZE {
-        if new_pixel_value[i] > u8::MAX as i32 {
-          new_pixel_value[i] = u8::MAX as i32;
-        } else if new_pixel_value[i] < 0 {
-          new_pixel_value[i] = u8::MIN as i32;
-        }
-      }
-
       result_image.set_pixel_by_coord(
         x, y,  &[
-        new_pixel_value[R_CH] as u8,
-        new_pixel_value[G_CH] as u8,
-        new_pixel_value[B_CH] as u8,
+        clamp_to_range(new_pixel_value[R_CH] as f32, u8::MAX),
+        clamp_to_range(new_pixel_value[G_CH] as f32, u8::MAX),
+        clamp_to_range(new_pixel_value[B_CH] as f32, u8::MAX),
       ]);
     }
   }
@@ -158,6 +565,174 @@ pub fn edge_detect(image: &PpmImage) -> OperationResult {
   perform_operation(&h_filtered, &v_filtered, OpType::Add)
 }
 
+/// The side length of the tiles that noise is measured in
+pub const NOISE_TILE_SIZE: u32 = 16;
+
+/// The share of the tiles (the flattest ones) that noise is measured from
+const FLATTEST_TILE_FRACTION: f32 = 0.1;
+
+/**
+ * Estimates the noise of an image (for instance the read noise of a camera,
+ * from a bias or flat frame) as a standard deviation in 8 bit units.
+ * 
+ * The image is split into tiles, and the tiles with the least edge response 
+ * are taken to be flat, so that whatever variation is left in them is noise
+ * rather than detail. The estimate is the median of the standard deviations 
+ * of those tiles (averaged over the color channels).
+ */
+pub fn estimate_noise(
+  image: &PpmImage, tile_size: u32
+) -> Result<f64, CoreError> {
+  if 0 == tile_size || image.width() < tile_size || image.height() < tile_size {
+    return Err(CoreError::InvalidDimensions(format!(
+      "The image needs to be at least {}x{} to measure its noise", 
+      tile_size, tile_size
+    )));
+  }
+
+  let edges = edge_detect(image)?;
+
+  // (mean edge response, standard deviation) of every whole tile
+  let mut tiles = Vec::<(f64, f64)>::new();
+
+  for tile_y in 0..(image.height() / tile_size) {
+    for tile_x in 0..(image.width() / tile_size) {
+      let mut edge_total: f64 = 0.;
+      let mut stats = [RunningStats::new(); PIXEL_SIZE];
+
+      for y in (tile_y * tile_size)..((tile_y + 1) * tile_size) {
+        for x in (tile_x * tile_size)..((tile_x + 1) * tile_size) {
+          let edge = edges.get_pixel_by_coord_ref(x, y);
+          let pixel = image.get_pixel_by_coord_ref(x, y);
+
+          for ch in COLOR_CHANNELS {
+            edge_total += edge[ch] as f64;
+            stats[ch].push(pixel[ch] as f64);
+          }
+        }
+      }
+
+      let count = (tile_size * tile_size) as f64;
+
+      let deviation = stats.iter()
+        .map(|channel| channel.stddev() / PIXEL_SIZE as f64)
+        .sum();
+
+      tiles.push((edge_total / (count * PIXEL_SIZE as f64), deviation));
+    }
+  }
+
+  // keep the flattest tiles, and take the median of their noise
+  tiles.sort_by(|a, b| a.0.total_cmp(&b.0));
+  let flat_count = ((tiles.len() as f32 * FLATTEST_TILE_FRACTION).ceil() as usize)
+    .max(1);
+
+  let mut deviations: Vec<f64> = tiles[..flat_count].iter()
+    .map(|tile| tile.1)
+    .collect();
+  deviations.sort_by(|a, b| a.total_cmp(b));
+
+  Ok(deviations[deviations.len() / 2])
+}
+
+/// The window size used for the SNR map in the GUI
+pub const SNR_MAP_WINDOW: u32 = 7;
+
+/**
+ * Renders the local signal to noise ratio of an image as a heatmap, going 
+ * from black (noise dominated) through red and yellow to white (signal 
+ * dominated).
+ * 
+ * The SNR of each pixel is the mean over the standard deviation of the 
+ * brightness in the window (window by window pixels) around it. The map is
+ * scaled so that the highest SNR in the image is white.
+ */
+pub fn snr_map(image: &PpmImage, window: u32) -> OperationResult {
+  if 0 == window % 2 {
+    return Err(CoreError::InvalidParameter(format!(
+      "The window size must be odd, but is {}", window
+    )));
+  }
+
+  let width = image.width() as usize;
+  let height = image.height() as usize;
+
+  // summed area tables of the brightness (mean of the channels) and its 
+  // square, so that the statistics of any window take constant time. They 
+  // have an extra row and column of zeros at the top and left.
+  let mut sums = vec![0_f64; (width + 1) * (height + 1)];
+  let mut squared_sums = vec![0_f64; (width + 1) * (height + 1)];
+
+  for y in 0..height {
+    for x in 0..width {
+      let pixel = image.get_pixel_by_coord_ref(x as u32, y as u32);
+      let brightness = pixel.iter().map(|ch| *ch as f64).sum::<f64>() 
+        / PIXEL_SIZE as f64;
+
+      let index = to_1d!(x + 1, y + 1, width + 1);
+      let above = to_1d!(x + 1, y, width + 1);
+      let left = to_1d!(x, y + 1, width + 1);
+      let corner = to_1d!(x, y, width + 1);
+
+      sums[index] = brightness + sums[above] + sums[left] - sums[corner];
+      squared_sums[index] = brightness * brightness + 
+        squared_sums[above] + squared_sums[left] - squared_sums[corner];
+    }
+  }
+
+  let half = (window / 2) as usize;
+  let area_sum = |table: &Vec<f64>, x0: usize, y0: usize, x1: usize, y1: usize| {
+    table[to_1d!(x1, y1, width + 1)] - table[to_1d!(x0, y1, width + 1)] -
+    table[to_1d!(x1, y0, width + 1)] + table[to_1d!(x0, y0, width + 1)]
+  };
+
+  let mut snr = vec![0_f64; width * height];
+  for y in 0..height {
+    for x in 0..width {
+      // the window is cut short at the edges of the image
+      let (x0, y0) = (x.saturating_sub(half), y.saturating_sub(half));
+      let (x1, y1) = ((x + half + 1).min(width), (y + half + 1).min(height));
+      let count = ((x1 - x0) * (y1 - y0)) as f64;
+
+      let mean = area_sum(&sums, x0, y0, x1, y1) / count;
+      let variance = (area_sum(&squared_sums, x0, y0, x1, y1) / count 
+        - mean * mean).max(0.);
+
+      // a window without any variation is all signal
+      snr[y * width + x] = if variance > 0. {
+        mean / variance.sqrt()
+      } else if mean > 0. {
+        f64::INFINITY
+      } else {
+        0.
+      };
+    }
+  }
+
+  let highest = snr.iter().cloned().filter(|value| value.is_finite())
+    .fold(0., f64::max);
+
+  let mut map = PpmImage::new(image.width(), image.height());
+
+  let mut pixel_index:usize = 0;
+  for value in snr {
+    let t = if highest > 0. { (value / highest).min(1.) } else { 1. };
+    map.set_pixel(&mut pixel_index, &heat_color(t as f32));
+  }
+
+  Ok(map)
+}
+
+/// Maps 0..1 onto a black, red, yellow, white color scale, which gets 
+/// brighter all the way along
+fn heat_color(t: f32) -> PixelBytes<u8> {
+  let channel = |offset: f32| {
+    clamp_to_range(((3. * t - offset) * u8::MAX as f32).round(), u8::MAX)
+  };
+
+  [channel(0.), channel(1.), channel(2.)]
+}
+
 /// Creates a matrix of float values that is kernel_size by kernel_size
 fn get_origin_matrix(kernel_size:i32) -> Vec<f32> {
 
@@ -169,7 +744,7 @@ fn get_origin_matrix(kernel_size:i32) -> Vec<f32> {
 }
 
 /// Creates a gaussian weight using the given kernel size and sigma
-fn get_gaussian_weight_matrix(kernel_size:i32, sigma:f32) -> Vec<f32> {
+pub fn get_gaussian_weight_matrix(kernel_size:i32, sigma:f32) -> Vec<f32> {
   let mut matrix = Vec::<f32>::with_capacity(
     (kernel_size * kernel_size) as usize
   );
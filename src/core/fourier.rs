@@ -1,9 +1,53 @@
 use std::{thread::current, f32::consts::PI, result};
 
 use super::{ppm::PpmImage, color, V_CH, PIXEL_SIZE};
+use super::operations::clamp_to_range;
 use rustfft::{*, num_complex::{Complex32, Complex}, algorithm::Dft};
 use fft2d::*;
 
+/// How the magnitudes of the coefficients are mapped onto 0 - 255 when a
+/// spectrum is turned back into an image
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum MagnitudeScale {
+  /// 0 - 1 is mapped straight onto 0 - 255
+  Linear,
+  /// 0 - max_magnitude is mapped onto 0 - 255 logarithmically, so that the
+  /// weaker coefficients aren't lost next to the strongest ones
+  Log { max_magnitude: f32 },
+}
+
+impl MagnitudeScale {
+  /// A log scale that fits the largest (finite) of the magnitudes
+  pub fn log_fitted(magnitudes: &[f32]) -> Self {
+    let max_magnitude = magnitudes.iter()
+      .map(|magnitude| magnitude.abs())
+      .filter(|magnitude| magnitude.is_finite())
+      .fold(0., f32::max);
+
+    MagnitudeScale::Log { max_magnitude: max_magnitude }
+  }
+}
+
+/// Converts the magnitude of a coefficient to a pixel value. Magnitudes past
+/// the top of the scale are clamped to 255 rather than wrapping, and NaN 
+/// (from a degenerate transform) is shown as 0.
+pub fn magnitude_to_u8(magnitude: f32, scale: MagnitudeScale) -> u8 {
+  if magnitude.is_nan() {
+    return 0;
+  }
+
+  let magnitude = magnitude.abs();
+  let normalized = match scale {
+    MagnitudeScale::Linear => magnitude,
+    MagnitudeScale::Log { max_magnitude } if max_magnitude > 0. => {
+      magnitude.ln_1p() / max_magnitude.ln_1p()
+    },
+    MagnitudeScale::Log { .. } => 0.,
+  };
+
+  (normalized * u8::MAX as f32).round().clamp(0., u8::MAX as f32) as u8
+}
+
 pub fn make_complex(image: PpmImage) -> Vec<Complex32> {
   let size = (image.width() * image.height()) as usize;
   let mut complex_image_data: Vec<Complex32> = Vec::with_capacity(size);
@@ -19,67 +63,202 @@ pub fn make_complex(image: PpmImage) -> Vec<Complex32> {
 
   complex_image_data
 }
-pub fn fast_fourier(input: PpmImage) -> PpmImage {
-  let mut resultant_image = PpmImage::new (
-    input.width(), input.height()
-  );
 
-  let mut complex = make_complex(input.clone());
+/// Pads the samples with zeros up to the next power of two, which is the only
+/// length that fast_fourier_1d can split in half all the way down
+pub fn pad_to_power_of_two(samples: &mut Vec<Complex32>) {
+  let padded_len = samples.len().max(1).next_power_of_two();
+  samples.resize(padded_len, Complex32 { re: 0.0, im: 0.0 });
+}
 
-  for x in 0..input.width() {
-    fast_fourier_1d(&mut complex)
+/**
+ * The magnitudes of the fourier transform of each column of the image. The
+ * columns are padded with zeros up to a power of two before they are
+ * transformed, and the coefficients that fit in the image are kept.
+ */
+pub fn fast_fourier(input: PpmImage, scale: MagnitudeScale) -> PpmImage {
+  let (width, height) = (input.width(), input.height());
+  let mut resultant_image = PpmImage::new(width, height);
+  if 0 == width || 0 == height {
+    return resultant_image;
   }
 
-  for i in 0..complex.len() {
-    let y = i / input.width() as usize;
-    let x = i - y * input.height() as usize;
+  // make_complex lays the image out one column at a time
+  let complex = make_complex(input);
+  for (x, column) in complex.chunks(height as usize).enumerate() {
+    let mut column = column.to_vec();
+    pad_to_power_of_two(&mut column);
+    fast_fourier_1d(&mut column);
 
-    resultant_image.set_pixel_by_coord(
-      x as u32, y as u32, &[(complex[i].re * u8::MAX as f32) as u8; PIXEL_SIZE]
-    );
+    for y in 0..height {
+      let magnitude = column[y as usize].norm();
+      resultant_image.set_pixel_by_coord(
+        x as u32, y, &[magnitude_to_u8(magnitude, scale); PIXEL_SIZE]
+      );
+    }
   }
 
   resultant_image
 }
 
+/**
+ * Replaces the samples with their discrete fourier transform, by splitting
+ * them into the even and odd samples and transforming each half in turn
+ * (radix 2 Cooley-Tukey). The number of samples has to be a power of two, see
+ * pad_to_power_of_two.
+ */
 pub fn fast_fourier_1d(input: &mut Vec<Complex32>) {
   let size = input.len();
-  let mut angle: f32 = 0.0;
-
-  let mut even = Vec::<Complex32>::new();
-  let mut odd = Vec::<Complex32>::new();
+  if size <= 1 {
+    return;
+  }
+  assert!(
+    size.is_power_of_two(),
+    "fast_fourier_1d needs a power of two samples, not {}", size
+  );
 
+  let mut even = Vec::<Complex32>::with_capacity(size / 2);
+  let mut odd = Vec::<Complex32>::with_capacity(size / 2);
   for pixel_index in (0..size).step_by(2) {
-    let mut temp_even = Vec::<Complex32>::new();
-    let mut temp_odd = Vec::<Complex32>::new();
-
-    temp_even.push(input[pixel_index]);
-    temp_odd.push(input[pixel_index]);
-
-    even.append(&mut temp_even);
-    odd.append(&mut temp_odd);
+    even.push(input[pixel_index]);
+    odd.push(input[pixel_index + 1]);
   }
 
   fast_fourier_1d(&mut even);
   fast_fourier_1d(&mut odd);
 
   for pixel_index in 0..(size / 2) {
-    angle = 2.0 * PI * pixel_index as f32 / size as f32;
+    let angle = -2.0 * PI * pixel_index as f32 / size as f32;
+    let w = Complex { re: angle.cos(), im: angle.sin() } * odd[pixel_index];
+
+    input[pixel_index] = even[pixel_index] + w;
+    input[(size / 2) + pixel_index] = even[pixel_index] - w;
+  }
+}
 
-    let real = angle.cos();
-    let imaginary = angle.sin();
+/* #region 2D Transform */
 
-    let mut w = Complex {re: real, im: imaginary};
+/**
+ * The 2D fourier transform of the intensity (the value channel) of the image.
+ * The coefficients are laid out a row at a time, like the pixels of the
+ * image, with the DC component first.
+ */
+pub fn fft_2d_forward(image: &PpmImage) -> Vec<Complex32> {
+  let (width, height) = (image.width(), image.height());
 
-    w = w * odd[pixel_index];
+  let mut data = Vec::<Complex32>::with_capacity((width * height) as usize);
+  for y in 0..height {
+    for x in 0..width {
+      let pixel = image.get_pixel_by_coord(x, y).unwrap();
+      let intensity = color::rgb_to_hsv(pixel)[V_CH];
+      data.push(Complex32 { re: intensity, im: 0.0 });
+    }
+  }
 
-    input[pixel_index] = even[pixel_index] + w;
-    input[(size / 2) + pixel_index] = even[pixel_index] - w;
+  transform_2d(&mut data, width as usize, height as usize, 
+    FftDirection::Forward);
+
+  data
+}
+
+/**
+ * Turns the coefficients from fft_2d_forward back into a (grayscale) image,
+ * normalizing them so that a forward and an inverse transform give back the
+ * intensity of the original. The real part of each pixel is kept, clamped to
+ * 0 - 255.
+ */
+pub fn fft_2d_inverse(data: &[Complex32], width: u32, height: u32) -> PpmImage {
+  assert_eq!(
+    data.len(), (width * height) as usize,
+    "There have to be width x height ({}x{}) coefficients", width, height
+  );
+
+  let mut data = data.to_vec();
+  transform_2d(&mut data, width as usize, height as usize,
+    FftDirection::Inverse);
+
+  // rustfft leaves the inverse scaled up by the number of samples
+  let normalization = (width * height).max(1) as f32;
+
+  let mut image = PpmImage::new(width, height);
+  for y in 0..height {
+    for x in 0..width {
+      let sample = data[(y * width + x) as usize].re / normalization;
+      let intensity: u8 = clamp_to_range(sample.round(), u8::MAX);
+      image.set_pixel_by_coord(x, y, &[intensity; PIXEL_SIZE]);
+    }
   }
 
+  image
 }
 
-pub fn dft_rows(image: PpmImage) -> PpmImage {
+/**
+ * The magnitude spectrum of the image, for spotting periodic noise. The 
+ * magnitudes of the 2D fourier transform are put on a log scale, and shifted 
+ * so that the DC component is in the middle of the image (fftshift). Periodic
+ * patterns show up as a pair of bright spots mirrored around the middle.
+ */
+pub fn magnitude_spectrum(image: &PpmImage) -> PpmImage {
+  let (width, height) = (image.width(), image.height());
+
+  let magnitudes: Vec<f32> = fft_2d_forward(image).iter()
+    .map(|coefficient| coefficient.norm())
+    .collect();
+  let scale = MagnitudeScale::log_fitted(&magnitudes);
+
+  let mut spectrum = PpmImage::new(width, height);
+  for y in 0..height {
+    for x in 0..width {
+      // the coefficient for (0, 0) lands at (width / 2, height / 2)
+      let from_x = (x + (width + 1) / 2) % width;
+      let from_y = (y + (height + 1) / 2) % height;
+      let magnitude = magnitudes[(from_y * width + from_x) as usize];
+
+      spectrum.set_pixel_by_coord(
+        x, y, &[magnitude_to_u8(magnitude, scale); PIXEL_SIZE]
+      );
+    }
+  }
+
+  spectrum
+}
+
+/// Transforms the rows of the data in place, and then its columns
+fn transform_2d(
+  data: &mut [Complex32],
+  width: usize,
+  height: usize,
+  direction: FftDirection
+) {
+  if data.is_empty() {
+    return;
+  }
+
+  let mut planner = FftPlanner::<f32>::new();
+
+  // the rows are already next to each other, so they go in one call
+  planner.plan_fft(width, direction).process(data);
+
+  // the columns are transposed into rows to do the same for them
+  let mut columns = vec![Complex32 { re: 0.0, im: 0.0 }; data.len()];
+  for y in 0..height {
+    for x in 0..width {
+      columns[x * height + y] = data[y * width + x];
+    }
+  }
+
+  planner.plan_fft(height, direction).process(&mut columns);
+
+  for y in 0..height {
+    for x in 0..width {
+      data[y * width + x] = columns[x * height + y];
+    }
+  }
+}
+
+/* #endregion */
+
+pub fn dft_rows(image: PpmImage, scale: MagnitudeScale) -> PpmImage {
   let pixel_count = (image.height() * image.width()) as usize;
 
   let mut rows: Vec<Vec<Complex32>> = Vec::with_capacity(image.width() as usize);
@@ -107,7 +286,7 @@ pub fn dft_rows(image: PpmImage) -> PpmImage {
   for y in 0..image.height() {
     for x in 0..image.width() {
       let intensity = &rows[y as usize][x as usize].im;
-      let new_pixel = [(u8::MAX as f32 * intensity) as u8; PIXEL_SIZE];
+      let new_pixel = [magnitude_to_u8(*intensity, scale); PIXEL_SIZE];
       new_image.set_pixel_by_coord(x, y, &new_pixel);
     }
   }
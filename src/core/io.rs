@@ -1,12 +1,17 @@
 use crate::core::ppm::{PpmImage, PpmType, PpmHeader};
 use std::fs::File;
-use std::io::{BufReader, Read, BufWriter, Write};
+use std::io::{BufReader, Read, BufWriter, Write, Seek};
+use std::sync::Arc;
 use std::str::FromStr;
-use std::default::Default;
-use image::DynamicImage;
+use image::{
+  ColorType, DynamicImage, EncodableLayout, ImageBuffer, ImageOutputFormat, Rgb
+};
+use image::codecs::tiff::TiffEncoder;
 use image::io::Reader as ImageReader;
+use strum_macros::{EnumIter, Display};
 
 use super::PIXEL_SIZE;
+use super::error::CoreError;
 use super::cr2::read_cr2;
 
 /* #region Types and Constants */
@@ -15,7 +20,7 @@ use super::cr2::read_cr2;
 /// during the course of the function an error is encountered, and a PpmImage
 /// cannot be returned, a String is returned instead that contains a message
 /// describing the nature of the problem
-pub type IOResult = Result<PpmImage, String>;
+pub type IOResult = Result<PpmImage, CoreError>;
 
 /// Carriage Return character
 const CR:[u8;1] = [13];
@@ -42,6 +47,146 @@ const FF:[u8;1] = [12];
 /// HTAB, FF
 const WHITESPACES: [[u8; 1]; 6] = [CR, LF, SPACE, VTAB, HTAB, FF];
 
+/// PPM files larger than this (in bytes) are memory mapped when opened, 
+/// instead of being read into memory
+pub const MAPPED_PPM_THRESHOLD: u64 = 256 * 1024 * 1024;
+
+/// The file extensions (in lower case) of the images that can be opened
+pub const IMAGE_EXTENSIONS: [&str; 10] = [
+  "ppm", "pbm", "pgm", "cr2", "png", "jpg", "jpeg", "tif", "tiff", "bmp"
+];
+
+/// The eight bytes that every PNG file starts with
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+/// The keyword of the PNG text chunks that comments are written into
+const PNG_COMMENT_KEYWORD: &str = "Comment";
+
+/// The quality (out of 100) that JPEGs are saved with. High enough that the
+/// loss is hard to see, without the file size of the top few settings.
+const JPEG_QUALITY: u8 = 90;
+
+/// PNG stores gamma multiplied by this, so 1.0 is written as 100000
+const PNG_GAMMA_SCALE: f32 = 100_000.;
+
+/// The color space that exported images are tagged with, so that color 
+/// managed editors know how to read their values
+#[derive(PartialEq, EnumIter, Display, Clone, Copy, Debug)]
+pub enum ColorSpace {
+  /// The values are written untagged, and left for the reader to guess
+  #[strum(serialize = "Untagged")]
+  Untagged,
+  /// The values are sRGB, which is what the viewer shows them as
+  #[strum(serialize = "sRGB")]
+  Srgb,
+  /// The values are linear light (a gamma of 1.0)
+  #[strum(serialize = "Linear")]
+  Linear,
+}
+
+/// The maxvalue written into the header of a PPM file. The pixels themselves
+/// are always written as they are; this only changes what a viewer takes as
+/// full intensity.
+#[derive(PartialEq, EnumIter, Display, Clone, Copy, Debug)]
+pub enum MaxValue {
+  /// 255, so that the values mean what they do in the viewer
+  #[strum(serialize = "Full (255)")]
+  Full,
+  /// The brightest value used in the image, which makes viewers stretch
+  /// dim images out to the full range
+  #[strum(serialize = "Brightest Used")]
+  Computed,
+}
+
+impl MaxValue {
+  /// The maxvalue to write for the given image. The computed value is never
+  /// less than the brightest sample in the data, so the file is always valid.
+  pub fn for_image(&self, image: &PpmImage) -> u8 {
+    match self {
+      MaxValue::Full => u8::MAX,
+      MaxValue::Computed => {
+        let brightest = image.get_data().iter().copied().max().unwrap_or(0);
+        image.max_value().max(brightest)
+      },
+    }
+  }
+}
+
+/// The formats that images can be written as. PPM and PNG files are written
+/// by this module, everything else is encoded through the image crate.
+#[derive(PartialEq, EnumIter, Display, Clone, Copy, Debug)]
+pub enum SaveFormat {
+  #[strum(serialize = "PPM")]
+  Ppm,
+  #[strum(serialize = "PNG")]
+  Png,
+  #[strum(serialize = "JPEG")]
+  Jpeg,
+  #[strum(serialize = "BMP")]
+  Bmp,
+  #[strum(serialize = "TIFF")]
+  Tiff,
+}
+
+impl SaveFormat {
+  /// The name shown in the save dialog's file type filter
+  pub const fn description(&self) -> &str {
+    match self {
+      SaveFormat::Ppm => "Portable Pixel Map",
+      SaveFormat::Png => "Portable Network Graphics",
+      SaveFormat::Jpeg => "JPEG",
+      SaveFormat::Bmp => "Bitmap",
+      SaveFormat::Tiff => "Tagged Image File Format",
+    }
+  }
+
+  /// The file extensions of the format, the first being the default
+  pub const fn extensions(&self) -> &[&str] {
+    match self {
+      SaveFormat::Ppm => &["ppm", "PPM"],
+      SaveFormat::Png => &["png", "PNG"],
+      SaveFormat::Jpeg => &["jpg", "jpeg", "JPG", "JPEG"],
+      SaveFormat::Bmp => &["bmp", "BMP"],
+      SaveFormat::Tiff => &["tif", "tiff", "TIF", "TIFF"],
+    }
+  }
+
+  /// The format that the extension of the path belongs to, if any
+  pub fn from_path(filepath: &str) -> Option<SaveFormat> {
+    use strum::IntoEnumIterator;
+
+    let extension = std::path::Path::new(filepath).extension()
+      .and_then(|ext| ext.to_str())?
+      .to_ascii_lowercase();
+
+    SaveFormat::iter()
+      .find(|format| format.extensions().contains(&extension.as_str()))
+  }
+}
+
+/// The options of the formats that have them, for write_image_as. A format 
+/// that doesn't have an option just ignores it.
+#[derive(PartialEq, Clone, Debug)]
+pub struct WriteOptions {
+  /// What PNGs are tagged with
+  pub color_space: ColorSpace,
+  /// The maxvalue written into PPMs
+  pub max_value: MaxValue,
+  /// Comments kept in the header of a PPM, or the text chunks of a PNG
+  pub comments: Vec<String>,
+}
+
+impl WriteOptions {
+  /// The options that write_image uses
+  pub fn new() -> Self {
+    WriteOptions {
+      color_space: ColorSpace::Untagged,
+      max_value: MaxValue::Full,
+      comments: Vec::<String>::new(),
+    }
+  }
+}
+
 
 
 /* #endregion */
@@ -52,78 +197,169 @@ pub fn open_image(path: &str) -> IOResult {
   let p = std::path::Path::new(path);
   
   if !p.exists() {
-    return Err(format!("Could not find file: \"{path}\""));
+    return Err(CoreError::Io(format!("Could not find file: \"{path}\"")));
   }
 
   if let Some(ext) = p.extension() {
-    match ext.to_str().unwrap().to_lowercase().as_str() {
-      "ppm" => { return read_ppm(path); },
+    match ext.to_string_lossy().to_lowercase().as_str() {
+      "pbm" | "pgm" => { return read_ppm(path); },
+      "ppm" => {
+        let size = p.metadata().map(|m| m.len()).unwrap_or(0);
+        if size > MAPPED_PPM_THRESHOLD {
+          return read_ppm_mapped(path);
+        }
+        return read_ppm(path);
+      },
       "cr2" => { return read_cr2(path); },
       _ => { return read_other(path); }
     }
   } else {
-    return Err(format!("File \"{path}\" has no file extension, cannot read."));
+    return Err(CoreError::UnsupportedFormat(
+      format!("File \"{path}\" has no file extension, cannot read.")
+    ));
   }
 }
 
+/// Whether a file has the extension of an image that can be opened
+pub fn is_image_file(path: &std::path::Path) -> bool {
+  let extension = path.extension()
+    .and_then(|ext| ext.to_str())
+    .map(|ext| ext.to_lowercase())
+    .unwrap_or_default();
+
+  IMAGE_EXTENSIONS.contains(&extension.as_str())
+}
+
+/// Reads an image with the image crate, keeping its bit depth. None if the
+/// file can't be opened or decoded.
 pub fn read_raw(path: &str) -> Option<DynamicImage> {
-  if let Ok(img) = ImageReader::open(path).unwrap().decode() {
-    return Some(img)
-  } else {
-    return None
-  }
+  ImageReader::open(path).ok()?.decode().ok()
 }
 
 fn read_other(path: &str) -> IOResult {
-  if let Ok(img) = ImageReader::open(path).unwrap().decode() {
-    let rgb8 = img.to_rgb8();
-    let mut img_ppm = PpmImage::new(img.width(), img.height());
+  let reader = match ImageReader::open(path) {
+    Ok(reader) => reader,
+    Err(why) => return Err(CoreError::Io(
+      format!("Could not open file: \"{path}\": {why}")
+    )),
+  };
 
-    let mut pixel_index:usize = 0;
-    for t in rgb8.chunks_exact(PIXEL_SIZE) {
-      img_ppm.set_pixel(&mut pixel_index, &t);
-    }
+  match reader.decode() {
+    Ok(img) => Ok(dynamic_to_ppm(&img)),
+    Err(why) => Err(CoreError::Decode(
+      format!("Could not decode file: \"{path}\": {why}")
+    )),
+  }
+}
 
-    return Ok(img_ppm);
-  } else {
-    return Err(format!("Could not open file: \"{path}\""));
+/// Converts an image decoded by the image crate into an 8 bit PpmImage
+pub fn dynamic_to_ppm(img: &DynamicImage) -> PpmImage {
+  let rgb8 = img.to_rgb8();
+  let mut img_ppm = PpmImage::new(img.width(), img.height());
+
+  let mut pixel_index:usize = 0;
+  for t in rgb8.chunks_exact(PIXEL_SIZE) {
+    img_ppm.set_pixel(&mut pixel_index, &t);
   }
+
+  img_ppm
 }
 
 fn read_ppm(path: &str) -> IOResult {
 
   if let Ok(mut file) = File::open(path) {
-    let header = read_ppm_header(&mut file);
+    let header = read_ppm_header(&mut file)?;
+    let max_value = header.max_value;
     let mut ppm = PpmImage::new(header.width, header.height);
   
     ppm.set_header(header);
     
     match ppm.ppm_type() {
       PpmType::P1 | PpmType::P2 | PpmType::P3 => { // ASCII formatted
-        read_ppm_ascii_file(&mut ppm, &mut file);
+        read_ppm_ascii_file(&mut ppm, &mut file, max_value)?;
+      },
+      PpmType::P5 | PpmType::P6 if max_value > u8::MAX as u16 => {
+        read_ppm_wide_image_data(&mut ppm, &mut file, max_value)?;
       },
       PpmType::P4 | PpmType::P5 | PpmType::P6 => { // Binary formatted
-        read_ppm_binary_image_data(&mut ppm, &mut file);
+        read_ppm_binary_image_data(&mut ppm, &mut file)?;
       }
       _ => {
-        return Err(format!("PPM file structure in file: \"{path}\" is corrupted"))
+        return Err(CoreError::Decode(
+          format!("PPM file structure in file: \"{path}\" is corrupted")
+        ))
       }
     }
     
     return Ok(ppm)
   } else {
-    return Err(format!("Could not open file: \"{path}\""))
+    return Err(CoreError::Io(format!("Could not open file: \"{path}\"")))
   }
   
 }
 
-fn read_ppm_header(file: &mut File) -> PpmHeader {
+/**
+ * Reads a PPM file, memory mapping the pixel data instead of copying it into
+ * memory. This keeps the peak memory down when opening very large images, as
+ * the pixels are only copied once the image is modified.
+ * 
+ * Only binary (P6) files store their pixels in the same layout as PpmImage,
+ * so every other type of PPM file is read normally.
+ */
+pub fn read_ppm_mapped(path: &str) -> IOResult {
+  use memmap2::Mmap;
+
+  let mut file = match File::open(path) {
+    Ok(file) => file,
+    Err(_) => return Err(CoreError::Io(
+      format!("Could not open file: \"{path}\"")
+    )),
+  };
+
+  let header = read_ppm_header(&mut file)?;
+
+  // 16 bit samples have to be scaled down, so they can't be mapped either
+  if PpmType::P6 != header.ppm_type || header.max_value > u8::MAX as u16 {
+    return read_ppm(path);
+  }
+
+  // the header reader leaves the file positioned at the start of the pixels
+  let offset = match file.stream_position() {
+    Ok(position) => position as usize,
+    Err(why) => return Err(CoreError::Io(
+      format!("Could not read file: \"{path}\": {why}")
+    )),
+  };
+
+  // safety: the mapping is read only, and is only invalidated if the file is 
+  // modified by someone else while the image is open
+  let map = match unsafe { Mmap::map(&file) } {
+    Ok(map) => map,
+    Err(why) => return Err(CoreError::Io(
+      format!("Could not map file: \"{path}\": {why}")
+    )),
+  };
+
+  PpmImage::from_mapped(header, Arc::new(map), offset)
+}
+
+/**
+ * Reads the header of a PPM file, leaving the file at the start of the pixel
+ * data. Anything that isn't a valid header is an error, and so is a header
+ * for an image that is larger than the rest of the file could hold, so that
+ * a corrupt file can't make the reader allocate a huge image.
+ */
+fn read_ppm_header(file: &mut File) -> Result<PpmHeader, CoreError> {
     let mut magic_number = [0; 2];
     
     /*#region Get the type of PPM file */
 
     // Get the type of PPM file we are reading
-    file.read_exact(&mut magic_number).unwrap();
+    if file.read_exact(&mut magic_number).is_err() {
+      return Err(CoreError::Decode(
+        "The file is too short to be a PPM file".to_string()
+      ));
+    }
     let ppm_type = match magic_number {
       [80, 49] => { PpmType::P1 },
       [80, 50] => { PpmType::P2 },
@@ -135,27 +371,83 @@ fn read_ppm_header(file: &mut File) -> PpmHeader {
     };
     let ppm_type = ppm_type;
 
+    if PpmType::P0 == ppm_type {
+      return Err(CoreError::Decode(format!(
+        "{:?} is not the magic number of a PPM file", 
+        String::from_utf8_lossy(&magic_number)
+      )));
+    }
+
     /* #endregion */
 
-    let width = read_number_ascii::<u32>(file);
-    let height = read_number_ascii::<u32>(file);
-    let max_value = read_number_ascii::<u16>(file);
-    
-    if max_value > 255 {
-      panic!(
-        "Cannot support PPM files with maxvalue greater than 255"
-      );
+    let width = read_number_ascii::<u32>(file, "width")?;
+    let height = read_number_ascii::<u32>(file, "height")?;
+
+    // bitmaps don't have a max value, their samples are only ever 0 or 1
+    let max_value = match ppm_type {
+      PpmType::P1 | PpmType::P4 => 1,
+      _ => read_number_ascii::<u16>(file, "max value")?,
+    };
+
+    if 0 == width || 0 == height {
+      return Err(CoreError::InvalidDimensions(
+        format!("A {}x{} image has no pixels", width, height)
+      ));
     }
 
-    PpmHeader {
+    if 0 == max_value {
+      return Err(CoreError::Decode(
+        "The max value of the image has to be at least 1".to_string()
+      ));
+    }
+    
+    let header = PpmHeader {
       ppm_type: ppm_type,
       width: width,
       height: height,
       max_value: max_value,
+    };
+
+    let remaining = match (file.metadata(), file.stream_position()) {
+      (Ok(metadata), Ok(position)) => metadata.len().saturating_sub(position),
+      _ => return Err(CoreError::Io(
+        "Could not find how much pixel data is in the file".to_string()
+      )),
+    };
+
+    match min_data_len(&header) {
+      Some(len) if len <= remaining => Ok(header),
+      _ => Err(CoreError::InvalidDimensions(format!(
+        "The header is for a {}x{} image, which is more than the {} bytes \
+         left in the file could hold", width, height, remaining
+      ))),
     }
 }
 
-fn read_ppm_binary_image_data(image: &mut PpmImage, file: &mut File) {
+/// The fewest bytes that the pixel data for the header could take up, or 
+/// None if the image is too large to hold in memory at all
+fn min_data_len(header: &PpmHeader) -> Option<u64> {
+  let pixel_count = header.width.checked_mul(header.height)?;
+  pixel_count.checked_mul(PIXEL_SIZE as u32)?;
+
+  let bytes_per_sample: u64 = match header.max_value > u8::MAX as u16 {
+    true => 2,
+    false => 1,
+  };
+
+  // every ASCII sample is at least one digit
+  match header.ppm_type {
+    PpmType::P4 => Some(header.height as u64 * ((header.width as u64 + 7) / 8)),
+    PpmType::P1 | PpmType::P2 => Some(pixel_count as u64),
+    PpmType::P3 => Some(pixel_count as u64 * PIXEL_SIZE as u64),
+    PpmType::P5 => Some(pixel_count as u64 * bytes_per_sample),
+    _ => Some(pixel_count as u64 * PIXEL_SIZE as u64 * bytes_per_sample),
+  }
+}
+
+fn read_ppm_binary_image_data(
+  image: &mut PpmImage, file: &mut File
+) -> Result<(), CoreError> {
 
     match image.ppm_type() {
       PpmType::P6 => {
@@ -174,16 +466,17 @@ fn read_ppm_binary_image_data(image: &mut PpmImage, file: &mut File) {
         }
         
         if overflow_count > 0 {
-          panic!("Overflowed image buffer when reading from file 
-          (means that there was more data in the file than there 
-            should have been");
+          return Err(CoreError::Decode(
+            "There is more pixel data in the file than the header says there \
+             should be".to_string()
+          ));
         }
       },
       PpmType::P5 => {
         let mut byte_for = [0; 1];
         let mut pixel_index:usize = 0;
         while let Ok(n) = file.read(&mut byte_for) {
-          if 0 == n { break; }
+          if 0 == n || pixel_index >= image.get_data().len() { break; }
           // TODO: Since we only implement 8 bit images - this code will fail
           let gs_data = u32::from_be_bytes([0,0,0,byte_for[0]]);
           
@@ -198,67 +491,177 @@ fn read_ppm_binary_image_data(image: &mut PpmImage, file: &mut File) {
         }
       },
       PpmType::P4 => {
-        let mut byte_buff:[u8; 1] = [0];
+        // each row is packed into whole bytes, most significant bit first, 
+        // so the bits past the width at the end of a row are only padding
+        let width = image.width() as usize;
+        let mut row_bytes = vec![0; (width + 7) / 8];
         let mut pixel_index:usize = 0;
-        while let Ok(n) = file.read(&mut byte_buff) {
-          let byte = byte_buff[0];
-          if 0 == n { break; }
-          for i in 8..0 {
-            let pixel = if 1 == byte & (1 << i) {
-              [255;PIXEL_SIZE]
-            } else {
+
+        let mut reader = BufReader::new(file);
+        for _ in 0..image.height() {
+          if reader.read_exact(&mut row_bytes).is_err() {
+            break;
+          }
+
+          for x in 0..width {
+            let bit = row_bytes[x / 8] & (1 << (7 - x % 8));
+            // in a bitmap 1 is black and 0 is white
+            let pixel = if 0 != bit {
               [0;PIXEL_SIZE]
+            } else {
+              [255;PIXEL_SIZE]
             };
 
             image.set_pixel(&mut pixel_index, &pixel);
           }
         }
       },
-      _ => { panic!("Improperly formatted PPM file"); }
+      _ => {
+        return Err(CoreError::Decode(
+          "Improperly formatted PPM file".to_string()
+        ));
+      }
     }
+
+    Ok(())
 }
 
-// for P1, P2, and P3 images
-fn read_ppm_ascii_file(ppm: &mut PpmImage, file: &mut File) {
+/// Reads the pixels of a binary (P5 or P6) file with more than 8 bits per
+/// sample. Each sample is two bytes, most significant first, and is scaled
+/// down to 8 bits since that is all PpmImage holds.
+fn read_ppm_wide_image_data(
+  image: &mut PpmImage, file: &mut File, max_value: u16
+) -> Result<(), CoreError> {
+  let samples_per_pixel = match image.ppm_type() {
+    PpmType::P5 => 1,
+    _ => PIXEL_SIZE,
+  };
+
   let mut reader = BufReader::new(file);
-  let pixel_count: usize = (ppm.height() * ppm.width()) as usize;
-  let mut current_pixel:usize = 0;
+  let mut bytes = vec![0; samples_per_pixel * 2];
+  let pixel_count = (image.width() * image.height()) as usize;
+  let mut pixel_index: usize = 0;
+
+  for _ in 0..pixel_count {
+    if reader.read_exact(&mut bytes).is_err() {
+      return Err(CoreError::Decode(
+        "The image data ended before every pixel was read".to_string()
+      ));
+    }
 
-  let mut contents: String = "".to_string();
-  
-  let rts_result = reader.read_to_string(
-    &mut contents
-  );
+    let samples: Vec<u8> = bytes.chunks_exact(2)
+      .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+      .map(|sample| scale_wide_sample(sample, max_value))
+      .collect();
 
-  if rts_result.is_err() {
-    // TODO: Do not panic here, return a sensible result instead
-    panic!("Could not read image contents to string");
+    let pixel = match samples_per_pixel {
+      1 => [samples[0]; PIXEL_SIZE],
+      _ => [samples[0], samples[1], samples[2]],
+    };
+
+    image.set_pixel(&mut pixel_index, &pixel);
   }
 
-  let pieces = contents.split_whitespace();
-  
-  let mut v = Vec::new();
-  
-  // TODO: Do not panic here - return a sensible result instead
-  for p in pieces {
-    match p {
-      "" => panic!("Empty string!"),
-      " " => panic!("Basically empty string!"),
-      _ => v.push(p.to_string().parse::<u8>().unwrap())
+  Ok(())
+}
+
+/// Scales a sample that goes up to max_value into 0..255. For 16 bit files
+/// (a max_value of 65535) this is the same as dividing by 257.
+fn scale_wide_sample(sample: u16, max_value: u16) -> u8 {
+  let scaled = sample.min(max_value) as f32 / max_value as f32;
+  (scaled * u8::MAX as f32).round() as u8
+}
+
+// for P1, P2, and P3 images
+fn read_ppm_ascii_file(
+  ppm: &mut PpmImage, file: &mut File, max_value: u16
+) -> Result<(), CoreError> {
+  // the samples are streamed out of the file rather than reading the whole
+  // thing into memory first
+  let mut bytes = BufReader::new(file).bytes().peekable();
+  let pixel_count: usize = (ppm.height() * ppm.width()) as usize;
+
+  // PBM (P1) and PGM (P2) files have one sample per pixel, and PPM (P3) files
+  // have an R, G, and B sample for every pixel
+  let samples_per_pixel = match ppm.ppm_type() {
+    PpmType::P1 | PpmType::P2 => 1,
+    _ => PIXEL_SIZE,
+  };
+
+  let mut pixel_index:usize = 0;
+  let mut samples = Vec::<u16>::with_capacity(samples_per_pixel);
+
+  for _ in 0..pixel_count {
+    samples.clear();
+
+    while samples.len() < samples_per_pixel {
+      let token = match read_ascii_token(&mut bytes, PpmType::P1 == ppm.ppm_type()) {
+        Some(token) => token,
+        None => return Err(CoreError::Decode(
+          "The image data ended before every pixel was read".to_string()
+        )),
+      };
+
+      match token.parse::<u16>() {
+        Ok(sample) if sample <= max_value => samples.push(sample),
+        _ => return Err(CoreError::Decode(
+          format!("\"{}\" is not a valid sample for this image", token)
+        )),
+      }
     }
+
+    let pixel = match ppm.ppm_type() {
+      // in a bitmap 1 is black and 0 is white
+      PpmType::P1 => [if 1 == samples[0] { 0 } else { u8::MAX }; PIXEL_SIZE],
+      // gray values are scaled into 0..255
+      PpmType::P2 => {
+        let gray = (samples[0] as f32 / max_value as f32 * 255.0).round() as u8;
+        [gray; PIXEL_SIZE]
+      },
+      // samples wider than 8 bits have to be scaled down to fit
+      _ if max_value > u8::MAX as u16 => [
+        scale_wide_sample(samples[0], max_value),
+        scale_wide_sample(samples[1], max_value),
+        scale_wide_sample(samples[2], max_value),
+      ],
+      _ => [samples[0] as u8, samples[1] as u8, samples[2] as u8],
+    };
+
+    ppm.set_pixel(&mut pixel_index, &pixel);
   }
 
-  let mut i = 0;
-  let mut pixel_index:usize = 0;
-  while current_pixel < pixel_count {
+  Ok(())
+}
 
-    ppm.set_pixel(&mut pixel_index, &v[i..(i + PIXEL_SIZE)]);
+/// Reads the next whitespace separated token of an ASCII image, skipping over
+/// any comments. The samples of a bitmap (P1) are single digits, which are
+/// allowed to run together without any whitespace between them.
+fn read_ascii_token<I: Iterator<Item = std::io::Result<u8>>>(
+  bytes: &mut std::iter::Peekable<I>, single_digits: bool
+) -> Option<String> {
+  let mut token = String::new();
+
+  while let Some(Ok(byte)) = bytes.next() {
+    if COMMENT[0] == byte {
+      // skip to the end of the line
+      while let Some(Ok(byte)) = bytes.next() {
+        if CR[0] == byte || LF[0] == byte { break; }
+      }
+    } else if WHITESPACES.contains(&[byte]) {
+      if !token.is_empty() { break; }
+    } else {
+      token.push(byte as char);
 
-    i += PIXEL_SIZE;
+      if single_digits { break; }
 
-    current_pixel += 1;
+      // stop before a comment that starts right after the token
+      if let Some(Ok(next)) = bytes.peek() {
+        if COMMENT[0] == *next { break; }
+      }
+    }
+  }
 
-}
+  if token.is_empty() { None } else { Some(token) }
 }
 
 /* #endregion */
@@ -266,55 +669,298 @@ fn read_ppm_ascii_file(ppm: &mut PpmImage, file: &mut File) {
 /* #region Writing Images */
 
 /**
- * Note that PPMs whenever written are going to be written as P6 (binary) files
+ * Writes an image in the format given by the extension of the path (see 
+ * SaveFormat), and as a PPM if the extension isn't one of them. Note that 
+ * PPMs whenever written are going to be written as P6 (binary) files with a 
+ * maxvalue of 255.
  */
-pub fn write_image(
-  image: &PpmImage, filepath: &str
-) -> Result<(), std::io::Error> {
-  // TODO: Comments should be preserved between read and write. Currently this
-  // is not supported. Also - comments inline with the image data cannot be 
-  // preserved with the P6 Data type, so this may need some additional 
-  // consideration.
-
-  let path = std::path::Path::new(filepath);
-  let display = path.display();
-
-  let file = match File::create(&path) {
-    Err(why) => panic!("Couldn't create {}: {}", display, why),
+pub fn write_image(image: &PpmImage, filepath: &str) -> Result<(), CoreError> {
+  let format = SaveFormat::from_path(filepath).unwrap_or(SaveFormat::Ppm);
+
+  write_image_as(image, filepath, format, &WriteOptions::new())
+}
+
+/// Writes an image in the given format, whatever the extension of the path
+pub fn write_image_as(
+  image: &PpmImage, filepath: &str, format: SaveFormat, options: &WriteOptions
+) -> Result<(), CoreError> {
+  match format {
+    SaveFormat::Ppm => write_ppm(
+      image, filepath, options.max_value, &options.comments
+    ),
+    SaveFormat::Png => write_png(
+      image, filepath, options.color_space, &options.comments
+    ),
+    SaveFormat::Jpeg => write_jpeg(image, filepath),
+    SaveFormat::Bmp => write_encoded(image, filepath, ImageOutputFormat::Bmp),
+    SaveFormat::Tiff => write_encoded(image, filepath, ImageOutputFormat::Tiff),
+  }
+}
+
+/// Writes a P6 file, with the maxvalue in its header chosen by max_value. Each
+/// of the comments is written into the header on a line of its own.
+pub fn write_ppm(
+  image: &PpmImage, filepath: &str, max_value: MaxValue, comments: &[String]
+) -> Result<(), CoreError> {
+  // TODO: Comments read from a file should be preserved between read and 
+  // write. Currently this is not supported. Also - comments inline with the 
+  // image data cannot be preserved with the P6 Data type, so this may need 
+  // some additional consideration.
+
+  let file = match File::create(filepath) {
     Ok(file) => file,
+    Err(why) => return Err(CoreError::Io(
+      format!("Could not create \"{}\": {}", filepath, why)
+    )),
   };
 
   let mut file_buffer = BufWriter::new(file);
 
   // build the image header here
   let mut header_str = PpmType::P6.to_string() + "\n";
+  for comment in comments {
+    // a comment runs to the end of its line, so it can't have line breaks
+    for line in comment.lines() {
+      header_str.push_str("# ");
+      header_str.push_str(line);
+      header_str.push_str("\n");
+    }
+  }
   header_str.push_str(image.width().to_string().as_str());
   header_str.push_str(" ");
   header_str.push_str(image.height().to_string().as_str());
   header_str.push_str("\n");
-  header_str.push_str(image.max_value().to_string().as_str());
+  header_str.push_str(max_value.for_image(image).to_string().as_str());
   header_str.push_str("\n");
   
 
-  match file_buffer.write(header_str.as_bytes()) {
-    Err(why) => panic!("Couldn't write header to file buffer: {}", why),
-    Ok(_) => {},
+  let written = file_buffer.write_all(header_str.as_bytes())
+    .and_then(|_| file_buffer.write_all(image.get_data()))
+    .and_then(|_| file_buffer.flush());
+
+  match written {
+    Ok(_) => Ok(()),
+    Err(why) => Err(CoreError::Io(
+      format!("Could not save \"{}\": {}", filepath, why)
+    )),
   }
+}
 
-  match file_buffer.write_all(image.get_data()){
-    Err(why) => { panic!("Couldn't write to file buffer: {}", why)},
-    Ok(_) => {},
+/// Writes an image as a PNG, tagged with the given color space. Each of the
+/// comments is stored in a text chunk of its own.
+pub fn write_png(
+  image: &PpmImage, 
+  filepath: &str, 
+  color_space: ColorSpace,
+  comments: &[String]
+) -> Result<(), CoreError> {
+  let buf: ImageBuffer<Rgb<u8>, Vec<u8>> = match ImageBuffer::from_raw(
+    image.width(), image.height(), image.get_data().to_vec()
+  ) {
+    Some(buf) => buf,
+    None => return Err(CoreError::InvalidDimensions(
+      "Image data does not match its size".to_string()
+    )),
   };
 
-  let result = file_buffer.flush();
+  let mut png = Vec::<u8>::new();
+  if let Err(why) = buf.write_to(
+    &mut std::io::Cursor::new(&mut png), ImageOutputFormat::Png
+  ) {
+    return Err(CoreError::Io(
+      format!("Could not encode \"{}\": {}", filepath, why)
+    ));
+  }
+
+  let mut png = tag_png_color_space(&png, color_space)?;
+  for comment in comments {
+    png = add_png_text(&png, PNG_COMMENT_KEYWORD, comment)?;
+  }
 
-  result
+  match std::fs::write(filepath, png) {
+    Ok(_) => Ok(()),
+    Err(why) => Err(CoreError::Io(
+      format!("Could not save \"{}\": {}", filepath, why)
+    )),
+  }
+}
+
+/**
+ * Writes an image as a JPEG with a quality of JPEG_QUALITY. JPEG is lossy, so
+ * the image read back won't be exactly the same as the one written, and it 
+ * has no alpha channel (which a PpmImage doesn't have either).
+ */
+pub fn write_jpeg(image: &PpmImage, filepath: &str) -> Result<(), CoreError> {
+  write_encoded(image, filepath, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+}
+
+/// Encodes an image through the image crate, and writes it to the file
+fn write_encoded(
+  image: &PpmImage, filepath: &str, format: ImageOutputFormat
+) -> Result<(), CoreError> {
+  let buf: ImageBuffer<Rgb<u8>, Vec<u8>> = match ImageBuffer::from_raw(
+    image.width(), image.height(), image.get_data().to_vec()
+  ) {
+    Some(buf) => buf,
+    None => return Err(CoreError::InvalidDimensions(
+      "Image data does not match its size".to_string()
+    )),
+  };
+
+  let mut encoded = Vec::<u8>::new();
+  if let Err(why) = buf.write_to(
+    &mut std::io::Cursor::new(&mut encoded), format
+  ) {
+    return Err(CoreError::Io(
+      format!("Could not encode \"{}\": {}", filepath, why)
+    ));
+  }
+
+  match std::fs::write(filepath, encoded) {
+    Ok(_) => Ok(()),
+    Err(why) => Err(CoreError::Io(
+      format!("Could not save \"{}\": {}", filepath, why)
+    )),
+  }
+}
+
+/**
+ * Writes 16 bit RGB data (like a stacked master frame) as a 16 bit TIFF, 
+ * whatever the extension of the path is. Nothing is lost in the conversion, 
+ * so this is the format that masters are kept in for further processing.
+ */
+pub fn write_tiff_16(
+  buffer: &ImageBuffer<Rgb<u16>, Vec<u16>>, filepath: &str
+) -> Result<(), CoreError> {
+  let file = match File::create(filepath) {
+    Ok(file) => file,
+    Err(why) => return Err(CoreError::Io(
+      format!("Could not create \"{}\": {}", filepath, why)
+    )),
+  };
+
+  let mut file_buffer = BufWriter::new(file);
+  if let Err(why) = TiffEncoder::new(&mut file_buffer).encode(
+    buffer.as_raw().as_bytes(), 
+    buffer.width(), 
+    buffer.height(), 
+    ColorType::Rgb16
+  ) {
+    return Err(CoreError::Io(
+      format!("Could not encode \"{}\": {}", filepath, why)
+    ));
+  }
+
+  match file_buffer.flush() {
+    Ok(_) => Ok(()),
+    Err(why) => Err(CoreError::Io(
+      format!("Could not save \"{}\": {}", filepath, why)
+    )),
+  }
+}
+
+/**
+ * Adds a chunk to an encoded PNG that says which color space it is in. sRGB 
+ * gets an sRGB chunk (with the perceptual rendering intent), and linear gets 
+ * a gAMA chunk with a gamma of 1.0. Either chunk has to come before the image
+ * data, so it is put straight after the header chunk.
+ */
+pub fn tag_png_color_space(
+  png: &[u8], 
+  color_space: ColorSpace
+) -> Result<Vec<u8>, CoreError> {
+  // the signature, then the header chunk (length, type, 13 bytes, crc)
+  let header_end = PNG_SIGNATURE.len() + 4 + 4 + 13 + 4;
+
+  if png.len() < header_end || 
+     png[..PNG_SIGNATURE.len()] != PNG_SIGNATURE || 
+     &png[12..16] != b"IHDR" {
+    return Err(CoreError::Decode(
+      "Not a PNG file, or its header is missing".to_string()
+    ));
+  }
+
+  let (chunk_type, data): (&[u8; 4], Vec<u8>) = match color_space {
+    ColorSpace::Untagged => return Ok(png.to_vec()),
+    ColorSpace::Srgb => (b"sRGB", vec![0]),
+    ColorSpace::Linear => {
+      (b"gAMA", (PNG_GAMMA_SCALE as u32).to_be_bytes().to_vec())
+    },
+  };
+
+  let mut tagged = Vec::<u8>::with_capacity(png.len() + data.len() + 12);
+  tagged.extend_from_slice(&png[..header_end]);
+  tagged.extend_from_slice(&png_chunk(chunk_type, &data));
+  tagged.extend_from_slice(&png[header_end..]);
+
+  Ok(tagged)
+}
+
+/**
+ * Adds a tEXt chunk to an encoded PNG, with the given keyword (such as 
+ * "Comment"). Text chunks can go anywhere after the header, so it is put 
+ * just before the end chunk, which leaves any earlier text chunks in order.
+ */
+pub fn add_png_text(
+  png: &[u8], 
+  keyword: &str, 
+  text: &str
+) -> Result<Vec<u8>, CoreError> {
+  // the end chunk is always the last 12 bytes (it has no data)
+  let end_chunk = png.len().saturating_sub(12);
+  if png.len() < PNG_SIGNATURE.len() + 12 || 
+     &png[end_chunk + 4..end_chunk + 8] != b"IEND" {
+    return Err(CoreError::Decode(
+      "Not a PNG file, or its end chunk is missing".to_string()
+    ));
+  }
+
+  // text chunks are Latin-1, with the keyword ended by a null byte
+  let mut data: Vec<u8> = keyword.bytes().filter(|byte| 0 != *byte).collect();
+  data.push(0);
+  data.extend(
+    text.chars().map(|ch| if (ch as u32) < 256 { ch as u8 } else { b'?' })
+  );
+
+  let mut with_text = Vec::<u8>::with_capacity(png.len() + data.len() + 12);
+  with_text.extend_from_slice(&png[..end_chunk]);
+  with_text.extend_from_slice(&png_chunk(b"tEXt", &data));
+  with_text.extend_from_slice(&png[end_chunk..]);
+
+  Ok(with_text)
 }
 
 /* #endregion */
 
 /* #region Utility Functions */
 
+/// A PNG chunk: the length of its data, its type, the data, and a checksum
+fn png_chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+  // the crc covers the chunk type and data, but not the length
+  let mut crc_bytes = chunk_type.to_vec();
+  crc_bytes.extend_from_slice(data);
+
+  let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+  chunk.extend_from_slice(&crc_bytes);
+  chunk.extend_from_slice(&crc32(&crc_bytes).to_be_bytes());
+
+  chunk
+}
+
+/// The CRC-32 checksum that PNG chunks end with
+fn crc32(bytes: &[u8]) -> u32 {
+  let mut crc = 0xFFFF_FFFF_u32;
+
+  for byte in bytes {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      crc = if 0 != crc & 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+    }
+  }
+
+  !crc
+}
+
 /// Reads a file stream until one of the bytes provided in [until_bytes] is 
 /// encountered, at which point the function returns. This equates to a sort
 /// of "scan until" functionality
@@ -342,13 +988,19 @@ fn read_until_not(file: &mut File, until_not_bytes: Vec<[u8;1]>) -> u8 {
 
     // if we encounter a comment, we read until the character is a CR or LF
     // note that this is not just a whitespace, specifically the PPM spec
-    // states that a comment line ends with CR or LF.
-    if COMMENT == byte_read {
+    // states that a comment line ends with CR or LF. Comments can follow one
+    // another, so this carries on until a line isn't a comment.
+    while COMMENT == byte_read {
       read_until(file, [CR, LF].to_vec());
 
       // TODO: Don't println here - but maybe silently fail
       match file.read(&mut byte_read) {
-        Err(why) => println!("Error reading file: {}", why),
+        Err(why) => {
+          println!("Error reading file: {}", why);
+          break;
+        },
+        // the file ended inside of the comment
+        Ok(0) => break,
         Ok(_) => {}
       }
     }
@@ -363,8 +1015,11 @@ fn read_until_not(file: &mut File, until_not_bytes: Vec<[u8;1]>) -> u8 {
 }
 
 /// Reads a number (type indicated by the templated variable "T", which must 
-/// implement the "FromStr" and "Default" traits).
-fn read_number_ascii<T : FromStr + Default>(file: &mut File) -> T {
+/// implement the "FromStr" trait). What the number is for is only used to
+/// describe it if it isn't valid.
+fn read_number_ascii<T : FromStr>(
+  file: &mut File, what: &str
+) -> Result<T, CoreError> {
   let mut ascii_number_bytes: Vec<u8> = Vec::<u8>::new();
   
   // read until it's not a whitespace
@@ -389,7 +1044,10 @@ fn read_number_ascii<T : FromStr + Default>(file: &mut File) -> T {
 
   // convert the bytes read for the number from their string representation into
   // the requested type, and return.
-  String::from_utf8_lossy(&ascii_number_bytes).parse::<T>().unwrap_or_default()
+  let ascii_number = String::from_utf8_lossy(&ascii_number_bytes);
+  ascii_number.trim().parse::<T>().map_err(|_| CoreError::Decode(format!(
+    "The {} {:?} in the header is not a valid number", what, ascii_number
+  )))
 }
 
 /* #endregion */
\ No newline at end of file
@@ -0,0 +1,210 @@
+use crate::core::ppm::{PpmImage, Padding, neighborhood_coords};
+use crate::to_1d;
+
+use super::{PIXEL_SIZE, V_CH, color};
+
+const BITS_PER_WORD: usize = 64;
+
+/* #region Binary Mask */
+
+/// A black and white mask over an image, stored as one bit per pixel. This is
+/// a lot lighter than keeping the mask in a full RGB PpmImage, and makes it
+/// clear that a pixel is only ever on or off.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinaryMask {
+  width: u32,
+  height: u32,
+  bits: Vec<u64>,
+}
+
+impl BinaryMask {
+  /// Creates a mask with every pixel turned off
+  pub fn new(width: u32, height: u32) -> Self {
+    let pixel_count = (width * height) as usize;
+
+    BinaryMask {
+      width: width,
+      height: height,
+      bits: vec![0; (pixel_count + BITS_PER_WORD - 1) / BITS_PER_WORD],
+    }
+  }
+
+  /// Creates a mask that is on wherever the value channel of the image is
+  /// above the given level (0..255)
+  pub fn from_threshold(image: &PpmImage, level: f32) -> Self {
+    let mut mask = BinaryMask::new(image.width(), image.height());
+
+    for (index, pixel) in image.get_data().chunks_exact(PIXEL_SIZE).enumerate() {
+      let value = color::rgb_to_hsv([pixel[0], pixel[1], pixel[2]])[V_CH];
+      if value > level {
+        mask.bits[index / BITS_PER_WORD] |= 1 << (index % BITS_PER_WORD);
+      }
+    }
+
+    mask
+  }
+
+  pub const fn width(&self) -> u32 {
+    self.width
+  }
+
+  pub const fn height(&self) -> u32 {
+    self.height
+  }
+
+  /// Whether the pixel is on. Pixels outside of the mask are always off.
+  pub fn get(&self, x: u32, y: u32) -> bool {
+    if x >= self.width || y >= self.height {
+      return false;
+    }
+
+    let index = to_1d!(x, y, self.width);
+    self.bits[index / BITS_PER_WORD] & (1 << (index % BITS_PER_WORD)) != 0
+  }
+
+  pub fn set(&mut self, x: u32, y: u32, on: bool) {
+    if x >= self.width || y >= self.height {
+      return;
+    }
+
+    let index = to_1d!(x, y, self.width);
+    let bit = 1 << (index % BITS_PER_WORD);
+
+    if on {
+      self.bits[index / BITS_PER_WORD] |= bit;
+    } else {
+      self.bits[index / BITS_PER_WORD] &= !bit;
+    }
+  }
+
+  /// Turns the pixels within radius of (x, y) on or off, like the tip of a
+  /// round brush
+  pub fn paint_circle(&mut self, x: u32, y: u32, radius: u32, on: bool) {
+    let radius_squared = (radius * radius) as i64;
+
+    let y_start = y.saturating_sub(radius);
+    let y_end = (y + radius).min(self.height.saturating_sub(1));
+    let x_start = x.saturating_sub(radius);
+    let x_end = (x + radius).min(self.width.saturating_sub(1));
+
+    for py in y_start..=y_end {
+      for px in x_start..=x_end {
+        let (dx, dy) = (px as i64 - x as i64, py as i64 - y as i64);
+        if dx * dx + dy * dy <= radius_squared {
+          self.set(px, py, on);
+        }
+      }
+    }
+  }
+
+  /// Paints circles all along the line between two points, so that a brush
+  /// dragged quickly doesn't leave gaps
+  pub fn paint_line(
+    &mut self, from: (u32, u32), to: (u32, u32), radius: u32, on: bool
+  ) {
+    let (dx, dy) = (to.0 as f32 - from.0 as f32, to.1 as f32 - from.1 as f32);
+    // a step of half the radius keeps the edge of the stroke smooth
+    let step = (radius as f32 / 2.).max(1.);
+    let steps = (dx.hypot(dy) / step).ceil() as u32;
+
+    for i in 0..=steps {
+      let t = if 0 == steps { 0. } else { i as f32 / steps as f32 };
+      self.paint_circle(
+        (from.0 as f32 + dx * t).round() as u32,
+        (from.1 as f32 + dy * t).round() as u32,
+        radius,
+        on
+      );
+    }
+  }
+
+  /// The runs of pixels that are on in a row, as (start, one past the end)
+  pub fn row_runs(&self, y: u32) -> Vec<(u32, u32)> {
+    let mut runs = Vec::<(u32, u32)>::new();
+    let mut start: Option<u32> = None;
+
+    for x in 0..self.width {
+      match (self.get(x, y), start) {
+        (true, None) => start = Some(x),
+        (false, Some(run_start)) => {
+          runs.push((run_start, x));
+          start = None;
+        },
+        _ => {},
+      }
+    }
+
+    if let Some(run_start) = start {
+      runs.push((run_start, self.width));
+    }
+
+    runs
+  }
+
+  /// The number of pixels that are on
+  pub fn count(&self) -> usize {
+    self.bits.iter().map(|word| word.count_ones() as usize).sum()
+  }
+
+  /// Grows the mask by a pixel in all eight directions (morphological
+  /// dilation with a 3x3 square)
+  pub fn dilate(&self) -> BinaryMask {
+    let mut dilated = self.clone();
+
+    for y in 0..self.height {
+      for x in 0..self.width {
+        if !self.get(x, y) {
+          continue;
+        }
+
+        let neighbors = neighborhood_coords(
+          x, y, 1, self.width, self.height, Padding::Zero
+        );
+        for (_, position) in neighbors {
+          if let Some((nx, ny)) = position {
+            dilated.set(nx, ny, true);
+          }
+        }
+      }
+    }
+
+    dilated
+  }
+
+  /// Converts the mask into an image, with white for the pixels that are on
+  /// and black for the pixels that are off
+  pub fn to_ppm(&self) -> PpmImage {
+    let mut image = PpmImage::new(self.width, self.height);
+
+    let mut pixel_index: usize = 0;
+    for y in 0..self.height {
+      for x in 0..self.width {
+        let value = if self.get(x, y) { u8::MAX } else { 0 };
+        image.set_pixel(&mut pixel_index, &[value; PIXEL_SIZE]);
+      }
+    }
+
+    image
+  }
+}
+
+/// Merges the runs of several rows (as given by row_runs) into the runs of
+/// pixels that are on in any of them, so that a band of rows can be drawn
+/// as one
+pub fn merge_runs(rows: &[Vec<(u32, u32)>]) -> Vec<(u32, u32)> {
+  let mut runs: Vec<(u32, u32)> = rows.iter().flatten().copied().collect();
+  runs.sort();
+
+  let mut merged = Vec::<(u32, u32)>::new();
+  for (start, end) in runs {
+    match merged.last_mut() {
+      // runs that overlap or touch become one
+      Some(last) if start <= last.1 => last.1 = last.1.max(end),
+      _ => merged.push((start, end)),
+    }
+  }
+
+  merged
+}
+
+/* #endregion */
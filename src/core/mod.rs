@@ -2,7 +2,12 @@ use std::collections::HashMap;
 
 use image::Primitive;
 
+use operations::clamp_to_range;
+
 pub mod args;
+pub mod batch;
+pub mod blend;
+pub mod error;
 pub mod io;
 pub mod operations;
 pub mod ppm;
@@ -12,6 +17,12 @@ pub mod color;
 pub mod filters;
 pub mod stacking;
 pub mod fourier;
+pub mod mask;
+pub mod font;
+pub mod threads;
+pub mod proxy;
+pub mod provenance;
+pub mod stats;
 
 pub const EULER:f32 = 2.718281828459045235360;
 
@@ -52,9 +63,9 @@ where V: Ord,
 
 fn float_pixel_to_rgb(pixel:[f32;PIXEL_SIZE]) -> PixelBytes<u8> {
   [
-    pixel[R_CH].round() as u8,
-    pixel[G_CH].round() as u8,
-    pixel[B_CH].round() as u8
+    clamp_to_range(pixel[R_CH].round(), u8::MAX),
+    clamp_to_range(pixel[G_CH].round(), u8::MAX),
+    clamp_to_range(pixel[B_CH].round(), u8::MAX)
   ]
 }
 
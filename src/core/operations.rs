@@ -1,15 +1,34 @@
 use std::collections::btree_map::Keys;
-use std::{collections::BTreeMap};
+use std::{collections::BTreeMap, fmt};
 use std::result::Result::Err;
 use strum_macros::{EnumIter, Display};
 
-use crate::core::{R_CH, G_CH, B_CH, H_CH, S_CH, V_CH, COLOR_CHANNELS};
-use crate::core::ppm::PpmImage;
+use crate::core::{R_CH, G_CH, B_CH, H_CH, S_CH, V_CH, COLOR_CHANNELS, max, min};
+use crate::core::ppm::{PpmImage, Padding, Selection};
 
 use super::PIXEL_SIZE;
-use super::{color::{HSVPixel, self, V_MULT}};
+use super::error::CoreError;
+use super::filters::{gaussian_blur, gaussian_kernel_size, MIN_GAUSSIAN_SIGMA};
+use super::mask::BinaryMask;
+use super::blend::composite_through_mask;
+use super::{color::{self, ColorMetric, V_MULT}, PixelBytes};
 
-pub type OperationResult = Result<PpmImage, String>;
+pub type OperationResult = Result<PpmImage, CoreError>;
+
+/// The number of bins histograms use by default. This is fine enough that 
+/// equalization doesn't band the image.
+pub const EQUALIZATION_HISTOGRAM_BINS: u32 = 256 * V_MULT as u32;
+
+/// The number of bins used when a histogram is shown, one per 8 bit intensity
+pub const DISPLAY_HISTOGRAM_BINS: u32 = 256;
+
+/// Shown histograms only look at every this many pixels. Their shape barely
+/// changes, and they are recomputed after every edit.
+pub const DISPLAY_HISTOGRAM_STRIDE: usize = 4;
+
+/// The percentile of each channel that auto white balance lines up. The median
+/// is less swayed by large patches of a single color than the mean is.
+pub const WHITE_BALANCE_PERCENTILE: f32 = 0.5;
 
 #[derive(PartialEq, Clone)]
 pub struct Histogram {
@@ -17,30 +36,47 @@ pub struct Histogram {
   pub max_value: f32,
   pub min_value: f32,
   pub pixel_count: u32,
+  // the number of bins that the value range (0..256) is split into
+  pub bins: u32,
 }
 
 impl Histogram {
-  fn new() -> Self {
+  fn new(bins: u32) -> Self {
     Histogram {
       data: BTreeMap::<u32, f32>::new(),
       max_value: f32::MIN,
       min_value: f32::MAX,
       pixel_count: 0,
+      bins: bins.max(1),
     }
   }
 
   pub fn from_image(image:&PpmImage) -> Self {
-    let mut histogram = Histogram::new();
-
-    for y in 0..image.height() {
-      for x in 0..image.width() {
-        if let Some(pixel) = image.get_pixel_by_coord(x, y) {
-          // convert the pixel to HSV
-          let hsv_pixel = color::rgb_to_hsv::<u8>(pixel);
-          // add the value channel to the histogram
-          histogram.add(&hsv_pixel[V_CH]);
-        }
-      }
+    Histogram::from_image_with_bins(image, EQUALIZATION_HISTOGRAM_BINS)
+  }
+
+  /// Creates the histogram of the value channel of an image, split into the 
+  /// given number of bins. Fewer bins make for a histogram that is easier to
+  /// read, while more bins give a smoother equalization.
+  pub fn from_image_with_bins(image:&PpmImage, bins: u32) -> Self {
+    Histogram::from_image_strided(image, bins, 1)
+  }
+
+  /// Creates the histogram from every stride-th pixel (in row order) instead
+  /// of all of them. The counts come out about stride times smaller, but the
+  /// shape is the same, which is all that showing a histogram needs. 
+  /// Equalization should always use every pixel.
+  pub fn from_image_strided(image:&PpmImage, bins: u32, stride: usize) -> Self {
+    let mut histogram = Histogram::new(bins);
+
+    let pixels = image.get_data().chunks_exact(PIXEL_SIZE);
+    for bytes in pixels.step_by(stride.max(1)) {
+      // convert the pixel to HSV
+      let hsv_pixel = color::rgb_to_hsv::<u8>(
+        [bytes[R_CH], bytes[G_CH], bytes[B_CH]]
+      );
+      // add the value channel to the histogram
+      histogram.add(&hsv_pixel[V_CH]);
     }
 
     histogram
@@ -55,10 +91,16 @@ impl Histogram {
     }
   }
 
-  fn downsample_float(value: f32) -> u32 {
-    // we multiply by V_MULT to store a less precise version of the f32 value
-    // inside a u32 variable
-    (value * V_MULT as f32) as u32
+  /// The bin that a value (0..256) falls into
+  pub fn to_key(&self, value: f32) -> u32 {
+    let key = (value / 256. * self.bins as f32) as u32;
+    key.min(self.bins - 1)
+  }
+
+  /// The value (0..256) at the start of a bin. Fractional keys (from 
+  /// equalization) are allowed.
+  pub fn to_value(&self, key: f32) -> f32 {
+    key * 256. / self.bins as f32
   }
 
   pub fn intensities(&self) -> Keys<u32, f32> {
@@ -67,7 +109,7 @@ impl Histogram {
 
   pub fn add(&mut self, value: &f32) {
     
-    let key = Histogram::downsample_float(*value);
+    let key = self.to_key(*value);
 
     self.update_max_min(key);
 
@@ -80,6 +122,15 @@ impl Histogram {
     self.pixel_count += 1;
   }
 
+  /// The fraction (0..1) of the pixels at or below each intensity
+  pub fn cdf(&self) -> BTreeMap<u32, f32> {
+    let mut running_count = 0.;
+    self.data.iter().map(|(intensity, count)| {
+      running_count += *count;
+      (*intensity, running_count / self.pixel_count as f32)
+    }).collect()
+  }
+
   pub fn equalize(&self) -> BTreeMap<u32, f32> {
     // calculate the probability for each intensity
     let mut intensity_eq = BTreeMap::<u32, f32>::new();
@@ -103,75 +154,344 @@ pub enum OpType {
   Add,
   Subtract,
   Multiply,
+  Divide,
+  /// Keeps the brighter of the two samples (a lighten blend), for star trails
+  /// and blending exposures
+  Lighten,
+  /// Keeps the darker of the two samples (a darken blend)
+  Darken,
 }
 
-#[derive(PartialEq, Clone, Copy)]
+/// What an operation on two images does when they aren't the same size
+#[derive(PartialEq, EnumIter, Display, Clone, Copy, Debug)]
+pub enum SizeMatching {
+  /// Both images are resized to the average of their sizes, which stretches 
+  /// or squashes both of them
+  #[strum(serialize = "Rescale Both")]
+  Rescale,
+  /// Both images are cropped to the part that they share, from their top 
+  /// left corners
+  #[strum(serialize = "Crop to Overlap")]
+  Intersection,
+  /// The result is the size of the first image, which is left as it is 
+  /// wherever the second image doesn't reach (as when compositing)
+  #[strum(serialize = "Keep First Size")]
+  FirstOperand,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum ResizeAlgorithm {
   NearestNeighbor,
-  BilinearInterpolation
+  BilinearInterpolation,
+  /// Blends the 4x4 surrounding pixels with a cubic, which is sharper than 
+  /// bilinear when enlarging
+  Bicubic,
+}
+
+/// How the brightness of a pixel is worked out when converting to grayscale
+#[derive(PartialEq, EnumIter, Display, Clone, Copy, Debug)]
+pub enum GrayscaleMode {
+  /// Weights the gamma encoded sRGB values directly, which is fast
+  #[strum(serialize = "sRGB")]
+  Srgb,
+  /// Weights the values in linear light, which gives the true luminance
+  #[strum(serialize = "linear light")]
+  LinearLight,
+}
+
+/// An operation that can be applied to a single image, along with all of the
+/// parameters it needs. This lets an operation be remembered and applied 
+/// again later on.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Operation {
+  Negate,
+  EdgeDetect,
+  MagnitudeSpectrum,
+  HistogramEqualization,
+  HistogramEqualizationRgb,
+  AutoWhiteBalance,
+  Gamma { gamma: f32 },
+  Log { base: f32 },
+  GaussianBlur { sigma: f32, kernel_size: i32, padding: Padding },
+  UnsharpMask { sigma: f32, kernel_size: i32, scale: f32, padding: Padding },
+  DifferenceOfGaussians { sigma1: f32, sigma2: f32, padding: Padding },
+  HighPass { sigma: f32, padding: Padding },
+  HighPassSharpen { sigma: f32, strength: f32, padding: Padding },
+  Clarity { radius: f32, strength: f32 },
+  Deconvolution { sigma: f32, kernel_size: i32, iterations: u32 },
+  Rotate90,
+  Rotate { degrees: f32, padding: Padding, algorithm: ResizeAlgorithm },
+  SnrMap { window: u32 },
+  Grayscale { mode: GrayscaleMode },
+  ReplaceColor {
+    target: PixelBytes<u8>, 
+    replacement: PixelBytes<u8>, 
+    tolerance: f32, 
+    metric: ColorMetric 
+  },
+  AdjustColorRange {
+    center_hue: f32,
+    hue_width: f32,
+    saturation_delta: f32,
+    value_delta: f32,
+  },
+}
+
+impl Operation {
+  pub fn apply(&self, image: &PpmImage) -> OperationResult {
+    use crate::core::{filters, fourier};
+
+    match *self {
+      Operation::Negate => negate(image),
+      Operation::EdgeDetect => filters::edge_detect(image),
+      Operation::MagnitudeSpectrum => Ok(fourier::magnitude_spectrum(image)),
+      Operation::HistogramEqualization => histogram_equalization(image, None),
+      Operation::HistogramEqualizationRgb => histogram_equalization_rgb(image),
+      Operation::AutoWhiteBalance => auto_white_balance(image),
+      Operation::Gamma { gamma } => gamma_transform(image, gamma, None),
+      Operation::Log { base } => log_transform(image, None, Some(base)),
+      Operation::GaussianBlur { sigma, kernel_size, padding } => {
+        filters::gaussian_blur(image, sigma, kernel_size, padding)
+      },
+      Operation::UnsharpMask { sigma, kernel_size, scale, padding } => {
+        filters::unsharp_mask(image, sigma, kernel_size, scale, padding)
+      },
+      Operation::DifferenceOfGaussians { sigma1, sigma2, padding } => {
+        filters::difference_of_gaussians(image, sigma1, sigma2, padding)
+      },
+      Operation::HighPass { sigma, padding } => {
+        filters::high_pass(image, sigma, padding)
+      },
+      Operation::HighPassSharpen { sigma, strength, padding } => {
+        filters::high_pass_sharpen(image, sigma, strength, padding)
+      },
+      Operation::Clarity { radius, strength } => {
+        filters::clarity(image, radius, strength)
+      },
+      Operation::Deconvolution { sigma, kernel_size, iterations } => {
+        let psf = filters::get_gaussian_weight_matrix(kernel_size, sigma);
+        filters::richardson_lucy(image, &psf, iterations)
+      },
+      Operation::Rotate90 => rotate_90(image),
+      Operation::Rotate { degrees, padding, algorithm } => {
+        rotate(image, degrees, padding, Some(algorithm))
+      },
+      Operation::SnrMap { window } => filters::snr_map(image, window),
+      Operation::Grayscale { mode } => to_grayscale(image, mode),
+      Operation::ReplaceColor { target, replacement, tolerance, metric } => {
+        replace_color(image, target, replacement, tolerance, metric)
+      },
+      Operation::AdjustColorRange { 
+        center_hue, hue_width, saturation_delta, value_delta 
+      } => adjust_color_range(
+        image, center_hue, hue_width, saturation_delta, value_delta
+      ),
+    }
+  }
+
+  /// Whether each pixel of the result only depends on the same pixel of the
+  /// image (along with statistics of the whole image), so that limiting the
+  /// operation to part of the image is as simple as compositing it
+  pub fn is_point_operation(&self) -> bool {
+    matches!(self,
+      Operation::Negate |
+      Operation::HistogramEqualization |
+      Operation::HistogramEqualizationRgb |
+      Operation::AutoWhiteBalance |
+      Operation::Gamma { .. } |
+      Operation::Log { .. } |
+      Operation::Grayscale { .. } |
+      Operation::ReplaceColor { .. } |
+      Operation::AdjustColorRange { .. }
+    )
+  }
+
+  /**
+   * Applies the operation only to the pixels that are on in the mask, leaving
+   * the rest of the image untouched. For now only point operations can be
+   * limited to a mask, anything that looks at neighboring pixels or moves
+   * them around is an error.
+   */
+  pub fn apply_masked(
+    &self, image: &PpmImage, mask: &BinaryMask
+  ) -> OperationResult {
+    if !self.is_point_operation() {
+      return Err(CoreError::InvalidParameter(format!(
+        "{} can't be limited to a mask", self
+      )));
+    }
+
+    let result = self.apply(image)?;
+    composite_through_mask(image, &result, mask)
+  }
+}
+
+impl fmt::Display for Operation {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Operation::Negate => write!(f, "Negate"),
+      Operation::EdgeDetect => write!(f, "Edge detection"),
+      Operation::MagnitudeSpectrum => write!(f, "Magnitude spectrum"),
+      Operation::HistogramEqualization => write!(f, "Histogram equalization"),
+      Operation::HistogramEqualizationRgb => {
+        write!(f, "Histogram equalization (RGB)")
+      },
+      Operation::AutoWhiteBalance => write!(f, "Auto white balance"),
+      Operation::Gamma { gamma } => write!(f, "Gamma ({:.2})", gamma),
+      Operation::Log { base } => write!(f, "Log (base {:.2})", base),
+      Operation::GaussianBlur { sigma, kernel_size, .. } => {
+        write!(f, "Gaussian blur ({:.2}, {}x{})", sigma, kernel_size, kernel_size)
+      },
+      Operation::UnsharpMask { sigma, kernel_size, scale, .. } => {
+        write!(f, "Unsharp mask ({:.2}, {}x{}, k = {:.2})", 
+          sigma, kernel_size, kernel_size, scale)
+      },
+      Operation::DifferenceOfGaussians { sigma1, sigma2, .. } => {
+        write!(f, "Difference of gaussians ({:.2}, {:.2})", sigma1, sigma2)
+      },
+      Operation::HighPass { sigma, .. } => write!(f, "High-pass ({:.2})", sigma),
+      Operation::HighPassSharpen { sigma, strength, .. } => {
+        write!(f, "High-pass sharpen ({:.2}, {:.0}%)", sigma, strength * 100.)
+      },
+      Operation::Clarity { radius, strength } => {
+        write!(f, "Clarity ({:.1}, {:.0}%)", radius, strength * 100.)
+      },
+      Operation::Deconvolution { iterations, .. } => {
+        write!(f, "Deconvolution ({} iterations)", iterations)
+      },
+      Operation::Rotate90 => write!(f, "Rotate 90 degrees"),
+      Operation::Rotate { degrees, .. } => {
+        write!(f, "Rotate ({:.1} degrees)", degrees)
+      },
+      Operation::SnrMap { window } => {
+        write!(f, "SNR map ({}x{})", window, window)
+      },
+      Operation::Grayscale { mode } => write!(f, "Grayscale ({})", mode),
+      Operation::ReplaceColor { target, replacement, tolerance, .. } => {
+        write!(f, "Replace color ({:?} with {:?}, tolerance {:.2})", 
+          target, replacement, tolerance)
+      },
+      Operation::AdjustColorRange { center_hue, hue_width, .. } => {
+        write!(f, "Adjust colors ({:.0} +/- {:.0} degrees)", 
+          center_hue, hue_width)
+      },
+    }
+  }
 }
 
 /* #endregion */
 
 /* #region Overflow safe math functions for pixels  */
 
-fn mult_safe(lhs: u8, rhs:u8) -> u8 {
-  let temp: u32 = lhs as u32 * rhs as u32;
+/**
+ * A single channel value of a pixel. The safe math functions are written over
+ * this, rather than u8, so that they saturate at the right value for both 8 
+ * and 16 bit images.
+ */
+pub trait Sample: Copy + PartialOrd {
+  /// The brightest value a sample can have
+  const MAX: Self;
+
+  fn saturating_add(self, rhs: Self) -> Self;
+  fn saturating_sub(self, rhs: Self) -> Self;
+  fn saturating_mul(self, rhs: Self) -> Self;
 
-  if temp > (u8::MAX as u32) {
-    return u8::MAX
+  fn to_f32(self) -> f32;
+  /// Converts from a float that is already in range, truncating any fraction
+  fn from_f32(value: f32) -> Self;
+
+  /// Whether the sample is at its max value, and so may have been clipped
+  fn is_saturated(self) -> bool {
+    Self::MAX == self
   }
+}
+
+macro_rules! impl_sample {
+  ($t:ty) => {
+    impl Sample for $t {
+      const MAX: Self = <$t>::MAX;
 
-  temp as u8
+      fn saturating_add(self, rhs: Self) -> Self { <$t>::saturating_add(self, rhs) }
+      fn saturating_sub(self, rhs: Self) -> Self { <$t>::saturating_sub(self, rhs) }
+      fn saturating_mul(self, rhs: Self) -> Self { <$t>::saturating_mul(self, rhs) }
+
+      fn to_f32(self) -> f32 { self as f32 }
+      fn from_f32(value: f32) -> Self { value as $t }
+    }
+  };
 }
 
-fn add_safe(lhs: u8, rhs:u8) -> u8 {
-  // cast up to u32, perform addition
-  let temp: u32 = lhs as u32 + rhs as u32;
-  
-  // check to see if overflow
-  if temp > (u8::MAX as u32) {
-    return u8::MAX
+impl_sample!(u8);
+impl_sample!(u16);
+
+/**
+ * Clamps a value into 0..=max and converts it to a sample, so that results 
+ * outside of the range saturate rather than wrapping. The value is not 
+ * rounded (round it first if that is wanted), and NaN becomes 0.
+ */
+pub fn clamp_to_range<T: Sample>(value: f32, max: T) -> T {
+  if value.is_nan() {
+    return T::from_f32(0.);
   }
 
-  // otherwise, return sum
-  temp as u8
+  T::from_f32(value.clamp(0., max.to_f32()))
 }
 
-fn sub_safe(lhs: u8, rhs:u8) -> u8 {
-  // cast to i32, perform subtraction
-  let temp: i32 = lhs as i32 - rhs as i32;
+pub fn mult_safe<T: Sample>(lhs: T, rhs: T) -> T {
+  lhs.saturating_mul(rhs)
+}
 
-  // if temp is greater than u8, set to max
-  if temp > (u8::MAX as i32) {
-    return u8::MAX;
-  } 
-  // if less than 0, set to zero
-  if temp < 0 {
-    return 0;    
+pub fn add_safe<T: Sample>(lhs: T, rhs: T) -> T {
+  lhs.saturating_add(rhs)
+}
+
+pub fn sub_safe<T: Sample>(lhs: T, rhs: T) -> T {
+  // clamps to zero rather than wrapping around
+  lhs.saturating_sub(rhs)
+}
+
+pub fn div_safe<T: Sample>(lhs: T, rhs: T) -> T {
+  // dividing by zero saturates rather than panicking
+  if 0. == rhs.to_f32() {
+    return T::MAX;
   }
 
-  temp as u8
+  clamp_to_range((lhs.to_f32() / rhs.to_f32()).round(), T::MAX)
+}
+
+/**
+ * Combines two sets of samples one by one with the given operation, clamping
+ * each result between zero and the max value of the sample type. Both sets
+ * need to be the same length.
+ */
+pub fn combine_samples<T: Sample>(lhs: &[T], rhs: &[T], optype: OpType) -> Vec<T> {
+  // declare a type for the sample operation function
+  type SampleOperation<T> = fn(T, T) -> T;
+
+  let operation_fn: SampleOperation<T> = match optype {
+    OpType::Add => add_safe,
+    OpType::Subtract => sub_safe,
+    OpType::Multiply => mult_safe,
+    OpType::Divide => div_safe,
+    OpType::Lighten => max,
+    OpType::Darken => min,
+  };
+
+  lhs.iter()
+    .zip(rhs.iter())
+    .map(|(lhs, rhs)| operation_fn(*lhs, *rhs))
+    .collect()
 }
 
 fn log_transform_safe(pixel:u8, c:f32, b:f32) -> u8 {
   let new_value = c * (pixel as f32 + 1.0).log(b);
-  if new_value > u8::MAX as f32 {
-    return u8::MAX
-  } else {
-    return new_value.round() as u8
-  }
+  clamp_to_range(new_value.round(), u8::MAX)
 }
 
 fn gamma_transform_safe(pixel:u8, gamma_correction:f32, _c:f32) -> u8 {
   // get the new value 
   let new_value: f32 = 255.0 * (pixel as f32 / 255.).powf(gamma_correction);
-
-  if new_value > u8::MAX as f32 {
-    return u8::MAX;
-  } else {
-    return new_value.round() as u8;
-  }
+  clamp_to_range(new_value.round(), u8::MAX)
 }
 
 /* #endregion */
@@ -243,14 +563,28 @@ pub fn log_transform(
 
 /* #endregion */
 
-/* #region Image Operations (addition, subtraction, and multiplication) */
+/* #region Image Operations (addition, subtraction, multiplication, division) */
 
 pub fn perform_operation(
   lhs: &PpmImage, 
   rhs: &PpmImage, 
   optype:OpType) -> OperationResult {
-  
-    use crate::core::PixelBytes;
+  perform_operation_with_matching(lhs, rhs, optype, SizeMatching::Rescale)
+}
+
+/**
+ * Combines two images with the given operation, with the matching deciding 
+ * what happens when the images aren't the same size. Rescaling keeps all of
+ * both images but distorts them, while the other two line the images up by 
+ * their top left corners, so that each pixel is combined with the one at the
+ * same place in the other image.
+ */
+pub fn perform_operation_with_matching(
+  lhs: &PpmImage, 
+  rhs: &PpmImage, 
+  optype: OpType,
+  matching: SizeMatching
+) -> OperationResult {
 
   /*
   TODO: 
@@ -258,69 +592,72 @@ pub fn perform_operation(
   - check for zero dimension images
   */
 
-  let mut lhs_copy = lhs.clone();
-  let mut rhs_copy = rhs.clone();
+  if lhs.width() == rhs.width() && lhs.height() == rhs.height() {
+    return Ok(combine_images(lhs, rhs, optype));
+  }
 
-  // determine the dimensions for the new image
-  let (w, h) = friendly_scale_match(
-    lhs.width(), lhs.height(), 
-    rhs.width(), rhs.height()
-  );
+  if SizeMatching::Rescale == matching {
+    // determine the dimensions for the new image
+    let (w, h) = friendly_scale_match(
+      lhs.width(), lhs.height(), 
+      rhs.width(), rhs.height()
+    );
 
-  // if the two images are of different sizes
-  if lhs.width() != rhs.width() || lhs.height() != rhs.height() {
-    let left_resize = resize(
+    let lhs_copy = resize(
       lhs, w, h, 
       Some(ResizeAlgorithm::BilinearInterpolation)
-    );
+    )?;
 
-    let right_resize = resize(
+    let rhs_copy = resize(
       rhs, w, h, 
       Some(ResizeAlgorithm::BilinearInterpolation)
-    );
+    )?;
 
-    // if either resize operation failed, bubble up the error and stop
-    if let Err(msg) = left_resize { return Err(msg); }
-    if let Err(msg) = right_resize { return Err(msg); }
-
-    lhs_copy = left_resize.ok().unwrap();
-    rhs_copy = right_resize.ok().unwrap();
+    return Ok(combine_images(&lhs_copy, &rhs_copy, optype));
   }
 
-  // container for the resulting image
-  let mut new_image = PpmImage::new(w, h);
-
-  // the number of pixels in the new image
-  let ppm_pixel_capacity = (w * h) as usize;
-    
-  // declare a type for the pixel operation function
-  type PixelOperation = fn(u8, u8) -> u8;
+  // the part of the images that they both cover
+  let overlap = Selection::new(
+    0, 0, min(lhs.width(), rhs.width()), min(lhs.height(), rhs.height())
+  );
+  let (lhs_overlap, rhs_overlap) = match (
+    lhs.crop(&overlap), rhs.crop(&overlap)
+  ) {
+    (Some(lhs_overlap), Some(rhs_overlap)) => (lhs_overlap, rhs_overlap),
+    _ => return Err(CoreError::InvalidDimensions(
+      "The images don't overlap, as one of them is empty".to_string()
+    )),
+  };
 
-  // default to setting the operation function to add_safe
-  let mut operation_fn: PixelOperation = add_safe;
+  let combined = combine_images(&lhs_overlap, &rhs_overlap, optype);
+  if SizeMatching::Intersection == matching {
+    return Ok(combined);
+  }
 
-  // depending on the optype, set the operation function
-  match optype {
-    OpType::Add => {}, // if the optype is Add, then we're already good
-    OpType::Subtract => operation_fn = sub_safe,
-    OpType::Multiply => operation_fn = mult_safe,
+  // outside of the overlap, the first image is kept as it is
+  let mut new_image = lhs.clone();
+  for y in 0..combined.height() {
+    for x in 0..combined.width() {
+      new_image.set_pixel_by_coord(x, y, combined.get_pixel_by_coord_ref(x, y));
+    }
   }
 
-  let mut pixel_index = 0;
-  for i in 0..ppm_pixel_capacity {
-    let mut output_pixel:PixelBytes<u8> = [0;PIXEL_SIZE];
+  Ok(new_image)
+}
 
-    for ch in COLOR_CHANNELS {
-      output_pixel[ch] = operation_fn(
-        lhs_copy.get_pixel_at(i)[ch],
-        rhs_copy.get_pixel_at(i)[ch]
-      );
-    }
+/// Combines two images of the same size sample by sample
+fn combine_images(lhs: &PpmImage, rhs: &PpmImage, optype: OpType) -> PpmImage {
+  // container for the resulting image
+  let mut new_image = PpmImage::new(lhs.width(), lhs.height());
+
+  let samples = combine_samples(lhs.get_data(), rhs.get_data(), optype);
 
-    new_image.set_pixel(&mut pixel_index, &output_pixel);
+  let mut pixel_index = 0;
+  for pixel in samples.chunks_exact(PIXEL_SIZE) {
+    new_image.set_pixel(&mut pixel_index, pixel);
   }
-  // return the result of the operation
-  Ok(new_image)
+
+  new_image
 }
 
 /* #endregion */
@@ -331,7 +668,7 @@ pub fn perform_operation(
  * Resizes an image given the image input object and a new width and height
  * 
  * Optionally include an indicator of what resizing algorithm to use, either
- * Nearest Neighbor, or Bilinear Interpolation.
+ * Nearest Neighbor, Bilinear Interpolation, or Bicubic.
  * 
  * If resize_algo is set to None, then the resize algorithm will be set to 
  * Nearest Neighbor
@@ -363,7 +700,9 @@ pub fn resize(
 
   // if the image is trying to be resized to zero zero, then just return
   if width == 0 || height == 0 {
-    return Err("The image cannot have height or width be zero.".to_string())
+    return Err(CoreError::InvalidDimensions(
+      "The image cannot have height or width be zero.".to_string()
+    ));
   }
 
   // based on the resize algorithm to use, resize the image
@@ -374,9 +713,55 @@ pub fn resize(
     ResizeAlgorithm::BilinearInterpolation => {
       bilinear_interpolation(image, width, height)
     },
+    ResizeAlgorithm::Bicubic => {
+      bicubic_interpolation(image, width, height)
+    },
   }
 }
 
+/// Prefilters with a smaller sigma than this are skipped, as they wouldn't
+/// do anything (see filters::validate_gaussian)
+const MIN_PREFILTER_SIGMA: f32 = MIN_GAUSSIAN_SIGMA;
+
+/// The sigma of the gaussian prefilter used when shrinking an image by the
+/// given factor, which grows with the factor so that the blur always cuts 
+/// out the detail that is finer than the new pixels can hold
+pub fn prefilter_sigma(scale_factor: f32) -> f32 {
+  ((scale_factor - 1.) / 2.).max(0.)
+}
+
+/**
+ * Resizes an image like resize, but can blur it first when it is being 
+ * shrunk. Detail finer than the new pixels can't be shown, and sampling it 
+ * anyway turns it into moire patterns, so the prefilter takes it out 
+ * beforehand (a low-pass at the new sampling rate).
+ * 
+ * This is only worth it for the nearest neighbor and bilinear resizes, an
+ * area average (see area_average) is already free of aliasing.
+ */
+pub fn resize_with_prefilter(
+  image: &PpmImage, 
+  width: u32, 
+  height: u32, 
+  resize_algo: Option<ResizeAlgorithm>,
+  prefilter: bool
+) -> OperationResult {
+  if prefilter && 0 != width && 0 != height {
+    let scale_factor = (image.width() as f32 / width as f32)
+      .max(image.height() as f32 / height as f32);
+    let sigma = prefilter_sigma(scale_factor);
+
+    if sigma >= MIN_PREFILTER_SIGMA {
+      let blurred = gaussian_blur(
+        image, sigma, gaussian_kernel_size(sigma), Padding::Repeat
+      )?;
+      return resize(&blurred, width, height, resize_algo);
+    }
+  }
+
+  resize(image, width, height, resize_algo)
+}
+
 /**
  * Perform a resize on the given image using the bilinear interpolation 
  * method
@@ -441,9 +826,9 @@ fn bilinear_interpolation(
       let fb = blerp(a.2, b.2, c.2, d.2, gx - gxi as f32, gy - gyi as f32);
 
       // round and clamp to 255 max
-      let r = clamp_color(fr.round() as u32);
-      let g = clamp_color(fg.round() as u32);
-      let b = clamp_color(fb.round() as u32);
+      let r = clamp_to_range(fr.round(), u8::MAX);
+      let g = clamp_to_range(fg.round(), u8::MAX);
+      let b = clamp_to_range(fb.round(), u8::MAX);
 
       // push the new pixel onto the new image
       new_image.set_pixel_by_coord(x as u32, y as u32, &[r, g, b]);
@@ -454,6 +839,39 @@ fn bilinear_interpolation(
   Ok(new_image)
 }
 
+/**
+ * Perform a resize on the given image using bicubic interpolation, which
+ * samples the 4x4 pixels around each new pixel with the cubic convolution
+ * kernel. Samples past the edges of the image are clamped to the edge, so the
+ * borders don't darken.
+ */
+fn bicubic_interpolation(
+  image: &PpmImage, 
+  width: u32, 
+  height: u32) -> OperationResult {
+  let mut new_image = PpmImage::new(width, height);
+
+  // the same mapping as bilinear_interpolation, so the two line up
+  let x_scale = (image.width() as f32 - 1.) / width as f32;
+  let y_scale = (image.height() as f32 - 1.) / height as f32;
+
+  for y in 0..height {
+    for x in 0..width {
+      let (r, g, b) = sample_bicubic(
+        image, x as f32 * x_scale, y as f32 * y_scale, Padding::Repeat
+      );
+
+      new_image.set_pixel_by_coord(x, y, &[
+        clamp_to_range(r.round(), u8::MAX),
+        clamp_to_range(g.round(), u8::MAX),
+        clamp_to_range(b.round(), u8::MAX),
+      ]);
+    }
+  }
+
+  Ok(new_image)
+}
+
 /**
  * Perform a resize on the given image using the nearest neighbor algorithm
  */
@@ -485,6 +903,361 @@ fn nearest_neighbor(
 
 /* #endregion */
 
+/* #region Image Pyramid */
+
+/**
+ * Builds a pyramid of successively half sized copies of an image, with the 
+ * image itself as level 0. Each level is made by averaging the pixels of the
+ * level above it, so detail is smoothed away rather than aliased the way 
+ * nearest neighbor scaling would.
+ *
+ * At most `levels` images are returned, stopping early once a level is only
+ * a single pixel wide and tall.
+ */
+pub fn build_pyramid(image: &PpmImage, levels: usize) -> Vec<PpmImage> {
+  let mut pyramid = Vec::<PpmImage>::with_capacity(levels);
+
+  if levels > 0 {
+    pyramid.push(image.clone());
+  }
+
+  while pyramid.len() < levels {
+    let last = pyramid.last().unwrap();
+    if last.width() <= 1 && last.height() <= 1 {
+      break;
+    }
+
+    let next = area_average(
+      last, (last.width() / 2).max(1), (last.height() / 2).max(1)
+    );
+    pyramid.push(next);
+  }
+
+  pyramid
+}
+
+/**
+ * Shrinks an image by averaging every source pixel that falls inside each new
+ * pixel. The blocks don't have to be the same size, so an odd row or column
+ * is folded into the last block rather than dropped.
+ */
+pub fn area_average(image: &PpmImage, width: u32, height: u32) -> PpmImage {
+  let mut new_image = PpmImage::new(width, height);
+
+  // the first source column / row of each new pixel, with one extra entry
+  // marking the end of the last block
+  let bounds = |length: u32, new_length: u32| -> Vec<u32> {
+    (0..=new_length)
+      .map(|i| (i as u64 * length as u64 / new_length as u64) as u32)
+      .collect()
+  };
+  let x_bounds = bounds(image.width(), width);
+  let y_bounds = bounds(image.height(), height);
+
+  for y in 0..height {
+    for x in 0..width {
+      let mut sums = [0_u32; PIXEL_SIZE];
+      let mut count = 0;
+
+      for source_y in y_bounds[y as usize]..y_bounds[y as usize + 1] {
+        for source_x in x_bounds[x as usize]..x_bounds[x as usize + 1] {
+          if let Some(pixel) = image.get_pixel_by_coord(source_x, source_y) {
+            for ch in COLOR_CHANNELS {
+              sums[ch] += pixel[ch] as u32;
+            }
+            count += 1;
+          }
+        }
+      }
+
+      if count > 0 {
+        let pixel = sums.map(|sum| ((sum as f32) / count as f32).round() as u8);
+        new_image.set_pixel_by_coord(x, y, &pixel);
+      }
+    }
+  }
+
+  new_image
+}
+
+/* #endregion */
+
+/* #region Rotation */
+
+/**
+ * Rotates an image by 90 degrees clockwise
+ */
+pub fn rotate_90(image: &PpmImage) -> OperationResult {
+  let mut rotated = PpmImage::new(image.height(), image.width());
+
+  for y in 0..rotated.height() {
+    for x in 0..rotated.width() {
+      let pixel = image.get_pixel_by_coord(y, image.height() - 1 - x).unwrap();
+      rotated.set_pixel_by_coord(x, y, &pixel);
+    }
+  }
+
+  Ok(rotated)
+}
+
+/**
+ * Rotates an image clockwise by an arbitrary number of degrees
+ * 
+ * The canvas is expanded so that none of the rotated image is cut off, and
+ * the corners that fall outside of the source image are filled according to
+ * the padding strategy.
+ * 
+ * The source image is sampled with the same algorithms used for resizing. If
+ * rotate_algo is set to None, then bilinear interpolation is used.
+ */
+pub fn rotate(
+  image: &PpmImage, 
+  degrees: f32, 
+  padding: Padding,
+  rotate_algo: Option<ResizeAlgorithm>) -> OperationResult {
+
+  let algorithm = rotate_algo.unwrap_or(
+    ResizeAlgorithm::BilinearInterpolation
+  );
+
+  if !degrees.is_finite() {
+    return Err(CoreError::InvalidParameter(format!(
+      "Cannot rotate an image by {} degrees", degrees
+    )));
+  }
+
+  // float noise would otherwise grow the canvas by a pixel at right angles
+  let snap = |value: f32| (value * 1e6).round() / 1e6;
+
+  let radians = degrees.rem_euclid(360.).to_radians();
+  let sin = snap(radians.sin());
+  let cos = snap(radians.cos());
+
+  let width = image.width() as f32;
+  let height = image.height() as f32;
+
+  // the bounding box of the rotated image
+  let new_width = (width * cos.abs() + height * sin.abs()).ceil() as u32;
+  let new_height = (width * sin.abs() + height * cos.abs()).ceil() as u32;
+
+  let mut rotated = PpmImage::new(new_width, new_height);
+
+  // rotate about the centers of both images
+  let cx = (width - 1.) / 2.;
+  let cy = (height - 1.) / 2.;
+  let new_cx = (new_width as f32 - 1.) / 2.;
+  let new_cy = (new_height as f32 - 1.) / 2.;
+
+  for y in 0..new_height {
+    for x in 0..new_width {
+      let dx = x as f32 - new_cx;
+      let dy = y as f32 - new_cy;
+
+      // rotate backwards to find where this pixel came from
+      let sx = cos * dx + sin * dy + cx;
+      let sy = -sin * dx + cos * dy + cy;
+
+      let (r, g, b) = match algorithm {
+        ResizeAlgorithm::NearestNeighbor => {
+          sample_nearest(image, sx, sy, padding)
+        },
+        ResizeAlgorithm::BilinearInterpolation => {
+          sample_bilinear(image, sx, sy, padding)
+        },
+        ResizeAlgorithm::Bicubic => {
+          sample_bicubic(image, sx, sy, padding)
+        },
+      };
+
+      rotated.set_pixel_by_coord(x, y, &[
+        clamp_to_range(r.round(), u8::MAX),
+        clamp_to_range(g.round(), u8::MAX),
+        clamp_to_range(b.round(), u8::MAX),
+      ]);
+    }
+  }
+
+  Ok(rotated)
+}
+
+/// Samples the image at a fractional coordinate by taking the closest pixel
+fn sample_nearest(image: &PpmImage, x: f32, y: f32, padding: Padding) -> (f32, f32, f32) {
+  get_padded_pixel(image, x.round() as i32, y.round() as i32, padding)
+}
+
+/// Samples the image at a fractional coordinate by blending the four 
+/// surrounding pixels
+fn sample_bilinear(image: &PpmImage, x: f32, y: f32, padding: Padding) -> (f32, f32, f32) {
+  let x0 = x.floor();
+  let y0 = y.floor();
+  let tx = x - x0;
+  let ty = y - y0;
+
+  let a = get_padded_pixel(image, x0 as i32, y0 as i32, padding);
+  let b = get_padded_pixel(image, x0 as i32 + 1, y0 as i32, padding);
+  let c = get_padded_pixel(image, x0 as i32, y0 as i32 + 1, padding);
+  let d = get_padded_pixel(image, x0 as i32 + 1, y0 as i32 + 1, padding);
+
+  let blerp = |a: f32, b: f32, c: f32, d: f32| {
+    let top = a + (b - a) * tx;
+    let bottom = c + (d - c) * tx;
+    top + (bottom - top) * ty
+  };
+
+  (
+    blerp(a.0, b.0, c.0, d.0),
+    blerp(a.1, b.1, c.1, d.1),
+    blerp(a.2, b.2, c.2, d.2),
+  )
+}
+
+/// The free parameter of the cubic convolution kernel. -0.5 is the value that
+/// matches the slope of the image best (Keys' cubic).
+const CUBIC_A: f32 = -0.5;
+
+/// The weight of a sample that is distance away from the point being sampled
+fn cubic_weight(distance: f32) -> f32 {
+  let t = distance.abs();
+
+  if t <= 1. {
+    (CUBIC_A + 2.) * t.powi(3) - (CUBIC_A + 3.) * t.powi(2) + 1.
+  } else if t < 2. {
+    CUBIC_A * (t.powi(3) - 5. * t.powi(2) + 8. * t - 4.)
+  } else {
+    0.
+  }
+}
+
+/// Samples the image at a fractional coordinate by blending the sixteen 
+/// surrounding pixels with the cubic convolution kernel
+fn sample_bicubic(image: &PpmImage, x: f32, y: f32, padding: Padding) -> (f32, f32, f32) {
+  let x0 = x.floor();
+  let y0 = y.floor();
+
+  let mut sum = (0., 0., 0.);
+  for j in -1..=2 {
+    let y_weight = cubic_weight(y - (y0 + j as f32));
+
+    for i in -1..=2 {
+      let weight = y_weight * cubic_weight(x - (x0 + i as f32));
+      let pixel = get_padded_pixel(
+        image, x0 as i32 + i, y0 as i32 + j, padding
+      );
+
+      sum.0 += pixel.0 * weight;
+      sum.1 += pixel.1 * weight;
+      sum.2 += pixel.2 * weight;
+    }
+  }
+
+  sum
+}
+
+/// Gets a pixel as floats, using the padding strategy for coordinates outside 
+/// of the image
+fn get_padded_pixel(image: &PpmImage, x: i32, y: i32, padding: Padding) -> (f32, f32, f32) {
+  let (x, y) = match (
+    padding.resolve(x, image.width()), 
+    padding.resolve(y, image.height())
+  ) {
+    (Some(x), Some(y)) => (x, y),
+    _ => return (0., 0., 0.),
+  };
+
+  let pixel = image.get_pixel_by_coord_ref(x, y);
+
+  (pixel[R_CH] as f32, pixel[G_CH] as f32, pixel[B_CH] as f32)
+}
+
+/* #endregion */
+
+/* #region Image Alignment */
+
+/**
+ * Moves an image by a whole number of pixels, so that the pixel at (x, y) ends
+ * up at (x + dx, y + dy). The pixels uncovered by the move are filled 
+ * according to the padding strategy.
+ */
+pub fn translate(
+  image: &PpmImage, dx: i32, dy: i32, padding: Padding
+) -> OperationResult {
+  let mut translated = PpmImage::new(image.width(), image.height());
+
+  for y in 0..image.height() {
+    for x in 0..image.width() {
+      let (r, g, b) = get_padded_pixel(
+        image, x as i32 - dx, y as i32 - dy, padding
+      );
+      translated.set_pixel_by_coord(x, y, &[r as u8, g as u8, b as u8]);
+    }
+  }
+
+  Ok(translated)
+}
+
+/// The part of an image of the given size that still holds its own pixels
+/// after being moved by translate (the rest is padding), or None if the whole
+/// image was moved out of the frame
+pub fn translated_region(
+  width: u32, height: u32, dx: i32, dy: i32
+) -> Option<Selection> {
+  let left = dx.max(0);
+  let top = dy.max(0);
+  let right = (width as i32 + dx).min(width as i32);
+  let bottom = (height as i32 + dy).min(height as i32);
+
+  if right <= left || bottom <= top {
+    return None;
+  }
+
+  Some(Selection::new(
+    left as u32, top as u32, (right - left) as u32, (bottom - top) as u32
+  ))
+}
+
+/**
+ * Measures how well a frame lines up with a reference frame, as the mean 
+ * absolute difference (0..255) of every channel in the region where the two 
+ * frames overlap. Identical frames give zero, and a failed registration shows
+ * up as a large error.
+ * 
+ * The overlap should only cover the pixels that came from both frames (see 
+ * translated_region), since the padding of a moved frame would otherwise be
+ * counted as well. If the frames don't overlap at all, the error is infinite.
+ */
+pub fn alignment_error(
+  reference: &PpmImage, aligned: &PpmImage, overlap: &Selection
+) -> f64 {
+  let width = reference.width().min(aligned.width());
+  let height = reference.height().min(aligned.height());
+
+  let overlap = match overlap.clamp_to(width, height) {
+    Some(overlap) => overlap,
+    None => return f64::INFINITY,
+  };
+
+  let mut total_difference: u64 = 0;
+  for y in overlap.y..(overlap.y + overlap.height) {
+    for x in overlap.x..(overlap.x + overlap.width) {
+      let lhs = reference.get_pixel_by_coord_ref(x, y);
+      let rhs = aligned.get_pixel_by_coord_ref(x, y);
+
+      for ch in COLOR_CHANNELS {
+        // one of the two is always zero, so their sum is the absolute 
+        // difference
+        total_difference += 
+          sub_safe(lhs[ch], rhs[ch]) as u64 + sub_safe(rhs[ch], lhs[ch]) as u64;
+      }
+    }
+  }
+
+  let sample_count = 
+    overlap.width as u64 * overlap.height as u64 * PIXEL_SIZE as u64;
+  total_difference as f64 / sample_count as f64
+}
+
+/* #endregion */
+
 /**
  * Negates an image 
  */
@@ -517,83 +1290,341 @@ pub fn histogram_equalization(
   image: &PpmImage, 
   target_histogram: Option<Histogram>) -> OperationResult {
 
-  // are we equalizing to ourself, or using a given histogram?
-  let equalizing_to_self = None == target_histogram;
-  
-  // this will be the histogram that we actually use
-  let mut histogram = if equalizing_to_self { 
-    Histogram::new()
-  } else { 
-    target_histogram.unwrap()
-  };
+  // equalize to the given histogram, or to the image's own histogram
+  let histogram = target_histogram.unwrap_or_else(
+    || Histogram::from_image(image)
+  );
 
-  let pixel_count = (image.width() * image.height()) as usize;
+  let intensity_eq = histogram.equalize();
 
-  // create a vector to hold the HSV version of the pixels
-  let mut hsv_pixels = Vec::<HSVPixel>::with_capacity(
-    pixel_count
-  );
-  
-  // convert the pixels to HSV so we can do histogram equalization on color
-  // images (even though this implementation kinda sucks)
-  for y in 0..image.height() {
-    for x in 0..image.width() {
-      if let Some(pixel) = image.get_pixel_by_coord(x, y) {
-        // get the pixel as hsv
-        let hsv_pixel = color::rgb_to_hsv(pixel);
-        // put the actual pixel in the right spot
-        hsv_pixels.push(hsv_pixel);  
-        // if we're equalizing to self, then we need to update the histogram
-        // for each pixel as well
-        if equalizing_to_self {
-          histogram.add(&hsv_pixel[V_CH]);
-        }
-      }
+  // equalization happens on the value channel only, so that the colors of 
+  // the image are preserved
+  apply_on_luminance(image, |value| {
+    let key = histogram.to_key(value as f32);
+
+    // use the closest intensity at or below this one, in case the histogram
+    // came from a different image that doesn't have this exact intensity
+    if let Some((_, equalized_value)) = intensity_eq.range(..=key).next_back() {
+      histogram.to_value(*equalized_value).round() as u8
+    } else {
+      value
     }
+  })
+}
+
+/**
+ * Matches the value channel of an image to the given histogram (histogram 
+ * specification), so that it takes on the brightness and contrast of the 
+ * image the histogram came from. Each value is moved to the value that sits 
+ * at the same point of the reference's cumulative distribution, so the 
+ * darkest tenth of the image becomes as bright as the darkest tenth of the 
+ * reference, and so on. Like histogram_equalization, the colors are kept.
+ */
+pub fn histogram_matching(
+  image: &PpmImage, reference: &Histogram
+) -> OperationResult {
+  if 0 == reference.pixel_count {
+    return Err(CoreError::InvalidParameter(
+      "The reference histogram is empty".to_string()
+    ));
   }
 
-  let intensity_eq = histogram.equalize();
+  let source = Histogram::from_image_with_bins(image, reference.bins);
+  let source_cdf = source.cdf();
+  let reference_cdf: Vec<(u32, f32)> = reference.cdf().into_iter().collect();
+
+  apply_on_luminance(image, |value| {
+    let key = source.to_key(value as f32);
+    let fraction = source_cdf.range(..=key)
+      .next_back()
+      .map_or(0., |(_, fraction)| *fraction);
+
+    // the first intensity of the reference that has at least as many pixels
+    // at or below it
+    let index = reference_cdf
+      .partition_point(|(_, reference_fraction)| *reference_fraction < fraction)
+      .min(reference_cdf.len() - 1);
+
+    clamp_to_range(
+      reference.to_value(reference_cdf[index].0 as f32).round(), u8::MAX
+    )
+  })
+}
 
-  let mut equalized_image = PpmImage::new(
-    image.width(), 
-    image.height()
-  );
+/**
+ * Equalizes the red, green, and blue channels of an image independently, 
+ * each one to its own histogram.
+ * 
+ * Unlike histogram_equalization, which only touches the value channel, this 
+ * spreads out every channel and so brings out more detail in images with a 
+ * strong color cast. The tradeoff is that the channels no longer move 
+ * together, so the hues of the image can shift. Equalizing the value channel
+ * keeps the hue and saturation of every pixel.
+ */
+pub fn histogram_equalization_rgb(image: &PpmImage) -> OperationResult {
+  let pixel_count = image.get_data().len() / PIXEL_SIZE;
+
+  if 0 == pixel_count {
+    return Err(CoreError::InvalidDimensions(
+      "Cannot equalize an empty image".to_string()
+    ));
+  }
+
+  let counts = channel_histograms(image);
+
+  // the equalized value of each intensity, scaled to the brightest intensity
+  // in the channel the same way histogram_equalization does
+  let mut lookup = [[0_u8; 256]; PIXEL_SIZE];
+  for ch in COLOR_CHANNELS {
+    let max_intensity = counts[ch].iter().rposition(|count| *count > 0)
+      .unwrap_or(0);
+
+    let mut running_cdf = 0.;
+    for intensity in 0..256 {
+      running_cdf += counts[ch][intensity] as f32 / pixel_count as f32;
+      lookup[ch][intensity] = (running_cdf * max_intensity as f32).round() as u8;
+    }
+  }
+
+  let mut new_image = PpmImage::new(image.width(), image.height());
 
   let mut pixel_index:usize = 0;
-  for hsv_pixel in hsv_pixels {
+  for pixel in image.get_data().chunks_exact(PIXEL_SIZE) {
+    let equalized_pixel = [
+      lookup[R_CH][pixel[R_CH] as usize],
+      lookup[G_CH][pixel[G_CH] as usize],
+      lookup[B_CH][pixel[B_CH] as usize],
+    ];
 
-    let orig_key = Histogram::downsample_float(hsv_pixel[V_CH]);
+    new_image.set_pixel(&mut pixel_index, &equalized_pixel);
+  }
 
-    if let Some(equalized_value) = intensity_eq.get(&orig_key) {
+  Ok(new_image)
+}
 
-      let rgb = color::hsv_to_rgb(
-        hsv_pixel[H_CH], 
-        hsv_pixel[S_CH], 
-        *equalized_value / V_MULT as f32
-      );
+/// Counts every 8 bit intensity of the red, green, and blue channels
+pub fn channel_histograms(image: &PpmImage) -> [[u32; 256]; PIXEL_SIZE] {
+  let mut counts = [[0_u32; 256]; PIXEL_SIZE];
 
-      equalized_image.set_pixel(&mut pixel_index, &rgb);
+  for pixel in image.get_data().chunks_exact(PIXEL_SIZE) {
+    for ch in COLOR_CHANNELS {
+      counts[ch][pixel[ch] as usize] += 1;
     }
   }
 
-  Ok(equalized_image)
+  counts
 }
 
+/// The intensity that the given fraction (0 to 1) of a channel falls at or 
+/// below
+fn channel_percentile(counts: &[u32; 256], percentile: f32) -> u8 {
+  let total: u32 = counts.iter().sum();
+  let target = (total as f32 * percentile.clamp(0., 1.)).ceil().max(1.) as u32;
+
+  let mut running_count = 0;
+  for (intensity, count) in counts.iter().enumerate() {
+    running_count += count;
+    if running_count >= target {
+      return intensity as u8;
+    }
+  }
 
-/* #region Utility Functions */
+  u8::MAX
+}
+
+/**
+ * Removes a color cast by scaling each channel so that the given percentile of
+ * its histogram lands on the same intensity. This is the gray world 
+ * assumption, but made with the percentiles of the channels rather than their
+ * means, which holds up better in scenes that are dominated by one color.
+ *
+ * The channels are lined up on the average of their percentiles, so the 
+ * overall brightness of the image stays about the same. A channel whose
+ * percentile is black is left alone, since it can't be scaled up.
+ */
+pub fn auto_white_balance_at(
+  image: &PpmImage, 
+  percentile: f32
+) -> OperationResult {
+  if image.get_data().is_empty() {
+    return Err(CoreError::InvalidDimensions(
+      "Cannot white balance an empty image".to_string()
+    ));
+  }
 
+  let counts = channel_histograms(image);
+  let levels = COLOR_CHANNELS.map(|ch| {
+    channel_percentile(&counts[ch], percentile) as f32
+  });
+  let target = levels.iter().sum::<f32>() / PIXEL_SIZE as f32;
+
+  let gains = levels.map(|level| {
+    if 0. == level { 1. } else { target / level }
+  });
+
+  let mut new_image = PpmImage::new(image.width(), image.height());
+
+  let mut pixel_index:usize = 0;
+  for pixel in image.get_data().chunks_exact(PIXEL_SIZE) {
+    let balanced_pixel = COLOR_CHANNELS.map(|ch| {
+      clamp_to_range((pixel[ch] as f32 * gains[ch]).round(), u8::MAX)
+    });
+
+    new_image.set_pixel(&mut pixel_index, &balanced_pixel);
+  }
+
+  Ok(new_image)
+}
+
+/// Removes a color cast by lining up the medians of the channels
+pub fn auto_white_balance(image: &PpmImage) -> OperationResult {
+  auto_white_balance_at(image, WHITE_BALANCE_PERCENTILE)
+}
 
 /**
- * Special clamp function for color values between 0 and 255
+ * Converts an image to grayscale using the Rec.709 weights. The sRGB mode 
+ * weights the stored values as they are, while the linear light mode decodes
+ * them first, which is slower but gives the actual luminance (and so is the
+ * one to use when measuring brightness).
  */
-fn clamp_color(num: u32) -> u8 {
-  if num > u8::MAX as u32 {
-    return u8::MAX
+pub fn to_grayscale(image: &PpmImage, mode: GrayscaleMode) -> OperationResult {
+  let mut new_image = PpmImage::new(image.width(), image.height());
+
+  let mut pixel_index:usize = 0;
+  for bytes in image.get_data().chunks_exact(PIXEL_SIZE) {
+    let pixel = [bytes[R_CH], bytes[G_CH], bytes[B_CH]];
+    let gray = match mode {
+      GrayscaleMode::Srgb => color::srgb_luma(pixel),
+      GrayscaleMode::LinearLight => color::linear_luminance(pixel),
+    };
+
+    new_image.set_pixel(&mut pixel_index, &[gray; PIXEL_SIZE]);
   }
-  
-  num as u8
+
+  Ok(new_image)
 }
 
+/**
+ * Replaces every pixel within the tolerance of the target color with the 
+ * replacement color, wherever it is in the image (unlike a flood fill, which
+ * only reaches the pixels connected to where it starts). The tolerance is 
+ * measured by the given metric, the same way as it is for CCL: 0 only 
+ * replaces the target color itself, and 1 is about as far as black is from 
+ * white.
+ */
+pub fn replace_color(
+  image: &PpmImage,
+  target: PixelBytes<u8>,
+  replacement: PixelBytes<u8>,
+  tolerance: f32,
+  metric: ColorMetric
+) -> OperationResult {
+  if tolerance.is_nan() || tolerance < 0. {
+    return Err(CoreError::InvalidParameter(
+      format!("A tolerance of {} is not valid, it can't be negative", tolerance)
+    ));
+  }
+
+  let mut new_image = PpmImage::new(image.width(), image.height());
+
+  let mut pixel_index:usize = 0;
+  for bytes in image.get_data().chunks_exact(PIXEL_SIZE) {
+    let pixel = [bytes[R_CH], bytes[G_CH], bytes[B_CH]];
+    let is_match = pixel == target || 
+      (tolerance > 0. && metric.distance(pixel, target) <= tolerance);
+
+    new_image.set_pixel(
+      &mut pixel_index, if is_match { &replacement } else { &pixel }
+    );
+  }
+
+  Ok(new_image)
+}
+
+/**
+ * Adjusts the saturation and value of only the pixels whose hue is within 
+ * hue_width degrees of center_hue (on either side, wrapping around at 360), 
+ * like the "adjust only the blues" tools of other editors. The deltas are 
+ * added to the saturation and value, which both go from 0 to 1. Grays have 
+ * no hue, and so are never adjusted.
+ */
+pub fn adjust_color_range(
+  image: &PpmImage,
+  center_hue: f32,
+  hue_width: f32,
+  saturation_delta: f32,
+  value_delta: f32
+) -> OperationResult {
+  if !center_hue.is_finite() || !hue_width.is_finite() || hue_width < 0. {
+    return Err(CoreError::InvalidParameter(format!(
+      "A hue range of {} +/- {} degrees is not valid", center_hue, hue_width
+    )));
+  }
+
+  let mut new_image = PpmImage::new(image.width(), image.height());
+
+  let mut pixel_index:usize = 0;
+  for bytes in image.get_data().chunks_exact(PIXEL_SIZE) {
+    let pixel = [bytes[R_CH], bytes[G_CH], bytes[B_CH]];
+    let hsv_pixel = color::rgb_to_hsv(pixel);
+
+    // the shortest way around the color wheel between the two hues
+    let hue_distance = (hsv_pixel[H_CH] - center_hue).rem_euclid(360.);
+    let hue_distance = hue_distance.min(360. - hue_distance);
+
+    // pixels outside of the range are copied as they are, rather than going
+    // through HSV and back
+    if hsv_pixel[S_CH] <= 0. || hue_distance > hue_width {
+      new_image.set_pixel(&mut pixel_index, &pixel);
+      continue;
+    }
+
+    let adjusted = color::hsv_to_rgb(
+      hsv_pixel[H_CH],
+      (hsv_pixel[S_CH] + saturation_delta).clamp(0., 1.),
+      (hsv_pixel[V_CH] / u8::MAX as f32 + value_delta).clamp(0., 1.)
+    );
+    new_image.set_pixel(&mut pixel_index, &adjusted);
+  }
+
+  Ok(new_image)
+}
+
+/**
+ * Applies the given function to the value channel of every pixel (in HSV 
+ * space), leaving hue and saturation untouched. This turns any point operation
+ * into one that preserves the colors of the image.
+ */
+pub fn apply_on_luminance<F: Fn(u8) -> u8>(
+  image: &PpmImage,
+  f: F) -> OperationResult {
+
+  let mut new_image = PpmImage::new(image.width(), image.height());
+
+  let mut pixel_index:usize = 0;
+  for bytes in image.get_data().chunks_exact(PIXEL_SIZE) {
+    let hsv_pixel = color::rgb_to_hsv([bytes[R_CH], bytes[G_CH], bytes[B_CH]]);
+
+    // the value channel of an 8 bit pixel is in 0 - 255, but hsv_to_rgb
+    // expects it to be in 0 - 1
+    let new_value = f(hsv_pixel[V_CH].round() as u8);
+
+    let rgb = color::hsv_to_rgb(
+      hsv_pixel[H_CH], 
+      hsv_pixel[S_CH], 
+      new_value as f32 / u8::MAX as f32
+    );
+
+    new_image.set_pixel(&mut pixel_index, &rgb);
+  }
+
+  Ok(new_image)
+}
+
+
+/* #region Utility Functions */
+
+
 // Gets the dimensions that are between the two given dimensions
 const fn friendly_scale_match(
   w1: u32, h1: u32, 
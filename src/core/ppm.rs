@@ -1,20 +1,176 @@
-use std::{fmt, collections::{BTreeMap, HashMap}};
+use std::{fmt, collections::{BTreeMap, HashMap}, ops::Deref, sync::Arc};
 
-use crate::core::{PixelBytes, PIXEL_SIZE, max, min};
+use memmap2::Mmap;
+
+use crate::core::{PixelBytes, PIXEL_SIZE, min};
 
 use super::color::BLACK;
+use super::error::CoreError;
 
-#[derive(PartialEq, Clone, Copy)]
+/// How the pixels past the edges of an image are filled in, for operations 
+/// (like convolution) that need to look outside of the image
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Padding {
+  /// Everything outside of the image is black
   Zero,
-  Repeat
+  /// The edge pixels are repeated outwards (clamping)
+  Repeat,
+  /// The image is reflected about its edge pixels, so that -1 becomes 1
+  Mirror,
+  /// The image is tiled, so that -1 becomes the last pixel of the row
+  Wrap,
+}
+
+impl Padding {
+  /// Maps a coordinate along an axis of the given length into the image. Zero
+  /// padding has no pixel to map coordinates outside of the image to, so 
+  /// those come back as None.
+  pub fn resolve(&self, coord: i32, length: u32) -> Option<u32> {
+    let length = length as i32;
+
+    if 0 == length {
+      return None;
+    }
+
+    if coord >= 0 && coord < length {
+      return Some(coord as u32);
+    }
+
+    let resolved = match self {
+      Padding::Zero => return None,
+      Padding::Repeat => coord.clamp(0, length - 1),
+      Padding::Mirror => {
+        // a single pixel reflects onto itself
+        if 1 == length {
+          0
+        } else {
+          let period = 2 * (length - 1);
+          let folded = coord.rem_euclid(period);
+          if folded < length { folded } else { period - folded }
+        }
+      },
+      Padding::Wrap => coord.rem_euclid(length),
+    };
+
+    Some(resolved as u32)
+  }
+}
+
+/* #region Selection         */
+
+/// A rectangular region of an image. The coordinate system has (0, 0) in the
+/// upper left hand of the image, the same as the pixel accessors on PpmImage
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Selection {
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+}
+
+impl Selection {
+  pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+    Selection {
+      x: x,
+      y: y,
+      width: width,
+      height: height,
+    }
+  }
+
+  /// Creates a selection spanning the two given corners (inclusive). The
+  /// corners can be given in any order, which is handy when the selection is
+  /// being dragged out with the mouse
+  pub fn from_corners(x1: u32, y1: u32, x2: u32, y2: u32) -> Self {
+    Selection {
+      x: min(x1, x2),
+      y: min(y1, y2),
+      width: x1.abs_diff(x2) + 1,
+      height: y1.abs_diff(y2) + 1,
+    }
+  }
+
+  /// Returns the part of this selection that lies inside an image of the given
+  /// dimensions, or None if the two do not overlap at all
+  pub fn clamp_to(&self, width: u32, height: u32) -> Option<Selection> {
+    if self.x >= width || self.y >= height ||
+       0 == self.width || 0 == self.height {
+      return None
+    }
+
+    Some(Selection {
+      x: self.x,
+      y: self.y,
+      width: min(self.width, width - self.x),
+      height: min(self.height, height - self.y),
+    })
+  }
+
+  /// Returns the part that this selection has in common with the other, or
+  /// None if the two do not overlap at all
+  pub fn intersect(&self, other: &Selection) -> Option<Selection> {
+    let x = self.x.max(other.x);
+    let y = self.y.max(other.y);
+    let right = min(self.x + self.width, other.x + other.width);
+    let bottom = min(self.y + self.height, other.y + other.height);
+
+    if right <= x || bottom <= y {
+      return None
+    }
+
+    Some(Selection::new(x, y, right - x, bottom - y))
+  }
 }
 
+/* #endregion */
+
+/* #region Pixel storage     */
+
+/// The bytes backing the pixels of a PpmImage. Pixels are normally owned, but
+/// a large binary PPM can instead borrow them from a memory mapped file. A 
+/// mapped store is copied into an owned one the first time it is written to.
+#[derive(Debug, Clone)]
+pub enum PixelStore {
+  Owned(Vec<u8>),
+  Mapped { map: Arc<Mmap>, offset: usize, len: usize },
+}
+
+impl PixelStore {
+  pub fn is_mapped(&self) -> bool {
+    matches!(self, PixelStore::Mapped { .. })
+  }
+
+  /// Gets the pixels for writing, copying them out of the mapping if needed
+  fn to_mut(&mut self) -> &mut Vec<u8> {
+    if let PixelStore::Mapped { map, offset, len } = self {
+      *self = PixelStore::Owned(map[*offset..(*offset + *len)].to_vec());
+    }
+
+    match self {
+      PixelStore::Owned(pixels) => pixels,
+      PixelStore::Mapped { .. } => unreachable!(),
+    }
+  }
+}
+
+impl Deref for PixelStore {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    match self {
+      PixelStore::Owned(pixels) => pixels,
+      PixelStore::Mapped { map, offset, len } => &map[*offset..(*offset + *len)],
+    }
+  }
+}
+
+/* #endregion */
+
 /* #region PPM object        */
 #[derive(Debug, Clone)]
 pub struct PpmImage {
     header: PpmHeader,
-    pixels: Vec<u8>,
+    pixels: PixelStore,
     histogram: HashMap<PixelBytes<u8>, usize>,
     rgb_components_used: BTreeMap<u8, usize>,
     pub keep_histogram_updated: bool,
@@ -32,7 +188,7 @@ impl PpmImage {
 
     PpmImage {
         header: PpmHeader::new(width, height),
-        pixels: vec![0;capacity],
+        pixels: PixelStore::Owned(vec![0;capacity]),
         histogram: histogram,
         rgb_components_used: BTreeMap::new(),
         keep_histogram_updated: false,
@@ -49,6 +205,48 @@ impl PpmImage {
     &self.pixels
   }
 
+  /**
+   * Creates an image whose pixels are borrowed from a memory mapped file 
+   * rather than copied into memory. The mapping must hold width * height 
+   * RGB pixels starting at offset.
+   */
+  pub fn from_mapped(
+    header: PpmHeader, 
+    map: Arc<Mmap>, 
+    offset: usize
+  ) -> Result<Self, CoreError> {
+    let len = PIXEL_SIZE * (header.width * header.height) as usize;
+
+    if map.len() < offset + len {
+      return Err(CoreError::Decode(format!(
+        "Expected {} bytes of pixel data, but the file only has {}", 
+        len, map.len().saturating_sub(offset)
+      )));
+    }
+
+    let mut image = PpmImage {
+      header: header,
+      pixels: PixelStore::Mapped { map: map, offset: offset, len: len },
+      histogram: HashMap::new(),
+      rgb_components_used: BTreeMap::new(),
+      keep_histogram_updated: false,
+    };
+
+    // the histogram is normally built up by set_pixel, which a mapped image
+    // never goes through
+    for index in (0..len).step_by(PIXEL_SIZE) {
+      let pixel = image.get_bytes_at(index);
+      image.add_to_hist(&pixel);
+    }
+
+    Ok(image)
+  }
+
+  /// Whether the pixels are still borrowed from a memory mapped file
+  pub fn is_mapped(&self) -> bool {
+    self.pixels.is_mapped()
+  }
+
   /* #region Header Accessors / Modifier functions */
 
   pub const fn height(&self) -> u32 {
@@ -148,8 +346,9 @@ impl PpmImage {
 
     self.remove_from_hist(&removed_pixel);
 
+    let pixels = self.pixels.to_mut();
     for ch in R_CH..(B_CH + 1) {
-      self.pixels[*index + ch] = pixel[ch];
+      pixels[*index + ch] = pixel[ch];
     }
 
     self.add_to_hist(pixel);
@@ -163,6 +362,38 @@ impl PpmImage {
     self.set_pixel(&mut index, pixel);
   }
 
+  /**
+   * Negates the image, overwriting the pixels rather than allocating a second
+   * image like operations::negate does. This is for when the original isn't
+   * needed afterwards, as there is nothing left to undo to.
+   *
+   * Like operations::negate, every value is flipped around the brightest
+   * value in use.
+   */
+  pub fn negate_in_place(&mut self) {
+    let max_value = self.max_value();
+
+    for sample in self.pixels.to_mut().iter_mut() {
+      *sample = max_value - *sample;
+    }
+
+    // negating just swaps the values around, so the histogram is remapped
+    // rather than rebuilt
+    self.histogram = self.histogram.drain()
+      .map(|(pixel, count)| (pixel.map(|ch| max_value - ch), count))
+      .collect();
+
+    // the values in use are counted again from the histogram, so that the
+    // brightest value is never less than a value in the image
+    self.rgb_components_used.clear();
+    for (pixel, count) in &self.histogram {
+      for value in pixel {
+        *self.rgb_components_used.entry(*value).or_insert(0) += count;
+      }
+    }
+    self.header.max_value = max_value as u16;
+  }
+
   /* #endregion */
 
   /* #region Getting Pixels */
@@ -172,39 +403,7 @@ impl PpmImage {
   ) -> Vec<&[u8]> {
     assert!(size % 2 != 0); // size must be odd for it to be centered on (x, y)
 
-    let start_delta = -1 * (size as i32 - 1) / 2;
-
-    let start_x = x as i32 + start_delta;
-    let start_y = y as i32 + start_delta;
-
-    let mut matrix = Vec::<&[u8]>::with_capacity(size * size);
-
-    for x in start_x..(start_x + size as i32) {
-      for y in start_y..(start_y + size as i32) {
-        // if we are using repeat padding
-        if padding == Padding::Repeat {
-          // if x or why is below 0, then set it to zero
-          let mut x_adj = max(x, 0);
-          let mut y_adj = max(y, 0);
-          
-          // if x or y is greater than the highest possible, then set it to 
-          // the highest possible
-          x_adj = min(x_adj, self.width() as i32 - 1);
-          y_adj = min(y_adj, self.height() as i32 - 1);
-          
-          matrix.push(self.get_pixel_by_coord_ref(x_adj as u32, y_adj as u32));
-        } else if padding == Padding::Zero {
-          if x < 0 || y < 0 ||
-             x as u32 >= self.width() || y as u32 >= self.height() {
-            matrix.push(&[0, 0, 0]); 
-          } else {
-            matrix.push(self.get_pixel_by_coord_ref(x as u32, y as u32));
-          }
-        }
-      }
-    }
-
-    return matrix
+    neighborhood(self, x, y, (size / 2) as u32, padding).collect()
   }
 
   /// This gets a pixel, where index points to the "r" byte in the array
@@ -238,6 +437,139 @@ impl PpmImage {
     return &self.pixels[index..(index + PIXEL_SIZE)];
   }
 
+  /// Copies the pixels inside the given selection into a new image. The
+  /// selection is clamped to the bounds of this image first.
+  pub fn crop(&self, selection: &Selection) -> Option<PpmImage> {
+    let region = selection.clamp_to(self.width(), self.height())?;
+
+    let mut cropped = PpmImage::new(region.width, region.height);
+
+    let mut pixel_index:usize = 0;
+    for y in region.y..(region.y + region.height) {
+      for x in region.x..(region.x + region.width) {
+        cropped.set_pixel(
+          &mut pixel_index,
+          self.get_pixel_by_coord_ref(x, y)
+        );
+      }
+    }
+
+    Some(cropped)
+  }
+
+  /* #endregion */
+
+  /* #region Drawing */
+
+  /// Sets a pixel if it lies inside of the image, and ignores it otherwise, so
+  /// that shapes can be drawn partially off of the image
+  fn plot(&mut self, x: i32, y: i32, color: &PixelBytes<u8>) {
+    if x >= 0 && y >= 0 && (x as u32) < self.width() && (y as u32) < self.height() {
+      self.set_pixel_by_coord(x as u32, y as u32, color);
+    }
+  }
+
+  /// Draws a one pixel wide line from a to b (inclusive) using Bresenham's
+  /// line algorithm
+  pub fn draw_line(&mut self, a: (i32, i32), b: (i32, i32), color: &PixelBytes<u8>) {
+    let (mut x, mut y) = a;
+    let dx = (b.0 - a.0).abs();
+    let dy = -(b.1 - a.1).abs();
+    let step_x = if a.0 < b.0 { 1 } else { -1 };
+    let step_y = if a.1 < b.1 { 1 } else { -1 };
+    let mut error = dx + dy;
+
+    loop {
+      self.plot(x, y, color);
+
+      if x == b.0 && y == b.1 {
+        break;
+      }
+
+      let doubled_error = 2 * error;
+      if doubled_error >= dy {
+        error += dy;
+        x += step_x;
+      }
+      if doubled_error <= dx {
+        error += dx;
+        y += step_y;
+      }
+    }
+  }
+
+  /// Draws the one pixel wide outline of the given rectangle
+  pub fn draw_rect(&mut self, rect: &Selection, color: &PixelBytes<u8>) {
+    if 0 == rect.width || 0 == rect.height {
+      return;
+    }
+
+    let left = rect.x as i32;
+    let top = rect.y as i32;
+    let right = left + rect.width as i32 - 1;
+    let bottom = top + rect.height as i32 - 1;
+
+    self.draw_line((left, top), (right, top), color);
+    self.draw_line((left, bottom), (right, bottom), color);
+    self.draw_line((left, top), (left, bottom), color);
+    self.draw_line((right, top), (right, bottom), color);
+  }
+
+  /// Draws the one pixel wide outline of a circle using the midpoint circle
+  /// algorithm
+  pub fn draw_circle(&mut self, center: (i32, i32), radius: u32, color: &PixelBytes<u8>) {
+    let (cx, cy) = center;
+    let mut x = radius as i32;
+    let mut y = 0;
+    let mut error = 1 - x;
+
+    while x >= y {
+      // each step of the first octant is mirrored into the other seven
+      for (px, py) in [
+        (x, y), (y, x), (-y, x), (-x, y), 
+        (-x, -y), (-y, -x), (y, -x), (x, -y)
+      ] {
+        self.plot(cx + px, cy + py, color);
+      }
+
+      y += 1;
+      if error < 0 {
+        error += 2 * y + 1;
+      } else {
+        x -= 1;
+        error += 2 * (y - x) + 1;
+      }
+    }
+  }
+
+  /// Burns text into the image using the small built in bitmap font, with
+  /// (x, y) being the top left corner of the first character. Newlines start
+  /// a new line of text below the first.
+  pub fn draw_text(&mut self, text: &str, x: i32, y: i32, color: &PixelBytes<u8>) {
+    use crate::core::font::{glyph, GLYPH_WIDTH, GLYPH_HEIGHT, GLYPH_SPACING};
+
+    let mut cursor_x = x;
+    let mut cursor_y = y;
+
+    for c in text.chars() {
+      if '\n' == c {
+        cursor_x = x;
+        cursor_y += (GLYPH_HEIGHT + GLYPH_SPACING) as i32;
+        continue;
+      }
+
+      for (row, bits) in glyph(c).iter().enumerate() {
+        for column in 0..GLYPH_WIDTH {
+          if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+            self.plot(cursor_x + column as i32, cursor_y + row as i32, color);
+          }
+        }
+      }
+
+      cursor_x += (GLYPH_WIDTH + GLYPH_SPACING) as i32;
+    }
+  }
+
   /* #endregion */
 
   /**
@@ -252,14 +584,19 @@ impl PpmImage {
     header.height = height;
     header.width = width;
 
-    let max_value = max(max(r, g), b) as u16;
+    let max_value = r.max(g).max(b) as u16;
 
     header.max_value = max_value;
     
+    // every pixel starts out black, and is swapped out of the histogram as it
+    // gets set
+    let mut histogram = HashMap::<PixelBytes<u8>, usize>::new();
+    histogram.insert(BLACK, pixel_count);
+
     let mut single_color_image = PpmImage {
       header: header,
-      pixels: vec![0;pixel_count * PIXEL_SIZE],
-      histogram: HashMap::new(),
+      pixels: PixelStore::Owned(vec![0;pixel_count * PIXEL_SIZE]),
+      histogram: histogram,
       rgb_components_used: BTreeMap::new(),
       keep_histogram_updated: false
     };
@@ -381,6 +718,49 @@ impl fmt::Display for PpmType {
 
 /* #endregion */
 
+/* #region Neighborhoods     */
+
+/**
+ * The pixels of the square window that reaches radius pixels out from (x, y)
+ * on every side, column by column (the order that the filter kernels are laid
+ * out in). The padding decides which pixel stands in for anything outside of
+ * the image, and zero padding has none, so those are black.
+ */
+pub fn neighborhood<'a>(
+  image: &'a PpmImage, x: u32, y: u32, radius: u32, padding: Padding
+) -> impl Iterator<Item = &'a [u8]> + 'a {
+  neighborhood_coords(x, y, radius, image.width(), image.height(), padding)
+    .map(move |(_, position)| match position {
+      Some((nx, ny)) => image.get_pixel_by_coord_ref(nx, ny),
+      None => &[0, 0, 0],
+    })
+}
+
+/// The offset from (x, y) and the position in the image of each pixel in the
+/// window, in the same order as neighborhood. The position is None when zero
+/// padding leaves the pixel outside of the image.
+pub fn neighborhood_coords(
+  x: u32, y: u32, radius: u32, width: u32, height: u32, padding: Padding
+) -> impl Iterator<Item = ((i32, i32), Option<(u32, u32)>)> {
+  let radius = radius as i32;
+
+  (-radius..=radius).flat_map(move |dx| {
+    (-radius..=radius).map(move |dy| {
+      let position = match (
+        padding.resolve(x as i32 + dx, width),
+        padding.resolve(y as i32 + dy, height)
+      ) {
+        (Some(nx), Some(ny)) => Some((nx, ny)),
+        _ => None,
+      };
+
+      ((dx, dy), position)
+    })
+  })
+}
+
+/* #endregion */
+
 /* #region Utility Functions */
 
 /**
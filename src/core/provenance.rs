@@ -0,0 +1,84 @@
+use crate::core::operations::Operation;
+
+/* #region OperationLog */
+
+/// How one version of an image came about
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+  /// The image was opened from the named file
+  Opened(String),
+  /// An operation was applied to the previous version
+  Operation(Operation),
+  /// The image was swapped for its proxy, or back to the full resolution 
+  /// image, which doesn't change what it shows
+  Proxy,
+  /// The image was changed in a way that isn't an operation (such as an
+  /// equalization with a custom histogram, or a command chain)
+  Other,
+}
+
+/**
+ * A record of how the current image was made, kept in step with the undo
+ * stack: one edit for every version of the image. This is written into
+ * exported files, so that anyone with the file can see what was done to it.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperationLog {
+  edits: Vec<Edit>,
+}
+
+impl OperationLog {
+  pub fn new() -> Self {
+    OperationLog {
+      edits: Vec::<Edit>::new(),
+    }
+  }
+
+  pub fn record(&mut self, edit: Edit) {
+    self.edits.push(edit);
+  }
+
+  /// Replaces the last edit with a more specific one, for when the image was
+  /// set before it was known how it was made
+  pub fn amend(&mut self, edit: Edit) {
+    match self.edits.last_mut() {
+      Some(last) => *last = edit,
+      None => self.edits.push(edit),
+    }
+  }
+
+  /// Forgets the last edit, when it is undone
+  pub fn undo(&mut self) {
+    self.edits.pop();
+  }
+
+  /// The edits made since the current image was last opened
+  pub fn since_opened(&self) -> &[Edit] {
+    let start = self.edits.iter()
+      .rposition(|edit| matches!(edit, Edit::Opened(_)))
+      .unwrap_or(0);
+
+    &self.edits[start..]
+  }
+
+  /// The edits since the image was opened, one per line, to be written into
+  /// the comments of an exported file
+  pub fn comments(&self) -> Vec<String> {
+    let mut step = 0;
+
+    self.since_opened().iter().filter_map(|edit| match edit {
+      Edit::Opened(name) => Some(format!("Opened from {}", name)),
+      Edit::Proxy => None,
+      Edit::Operation(operation) => {
+        step += 1;
+        Some(format!("Step {}: {}", step, operation))
+      },
+      Edit::Other => {
+        step += 1;
+        Some(format!("Step {}: an edit that wasn't recorded", step))
+      },
+    }).collect()
+  }
+}
+
+/* #endregion */
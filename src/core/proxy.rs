@@ -0,0 +1,99 @@
+use crate::core::ppm::PpmImage;
+use crate::core::operations::{area_average, Operation, OperationResult};
+use crate::core::error::CoreError;
+
+/// Images with more pixels than this are edited through a proxy
+pub const PROXY_MAX_PIXELS: u32 = 2_000_000;
+
+/* #region ProxyEdit */
+
+/**
+ * Edits a large image through a smaller copy of it (the proxy). Operations are
+ * applied to the proxy as they are made, which keeps the viewer responsive,
+ * and are recorded so that they can be replayed on the full resolution image
+ * when it is exported.
+ *
+ * Operations are replayed with the same parameters, so filters with a pixel
+ * sized kernel look a little stronger on the proxy than on the full image.
+ */
+pub struct ProxyEdit {
+  full_image: PpmImage,
+  // the edits made to the proxy, in order. None is an edit that isn't an
+  // operation (such as an equalization with a custom histogram), and so
+  // can't be replayed.
+  operations: Vec<Option<Operation>>,
+}
+
+impl ProxyEdit {
+  pub fn new(full_image: PpmImage) -> Self {
+    ProxyEdit {
+      full_image: full_image,
+      operations: Vec::<Option<Operation>>::new(),
+    }
+  }
+
+  /// Whether an image is large enough to be worth editing through a proxy
+  pub fn is_needed(image: &PpmImage, max_pixels: u32) -> bool {
+    image.width() as u64 * image.height() as u64 > max_pixels as u64
+  }
+
+  /// Creates the proxy, shrinking the full image down to about max_pixels
+  pub fn create_proxy(&self, max_pixels: u32) -> OperationResult {
+    let image = &self.full_image;
+    if !ProxyEdit::is_needed(image, max_pixels) {
+      return Ok(image.clone());
+    }
+
+    let pixel_count = image.width() as f64 * image.height() as f64;
+    let scale = (max_pixels as f64 / pixel_count).sqrt();
+
+    Ok(area_average(
+      image,
+      ((image.width() as f64 * scale) as u32).max(1),
+      ((image.height() as f64 * scale) as u32).max(1)
+    ))
+  }
+
+  pub fn full_image(&self) -> &PpmImage {
+    &self.full_image
+  }
+
+  pub fn operations(&self) -> &[Option<Operation>] {
+    &self.operations
+  }
+
+  /// Records an operation that was applied to the proxy
+  pub fn record(&mut self, operation: Operation) {
+    self.operations.push(Some(operation));
+  }
+
+  /// Records an edit to the proxy that can't be replayed
+  pub fn record_unreplayable(&mut self) {
+    self.operations.push(None);
+  }
+
+  /// Forgets the last edit. Returns false if there was nothing to forget,
+  /// which means that the proxy itself is being undone.
+  pub fn undo(&mut self) -> bool {
+    self.operations.pop().is_some()
+  }
+
+  /// Replays every edit on the full resolution image
+  pub fn render_full(&self) -> OperationResult {
+    let mut image = self.full_image.clone();
+
+    for operation in self.operations.iter() {
+      image = match operation {
+        Some(operation) => operation.apply(&image)?,
+        None => return Err(CoreError::InvalidParameter(
+          "The proxy has edits that can't be replayed on the full image"
+            .to_string()
+        )),
+      };
+    }
+
+    Ok(image)
+  }
+}
+
+/* #endregion */
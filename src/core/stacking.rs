@@ -1,13 +1,22 @@
 
 
 use std::{collections::HashMap, rc::Rc};
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
 
 use image::{DynamicImage, ImageBuffer, Rgb};
 use palette::encoding::pixel;
 
+use rayon::prelude::*;
+
 use super::{PIXEL_SIZE, R_CH, G_CH, B_CH, color::{self}, V_CH, min, max, io::read_raw};
+use super::io::{dynamic_to_ppm, write_tiff_16};
+use super::operations::{alignment_error, translate, translated_region};
+use super::ppm::{PpmImage, Padding, Selection};
+use super::threads;
+use super::stats::RunningStats;
+use super::error::CoreError;
 
-pub type StackingFunction = dyn Fn(&Vec<Rgb<u16>>) -> Rgb<u16>;
+pub type StackingFunction = dyn Fn(&Vec<Rgb<u16>>) -> Rgb<u16> + Send + Sync;
 
 pub type ChunkBuffer = ImageBuffer<Rgb<u16>, Vec<u16>>;
 /// This stores the percentage of system memory that is allowed to be allocated
@@ -27,7 +36,54 @@ pub enum ClippingStrategy {
   ReplaceWithMedian
 }
 
-pub trait StackOperation {
+/// How the Maximum stacker decides which pixel is the brightest
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BrightnessMetric {
+  /// The HSV value (the largest channel), so a saturated color counts as 
+  /// bright as white
+  Value,
+  /// The Rec.709 weighted sum of the channels, which is closer to how bright
+  /// a pixel looks
+  Luminance,
+  /// Each channel takes its own maximum, so the result can mix channels from 
+  /// different frames (as a lighten blend does)
+  PerChannel,
+}
+
+/// Determines what is done to each master frame after stacking, before it is
+/// written to disk
+#[derive(Clone, Copy, PartialEq)]
+pub enum StackNormalization {
+  /// The master frame is written exactly as the stacking algorithm produced it
+  Raw,
+  /// The master frame is linearly stretched so that it fills the whole 16-bit
+  /// range, which makes masters from different algorithms comparable
+  Stretch,
+}
+
+/// Settings for drizzle integration, which spreads every input pixel over a
+/// finer output grid so that dithered frames can recover some resolution
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DrizzleSettings {
+  /// How many output pixels there are for each input pixel, on each axis
+  pub scale: u32,
+  /// The side length of each input pixel's "drop", as a fraction of the input
+  /// pixel (1.0 keeps the drop the size of the pixel)
+  pub pixel_fraction: f32,
+}
+
+/// How a call to process_stack finished
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StackStatus {
+  /// Every chunk was stacked and the master frames were written (a master 
+  /// that couldn't be written is an error instead)
+  Completed,
+  /// The stack was cancelled after the given number of chunks, and no master
+  /// frames were written
+  Cancelled { chunks_processed: usize, chunk_count: usize },
+}
+
+pub trait StackOperation: Send + Sync {
   fn get_function(&self) -> &Box<StackingFunction>;
 }
 
@@ -35,7 +91,18 @@ pub struct ImageStack {
   pub stacking_fns: Vec<(Box<dyn StackOperation>, String)>,
   width: u32,
   height: u32,
-  pub data: Vec<String>
+  pub data: Vec<String>,
+  /// The most memory (in bytes) that the chunks of the stack may use at once.
+  /// If this is None, STACKING_MEMORY_USAGE of the system memory is used.
+  pub memory_limit: Option<u64>,
+  /// The sub-pixel (x, y) offset of each image in data, which moves it onto
+  /// the reference frame
+  pub offsets: Vec<(f32, f32)>,
+  pub normalization: StackNormalization,
+  /// How many pixels of the neighboring chunks are read in around each chunk,
+  /// so that anything looking at the neighbors of a pixel (like a filter)
+  /// doesn't leave seams at the chunk edges
+  pub halo: u32,
 }
 
 impl ImageStack {
@@ -49,11 +116,76 @@ impl ImageStack {
       stacking_fns: Vec::new(),
       width: 0,
       height: 0,
-      data: Vec::new()
+      data: Vec::new(),
+      memory_limit: None,
+      offsets: Vec::new(),
+      normalization: StackNormalization::Raw,
+      halo: 0,
     }
   }
 
-  pub fn process_stack(&self) {
+  pub fn set_normalization(&mut self, normalization: StackNormalization) {
+    self.normalization = normalization;
+  }
+
+  /// Stacks the images chunk by chunk, and writes a master frame for each of
+  /// the stacking algorithms (as a 16 bit TIFF). Setting the cancel flag stops
+  /// the stack once the chunk currently being processed is done.
+  /// 
+  /// A master that can't be written doesn't stop the others from being 
+  /// written, but the stack returns an error listing every one that failed.
+  pub fn process_stack(
+    &self, cancel: &Arc<AtomicBool>
+  ) -> Result<StackStatus, CoreError> {
+    let master_frames = match self.stack_master_frames(cancel) {
+      Ok(master_frames) => master_frames,
+      Err(status) => return Ok(status),
+    };
+
+    let failures: Vec<String> = master_frames.iter()
+      .zip(&self.stacking_fns)
+      .filter_map(|(frame, (_, filename))| {
+        write_tiff_16(frame, filename).err().map(|why| why.to_string())
+      })
+      .collect();
+
+    if !failures.is_empty() {
+      return Err(CoreError::Io(format!(
+        "{} of {} master frames could not be saved\n{}",
+        failures.len(), master_frames.len(), failures.join("\n")
+      )));
+    }
+
+    Ok(StackStatus::Completed)
+  }
+
+  /**
+   * Stacks the images like process_stack, but returns the master frames 
+   * instead of writing them, so that the results of different algorithms can
+   * be compared side by side. The masters are keyed by the name each 
+   * algorithm was added with, and are converted down to 8 bits for display.
+   * 
+   * If the stack is cancelled, the status is returned instead.
+   */
+  pub fn process_all_and_label(
+    &self, cancel: &Arc<AtomicBool>
+  ) -> Result<HashMap<String, PpmImage>, StackStatus> {
+    let master_frames = self.stack_master_frames(cancel)?;
+
+    Ok(master_frames.into_iter()
+      .zip(&self.stacking_fns)
+      .map(|(frame, (_, name))| {
+        (name.clone(), dynamic_to_ppm(&DynamicImage::ImageRgb16(frame)))
+      })
+      .collect())
+  }
+
+  /// Runs every stacking algorithm over the stack, and returns their master
+  /// frames in the order the algorithms were added (normalized if that is
+  /// turned on). A cancelled stack returns its status as the error.
+  fn stack_master_frames(
+    &self, cancel: &Arc<AtomicBool>
+  ) -> Result<Vec<ChunkBuffer>, StackStatus> {
 
     if 0 == self.width() || 0 == self.height() {
       panic!("The stack does not have an image size set (perhaps images were not read properly)");
@@ -64,10 +196,16 @@ impl ImageStack {
 
     // get the dimensions of the chunks that each image in the stack will need
     // to be broken up into
+    let memory_limit = self.memory_limit.unwrap_or_else(|| {
+      (get_system_memory() as f32 * STACKING_MEMORY_USAGE).round() as u64
+    });
+
     let (chunk_dimensions, chunk_cols, chunk_rows) = find_dimensions_that_match_mem_requirements(
       total_width, 
       total_height, 
-      self.data.len()
+      self.data.len(),
+      memory_limit,
+      self.halo
     );
 
     // this should never really happen, but the check is for safety
@@ -94,22 +232,32 @@ impl ImageStack {
       master_frames.push(ImageBuffer::new (total_width, total_height));
     }
 
-    // stores the slice of pixels from the stack
-    let mut pixel_slice: Vec<Rgb<u16>> = vec![Rgb::<u16>::from([0, 0, 0]); stack_depth];
-
     // for each chunk
     for chunk_row in 0..chunk_rows {
       for chunk_col in 0..chunk_cols {
 
+        if cancel.load(Ordering::Relaxed) {
+          println!("Stack cancelled after {} out of {} chunks", chunks_processed, chunk_count);
+          return Err(StackStatus::Cancelled { 
+            chunks_processed: chunks_processed, 
+            chunk_count: chunk_count 
+          });
+        }
+
         // get the upper left (x, y) corner of the current chunk
         let offset_x = chunk_col * chunk_width;
         let offset_y = chunk_row * chunk_height;
 
         // get chunks from stack
-        let mut chunks_from_stack: Vec<ChunkBuffer> = Vec::with_capacity(chunk_count);
+        let mut chunks_from_stack: Vec<HaloChunk> = Vec::with_capacity(chunk_count);
         for image_path in &self.data {
           if let Some(image_chunk) = get_image_chunk(
-            image_path.as_str(), offset_x, offset_y, chunk_width, chunk_height
+            image_path.as_str(), 
+            offset_x, 
+            offset_y, 
+            chunk_width, 
+            chunk_height, 
+            self.halo
           ) {
             chunks_from_stack.push(image_chunk);
           }
@@ -117,24 +265,47 @@ impl ImageStack {
 
         println!("Chunk ({}, {}) has been loaded into memory", chunk_row, chunk_col);
 
-        // for each pixel across the whole stack
-        for x in 0..chunk_width {
-          for y in 0..chunk_height  {
-
-            // extract the slice of pixels
-            for stack_index in 0..stack_depth {
-              
-              // NOTE: This will break if the RAW image is not rgb16
-              pixel_slice[stack_index] = *chunks_from_stack[stack_index].get_pixel(
-                x as u32,
-                y as u32
-              );
-            }
+        // the stacked pixels of every master frame, laid out a pixel at a 
+        // time. Each pixel of the chunk is stacked on whichever thread of the
+        // pool gets to it, and only ever writes to its own slots, so the 
+        // result is the same however the work is split up.
+        let fn_count = self.stacking_fns.len();
+        let mut stacked = vec![
+          Rgb::<u16>::from([0, 0, 0]); pixels_per_chunk * fn_count
+        ];
+        threads::install(|| {
+          stacked.par_chunks_mut(fn_count.max(1)).enumerate().for_each_init(
+            // stores the slice of pixels from the stack
+            || vec![Rgb::<u16>::from([0, 0, 0]); stack_depth],
+            |pixel_slice, (pixel_index, master_pixels)| {
+              let x = (pixel_index % chunk_width as usize) as u32;
+              let y = (pixel_index / chunk_width as usize) as u32;
+
+              // extract the slice of pixels
+              for stack_index in 0..stack_depth {
+                // the chunk itself starts past the halo
+                let chunk = &chunks_from_stack[stack_index];
+                pixel_slice[stack_index] = *chunk.buffer.get_pixel(
+                  chunk.inner_x + x,
+                  chunk.inner_y + y
+                );
+              }
 
-            for frame_index in 0..master_frames.len() {
-              let master_pixel = (self.stacking_fns[frame_index].0.get_function())(&pixel_slice);
-              master_frames[frame_index].put_pixel(offset_x + x, offset_y + y, master_pixel);
+              let algorithms = self.stacking_fns.iter();
+              for (master_pixel, (algorithm, _)) in 
+                master_pixels.iter_mut().zip(algorithms) {
+                *master_pixel = (algorithm.get_function())(pixel_slice);
+              }
             }
+          );
+        });
+
+        let stacked_pixels = stacked.chunks(fn_count.max(1)).enumerate();
+        for (pixel_index, master_pixels) in stacked_pixels {
+          let x = offset_x + (pixel_index % chunk_width as usize) as u32;
+          let y = offset_y + (pixel_index / chunk_width as usize) as u32;
+          for (frame, pixel) in master_frames.iter_mut().zip(master_pixels) {
+            frame.put_pixel(x, y, *pixel);
           }
         }
 
@@ -143,12 +314,98 @@ impl ImageStack {
       }
     }
 
-    for frame_index in 0..master_frames.len() {
-      master_frames[frame_index].save(self.stacking_fns[frame_index].1.as_str());
+    if StackNormalization::Stretch == self.normalization {
+      for frame in master_frames.iter_mut() {
+        normalize_master(frame);
+      }
+    }
+
+    Ok(master_frames)
+  }
+
+  /// Integrates the stack with drizzle instead of the stacking algorithms, and
+  /// writes the (upscaled) master frame to the given path. 
+  /// 
+  /// Returns the alignment error of each frame that was drizzled against the
+  /// first one, along with its path, so that a frame with a wrong offset can
+  /// be spotted. The first frame has an error of zero.
+  pub fn process_drizzle(
+    &self, settings: DrizzleSettings, path: &str
+  ) -> Result<Vec<(String, f64)>, CoreError> {
+    if 0 == self.width() || 0 == self.height() {
+      panic!("The stack does not have an image size set (perhaps images were not read properly)");
+    }
+
+    let mut drizzle = Drizzle::new(self.width(), self.height(), settings);
+
+    // the first frame is kept (at 8 bits), along with the part of it that
+    // isn't padding, so that the alignment of the others can be checked 
+    // against it
+    let mut reference: Option<(PpmImage, Option<Selection>)> = None;
+    let mut alignment_errors = Vec::<(String, f64)>::new();
+
+    // each frame is dropped onto the output grid and released before the next
+    // one is read, so only one frame is held in memory at a time
+    for (image_path, offset) in self.data.iter().zip(self.offsets.iter()) {
+      if let Some(image) = read_raw(image_path.as_str()) {
+        if let Some(frame) = image.as_rgb16() {
+          drizzle.add_frame(frame, *offset);
+          println!("{} has been drizzled", image_path);
+
+          let (dx, dy) = (offset.0.round() as i32, offset.1.round() as i32);
+          let aligned = match translate(
+            &dynamic_to_ppm(&image), dx, dy, Padding::Repeat
+          ) {
+            Ok(aligned) => aligned,
+            Err(_) => continue,
+          };
+          let region = translated_region(
+            aligned.width(), aligned.height(), dx, dy
+          );
+
+          // only the pixels that came from both frames are compared, and a 
+          // large error usually means the offset is wrong
+          let error = match &reference {
+            Some((reference, reference_region)) => {
+              let overlap = reference_region.zip(region)
+                .and_then(|(lhs, rhs)| lhs.intersect(&rhs));
+              match overlap {
+                Some(overlap) => alignment_error(reference, &aligned, &overlap),
+                None => f64::INFINITY,
+              }
+            },
+            None => {
+              reference = Some((aligned, region));
+              0.
+            },
+          };
+          alignment_errors.push((image_path.clone(), error));
+        }
+      }
+    }
+
+    let mut master = drizzle.finish();
+
+    if StackNormalization::Stretch == self.normalization {
+      normalize_master(&mut master);
+    }
+
+    if let Err(why) = master.save(path) {
+      return Err(CoreError::Io(format!(
+        "Could not save the drizzled master to {}: {}", path, why
+      )));
     }
+
+    Ok(alignment_errors)
   }
 
   pub fn add_image(&mut self, path: &str) {
+    self.add_image_with_offset(path, (0., 0.));
+  }
+
+  /// Adds an image along with the sub-pixel offset that aligns it to the rest
+  /// of the stack (only used by drizzle integration)
+  pub fn add_image_with_offset(&mut self, path: &str, offset: (f32, f32)) {
     if 0 == self.width || 0 == self.height {
       if let Some(image) = read_raw(path) {
         self.width = image.width();
@@ -158,6 +415,7 @@ impl ImageStack {
 
     // add the file path to the list of image file paths
     self.data.push(path.to_string());
+    self.offsets.push(offset);
   }
 
   pub fn height(&self) -> u32 {
@@ -169,6 +427,100 @@ impl ImageStack {
   }
 }
 
+/* #region Drizzle */
+
+/// Accumulates frames onto the upscaled output grid of a drizzle integration.
+/// Every input pixel is shrunk to a drop of pixel_fraction, moved by the frame
+/// offset, and added to each output pixel it covers in proportion to the 
+/// overlapping area.
+pub struct Drizzle {
+  settings: DrizzleSettings,
+  width: u32,
+  height: u32,
+  flux: Vec<f64>,
+  weights: Vec<f64>,
+}
+
+impl Drizzle {
+  pub fn new(width: u32, height: u32, settings: DrizzleSettings) -> Self {
+    let width = width * settings.scale;
+    let height = height * settings.scale;
+    let pixel_count = (width * height) as usize;
+
+    Drizzle {
+      settings: settings,
+      width: width,
+      height: height,
+      flux: vec![0.; pixel_count * PIXEL_SIZE],
+      weights: vec![0.; pixel_count],
+    }
+  }
+
+  pub fn add_frame(&mut self, frame: &ChunkBuffer, offset: (f32, f32)) {
+    let scale = self.settings.scale as f64;
+    // half of the drop's side length, in output pixels
+    let half_drop = self.settings.pixel_fraction as f64 * scale / 2.;
+
+    for (x, y, pixel) in frame.enumerate_pixels() {
+      // the center of the drop on the output grid
+      let cx = (x as f64 + 0.5 + offset.0 as f64) * scale;
+      let cy = (y as f64 + 0.5 + offset.1 as f64) * scale;
+
+      let (left, right) = (cx - half_drop, cx + half_drop);
+      let (top, bottom) = (cy - half_drop, cy + half_drop);
+
+      let x_start = left.floor().max(0.) as u32;
+      let x_end = min(right.ceil().max(0.) as u32, self.width);
+      let y_start = top.floor().max(0.) as u32;
+      let y_end = min(bottom.ceil().max(0.) as u32, self.height);
+
+      for oy in y_start..y_end {
+        let overlap_y = bottom.min(oy as f64 + 1.) - top.max(oy as f64);
+        if overlap_y <= 0. {
+          continue;
+        }
+
+        for ox in x_start..x_end {
+          let overlap_x = right.min(ox as f64 + 1.) - left.max(ox as f64);
+          if overlap_x <= 0. {
+            continue;
+          }
+
+          let area = overlap_x * overlap_y;
+          let index = (oy * self.width + ox) as usize;
+
+          self.weights[index] += area;
+          for ch in [R_CH, G_CH, B_CH] {
+            self.flux[index * PIXEL_SIZE + ch] += area * pixel.0[ch] as f64;
+          }
+        }
+      }
+    }
+  }
+
+  /// Divides out the accumulated weights to get the master frame. Output 
+  /// pixels that no drop landed on are left black.
+  pub fn finish(&self) -> ChunkBuffer {
+    let mut master = ChunkBuffer::new(self.width, self.height);
+
+    for (x, y, pixel) in master.enumerate_pixels_mut() {
+      let index = (y * self.width + x) as usize;
+      let weight = self.weights[index];
+
+      if weight > 0. {
+        for ch in [R_CH, G_CH, B_CH] {
+          let value = self.flux[index * PIXEL_SIZE + ch] / weight;
+          pixel.0[ch] = value.round().clamp(0., u16::MAX as f64) as u16;
+        }
+      }
+    }
+
+    master
+  }
+}
+
+/* #endregion */
+
 /* #region Average Stack */
 
 pub struct Average {
@@ -178,21 +530,15 @@ pub struct Average {
 impl Average {
   pub fn new() -> Self {
     fn stack_algorithm(pixels: &Vec<Rgb<u16>>) -> Rgb<u16> {
-      let mut r_sum: usize = 0;
-      let mut g_sum: usize = 0;
-      let mut b_sum: usize = 0;
+      let mut stats = [RunningStats::new(); PIXEL_SIZE];
 
       for pixel in pixels {
-        r_sum += pixel.0[R_CH] as usize;
-        g_sum += pixel.0[G_CH] as usize;
-        b_sum += pixel.0[B_CH] as usize;
+        for ch in [R_CH, G_CH, B_CH] {
+          stats[ch].push(pixel.0[ch] as f64);
+        }
       }
 
-      Rgb::<u16>::from([
-        (r_sum as usize / pixels.len()) as u16,
-        (g_sum as usize / pixels.len()) as u16,
-        (b_sum as usize / pixels.len()) as u16,
-      ])
+      Rgb::<u16>::from(stats.map(|channel| channel.mean().round() as u16))
     }
 
     Average {
@@ -254,18 +600,39 @@ impl StackOperation for Median {
 
 /* #region Maximum Stack */
 
+/**
+ * Keeps the brightest pixel of the stack, for star trails and lightning 
+ * composites. What counts as the brightest is decided by the metric.
+ */
 pub struct Maximum {
   pub stacking_function: Box<StackingFunction>
 }
 
 impl Maximum {
-  pub fn new() -> Self {
-    fn stack_algorithm(pixels: &Vec<Rgb<u16>>) -> Rgb<u16> {
+  pub fn new(metric: BrightnessMetric) -> Self {
+    let stack_algorithm = move |pixels: &Vec<Rgb<u16>>| -> Rgb<u16> {
+      if BrightnessMetric::PerChannel == metric {
+        let mut max_pixel = Rgb::<u16>::from([0, 0, 0]);
+        for pixel in pixels {
+          for ch in [R_CH, G_CH, B_CH] {
+            max_pixel.0[ch] = max(max_pixel.0[ch], pixel.0[ch]);
+          }
+        }
+
+        return max_pixel;
+      }
+
       let mut max_intensity = 0.0;
       let mut max_pixel = Rgb::<u16>::from([0,0,0]);
 
       for pixel in pixels {
-        let intensity = color::rgb_to_hsv(pixel.0)[V_CH];
+        let intensity = match metric {
+          BrightnessMetric::Luminance => pixel.0.iter()
+            .zip(color::REC709_WEIGHTS.iter())
+            .map(|(&ch, weight)| ch as f32 * weight)
+            .sum(),
+          _ => color::rgb_to_hsv(pixel.0)[V_CH],
+        };
 
         if intensity > max_intensity {
           max_intensity = intensity;
@@ -274,7 +641,7 @@ impl Maximum {
       }
 
       max_pixel
-    }
+    };
 
     Maximum {
       stacking_function: Box::new(stack_algorithm)
@@ -367,25 +734,129 @@ impl StackOperation for KappaSigmaClipping {
   
 /* #endregion */
 
+/* #region PercentileClipAverage */
+
+/**
+ * Averages each channel of the stack separately, after throwing away the 
+ * samples below the low percentile and above the high percentile (both from 
+ * 0 to 100). Airplanes, satellites, and hot pixels only show up in a few 
+ * frames, so they end up at the ends of the sorted samples and are dropped.
+ */
+pub struct PercentileClipAverage {
+  stacking_function: Box<StackingFunction>
+}
+
+impl PercentileClipAverage {
+  pub fn new(low_pct: f32, high_pct: f32) -> Self {
+    let low = low_pct.clamp(0., 100.) / 100.;
+    let high = high_pct.clamp(0., 100.) / 100.;
+    let high = high.max(low);
+
+    let stacking_algorithm = move |pixels: &Vec<Rgb<u16>>| -> Rgb<u16> {
+      let count = pixels.len();
+      if 0 == count {
+        return Rgb::<u16>::from([0, 0, 0]);
+      }
+
+      // at least one sample is always kept, even when the low percentile is
+      // 100
+      let start = ((count as f32 * low).floor() as usize).min(count - 1);
+      let end = ((count as f32 * high).ceil() as usize).clamp(start + 1, count);
+
+      let mut samples: Vec<u16> = Vec::with_capacity(count);
+      let mut stacked = Rgb::<u16>::from([0, 0, 0]);
+
+      for ch in [R_CH, G_CH, B_CH] {
+        samples.clear();
+        samples.extend(pixels.iter().map(|pixel| pixel.0[ch]));
+        samples.sort_unstable();
+
+        let mut stats = RunningStats::new();
+        for sample in &samples[start..end] {
+          stats.push(*sample as f64);
+        }
+        stacked.0[ch] = stats.mean().round() as u16;
+      }
+
+      stacked
+    };
+
+    PercentileClipAverage {
+      stacking_function: Box::new(stacking_algorithm)
+    }
+  }
+}
+
+impl StackOperation for PercentileClipAverage {
+  fn get_function(&self) -> &Box<StackingFunction> {
+    &self.stacking_function
+  }
+}
+
+/* #endregion */
+
 /* #region Utility Functions */
 
-fn get_image_chunk(path: &str, x: u32, y: u32, width: u32, height: u32) -> Option<ChunkBuffer> {
+/// A chunk of an image along with a border (the halo) of the pixels around 
+/// it, so that a filter applied to the chunk sees the same neighbors that it
+/// would in the whole image. The halo is cut short at the edges of the image.
+pub struct HaloChunk {
+  pub buffer: ChunkBuffer,
+  /// Where the chunk itself starts inside of the buffer
+  pub inner_x: u32,
+  pub inner_y: u32,
+}
+
+/// Crops the given chunk out of the image, along with up to halo pixels on 
+/// each side of it
+pub fn crop_with_halo(
+  image: &DynamicImage, x: u32, y: u32, width: u32, height: u32, halo: u32
+) -> HaloChunk {
+  let left = x.saturating_sub(halo);
+  let top = y.saturating_sub(halo);
+  let right = (x + width + halo).min(image.width());
+  let bottom = (y + height + halo).min(image.height());
+
+  HaloChunk {
+    buffer: image.crop_imm(left, top, right - left, bottom - top).to_rgb16(),
+    inner_x: x - left,
+    inner_y: y - top,
+  }
+}
 
-  let mut image_chunk: Option<ChunkBuffer> = None;
+fn get_image_chunk(
+  path: &str, x: u32, y: u32, width: u32, height: u32, halo: u32
+) -> Option<HaloChunk> {
+  read_raw(path).map(|image| crop_with_halo(&image, x, y, width, height, halo))
+}
 
-  if let Some(image) = read_raw(path) {
-    image_chunk = Some(
-      image.crop_imm(
-        x as u32, y as u32,
-        width as u32, height as u32
-      ).as_rgb16().unwrap().clone()
-    );
+/// Linearly stretches the values in the given master frame so that the lowest
+/// value becomes zero and the highest value becomes u16::MAX. All channels are
+/// stretched by the same amount so that the color balance is preserved.
+pub fn normalize_master(master: &mut ChunkBuffer) {
+  let mut lowest = u16::MAX;
+  let mut highest = u16::MIN;
+
+  for pixel in master.pixels() {
+    for ch in [R_CH, G_CH, B_CH] {
+      lowest = min(lowest, pixel.0[ch]);
+      highest = max(highest, pixel.0[ch]);
+    }
+  }
 
-    // dunno if this helps or not
-    drop(image);
+  // a flat frame cannot be stretched
+  if highest <= lowest {
+    return
   }
 
-  image_chunk
+  let scale = u16::MAX as f32 / (highest - lowest) as f32;
+
+  for pixel in master.pixels_mut() {
+    for ch in [R_CH, G_CH, B_CH] {
+      let stretched = (pixel.0[ch] - lowest) as f32 * scale;
+      pixel.0[ch] = stretched.round() as u16;
+    }
+  }
 }
 
 fn get_system_memory() -> u64 {
@@ -406,22 +877,22 @@ fn find_intensity_to_clip(
 ) -> Option<usize> {
   let mut to_clip: Option<usize> = None;
   
-  let mut sum:f64 = 0.0;
+  let mut stats = RunningStats::new();
   for intensity in intensities {
-    sum += *intensity as f64;
+    stats.push(*intensity as f64);
   }
 
-  let mean = sum / intensities.len() as f64;
-
-  let variance: f64 = intensities.iter().map(|x| (*x as f64 - mean).powi(2)).sum();
-  let standard_deviation = variance.sqrt();
+  let mean = stats.mean();
+  let standard_deviation = stats.stddev();
 
   let mut furthest_distance: f64 = 0.0;
 
+  // only intensities more than kappa standard deviations from the mean are
+  // outliers
   for intensity_index in 0..intensities.len() {
     let intensity = intensities[intensity_index] as f64;
-    if kappa * standard_deviation < intensity {
-      let distance = (mean - intensity).abs();
+    let distance = (mean - intensity).abs();
+    if kappa * standard_deviation < distance {
       if furthest_distance < distance {
         furthest_distance = distance;
         to_clip = Some(intensity_index);
@@ -432,10 +903,7 @@ fn find_intensity_to_clip(
   to_clip
 }
 
-fn find_dimensions_that_match_mem_requirements(total_width: u32, total_height: u32, image_count: usize) -> (Option<(u32, u32)>, u32, u32) {
-
-  let sys_mem = get_system_memory() as f32;
-  let mem_limit = (sys_mem * STACKING_MEMORY_USAGE).round() as u64;
+fn find_dimensions_that_match_mem_requirements(total_width: u32, total_height: u32, image_count: usize, mem_limit: u64, halo: u32) -> (Option<(u32, u32)>, u32, u32) {
 
   let mut height_factors = get_factors(total_height);
   let mut width_factors = get_factors(total_width);
@@ -453,7 +921,13 @@ fn find_dimensions_that_match_mem_requirements(total_width: u32, total_height: u
   let mut chunk_width = width_factors[0];
   let mut factor_index = 0;
 
-  let mut memory_usage = chunk_height as u64 * chunk_width as u64 * (image_count * RAW_BYTES_PER_PIXEL) as u64;
+  // the halo is read in on every side of a chunk, which takes memory too
+  let chunk_memory = |width: u32, height: u32| {
+    (height + 2 * halo) as u64 * (width + 2 * halo) as u64 * 
+      (image_count * RAW_BYTES_PER_PIXEL) as u64
+  };
+
+  let mut memory_usage = chunk_memory(chunk_width, chunk_height);
 
   // keep lowering which factor of height and width to use until memory 
   // requirements are met
@@ -474,7 +948,7 @@ fn find_dimensions_that_match_mem_requirements(total_width: u32, total_height: u
     };
 
     // update the memory usage candidate
-    memory_usage = chunk_height as u64 * chunk_width as u64 * (image_count * RAW_BYTES_PER_PIXEL) as u64;
+    memory_usage = chunk_memory(chunk_width, chunk_height);
 
     // increment the factor index
     factor_index += 1;
@@ -0,0 +1,53 @@
+/* #region RunningStats */
+
+/**
+ * The mean and variance of a stream of values, updated one value at a time
+ * (Welford's method). Nothing but the running totals is kept, so the values
+ * never have to be collected first, and unlike a sum of squares it doesn't
+ * lose precision when the values are large and close together.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RunningStats {
+  count: usize,
+  mean: f64,
+  // the sum of the squared distances from the mean
+  squared_distances: f64,
+}
+
+impl RunningStats {
+  pub fn new() -> Self {
+    RunningStats {
+      count: 0,
+      mean: 0.,
+      squared_distances: 0.,
+    }
+  }
+
+  pub fn push(&mut self, value: f64) {
+    self.count += 1;
+
+    let distance = value - self.mean;
+    self.mean += distance / self.count as f64;
+    self.squared_distances += distance * (value - self.mean);
+  }
+
+  /// The mean of the values, or 0 if there are none
+  pub const fn mean(&self) -> f64 {
+    self.mean
+  }
+
+  /// The population variance of the values, or 0 if there are none
+  pub fn variance(&self) -> f64 {
+    match self.count {
+      0 => 0.,
+      count => self.squared_distances / count as f64,
+    }
+  }
+
+  /// The population standard deviation of the values
+  pub fn stddev(&self) -> f64 {
+    self.variance().sqrt()
+  }
+}
+
+/* #endregion */
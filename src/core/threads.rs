@@ -0,0 +1,71 @@
+use std::sync::{Arc, Mutex};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::core::error::CoreError;
+
+/// The thread pool shared by the stacking and filter code. It is created the
+/// first time it is needed, or whenever the thread count is changed.
+static THREAD_POOL: Mutex<Option<Arc<ThreadPool>>> = Mutex::new(None);
+
+/// The number of threads that the system can run at once
+pub fn available_threads() -> usize {
+  std::thread::available_parallelism()
+    .map(|threads| threads.get())
+    .unwrap_or(1)
+}
+
+/// Caps the number of threads used for stacking and filtering, so that some
+/// cores can be left free. Work that is already running keeps the pool it
+/// started with.
+pub fn set_thread_count(threads: usize) -> Result<(), CoreError> {
+  if 0 == threads {
+    return Err(CoreError::InvalidParameter(
+      "The thread count must be at least one".to_string()
+    ));
+  }
+
+  let pool = match ThreadPoolBuilder::new().num_threads(threads).build() {
+    Ok(pool) => pool,
+    Err(why) => return Err(CoreError::InvalidParameter(format!(
+      "Could not create a thread pool of {} threads: {}", threads, why
+    ))),
+  };
+
+  *THREAD_POOL.lock().unwrap() = Some(Arc::new(pool));
+
+  Ok(())
+}
+
+/// Runs the work on the thread pool. Work that is already running on a pool
+/// (such as one that a test built for itself) stays on that pool instead.
+pub fn install<OP, R>(op: OP) -> R 
+where 
+  OP: FnOnce() -> R + Send, 
+  R: Send 
+{
+  match rayon::current_thread_index() {
+    Some(_) => op(),
+    None => thread_pool().install(op),
+  }
+}
+
+/// The number of threads in the pool (all of the cores by default)
+pub fn thread_count() -> usize {
+  thread_pool().current_num_threads()
+}
+
+pub fn thread_pool() -> Arc<ThreadPool> {
+  let mut pool = THREAD_POOL.lock().unwrap();
+
+  if pool.is_none() {
+    let default_pool = ThreadPoolBuilder::new()
+      .num_threads(available_threads())
+      .build()
+      .expect("Could not create the default thread pool");
+
+    *pool = Some(Arc::new(default_pool));
+  }
+
+  pool.as_ref().unwrap().clone()
+}
@@ -2,23 +2,56 @@
 use eframe::{egui::{CentralPanel, TopBottomPanel, self, Modifiers, Response}};
 use image::{ImageBuffer, Rgb};
 use egui::Vec2;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::time::{Duration, Instant, SystemTime};
+use std::mem::{discriminant, Discriminant};
 use egui_extras::RetainedImage;
 use strum::IntoEnumIterator;
+use strum_macros::{EnumIter, Display};
 
-use crate::core::{ppm::{PpmImage, Padding}, filters, 
-args::parse_arguments
+use crate::core::{ppm::{PpmImage, Padding, Selection}, filters, 
+args::{parse_arguments, split_chain, run_chain}
 };
-use crate::core::operations::{ResizeAlgorithm, OpType, OperationResult};
-use crate::core::{io};
 use crate::core::operations::{
-  perform_operation, 
+  ResizeAlgorithm, OpType, OperationResult, Operation, GrayscaleMode, 
+  SizeMatching
+};
+use crate::core::{io, threads};
+use crate::core::io::{SaveFormat, WriteOptions};
+use crate::core::color::ColorMetric;
+use crate::core::batch::{self, BatchProgress, BatchSummary};
+use crate::core::error::CoreError;
+use crate::core::blend::{inpaint, poisson_blend};
+use crate::core::mask::BinaryMask;
+use crate::core::proxy::{ProxyEdit, PROXY_MAX_PIXELS};
+use crate::core::provenance::{Edit, OperationLog};
+use crate::core::stacking::{self, ImageStack, StackStatus};
+use crate::core::operations::{
+  perform_operation_with_matching, 
   resize, 
-  histogram_equalization, 
-  negate
+  resize_with_prefilter,
+  histogram_equalization
 };
 
+use super::presets::{
+  PresetStore, PresetParameters, preset_file_path, save_requested_preset
+};
+use super::preview::{render_preview, DEFAULT_PREVIEW_DELAY_MS};
+use super::sequence::ImageSequence;
+use super::thumbnails::{
+  Thumbnail, ThumbnailCache, DEFAULT_THUMBNAIL_CACHE_BYTES, 
+  THUMBNAIL_MAX_DIMENSION, modified_time
+};
+use super::history::{CommandHistory, history_file_path, MAX_HISTORY_LENGTH};
+use super::settings::{Settings, config_directory, settings_file_path};
 use super::windows::{
-  self, HistogramWindow, GaussianBlurWindow, UnsharpMaskWindow
+  self, HistogramWindow, GaussianBlurWindow, UnsharpMaskWindow, 
+  DeconvolutionWindow, HistogramOverlay, RotateWindow, ClippingOverlay,
+  DifferenceOfGaussiansWindow, HighPassWindow, BatchWindow, ReplaceColorWindow,
+  SelectiveColorWindow, ClarityWindow, MaskBrushWindow
 };
 
 pub const BUTTON_PADDING: f32 = 5.0;
@@ -27,10 +60,92 @@ const VIEWPORT_HMARGIN:f32 = 50.;
 const VIEWPORT_WMARGIN:f32 = 50.;
 const DEBUG_FILE_NAME:&str = "0.png";
 
+/// Uploads the image as a texture that egui can draw
+fn to_retained_image(image: &PpmImage) -> RetainedImage {
+  let mut buf: ImageBuffer<Rgb<u8>, Vec<u8>> = image::ImageBuffer::new(
+    image.width(),
+    image.height()
+  );
+
+  for (x, y, pixels) in buf.enumerate_pixels_mut() {
+    if let Some(pixel) = image.get_pixel_by_coord(x, y) {
+      *pixels = image::Rgb(pixel);
+    }
+  }
+
+  let color_image = egui::ColorImage::from_rgb(
+    [image.width() as usize, image.height() as usize],
+    &buf.as_ref(),
+  );
+
+  RetainedImage::from_color_image(DEBUG_FILE_NAME, color_image)
+}
+
+/// How an image is scaled to the viewport when it is fit to the screen
+#[derive(PartialEq, EnumIter, Display, Clone, Copy, Debug)]
+pub enum FitMode {
+  /// Scales the image to fit the viewport, keeping its aspect ratio
+  Fit,
+  /// Stretches the image to fill the viewport
+  Fill,
+  /// Like Fit, but images smaller than the viewport stay at their native size
+  /// rather than being blown up (which blurs pixel art and thumbnails)
+  #[strum(serialize = "Fit but don't enlarge")]
+  FitNoEnlarge,
+}
+
+impl FitMode {
+  /// The size that an image is drawn at in a viewport of the given size
+  pub fn fitted_size(
+    &self, 
+    width: u32, 
+    height: u32, 
+    viewport_width: f32, 
+    viewport_height: f32
+  ) -> (u32, u32) {
+    use crate::core::min;
+
+    if FitMode::Fill == *self {
+      return (viewport_width as u32, viewport_height as u32);
+    }
+
+    let w_ratio = viewport_width / width as f32;
+    let h_ratio = viewport_height / height as f32;
+
+    let mut ratio = min(w_ratio, h_ratio);
+    if FitMode::FitNoEnlarge == *self {
+      ratio = min(ratio, 1.);
+    }
+
+    (
+      (width as f32 * ratio) as u32, 
+      (height as f32 * ratio) as u32
+    )
+  }
+}
+
+/// Sent from the thread opening an image back to the GUI. When the image is a
+/// frame of a sequence, a thumbnail of it comes along too, with the time that
+/// the file was modified before it was read.
+type LoadResult = Result<
+  (PpmImage, Option<(SystemTime, Thumbnail)>), CoreError
+>;
+
+/// Sent from the thread running a batch back to the GUI
+enum BatchMessage {
+  Progress(BatchProgress),
+  Finished(BatchSummary),
+}
+
 pub struct ImageViewer {
   // option is used because the image viewer may or may not actually have an
   // image open
   drawn_image: Option<egui_extras::RetainedImage>,
+  // the size that the drawn image is shown at, which can be larger than its
+  // texture on a screen with fewer physical pixels than points
+  drawn_size: Vec2,
+  // how many physical pixels there are to a point on the screen
+  pixels_per_point: f32,
   image_hidden: Option<crate::core::ppm::PpmImage>,
   pub ccl_image_mask: Option<crate::core::ppm::PpmImage>,
   
@@ -38,21 +153,108 @@ pub struct ImageViewer {
   image_histogram_window: HistogramWindow,
   gaussian_blur_window: GaussianBlurWindow,
   unsharp_mask_window: UnsharpMaskWindow,
+  deconvolution_window: DeconvolutionWindow,
+  rotate_window: RotateWindow,
+  dog_window: DifferenceOfGaussiansWindow,
+  high_pass_window: HighPassWindow,
+  clarity_window: ClarityWindow,
+  mask_brush_window: MaskBrushWindow,
+  // whether operations only change the pixels inside of the painted mask
+  restrict_to_mask: bool,
+  replace_color_window: ReplaceColorWindow,
+  selective_color_window: SelectiveColorWindow,
+  histogram_overlay: HistogramOverlay,
+  clipping_overlay: ClippingOverlay,
+  // the saved parameters of the filter windows
+  presets: PresetStore,
+  // the current image with a filter previewed on it. This is shown in place
+  // of the image, but is never applied to it.
+  preview_image: Option<PpmImage>,
+  // how long the filter sliders have to sit still before the preview updates
+  preview_delay_ms: u64,
 
   previous_images: Vec<PpmImage>,
+  // how many edits each of the previous images is undoing, which is more than
+  // one when applies of the same operation were coalesced
+  previous_image_edits: Vec<usize>,
+  // the kind of operation that the last undo step was made by, for as long as
+  // applying it again is coalesced into that step
+  coalescing: Option<Discriminant<Operation>>,
+  // an image being opened on a background thread, along with its path. The
+  // result is sent back over the channel once the image has been read.
+  loading_image: Option<(String, Receiver<LoadResult>)>,
+  // a slow edit (like a seamless paste) being worked out on a background 
+  // thread, along with its name and when it was started. The edited image 
+  // replaces the current one once it arrives.
+  background_edit: Option<(String, Instant, Receiver<OperationResult>)>,
+  // the folder of images being stepped through, if one was opened
+  sequence: Option<ImageSequence>,
+  // thumbnails of the frames of the sequence that have been viewed, which 
+  // stand in for a frame while it is loaded again
+  thumbnails: ThumbnailCache,
+  // the thumbnail that is shown in place of a frame while it is loaded
+  thumbnail_shown: Option<Thumbnail>,
+  // the stack of the included frames of the sequence, while it runs on a 
+  // background thread, along with the flag that cancels it
+  sequence_stack: Option<Receiver<Result<StackStatus, CoreError>>>,
+  sequence_stack_cancel: Option<Arc<AtomicBool>>,
+  batch_window: BatchWindow,
+  // the batch running on a background thread, and how far along it is
+  batch: Option<Receiver<BatchMessage>>,
+  batch_progress: Option<BatchProgress>,
+  // the last operation applied, so that it can be repeated
+  last_operation: Option<Operation>,
+  // whether large images are edited through a smaller proxy
+  proxy_mode: bool,
+  // the full resolution image and the edits made to its proxy, while the 
+  // current image is a proxy
+  proxy: Option<ProxyEdit>,
+  // proxy sessions that were stopped, along with the number of previous 
+  // images there were before the stop, so that undoing the stop goes back
+  // to editing the proxy
+  stopped_proxies: Vec<(usize, ProxyEdit)>,
+  // how each version of the image on the undo stack was made, which is 
+  // written into exported files
+  operation_log: OperationLog,
   viewport_height: f32,
   viewport_width: f32,
 
   fit_to_window: bool,
-  maintain_aspect_ratio: bool,
+  fit_mode: FitMode,
+
+  // the region of the image that the user has dragged out with the mouse
+  selection: Option<Selection>,
+  selection_anchor: Option<(u32, u32)>,
+  // the region of the image currently shown in the viewport (zoom / pan 
+  // state). If this is None, then the whole image is shown
+  view_region: Option<Selection>,
   
   command: String,
-  command_resp: String,
+  pub command_resp: String,
+  // the commands entered into the command box, for stepping back through
+  command_history: CommandHistory,
   resize_algorithm: ResizeAlgorithm,
+  // whether the image is blurred before it is shrunk to fit the viewport,
+  // which keeps fine detail from turning into moire patterns
+  resize_prefilter: bool,
   pub padding_strategy: Padding,
+  // the number of threads used for stacking and filtering
+  thread_count: usize,
+  // how images of different sizes are lined up for the image operations
+  size_matching: SizeMatching,
+  // the format that the save as dialog starts out with
+  default_save_format: SaveFormat,
+  // the color space that exported PNGs are tagged with
+  export_color_space: io::ColorSpace,
+  // the maxvalue written into the header of saved PPMs
+  export_max_value: io::MaxValue,
 
   pub show_ccl_controls: bool,
   pub ccl_tolerance: f32,
+  // how the tolerance measures the difference between two colors
+  pub ccl_metric: ColorMetric,
+  // regions with fewer pixels than this are left out of the mask
+  pub ccl_min_area: usize,
 
   /* Gamma window stuff */
   pub show_gamma_controls: bool,
@@ -65,12 +267,23 @@ pub struct ImageViewer {
 
   pub show_histogram_window: bool,
 
+  // where the settings, presets, and command history are kept between 
+  // sessions. Nothing is kept if this is None.
+  config_directory: Option<PathBuf>,
+
   quit: bool,
 }
 
 impl ImageViewer {
   pub fn new() -> Self {
-    ImageViewer {
+    ImageViewer::with_config_directory(config_directory())
+  }
+
+  /// Creates a viewer that keeps its preferences in the given directory. 
+  /// Without one, the viewer starts from the defaults and nothing is saved 
+  /// (which keeps the tests away from the user's own preferences).
+  pub fn with_config_directory(config_directory: Option<PathBuf>) -> Self {
+    let mut viewer = ImageViewer {
       image_histogram_window: HistogramWindow::new(
         "Histogram from Selected Image".to_string(),
         "image_histogram".to_string(),
@@ -89,26 +302,87 @@ impl ImageViewer {
         "Unsharp Masking".to_string()
       ),
 
+      deconvolution_window: DeconvolutionWindow::new(
+        "Richardson-Lucy Deconvolution".to_string()
+      ),
+
+      rotate_window: RotateWindow::new("Rotate".to_string()),
+      dog_window: DifferenceOfGaussiansWindow::new(
+        "Difference of Gaussians".to_string()
+      ),
+      high_pass_window: HighPassWindow::new(
+        "High-pass Sharpening".to_string()
+      ),
+      clarity_window: ClarityWindow::new("Clarity".to_string()),
+      mask_brush_window: MaskBrushWindow::new("Paint Mask".to_string()),
+      restrict_to_mask: false,
+      replace_color_window: ReplaceColorWindow::new(
+        "Replace Color".to_string()
+      ),
+      selective_color_window: SelectiveColorWindow::new(
+        "Selective Color".to_string()
+      ),
+
+      histogram_overlay: HistogramOverlay::new(),
+      clipping_overlay: ClippingOverlay::new(),
+      presets: PresetStore::new(),
+      preview_image: None,
+      preview_delay_ms: DEFAULT_PREVIEW_DELAY_MS,
+
       drawn_image: None,
+      drawn_size: Vec2::ZERO,
+      pixels_per_point: 1.,
       image_hidden: None,
       previous_images: Vec::<PpmImage>::new(),
+      previous_image_edits: Vec::<usize>::new(),
+      coalescing: None,
+      loading_image: None,
+      background_edit: None,
+      sequence: None,
+      thumbnails: ThumbnailCache::new(
+        DEFAULT_THUMBNAIL_CACHE_BYTES, THUMBNAIL_MAX_DIMENSION
+      ),
+      thumbnail_shown: None,
+      sequence_stack: None,
+      sequence_stack_cancel: None,
+      batch_window: BatchWindow::new("Batch Process Folder".to_string()),
+      batch: None,
+      batch_progress: None,
+      last_operation: None,
+      proxy_mode: false,
+      proxy: None,
+      stopped_proxies: Vec::<(usize, ProxyEdit)>::new(),
+      operation_log: OperationLog::new(),
       ccl_image_mask: None,
       viewport_height: 0.,
       viewport_width: 0.,
 
       fit_to_window: true,
-      maintain_aspect_ratio: true,
+      fit_mode: FitMode::Fit,
+
+      selection: None,
+      selection_anchor: None,
+      view_region: None,
       
       command: "".to_owned(),
       command_resp: "".to_owned(),
+      command_history: CommandHistory::new(MAX_HISTORY_LENGTH),
       resize_algorithm: ResizeAlgorithm::NearestNeighbor,
+      resize_prefilter: false,
       padding_strategy: Padding::Zero,
+      thread_count: threads::thread_count(),
+      size_matching: SizeMatching::Rescale,
+      default_save_format: SaveFormat::Ppm,
+      export_color_space: io::ColorSpace::Srgb,
+      export_max_value: io::MaxValue::Full,
 
       // determines whether the gamma controls should be shown
       show_gamma_controls: false,
       show_log_controls: false,
       show_ccl_controls: false,
-      ccl_tolerance: 0.9,
+      ccl_tolerance: 0.1,
+      ccl_metric: ColorMetric::Redmean,
+      ccl_min_area: 0,
       show_histogram_window: false,
 
       gamma: 1.,
@@ -116,10 +390,91 @@ impl ImageViewer {
       log_c: 0.,
       log_b: 10.,
 
+      config_directory: config_directory,
+
       quit: false,
+    };
+
+    viewer.load_presets();
+    viewer.load_command_history();
+    viewer.load_settings();
+    viewer
+  }
+
+  /// Where one of the preferences files is kept, or None if preferences 
+  /// aren't being kept
+  fn config_file(&self, file_path: fn(&Path) -> PathBuf) -> Option<PathBuf> {
+    self.config_directory.as_deref().map(file_path)
+  }
+
+  /// Reads the settings chosen in earlier sessions
+  fn load_settings(&mut self) {
+    let settings = match self.config_file(settings_file_path) {
+      Some(path) => match Settings::load_file(&path) {
+        Ok(settings) => settings,
+        Err(why) => {
+          println!("{}", why);
+          return;
+        },
+      },
+      None => return,
+    };
+
+    self.default_save_format = settings.default_save_format;
+    self.export_color_space = settings.export_color_space;
+    self.export_max_value = settings.export_max_value;
+
+    // a machine with fewer cores than the last one gets all of them
+    self.thread_count = settings.thread_count
+      .min(threads::available_threads());
+    if let Err(why) = threads::set_thread_count(self.thread_count) {
+      println!("{}", why);
+    }
+  }
+
+  /// Keeps the settings for the next session
+  fn save_settings(&self) {
+    let settings = Settings {
+      default_save_format: self.default_save_format,
+      export_color_space: self.export_color_space,
+      export_max_value: self.export_max_value,
+      thread_count: self.thread_count,
+    };
+
+    if let Some(path) = self.config_file(settings_file_path) {
+      if let Err(why) = settings.write_file(&path) {
+        println!("{}", why);
+      }
+    }
+  }
+
+  /// Reads the commands entered in earlier sessions
+  fn load_command_history(&mut self) {
+    if let Some(path) = self.config_file(history_file_path) {
+      match CommandHistory::load_file(&path, MAX_HISTORY_LENGTH) {
+        Ok(history) => self.command_history = history,
+        Err(why) => println!("{}", why),
+      }
     }
   }
 
+  /// Reads the saved filter presets, and hands them to their windows
+  fn load_presets(&mut self) {
+    if let Some(path) = self.config_file(preset_file_path) {
+      match PresetStore::load_file(&path) {
+        Ok(presets) => self.presets = presets,
+        Err(why) => println!("{}", why),
+      }
+    }
+
+    self.gaussian_blur_window.preset_controls.presets = 
+      self.presets.presets(self.gaussian_blur_window.filter_name()).to_vec();
+    self.unsharp_mask_window.preset_controls.presets = 
+      self.presets.presets(self.unsharp_mask_window.filter_name()).to_vec();
+    self.deconvolution_window.preset_controls.presets = 
+      self.presets.presets(self.deconvolution_window.filter_name()).to_vec();
+  }
+
   pub fn undo(&mut self) {
     // if there are previous images
     if !self.previous_images.is_empty() {
@@ -127,12 +482,43 @@ impl ImageViewer {
       // set the current image to the last image popped off the previous_images
       // list
       if let Some(last_image) = self.previous_images.pop() {
+        let edits = self.previous_image_edits.pop().unwrap_or(1);
+        self.coalescing = None;
+
+        for _ in 0..edits {
+          // undoing past the first edit of a proxy goes back to the full 
+          // image
+          let proxy_undone = match self.proxy.as_mut() {
+            Some(proxy) => !proxy.undo(),
+            None => false,
+          };
+          if proxy_undone {
+            self.proxy = None;
+          }
+          self.operation_log.undo();
+        }
+
+        // undoing the end of a proxy session picks the session back up, with
+        // the full resolution image and the edits made to the proxy
+        let stop_undone = self.stopped_proxies.last()
+          .map_or(false, |(depth, _)| *depth == self.previous_images.len());
+        if stop_undone {
+          self.proxy = self.stopped_proxies.pop().map(|(_, proxy)| proxy);
+          self.proxy_mode = true;
+        }
+
         // update the histogram window
         self.histogram_window.update(&last_image);
+        self.histogram_overlay.update(&last_image);
+        self.clipping_overlay.update(&last_image);
 
         // explicitly set the underlying image to the last image. Note here that
         // set_image is not used here - because that would mess up the undo list
         self.image_hidden = Some(last_image);
+        self.preview_image = None;
+
+        // the previous image may not have the same dimensions
+        self.validate_view();
 
         // request redraw
         self.redraw_image("Undo action taken".to_string());
@@ -147,27 +533,259 @@ impl ImageViewer {
   pub fn set_image(&mut self, image:Option<PpmImage>) {
     // if the new image being set exists
     if let Some(new_image) = image {
+      // edits that don't go through apply_operation can't be replayed
+      if let Some(proxy) = self.proxy.as_mut() {
+        proxy.record_unreplayable();
+      }
+
       // if the current image exists
       if None != self.image_hidden {
         // push a copy of the current image onto the stack of "previous" images
         let current_image = self.image_hidden.clone();
         self.previous_images.push(current_image.unwrap());
+        self.previous_image_edits.push(1);
       }
-      
-      // update the histogram window
-      self.histogram_window.update(&new_image);
+      self.coalescing = None;
+
+      self.operation_log.record(Edit::Other);
+      self.show_image(new_image);
+    }
+  }
+
+  /// Swaps in a new version of the image, leaving the undo stack alone
+  fn show_image(&mut self, new_image: PpmImage) {
+    // update the histogram window
+    self.histogram_window.update(&new_image);
+    self.histogram_overlay.update(&new_image);
+    self.clipping_overlay.update(&new_image);
+
+    // set the new image
+    self.image_hidden = Some(new_image);
+
+    // a preview of the old image doesn't apply to the new one
+    self.preview_image = None;
+    self.thumbnail_shown = None;
+
+    // clear ccl in case it is open
+    self.ccl_image_mask = None;
+
+    // the new image may not have the same dimensions as the last one
+    self.validate_view();
+
+    // redraw the image
+    self.redraw_image("set_image was called".to_string());
+  }
+
+  /// Applies the operation to the current image, and remembers it so that it
+  /// can be repeated later on. Applying the same kind of operation again
+  /// (such as a gamma that is being adjusted) is folded into the same undo 
+  /// step, until something else is done to the image.
+  pub fn apply_operation(&mut self, operation: Operation) -> Result<(), String> {
+    let start = Instant::now();
+
+    // the operation is limited to the painted mask if that's turned on
+    let mask = self.mask_brush_window.mask.as_ref().filter(|_| {
+      self.restrict_to_mask && self.mask_brush_window.has_mask()
+    });
+    let masked = mask.is_some();
+
+    let result = match (self.get_image(), mask) {
+      (Some(image), Some(mask)) => operation.apply_masked(image, mask)?,
+      (Some(image), None) => operation.apply(image)?,
+      (None, _) => return Err("There is no image to apply the operation to".to_string()),
+    };
+
+    self.last_operation = Some(operation);
+
+    let kind = discriminant(&operation);
+    let coalesced = !masked && self.coalescing == Some(kind);
+    if coalesced {
+      if let Some(edits) = self.previous_image_edits.last_mut() {
+        *edits += 1;
+      }
+      self.operation_log.record(Edit::Other);
+      self.show_image(result);
+    } else {
+      // the operation is recorded on the proxy, rather than the unreplayable 
+      // edit that set_image would record
+      let proxy = self.proxy.take();
+      self.set_image(Some(result));
+      self.proxy = proxy;
+    }
+
+    // the mask isn't part of the operation, so a masked edit can't be 
+    // replayed or described by the operation alone
+    if !masked {
+      if let Some(proxy) = self.proxy.as_mut() {
+        proxy.record(operation);
+      }
+      self.operation_log.amend(Edit::Operation(operation));
+    } else if let Some(proxy) = self.proxy.as_mut() {
+      proxy.record_unreplayable();
+    }
+
+    // a masked edit isn't coalesced, since the mask may have changed
+    self.coalescing = match masked {
+      true => None,
+      false => Some(kind),
+    };
+
+    self.report_duration(&operation.to_string(), start);
+
+    Ok(())
+  }
+
+  /// Shows how long an operation took in the command response area
+  pub fn report_duration(&mut self, name: &str, start: Instant) {
+    self.command_resp = format!(
+      "{} completed in {}ms", name, start.elapsed().as_millis()
+    );
+  }
+
+  /// Turns proxy mode on or off, starting or stopping the proxy of the
+  /// current image
+  pub fn set_proxy_mode(&mut self, proxy_mode: bool) {
+    self.proxy_mode = proxy_mode;
+
+    if self.proxy_mode {
+      self.start_proxy();
+    } else {
+      self.stop_proxy();
+    }
+  }
+
+  /// Switches to editing a proxy of the current image, if proxy mode is on
+  /// and the image is large enough to need one
+  pub fn start_proxy(&mut self) {
+    if !self.proxy_mode || self.proxy.is_some() {
+      return;
+    }
+
+    let proxy = match self.get_image() {
+      Some(image) if ProxyEdit::is_needed(image, PROXY_MAX_PIXELS) => {
+        ProxyEdit::new(image.clone())
+      },
+      _ => return,
+    };
+
+    match proxy.create_proxy(PROXY_MAX_PIXELS) {
+      Ok(small) => {
+        self.command_resp = format!(
+          "Editing a {}x{} proxy of the {}x{} image", 
+          small.width(), small.height(),
+          proxy.full_image().width(), proxy.full_image().height()
+        );
+        self.set_image(Some(small));
+        self.operation_log.amend(Edit::Proxy);
+        self.proxy = Some(proxy);
+      },
+      Err(why) => self.command_resp = format!("Error: {why}"),
+    }
+  }
+
+  /// Replays the edits made to the proxy on the full resolution image, and
+  /// goes back to editing the image directly
+  pub fn stop_proxy(&mut self) {
+    let result = match self.proxy.as_ref() {
+      Some(proxy) => proxy.render_full(),
+      None => return,
+    };
+
+    match result {
+      Ok(full_image) => {
+        // the proxy is kept with the undo step, since the previous image is
+        // only the proxy
+        let depth = self.previous_images.len();
+        if let Some(proxy) = self.proxy.take() {
+          self.stopped_proxies.push((depth, proxy));
+        }
+
+        self.set_image(Some(full_image));
+        self.operation_log.amend(Edit::Proxy);
+      },
+      Err(why) => {
+        // stay on the proxy rather than lose the edits
+        self.proxy_mode = true;
+        self.command_resp = format!("Error: {why}");
+      }
+    }
+  }
+
+  /// Recomputes the live preview of the gaussian or unsharp mask window once
+  /// its sliders have settled. Until then the last preview is left up.
+  fn update_preview(&mut self) {
+    let now = Instant::now();
+    let gaussian_previewing = self.gaussian_blur_window.is_previewing();
 
-      // set the new image
-      self.image_hidden = Some(new_image);
+    let operation = if gaussian_previewing {
+      if !self.gaussian_blur_window.preview_debounce.ready(now) {
+        return;
+      }
+      Operation::GaussianBlur {
+        sigma: self.gaussian_blur_window.sigma,
+        kernel_size: self.gaussian_blur_window.kernel.size,
+        padding: self.padding_strategy,
+      }
+    } else if self.unsharp_mask_window.is_previewing() {
+      if !self.unsharp_mask_window.preview_debounce.ready(now) {
+        return;
+      }
+      Operation::UnsharpMask {
+        sigma: self.unsharp_mask_window.sigma,
+        kernel_size: self.unsharp_mask_window.kernel.size,
+        scale: self.unsharp_mask_window.scaling_factor,
+        padding: self.padding_strategy,
+      }
+    } else {
+      // nothing is being previewed, so go back to showing the image
+      if None != self.preview_image {
+        self.preview_image = None;
+        self.redraw_image("preview was turned off".to_string());
+      }
+      return;
+    };
 
-      // clear ccl in case it is open
-      self.ccl_image_mask = None;
+    let result = match self.get_image() {
+      Some(image) => render_preview(image, &operation),
+      None => return,
+    };
 
-      // redraw the image
-      self.redraw_image("set_image was called".to_string());
+    match result {
+      Ok(preview) => {
+        self.preview_image = Some(preview);
+        self.redraw_image("preview was updated".to_string());
+      },
+      Err(why) => {
+        if gaussian_previewing {
+          self.gaussian_blur_window.error_msg = why.to_string();
+        } else {
+          self.unsharp_mask_window.error_msg = why.to_string();
+        }
+      }
     }
   }
 
+  /// Applies the last operation again, with the same parameters
+  pub fn repeat_last_operation(&mut self) {
+    if let Some(operation) = self.last_operation {
+      if let Err(why) = self.apply_operation(operation) {
+        println!("Could not repeat {}: {}", operation, why);
+      }
+    }
+  }
+
+  /// Runs each command of a chain on the current image, in order. Every step
+  /// is pushed onto the undo stack so that they can be undone one at a time.
+  pub fn run_command_chain(&mut self, commands: &Vec<String>) -> Result<(), String> {
+    let steps = run_chain(commands, self.get_image())?;
+
+    for step in steps {
+      self.set_image(Some(step));
+    }
+
+    Ok(())
+  }
+
   /* #region Helper functions */
 
   fn image_ops_helper(&mut self, op_type: OpType) {
@@ -179,14 +797,21 @@ impl ImageViewer {
         path.to_str().unwrap()
       ).unwrap();
     
-      let operation_result = perform_operation(
+      let start = Instant::now();
+      let operation_result = perform_operation_with_matching(
         &lh_image,
         &rh_image, 
-        op_type);
+        op_type,
+        self.size_matching);
 
       match operation_result {
         Ok(image) => {
+          self.report_duration(&format!("{} image", op_type), start);
+          self.histogram_overlay.update(&image);
+          self.clipping_overlay.update(&image);
           self.image_hidden = Some(image);
+          self.coalescing = None;
+          self.validate_view();
           self.redraw_image(format!(
             "Image operation \"{}\" completed successfully.", op_type
           ));
@@ -198,38 +823,333 @@ impl ImageViewer {
     }
   }
 
-  fn fit_to_screen(&mut self, image:&mut Option<PpmImage>) -> OperationResult {
+  /// Pastes an image picked from disk onto the current one with Poisson
+  /// blending, at the top left of the selection if there is one
+  fn paste_seamlessly(&mut self) {
+    if let Some(path) = rfd::FileDialog::new().pick_file() {
+      let src = match io::open_image(path.to_str().unwrap()) {
+        Ok(src) => src,
+        Err(why) => {
+          self.command_resp = format!("Error: {why}");
+          return;
+        }
+      };
 
-    use crate::core::min;
+      let (x, y) = match self.selection {
+        Some(selection) => (selection.x, selection.y),
+        None => (0, 0),
+      };
+
+      let mut mask = BinaryMask::new(src.width(), src.height());
+      for mask_y in 0..src.height() {
+        for mask_x in 0..src.width() {
+          mask.set(mask_x, mask_y, true);
+        }
+      }
+
+      let image = match self.get_image() {
+        Some(image) => image.clone(),
+        None => return,
+      };
+
+      // the blend can take thousands of passes over a large paste
+      self.start_background_edit("Seamless paste", move || {
+        poisson_blend(&image, &src, &mask, x, y)
+      });
+    }
+  }
+
+  /// Works out an edit of the current image on a background thread, so that
+  /// the GUI stays responsive. The image is picked up by poll_background_edit
+  /// once it is ready, and other edits are turned off until then.
+  fn start_background_edit<F>(&mut self, name: &str, edit: F)
+  where
+    F: FnOnce() -> OperationResult + Send + 'static
+  {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+      // if the receiver is gone there is nobody left to tell
+      let _ = sender.send(edit());
+    });
+
+    self.background_edit = Some((name.to_string(), Instant::now(), receiver));
+    self.command_resp = format!("{}...", name);
+  }
+
+  pub fn is_editing_in_background(&self) -> bool {
+    self.background_edit.is_some()
+  }
+
+  /// Checks whether the edit running in the background has finished
+  pub fn poll_background_edit(&mut self) {
+    let result = match &self.background_edit {
+      Some((_, _, receiver)) => match receiver.try_recv() {
+        Ok(result) => result,
+        Err(TryRecvError::Empty) => return,
+        Err(TryRecvError::Disconnected) => Err(CoreError::Io(
+          "The editing thread stopped unexpectedly".to_string()
+        )),
+      },
+      None => return,
+    };
+
+    let (name, start, _) = self.background_edit.take().unwrap();
+
+    match result {
+      Ok(image) => {
+        self.set_image(Some(image));
+        self.report_duration(&name, start);
+      },
+      Err(why) => self.command_resp = format!("Error: {why}"),
+    }
+  }
+
+  /// Fills in the selected part of the image from the pixels around it
+  fn fill_selection(&mut self) {
+    let (image, selection) = match (self.get_image(), self.selection) {
+      (Some(image), Some(selection)) => (image, selection),
+      _ => return,
+    };
+
+    let mut mask = BinaryMask::new(image.width(), image.height());
+    for y in selection.y..selection.y + selection.height {
+      for x in selection.x..selection.x + selection.width {
+        mask.set(x, y, true);
+      }
+    }
+
+    if let Err(why) = self.content_aware_fill(&mask) {
+      self.command_resp = format!("Error: {why}");
+    }
+  }
+
+  /// Fills in the masked part of the image from the pixels around it. The
+  /// fill is worked out in the background, like a seamless paste.
+  pub fn content_aware_fill(
+    &mut self, mask: &BinaryMask
+  ) -> Result<(), String> {
+    let image = match self.get_image() {
+      Some(image) => image.clone(),
+      None => return Err("There is no image to fill".to_string()),
+    };
+
+    if self.is_editing_in_background() {
+      return Err("Another edit is still running".to_string());
+    }
+
+    let mask = mask.clone();
+    self.start_background_edit("Content aware fill", move || {
+      inpaint(&image, &mask)
+    });
+
+    Ok(())
+  }
+
+  fn fit_to_screen(&mut self, image:&mut Option<PpmImage>) -> OperationResult {
 
     if let Some(image) = image.as_mut() {
+      let (new_width, new_height) = self.fitted_size(
+        image.width(), image.height()
+      );
 
-      if self.maintain_aspect_ratio {
-        let w_ratio = self.viewport_width / image.width() as f32;
-        let h_ratio = self.viewport_height / image.height() as f32;
+      return resize_with_prefilter(
+        image, 
+        new_width, 
+        new_height, 
+        Some(self.resize_algorithm),
+        self.resize_prefilter
+      )
+    } else {
+      return Err(CoreError::InvalidDimensions("No image to resize".to_string()))
+    }
+  }
 
-        let ratio = min(w_ratio, h_ratio);
+  /// The size that an image (or the zoomed in region of one) is fitted to
+  /// the viewport at
+  fn fitted_size(&self, width: u32, height: u32) -> (u32, u32) {
+    // a zoomed in region is always blown up to the viewport
+    let fit_mode = match (self.fit_mode, self.view_region) {
+      (FitMode::FitNoEnlarge, Some(_)) => FitMode::Fit,
+      (fit_mode, _) => fit_mode,
+    };
 
-        let new_width = image.width() as f32 * ratio;
-        let new_height = image.height() as f32 * ratio;
+    fit_mode.fitted_size(
+      width, 
+      height, 
+      self.viewport_width, 
+      self.viewport_height
+    )
+  }
+
+  /// The region of the image that is currently shown in the viewport
+  fn current_view(&self) -> Option<Selection> {
+    if let Some(view) = self.view_region {
+      return Some(view)
+    }
 
-        return resize(
-          image, 
-          new_width as u32, 
-          new_height as u32, 
-          Some(self.resize_algorithm))
+    if let Some(image) = self.get_image() {
+      if self.fit_to_window {
+        Some(Selection::new(0, 0, image.width(), image.height()))
       } else {
-        return resize(
-          image, 
-          self.viewport_width as u32,
-          self.viewport_height as u32,
-          Some(self.resize_algorithm)
-        )
+        Some(self.visible_region(image.width(), image.height()))
       }
-
-      
     } else {
-      return Err("No image to resize".to_string())
+      None
+    }
+  }
+
+  /// The part of an image shown at its full size that fits in the viewport.
+  /// The image is centered, so anything larger than the viewport only has
+  /// its middle showing.
+  fn visible_region(&self, width: u32, height: u32) -> Selection {
+    // nothing is cropped until the size of the viewport is known
+    if self.viewport_width < 1. || self.viewport_height < 1. {
+      return Selection::new(0, 0, width, height);
+    }
+
+    let visible_width = width.min(self.viewport_width as u32);
+    let visible_height = height.min(self.viewport_height as u32);
+
+    Selection::new(
+      (width - visible_width) / 2,
+      (height - visible_height) / 2,
+      visible_width,
+      visible_height
+    )
+  }
+
+  /// Drops the selection and the zoom region if they no longer fit inside
+  /// the current image (for instance after resizing, or undoing a resize)
+  fn validate_view(&mut self) {
+    let (width, height) = match self.get_image() {
+      Some(image) => (image.width(), image.height()),
+      None => (0, 0),
+    };
+
+    if let Some(view) = self.view_region {
+      if view.clamp_to(width, height) != Some(view) {
+        self.view_region = None;
+      }
+    }
+
+    if let Some(selection) = self.selection {
+      if selection.clamp_to(width, height) != Some(selection) {
+        self.selection = None;
+        self.selection_anchor = None;
+      }
+    }
+
+    // a painted mask only lines up with an image of the same size
+    self.mask_brush_window.fit_to(width, height);
+  }
+
+  /// Converts a position on the screen into a pixel coordinate of the image,
+  /// given the rectangle that the drawn image occupies on the screen
+  fn screen_to_image(&self, rect: egui::Rect, pos: egui::Pos2) -> Option<(u32, u32)> {
+    let view = self.current_view()?;
+
+    let fx = ((pos.x - rect.min.x) / rect.width()).clamp(0., 1.);
+    let fy = ((pos.y - rect.min.y) / rect.height()).clamp(0., 1.);
+
+    let x = view.x + (fx * view.width as f32) as u32;
+    let y = view.y + (fy * view.height as f32) as u32;
+
+    Some((
+      x.min(view.x + view.width - 1),
+      y.min(view.y + view.height - 1),
+    ))
+  }
+
+  /// Converts a region of the image into the rectangle it occupies on the
+  /// screen, given the rectangle that the drawn image occupies on the screen
+  fn image_to_screen(&self, rect: egui::Rect, region: &Selection) -> Option<egui::Rect> {
+    let view = self.current_view()?;
+
+    let scale_x = rect.width() / view.width as f32;
+    let scale_y = rect.height() / view.height as f32;
+
+    let min = egui::pos2(
+      rect.min.x + (region.x as f32 - view.x as f32) * scale_x,
+      rect.min.y + (region.y as f32 - view.y as f32) * scale_y,
+    );
+
+    let max = egui::pos2(
+      min.x + region.width as f32 * scale_x,
+      min.y + region.height as f32 * scale_y,
+    );
+
+    Some(egui::Rect::from_min_max(min, max).intersect(rect))
+  }
+
+  /// Paints into the mask of the brush window from mouse interaction with
+  /// the drawn image
+  fn update_brush(&mut self, response: &Response) {
+    if let Some(image) = self.get_image() {
+      let (width, height) = (image.width(), image.height());
+      self.mask_brush_window.fit_to(width, height);
+    }
+
+    if response.dragged() || response.clicked() {
+      if let Some(pos) = response.interact_pointer_pos() {
+        if let Some((x, y)) = self.screen_to_image(response.rect, pos) {
+          self.mask_brush_window.paint(x, y);
+        }
+      }
+    }
+
+    if response.drag_released() || response.clicked() {
+      self.mask_brush_window.end_stroke();
+    }
+  }
+
+  /// Updates the selection from mouse interaction with the drawn image. 
+  /// Dragging creates a new selection, and a single click clears it.
+  fn update_selection(&mut self, response: &Response) {
+    if response.clicked() {
+      self.selection = None;
+      self.selection_anchor = None;
+      return
+    }
+
+    if let Some(pos) = response.interact_pointer_pos() {
+      if let Some((x, y)) = self.screen_to_image(response.rect, pos) {
+        if response.drag_started() {
+          self.selection_anchor = Some((x, y));
+        }
+
+        if response.dragged() {
+          if let Some(anchor) = self.selection_anchor {
+            self.selection = Some(Selection::from_corners(
+              anchor.0, anchor.1, x, y
+            ));
+          }
+        }
+      }
+    }
+
+    if response.drag_released() {
+      self.selection_anchor = None;
+    }
+  }
+
+  /* #endregion */
+
+  /* #region Zoom */
+
+  /// Sets the zoom so that the selected region fills the viewport. The image
+  /// itself is left untouched.
+  pub fn zoom_to_selection(&mut self) {
+    if let Some(selection) = self.selection {
+      self.view_region = Some(selection);
+      self.redraw_image("zoomed to selection".to_string());
+    }
+  }
+
+  /// Shows the whole image in the viewport again
+  pub fn reset_zoom(&mut self) {
+    if None != self.view_region {
+      self.view_region = None;
+      self.redraw_image("zoom was reset".to_string());
     }
   }
 
@@ -239,94 +1159,511 @@ impl ImageViewer {
 
     println!("Redrawing because: '{}'", reason);
 
+    if let Some(thumbnail) = self.thumbnail_shown.take() {
+      self.draw_thumbnail(&thumbnail);
+      self.thumbnail_shown = Some(thumbnail);
+      return;
+    }
+
     let mut image_copy = if None != self.ccl_image_mask { 
       self.ccl_image_mask.clone() 
+    } else if None != self.preview_image {
+      self.preview_image.clone()
     } else { 
       self.image_hidden.clone() 
     };
     
+    // only show the part of the image that is zoomed in on, or that fits in
+    // the viewport
+    let view = match image_copy.as_ref() {
+      Some(image) => match self.view_region {
+        Some(view) => Some(view),
+        None if !self.fit_to_window => {
+          Some(self.visible_region(image.width(), image.height()))
+        },
+        None => None,
+      },
+      None => None,
+    };
+    if let (Some(view), Some(image)) = (view, image_copy.as_ref()) {
+      if let Some(cropped) = image.crop(&view) {
+        image_copy = Some(cropped);
+      }
+    }
+    
     if None != image_copy {
-      if self.fit_to_window {
+      // a zoomed in region always fills the viewport
+      if self.fit_to_window || None != self.view_region {
         if let Ok(resized) = self.fit_to_screen(&mut image_copy) {
           image_copy = Some(resized);
         }
       }
 
-      let image = image_copy.unwrap();
- 
-      let mut buf: ImageBuffer<Rgb<u8>, Vec<u8>> = image::ImageBuffer::new(
-        image.width(),
-        image.height()
-      );
+      let mut image = image_copy.unwrap();
+      self.drawn_size = Vec2::new(image.width() as f32, image.height() as f32);
 
-      for (x, y, pixels) in buf.enumerate_pixels_mut() {
-        if let Some(pixel) = image.get_pixel_by_coord(x, y) {
-          *pixels = image::Rgb(pixel);
+      // the texture never needs more pixels than the viewport has on the
+      // screen (this only affects the display)
+      let (texture_width, texture_height) = FitMode::FitNoEnlarge.fitted_size(
+        image.width(), 
+        image.height(), 
+        self.viewport_width * self.pixels_per_point, 
+        self.viewport_height * self.pixels_per_point
+      );
+      let capped = texture_width < image.width() || 
+        texture_height < image.height();
+      if capped && texture_width > 0 && texture_height > 0 {
+        if let Ok(downsampled) = resize(
+          &image, 
+          texture_width, 
+          texture_height, 
+          Some(ResizeAlgorithm::NearestNeighbor)
+        ) {
+          image = downsampled;
         }
       }
-    
-      let color_image = egui::ColorImage::from_rgb(
-        [image.width() as usize, image.height() as usize],
-        &buf.as_ref(),
-        );
-    
-      let render_result = RetainedImage::from_color_image(
-        DEBUG_FILE_NAME, 
-        color_image
-      );
-      
-      self.drawn_image = Some(render_result);
+ 
+      self.drawn_image = Some(to_retained_image(&image));
     }
   }
 
+  /// Draws the thumbnail standing in for a frame that is being loaded. Only
+  /// the small thumbnail is uploaded, and egui scales it up to the size that
+  /// the frame itself would be drawn at.
+  fn draw_thumbnail(&mut self, thumbnail: &Thumbnail) {
+    let full_width = thumbnail.full_width;
+    let full_height = thumbnail.full_height;
+
+    let zoomed = self.view_region
+      .and_then(|view| view.clamp_to(full_width, full_height));
+    let view = match zoomed {
+      Some(view) => view,
+      None if !self.fit_to_window => {
+        self.visible_region(full_width, full_height)
+      },
+      None => Selection::new(0, 0, full_width, full_height),
+    };
+
+    self.drawn_size = if self.fit_to_window || None != zoomed {
+      let (width, height) = self.fitted_size(view.width, view.height);
+      Vec2::new(width as f32, height as f32)
+    } else {
+      Vec2::new(view.width as f32, view.height as f32)
+    };
+
+    // the same part of the thumbnail, which is smaller by this much
+    let scale_x = thumbnail.image.width() as f32 / full_width as f32;
+    let scale_y = thumbnail.image.height() as f32 / full_height as f32;
+    let region = Selection::new(
+      (view.x as f32 * scale_x) as u32,
+      (view.y as f32 * scale_y) as u32,
+      ((view.width as f32 * scale_x).ceil() as u32).max(1),
+      ((view.height as f32 * scale_y).ceil() as u32).max(1)
+    );
+
+    let cropped = thumbnail.image.crop(&region);
+    let image = cropped.as_ref().unwrap_or(&thumbnail.image);
+    self.drawn_image = Some(to_retained_image(image));
+  }
+
   /* #region Control Windows */
 
   /* #endregion */
 
+  /// Saves the current image, picking the format from the file extension. 
+  /// Paths without a known extension are saved as the default save format.
+  fn save_image(&self, path: &str) -> Result<(), String> {
+    // a proxy is saved at the full resolution of the image
+    let full_image;
+    let image = match (self.proxy.as_ref(), self.get_image()) {
+      (Some(proxy), _) => {
+        full_image = proxy.render_full()?;
+        &full_image
+      },
+      (None, Some(image)) => image,
+      (None, None) => return Err("There is no image to save".to_string()),
+    };
+
+    let format = SaveFormat::from_path(path)
+      .unwrap_or(self.default_save_format);
+
+    // what was done to the image is kept with it, where the format allows
+    let options = WriteOptions {
+      color_space: self.export_color_space,
+      max_value: self.export_max_value,
+      comments: self.operation_log.comments(),
+    };
+
+    io::write_image_as(image, path, format, &options)?;
+    Ok(())
+  }
+
+  /// Opens an image on a background thread, so that the GUI stays responsive
+  /// while large files are read. The image is picked up by 
+  /// poll_loading_image once it is ready.
+  pub fn load_image(&mut self, path: &str) {
+    let (sender, receiver) = channel();
+    let thread_path = path.to_string();
+    let thumbnail_size = self.sequence.as_ref()
+      .map(|_| self.thumbnails.max_dimension());
+
+    thread::spawn(move || {
+      // read first, so that a change made while the image is being read
+      // makes the thumbnail out of date rather than passing unnoticed
+      let modified = thumbnail_size
+        .and_then(|_| modified_time(Path::new(&thread_path)));
+
+      // the frames of a sequence are shrunk into thumbnails here as well, so
+      // that the GUI thread doesn't have to
+      let result = io::open_image(&thread_path).map(|image| {
+        let thumbnail = thumbnail_size.zip(modified)
+          .map(|(size, modified)| (modified, Thumbnail::new(&image, size)));
+        (image, thumbnail)
+      });
+
+      // if the receiver is gone there is nobody left to tell
+      let _ = sender.send(result);
+    });
+
+    self.loading_image = Some((path.to_string(), receiver));
+    self.command_resp = format!("Loading \"{}\"...", path);
+  }
+
+  pub fn is_loading(&self) -> bool {
+    self.loading_image.is_some()
+  }
+
+  /// Checks whether the image being loaded in the background has finished
+  pub fn poll_loading_image(&mut self) {
+    let result = match &self.loading_image {
+      Some((_, receiver)) => match receiver.try_recv() {
+        Ok(result) => result,
+        Err(TryRecvError::Empty) => return,
+        Err(TryRecvError::Disconnected) => Err(CoreError::Io(
+          "The image loading thread stopped unexpectedly".to_string()
+        )),
+      },
+      None => return,
+    };
+
+    let (path, _) = self.loading_image.take().unwrap();
+
+    match result {
+      Ok((image, thumbnail)) => {
+        self.command_resp = format!("Opened \"{}\"", path);
+
+        if let Some((modified, thumbnail)) = thumbnail {
+          self.thumbnails.insert(Path::new(&path), modified, thumbnail);
+        }
+
+        // a newly opened image gets its own proxy
+        self.proxy = None;
+        self.set_image(Some(image));
+
+        let name = Path::new(&path).file_name()
+          .map(|name| name.to_string_lossy().to_string())
+          .unwrap_or(path.clone());
+        self.operation_log.amend(Edit::Opened(name));
+
+        self.start_proxy();
+      },
+      Err(why) => {
+        println!("{}", why);
+        self.command_resp = format!("Error: {why}");
+
+        // the frame that the thumbnail stood in for never arrived
+        if self.thumbnail_shown.take().is_some() {
+          self.redraw_image("The image could not be loaded".to_string());
+        }
+      }
+    }
+  }
+
+  /* #region Image Sequence */
+
+  /// Opens a folder of images, showing the first one
+  pub fn open_sequence(&mut self, directory: &Path) -> Result<(), String> {
+    let sequence = ImageSequence::from_directory(directory)?;
+    let path = sequence.current_path().to_str().unwrap().to_string();
+
+    // the sequence is set first, so that its frames get thumbnails
+    self.sequence = Some(sequence);
+    self.load_image(&path);
+
+    Ok(())
+  }
+
+  /// Shows the next (or previous) image of the open folder
+  pub fn step_sequence(&mut self, forward: bool) {
+    if self.is_loading() {
+      return;
+    }
+
+    let path = match self.sequence.as_mut() {
+      Some(sequence) => {
+        let moved = if forward { sequence.next() } else { sequence.previous() };
+        if !moved {
+          return;
+        }
+        sequence.current_path().to_str().unwrap().to_string()
+      },
+      None => return,
+    };
+
+    self.load_image(&path);
+    self.show_thumbnail(&path);
+  }
+
+  /// Shows the thumbnail of a frame in place of the current image, if it is
+  /// cached, until the frame itself has been loaded
+  fn show_thumbnail(&mut self, path: &str) {
+    if let Some(thumbnail) = self.thumbnails.get(Path::new(path)) {
+      self.thumbnail_shown = Some(thumbnail.clone());
+      self.redraw_image("Showing a cached thumbnail".to_string());
+    }
+  }
+
+  /// Excludes the current frame of the sequence from stacking (or includes 
+  /// it again), remembering the choice in the folder
+  pub fn toggle_frame_included(&mut self) {
+    if let Some(sequence) = self.sequence.as_mut() {
+      sequence.toggle_included();
+
+      if let Err(why) = sequence.save_excluded() {
+        self.command_resp = format!("Error: {why}");
+      }
+    }
+  }
+
+  /// Median stacks the frames of the sequence that weren't excluded, writing
+  /// the master frame into the folder of the sequence
+  fn stack_sequence(&mut self) {
+    let sequence = match self.sequence.as_ref() {
+      Some(sequence) => sequence,
+      None => return,
+    };
+
+    let frame_count = sequence.included_paths().len();
+    if 0 == frame_count {
+      self.command_resp = "Error: every frame has been excluded".to_string();
+      return;
+    }
+
+    let folder_name = sequence.directory().file_name()
+      .map(|name| name.to_string_lossy().to_string())
+      .unwrap_or_default();
+    let master_path = sequence.directory().join(batch::expand_template(
+      batch::DEFAULT_MASTER_TEMPLATE,
+      &batch::OutputName { 
+        name: &folder_name, algo: "median", params: "", ext: "tiff" 
+      }
+    ));
+
+    let mut stack = ImageStack::new();
+    stack.add_algorithm(
+      Box::new(stacking::Median::new()),
+      master_path.to_str().unwrap().to_string()
+    );
+    sequence.add_to_stack(&mut stack);
+
+    let cancel = Arc::new(AtomicBool::new(false));
+    let thread_cancel = cancel.clone();
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+      let _ = sender.send(stack.process_stack(&thread_cancel));
+    });
+
+    self.sequence_stack = Some(receiver);
+    self.sequence_stack_cancel = Some(cancel);
+    self.command_resp = format!(
+      "Stacking {} frames into \"{}\"...", frame_count, master_path.display()
+    );
+  }
+
+  /// Checks whether the stack of the sequence has finished
+  fn poll_sequence_stack(&mut self) {
+    let status = match &self.sequence_stack {
+      Some(receiver) => match receiver.try_recv() {
+        Ok(status) => status.map_err(|why| why.to_string()),
+        Err(TryRecvError::Empty) => return,
+        Err(TryRecvError::Disconnected) => Err(
+          "The stacking thread stopped unexpectedly".to_string()
+        ),
+      },
+      None => return,
+    };
+
+    self.sequence_stack = None;
+    self.sequence_stack_cancel = None;
+
+    self.command_resp = match status {
+      Ok(StackStatus::Completed) => "Stacking completed".to_string(),
+      Ok(StackStatus::Cancelled { .. }) => "Stacking was cancelled".to_string(),
+      Err(why) => format!("Error: {why}"),
+    };
+  }
+
+  /// Starts the batch set up in the batch window on a background thread
+  fn start_batch(&mut self) {
+    let window = &mut self.batch_window;
+    let (input_directory, output_directory) = match (
+      window.input_directory.clone(), window.output_directory.clone()
+    ) {
+      (Some(input), Some(output)) => (input, output),
+      _ => return,
+    };
+
+    let paths = match batch::batch_images(&input_directory) {
+      Ok(paths) => paths,
+      Err(why) => {
+        window.error_msg = why.to_string();
+        return;
+      }
+    };
+    window.error_msg = "".to_owned();
+
+    let total = paths.len();
+    let command = window.command.clone();
+    let template = window.template.clone();
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+      let progress_sender = sender.clone();
+      let result = batch::run_batch(
+        &paths, &output_directory, &command, &template, |progress| {
+          let _ = progress_sender.send(BatchMessage::Progress(progress));
+        }
+      );
+
+      // a command that can't be run at all fails every file the same way
+      let summary = result.unwrap_or_else(|why| BatchSummary {
+        total: paths.len(),
+        failures: paths.iter().map(|path| (path.clone(), why.clone())).collect(),
+      });
+      let _ = sender.send(BatchMessage::Finished(summary));
+    });
+
+    self.batch = Some(receiver);
+    self.batch_progress = Some(BatchProgress { completed: 0, total: total });
+    self.command_resp = format!(
+      "Processing \"{}\"...", input_directory.display()
+    );
+  }
+
+  /// Picks up the progress of the running batch, and its summary once it has
+  /// finished
+  fn poll_batch(&mut self) {
+    loop {
+      let message = match &self.batch {
+        Some(receiver) => match receiver.try_recv() {
+          Ok(message) => message,
+          Err(TryRecvError::Empty) => return,
+          Err(TryRecvError::Disconnected) => {
+            self.command_resp = 
+              "Error: The batch thread stopped unexpectedly".to_string();
+            self.batch = None;
+            self.batch_progress = None;
+            return;
+          }
+        },
+        None => return,
+      };
+
+      match message {
+        BatchMessage::Progress(progress) => self.batch_progress = Some(progress),
+        BatchMessage::Finished(summary) => {
+          self.command_resp = summary.report();
+          self.batch = None;
+          self.batch_progress = None;
+          return;
+        }
+      }
+    }
+  }
+
+  /* #endregion */
+
   fn create_file_menu(&mut self, ui: &mut egui::Ui) {
     ui.menu_button("File", |ui| {
       ui.spacing_mut().button_padding = Vec2::new(
         BUTTON_PADDING, 
         BUTTON_PADDING
       );
-      if ui.button("Open").clicked() {
+      // only one image is loaded at a time
+      if ui.add_enabled(
+        !self.is_loading(), egui::Button::new("Open")
+      ).clicked() {
         ui.close_menu();
         if let Some(path) = rfd::FileDialog::new().pick_file() {
-          let open_image_result = io::open_image(
-            path.to_str().unwrap()
-          );
+          self.sequence = None;
+          self.load_image(path.to_str().unwrap());
+        }
+      }
 
-          match open_image_result {
-            Ok(image) => {
-              self.set_image(Some(image));
-            },
-            Err(why) => {
-              println!("{}", why);
-            }
+      if ui.add_enabled(
+        !self.is_loading(), egui::Button::new("Open folder")
+      ).clicked() {
+        ui.close_menu();
+        if let Some(directory) = rfd::FileDialog::new().pick_folder() {
+          if let Err(why) = self.open_sequence(&directory) {
+            self.command_resp = format!("Error: {why}");
           }
         }
       }
 
+      if let Some(sequence) = self.sequence.as_mut() {
+        let toggle_label = if sequence.is_included(sequence.index()) {
+          "Exclude frame (X)"
+        } else {
+          "Include frame (X)"
+        };
+
+        if ui.button(toggle_label).clicked() {
+          self.toggle_frame_included();
+        }
+
+        if ui.add_enabled(
+          self.sequence_stack.is_none(),
+          egui::Button::new("Stack included frames")
+        ).clicked() {
+          ui.close_menu();
+          self.stack_sequence();
+        }
+      }
+
+      if ui.add_enabled(
+        self.batch.is_none(), egui::Button::new("Batch process folder")
+      ).clicked() {
+        ui.close_menu();
+        self.batch_window.toggle();
+      }
+
       // should the save as be enabled?
       let save_as_enabled = None != self.get_image();
       if ui.add_enabled(
         save_as_enabled, egui::Button::new("Save as")
       ).clicked() {
-        if let Some(path) = rfd::FileDialog::new().add_filter(
-          "Portable Pixel Map",
-          &["ppm", "PPM"]).save_file() {      
-            // TODO: Do a better job error handling when you can't write file
-            // note that we can safely use unwrap here with get_image, because
-            // the button is only enabled if get_image() is not none
-            match io::write_image(
-              self.get_image().unwrap(), 
-              path.to_str().unwrap()
-            ) {
-              Err(why) => {
-                println!("Not able to save file: {}", why)
-              },
-              Ok(_) => {}
-            }
+        ui.close_menu();
+
+        // the default format is listed first, so the dialog starts out on it
+        let mut dialog = rfd::FileDialog::new().add_filter(
+          self.default_save_format.description(),
+          self.default_save_format.extensions()
+        );
+        for format in SaveFormat::iter() {
+          if format != self.default_save_format {
+            dialog = dialog.add_filter(format.description(), format.extensions());
+          }
+        }
+
+        if let Some(mut path) = dialog.save_file() {
+          if path.extension().is_none() {
+            path.set_extension(self.default_save_format.extensions()[0]);
+          }
+
+          // TODO: Do a better job error handling when you can't write file
+          if let Err(why) = self.save_image(path.to_str().unwrap()) {
+            println!("Not able to save file: {}", why)
+          }
         }
       }
 
@@ -337,7 +1674,8 @@ impl ImageViewer {
   }
 
   fn create_edit_menu(&mut self, ui: &mut egui::Ui) {
-    let edit_enabled = None != self.get_image();
+    let edit_enabled = None != self.get_image() && 
+      !self.is_editing_in_background();
     ui.menu_button("Edit", |ui| {
       ui.spacing_mut().button_padding = Vec2::new(
         BUTTON_PADDING, 
@@ -359,13 +1697,82 @@ impl ImageViewer {
         edit_enabled, egui::Button::new("Negate")
       ).clicked() {
         ui.close_menu();
-        // we can safely use unwrap here because this button is only enabled
-        // if get_image is not none
-        if let Ok(negated_image) = negate(
-          self.get_image().unwrap()
-        ) {
-          self.set_image(Some(negated_image));
+        let _ = self.apply_operation(Operation::Negate);
+      }
+
+      if ui.add_enabled(
+        edit_enabled, egui::Button::new("Auto white balance")
+      ).clicked() {
+        ui.close_menu();
+        let _ = self.apply_operation(Operation::AutoWhiteBalance);
+      }
+
+      if ui.add_enabled(
+        edit_enabled, egui::Button::new("Replace color")
+      ).clicked() {
+        ui.close_menu();
+        self.replace_color_window.toggle();
+      }
+
+      if ui.add_enabled(
+        edit_enabled, egui::Button::new("Selective color")
+      ).clicked() {
+        ui.close_menu();
+        self.selective_color_window.toggle();
+      }
+
+      if ui.add_enabled(
+        edit_enabled, egui::Button::new("Paste seamlessly")
+      ).clicked() {
+        ui.close_menu();
+        self.paste_seamlessly();
+      }
+
+      if ui.add_enabled(
+        edit_enabled && None != self.selection,
+        egui::Button::new("Fill selection")
+      ).clicked() {
+        ui.close_menu();
+        self.fill_selection();
+      }
+
+      if ui.add_enabled(
+        edit_enabled, egui::Button::new("Paint mask")
+      ).clicked() {
+        ui.close_menu();
+        self.mask_brush_window.toggle();
+      }
+
+      ui.add_enabled(
+        edit_enabled,
+        egui::Checkbox::new(
+          &mut self.restrict_to_mask, "Only edit inside the mask"
+        )
+      );
+
+      ui.menu_button("Grayscale", |ui| {
+        for mode in GrayscaleMode::iter() {
+          if ui.add_enabled(
+            edit_enabled, egui::Button::new(mode.to_string())
+          ).clicked() {
+            ui.close_menu();
+            let _ = self.apply_operation(Operation::Grayscale { mode: mode });
+          }
         }
+      });
+
+      // repeating is only enabled once an operation has been applied
+      let repeat_label = match self.last_operation {
+        Some(operation) => format!("Repeat {} (Ctrl+F)", operation),
+        None => "Repeat last (Ctrl+F)".to_string(),
+      };
+
+      if ui.add_enabled(
+        edit_enabled && None != self.last_operation, 
+        egui::Button::new(repeat_label)
+      ).clicked() {
+        ui.close_menu();
+        self.repeat_last_operation();
       }
 
       ui.menu_button("Image", |ui| {
@@ -384,6 +1791,19 @@ impl ImageViewer {
             self.image_ops_helper(op_type);
           }
         }
+
+        if ui.add_enabled(
+          edit_enabled, 
+          egui::Button::new("Rotate 90 degrees")
+        ).clicked() {
+          ui.close_menu();
+          let _ = self.apply_operation(Operation::Rotate90);
+        }
+
+        if ui.add_enabled(edit_enabled, egui::Button::new("Rotate")).clicked() {
+          ui.close_menu();
+          self.rotate_window.toggle();
+        }
       });
 
       ui.menu_button("Filters", |ui| {
@@ -408,17 +1828,52 @@ impl ImageViewer {
           self.unsharp_mask_window.toggle();
         }
 
+        if ui.add_enabled(
+          edit_enabled, 
+          egui::Button::new("Deconvolution")
+        ).clicked() {
+          ui.close_menu();
+          self.deconvolution_window.toggle();
+        }
+
+        if ui.add_enabled(
+          edit_enabled, 
+          egui::Button::new("Difference of gaussians")
+        ).clicked() {
+          ui.close_menu();
+          self.dog_window.toggle();
+        }
+
+        if ui.add_enabled(
+          edit_enabled, 
+          egui::Button::new("High-pass sharpening")
+        ).clicked() {
+          ui.close_menu();
+          self.high_pass_window.toggle();
+        }
+
+        if ui.add_enabled(
+          edit_enabled, 
+          egui::Button::new("Clarity")
+        ).clicked() {
+          ui.close_menu();
+          self.clarity_window.toggle();
+        }
+
         if ui.add_enabled(edit_enabled, 
           egui::Button::new("Edge detection")
         ).clicked() { 
           ui.close_menu();
-          // note that we can use unwrap with confidence because the button
-          // is disabled if image is None
-          if let Ok(edge_detected) = filters::edge_detect(
-            self.get_image().unwrap()
-          ) {
-            self.set_image(Some(edge_detected));
-          }
+          let _ = self.apply_operation(Operation::EdgeDetect);
+        }
+
+        if ui.add_enabled(edit_enabled, 
+          egui::Button::new("SNR map")
+        ).clicked() { 
+          ui.close_menu();
+          let _ = self.apply_operation(Operation::SnrMap {
+            window: filters::SNR_MAP_WINDOW
+          });
         }
       });
 
@@ -443,6 +1898,14 @@ impl ImageViewer {
           ui.close_menu();
           self.show_log_controls = !self.show_log_controls;
         }
+
+        if ui.add_enabled(
+          edit_enabled, 
+          egui::Button::new("Magnitude Spectrum")
+        ).clicked() {
+          ui.close_menu();
+          let _ = self.apply_operation(Operation::MagnitudeSpectrum);
+        }
       });
 
       ui.menu_button("Hist. Equalization", |ui| {
@@ -456,11 +1919,17 @@ impl ImageViewer {
           egui::Button::new("Equalize to current")
         ).clicked() {
           ui.close_menu();
-          if let Ok(equalized_image) = histogram_equalization(
-            self.get_image().unwrap(), None
-          ) {
-            self.set_image(Some(equalized_image));
-          }
+          let _ = self.apply_operation(Operation::HistogramEqualization);
+        }
+
+        // equalizing the channels separately can shift the colors, but brings
+        // out more detail in images with a color cast
+        if ui.add_enabled(
+          edit_enabled,
+          egui::Button::new("Equalize each RGB channel")
+        ).clicked() {
+          ui.close_menu();
+          let _ = self.apply_operation(Operation::HistogramEqualizationRgb);
         }
 
         if ui.add_enabled(
@@ -507,13 +1976,26 @@ impl ImageViewer {
           "fit image to screen setting changed".to_string()
         );
       }
-      if ui.checkbox(
-        &mut self.maintain_aspect_ratio,
-        "Maintain aspect ratio"
-      ).changed() {
-        self.redraw_image(
-          "aspect ratio setting changed.".to_string()
-        );
+      for fit_mode in FitMode::iter() {
+        if ui.radio(self.fit_mode == fit_mode, fit_mode.to_string()).clicked() {
+          self.fit_mode = fit_mode;
+          self.redraw_image("fit mode changed".to_string());
+        }
+      }
+      ui.add_space(SPACING);
+      if ui.add_enabled(
+        None != self.selection,
+        egui::Button::new("Zoom to selection (Ctrl+Shift+F)")
+      ).clicked() {
+        ui.close_menu();
+        self.zoom_to_selection();
+      }
+      if ui.add_enabled(
+        None != self.view_region,
+        egui::Button::new("Reset zoom (Ctrl+0)")
+      ).clicked() {
+        ui.close_menu();
+        self.reset_zoom();
       }
       ui.add_space(SPACING);
       ui.add_enabled(
@@ -522,6 +2004,27 @@ impl ImageViewer {
         &mut self.histogram_window.is_open, "Show histogram"
         )
       );
+      if ui.add_enabled(
+        None != self.get_image(), 
+        egui::Checkbox::new(
+        &mut self.histogram_overlay.is_open, "Show histogram overlay"
+        )
+      ).changed() {
+        // the overlay isn't kept up to date while it is hidden
+        if let Some(image) = self.image_hidden.as_ref() {
+          self.histogram_overlay.update(image);
+        }
+      }
+      if ui.add_enabled(
+        None != self.get_image(), 
+        egui::Checkbox::new(
+        &mut self.clipping_overlay.is_open, "Show clipped pixels"
+        )
+      ).changed() {
+        if let Some(image) = self.image_hidden.as_ref() {
+          self.clipping_overlay.update(image);
+        }
+      }
       ui.add_space(SPACING);
     });
   }
@@ -560,6 +2063,25 @@ impl ImageViewer {
         }
 
         ui.add_space(SPACING);
+
+        if ui.radio_value(
+          &mut self.resize_algorithm, 
+          ResizeAlgorithm::Bicubic, 
+          "Bicubic"
+        ).changed() {
+          self.redraw_image("resize algorithm changed".to_string());
+        }
+
+        ui.add_space(SPACING);
+
+        if ui.checkbox(
+          &mut self.resize_prefilter, 
+          "Anti-moire prefilter"
+        ).changed() {
+          self.redraw_image("resize prefilter changed".to_string());
+        }
+
+        ui.add_space(SPACING);
       });
       ui.menu_button("Padding Strategy", |ui|{
         ui.spacing_mut().button_padding = Vec2::new(
@@ -577,7 +2099,118 @@ impl ImageViewer {
           Padding::Zero, "Zero"
         );
         ui.add_space(SPACING);
+        ui.radio_value(
+          &mut self.padding_strategy, 
+          Padding::Mirror, "Mirror"
+        );
+        ui.add_space(SPACING);
+        ui.radio_value(
+          &mut self.padding_strategy, 
+          Padding::Wrap, "Wrap"
+        );
+        ui.add_space(SPACING);
+      });
+
+      ui.menu_button("Image Size Matching", |ui|{
+        ui.spacing_mut().button_padding = Vec2::new(
+          BUTTON_PADDING, 
+          BUTTON_PADDING
+        );
+
+        for matching in SizeMatching::iter() {
+          ui.add_space(SPACING);
+          ui.radio_value(
+            &mut self.size_matching, matching, matching.to_string()
+          );
+        }
+
+        ui.add_space(SPACING);
+      });
+
+      ui.menu_button("Default Save Format", |ui|{
+        ui.spacing_mut().button_padding = Vec2::new(
+          BUTTON_PADDING, 
+          BUTTON_PADDING
+        );
+
+        for format in SaveFormat::iter() {
+          ui.add_space(SPACING);
+          if ui.radio_value(
+            &mut self.default_save_format, format, format.to_string()
+          ).changed() {
+            self.save_settings();
+          }
+        }
+
+        ui.add_space(SPACING);
+      });
+
+      // only PNGs are tagged for now
+      ui.menu_button("PNG Color Space", |ui|{
+        ui.spacing_mut().button_padding = Vec2::new(
+          BUTTON_PADDING, 
+          BUTTON_PADDING
+        );
+
+        for color_space in io::ColorSpace::iter() {
+          ui.add_space(SPACING);
+          if ui.radio_value(
+            &mut self.export_color_space, color_space, color_space.to_string()
+          ).changed() {
+            self.save_settings();
+          }
+        }
+
+        ui.add_space(SPACING);
+      });
+
+      ui.menu_button("PPM Max Value", |ui|{
+        ui.spacing_mut().button_padding = Vec2::new(
+          BUTTON_PADDING, 
+          BUTTON_PADDING
+        );
+
+        for max_value in io::MaxValue::iter() {
+          ui.add_space(SPACING);
+          if ui.radio_value(
+            &mut self.export_max_value, max_value, max_value.to_string()
+          ).changed() {
+            self.save_settings();
+          }
+        }
+
+        ui.add_space(SPACING);
       });
+
+      // leaving some cores free keeps the machine usable while stacking
+      if ui.add(egui::Slider::new(
+        &mut self.thread_count, 1..=threads::available_threads()
+      ).text("threads")).changed() {
+        if let Err(why) = threads::set_thread_count(self.thread_count) {
+          println!("{}", why);
+        }
+        self.save_settings();
+      }
+
+      let mut proxy_mode = self.proxy_mode;
+      if ui.checkbox(
+        &mut proxy_mode, "Edit large images through a proxy"
+      ).changed() {
+        self.set_proxy_mode(proxy_mode);
+      }
+      if let Some(proxy) = self.proxy.as_ref() {
+        ui.label(format!(
+          "{} edit(s) to replay at full resolution", proxy.operations().len()
+        ));
+      }
+
+      if ui.add(egui::Slider::new(
+        &mut self.preview_delay_ms, 0..=2000
+      ).text("preview delay (ms)")).changed() {
+        let delay = Duration::from_millis(self.preview_delay_ms);
+        self.gaussian_blur_window.preview_debounce.delay = delay;
+        self.unsharp_mask_window.preview_debounce.delay = delay;
+      }
       
     });
   }
@@ -615,6 +2248,9 @@ impl ImageViewer {
         }
         
         ui.horizontal(|ui| {
+          if let Some(sequence) = &self.sequence {
+            ui.label(sequence.status());
+          }
           ui.label(
             format!(
               "Viewport Size: {} x {}", 
@@ -627,6 +2263,14 @@ impl ImageViewer {
               current_image.height()
             ));
           }
+          if let Some(selection) = &self.selection {
+            ui.label(format!("Selection: {} x {} at ({}, {})",
+              selection.width,
+              selection.height,
+              selection.x,
+              selection.y
+            ));
+          }
           ui.add_space(SPACING);
           if let Some(pos) = ctx.pointer_latest_pos() {
             ui.label(
@@ -663,6 +2307,23 @@ impl ImageViewer {
           egui::Layout::left_to_right(egui::Align::Center), 
           |ui| {
           ui.vertical(|ui| {
+            if let Some(progress) = self.batch_progress {
+              ui.add(egui::ProgressBar::new(progress.fraction()).text(format!(
+                "{} of {} images, {} remaining",
+                progress.completed, progress.total, progress.remaining()
+              )));
+            }
+            if let Some(cancel) = &self.sequence_stack_cancel {
+              ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Stacking the sequence");
+                if ui.add_enabled(
+                  !cancel.load(Ordering::Relaxed), egui::Button::new("Cancel")
+                ).clicked() {
+                  cancel.store(true, Ordering::Relaxed);
+                }
+              });
+            }
             ui.label(&self.command_resp);
             ui.add_space(5.);
             ui.horizontal(|ui| {
@@ -677,10 +2338,77 @@ impl ImageViewer {
       });
     });
 
+    let command_box_response = command_box_response.unwrap();
+
+    // up and down step through the commands entered before
+    if command_box_response.has_focus() {
+      let (up_pressed, down_pressed) = {
+        let input = ctx.input();
+        (
+          input.key_pressed(egui::Key::ArrowUp), 
+          input.key_pressed(egui::Key::ArrowDown)
+        )
+      };
+
+      let recalled = if up_pressed {
+        self.command_history.previous(&self.command)
+      } else if down_pressed {
+        self.command_history.next()
+      } else {
+        None
+      };
+
+      if let Some(command) = recalled {
+        self.command = command.to_string();
+      }
+    }
+
     // handle call-back for the command box
-    if command_box_response.unwrap().lost_focus() && 
+    if command_box_response.lost_focus() && 
        ctx.input().key_pressed(egui::Key::Enter) {
+
+      self.command_history.push(&self.command);
+      if let Some(path) = self.config_file(history_file_path) {
+        if let Err(why) = self.command_history.write_file(&path) {
+          println!("{}", why);
+        }
+      }
+
+      // chained commands are run on the current image, one step at a time
+      if let Some(commands) = split_chain(&self.command) {
+        let start = Instant::now();
+        match self.run_command_chain(&commands) {
+          Err(why) => self.command_resp = format!("Error: {why}"),
+          Ok(_) => {
+            self.report_duration(
+              &format!("Chain of {} commands", commands.len()), start
+            );
+            self.command = "".to_string();
+          }
+        }
+        return
+      }
       
+      // noise is measured on the current image, and reported rather than
+      // producing a new image
+      if "noise" == self.command.trim() {
+        let noise = match self.get_image() {
+          Some(image) => filters::estimate_noise(image, filters::NOISE_TILE_SIZE),
+          None => Err(CoreError::InvalidDimensions(
+            "There is no image to measure".to_string()
+          )),
+        };
+
+        match noise {
+          Ok(noise) => {
+            self.command_resp = format!("Estimated noise: {:.3}", noise);
+            self.command = "".to_string();
+          },
+          Err(why) => self.command_resp = format!("Error: {why}"),
+        }
+        return
+      }
+
       // split the contents of the command text box into a vector of &str
       let args: Vec<&str> = self.command.split_whitespace().collect();
 
@@ -697,12 +2425,15 @@ impl ImageViewer {
       }
 
       // pass the arguments to the argument parser
+      let start = Instant::now();
       match parse_arguments(Some(string_args)) {
         Err(why) => self.command_resp = format!("Error: {why}"),
         Ok(image_output) => {
           if let Some(image) = image_output {
+            let name = format!("\"{}\"", self.command.trim());
             self.command = "".to_string();
             self.set_image(Some(image));
+            self.report_duration(&name, start);
           }
         }
       }
@@ -722,6 +2453,30 @@ impl eframe::App for ImageViewer {
       self.undo();
     }
 
+    // zoom to the selection with control-shift-f, back out with control-0, 
+    // and repeat the last operation with control-f
+    let (zoom_pressed, reset_zoom_pressed, repeat_pressed) = {
+      let input = ctx.input();
+      let f_pressed = input.modifiers.ctrl && input.key_pressed(egui::Key::F);
+      (
+        f_pressed && input.modifiers.shift,
+        input.modifiers.ctrl && input.key_pressed(egui::Key::Num0),
+        f_pressed && !input.modifiers.shift,
+      )
+    };
+
+    if repeat_pressed {
+      self.repeat_last_operation();
+    }
+
+    if zoom_pressed {
+      self.zoom_to_selection();
+    }
+
+    if reset_zoom_pressed {
+      self.reset_zoom();
+    }
+
     let scroll_x = ctx.input().scroll_delta.x;
     let scroll_y = ctx.input().scroll_delta.y;
 
@@ -733,6 +2488,32 @@ impl eframe::App for ImageViewer {
       frame.close();
     }
 
+    self.poll_loading_image();
+    self.poll_background_edit();
+    self.poll_sequence_stack();
+    self.poll_batch();
+
+    // step through an open folder with the arrow keys, and exclude frames
+    // from its stack with x (unless something is being typed)
+    if !ctx.wants_keyboard_input() {
+      let (next_pressed, previous_pressed, exclude_pressed) = {
+        let input = ctx.input();
+        (
+          input.key_pressed(egui::Key::ArrowRight),
+          input.key_pressed(egui::Key::ArrowLeft),
+          input.key_pressed(egui::Key::X),
+        )
+      };
+
+      if next_pressed || previous_pressed {
+        self.step_sequence(next_pressed);
+      }
+
+      if exclude_pressed {
+        self.toggle_frame_included();
+      }
+    }
+
     // handle loading of the various windows.
     windows::ccl_window(self, ctx);
     windows::gamma_window(self, ctx);
@@ -742,6 +2523,16 @@ impl eframe::App for ImageViewer {
     
     self.create_command_box(ctx);
 
+    // keep the kernel sliders within what makes sense for the current image
+    if let Some(image) = self.get_image() {
+      let max_kernel_size = filters::max_kernel_size(
+        image.width(), image.height()
+      );
+      self.gaussian_blur_window.kernel.set_max_size(max_kernel_size);
+      self.unsharp_mask_window.kernel.set_max_size(max_kernel_size);
+      self.deconvolution_window.kernel.set_max_size(max_kernel_size);
+    }
+
     CentralPanel::default().show(ctx, |ui| {
       
       self.histogram_window.draw(ctx);
@@ -756,64 +2547,226 @@ impl eframe::App for ImageViewer {
       }
 
       if self.histogram_window.apply_to_current {
-        if let Ok(equalized_image) = histogram_equalization(
-          self.get_image().unwrap(), None
+        if let Ok(_) = self.apply_operation(
+          Operation::HistogramEqualization
         ) {
           self.histogram_window.apply_to_current = false;
-          self.set_image(Some(equalized_image));
         }
       }
 
       self.image_histogram_window.draw(ctx);
 
       if self.image_histogram_window.apply_to_current {
+        let start = Instant::now();
         if let Ok(equalized_image) = histogram_equalization(
           self.get_image().unwrap(), 
           self.image_histogram_window.histogram.clone()
         ) {
           self.image_histogram_window.apply_to_current = false;
           self.set_image(Some(equalized_image));
+          self.report_duration("Histogram matching", start);
         }
       }
+      // where the filter windows save their presets
+      let preset_path = self.config_file(preset_file_path);
+
       /* #region Handle Gaussian Blur Window */
-      if self.gaussian_blur_window.draw(ctx) {
-        if let Some(image) = self.get_image() {
-          let result = filters::gaussian_blur(
-            image,
-            self.gaussian_blur_window.sigma,
-            self.gaussian_blur_window.kernel_size,
-            self.padding_strategy);
+      let gaussian_apply = self.gaussian_blur_window.draw(ctx);
+      save_requested_preset(
+        &mut self.presets, &mut self.gaussian_blur_window, 
+        preset_path.clone()
+      );
+      if gaussian_apply {
+        let result = self.apply_operation(Operation::GaussianBlur {
+          sigma: self.gaussian_blur_window.sigma,
+          kernel_size: self.gaussian_blur_window.kernel.size,
+          padding: self.padding_strategy,
+        });
           
-          match result {
-            Ok(image) => {
-              self.gaussian_blur_window.error_msg = "".to_owned();
-              self.set_image(Some(image))
-            },
-            Err(why) => {
-              self.gaussian_blur_window.error_msg = why;
-            }
+        match result {
+          Ok(_) => {
+            self.gaussian_blur_window.error_msg = "".to_owned();
+          },
+          Err(why) => {
+            self.gaussian_blur_window.error_msg = why;
           }
         }
       }
       /* #endregion */
 
-      if self.unsharp_mask_window.draw(ctx) {
-        if let Some(image) = self.get_image() {
-          let result = filters::unsharp_mask(
-            image,
-            self.unsharp_mask_window.sigma,
-            self.unsharp_mask_window.kernel_size,
-            self.unsharp_mask_window.scaling_factor,
-            self.padding_strategy);
+      let unsharp_apply = self.unsharp_mask_window.draw(ctx);
+      save_requested_preset(
+        &mut self.presets, &mut self.unsharp_mask_window, 
+        preset_path.clone()
+      );
+      if unsharp_apply {
+        let result = self.apply_operation(Operation::UnsharpMask {
+          sigma: self.unsharp_mask_window.sigma,
+          kernel_size: self.unsharp_mask_window.kernel.size,
+          scale: self.unsharp_mask_window.scaling_factor,
+          padding: self.padding_strategy,
+        });
           
-          match result {
-            Ok(image) => {
-              self.unsharp_mask_window.error_msg = "".to_owned();
-              self.set_image(Some(image))
-            },
-            Err(why) => {
-              self.unsharp_mask_window.error_msg = why;
-            }
+        match result {
+          Ok(_) => {
+            self.unsharp_mask_window.error_msg = "".to_owned();
+          },
+          Err(why) => {
+            self.unsharp_mask_window.error_msg = why;
+          }
+        }
+      }
+
+      let deconvolution_apply = self.deconvolution_window.draw(ctx);
+      save_requested_preset(
+        &mut self.presets, &mut self.deconvolution_window, 
+        preset_path.clone()
+      );
+      if deconvolution_apply {
+        let result = self.apply_operation(Operation::Deconvolution {
+          sigma: self.deconvolution_window.sigma,
+          kernel_size: self.deconvolution_window.kernel.size,
+          iterations: self.deconvolution_window.iterations,
+        });
+
+        match result {
+          Ok(_) => {
+            self.deconvolution_window.error_msg = "".to_owned();
+          },
+          Err(why) => {
+            self.deconvolution_window.error_msg = why;
+          }
+        }
+      }
+
+      self.update_preview();
+
+      if self.dog_window.draw(ctx) {
+        let result = self.apply_operation(Operation::DifferenceOfGaussians {
+          sigma1: self.dog_window.sigma1,
+          sigma2: self.dog_window.sigma2,
+          padding: self.padding_strategy,
+        });
+
+        match result {
+          Ok(_) => {
+            self.dog_window.error_msg = "".to_owned();
+          },
+          Err(why) => {
+            self.dog_window.error_msg = why;
+          }
+        }
+      }
+
+      if self.batch_window.draw(ctx, self.batch.is_some()) {
+        self.start_batch();
+      }
+
+      if self.high_pass_window.draw(ctx) {
+        let operation = if self.high_pass_window.high_pass_only {
+          Operation::HighPass {
+            sigma: self.high_pass_window.sigma,
+            padding: self.padding_strategy,
+          }
+        } else {
+          Operation::HighPassSharpen {
+            sigma: self.high_pass_window.sigma,
+            strength: self.high_pass_window.strength,
+            padding: self.padding_strategy,
+          }
+        };
+
+        match self.apply_operation(operation) {
+          Ok(_) => {
+            self.high_pass_window.error_msg = "".to_owned();
+          },
+          Err(why) => {
+            self.high_pass_window.error_msg = why;
+          }
+        }
+      }
+
+      if self.clarity_window.draw(ctx) {
+        let result = self.apply_operation(Operation::Clarity {
+          radius: self.clarity_window.radius,
+          strength: self.clarity_window.strength,
+        });
+
+        match result {
+          Ok(_) => {
+            self.clarity_window.error_msg = "".to_owned();
+          },
+          Err(why) => {
+            self.clarity_window.error_msg = why;
+          }
+        }
+      }
+
+      if self.mask_brush_window.draw(ctx) {
+        let result = match self.mask_brush_window.mask.clone() {
+          Some(mask) => self.content_aware_fill(&mask),
+          None => Ok(()),
+        };
+
+        match result {
+          Ok(_) => {
+            self.mask_brush_window.error_msg = "".to_owned();
+          },
+          Err(why) => {
+            self.mask_brush_window.error_msg = why;
+          }
+        }
+      }
+
+      if self.rotate_window.draw(ctx) {
+        let result = self.apply_operation(Operation::Rotate {
+          degrees: self.rotate_window.degrees,
+          padding: self.padding_strategy,
+          algorithm: self.resize_algorithm,
+        });
+
+        match result {
+          Ok(_) => {
+            self.rotate_window.error_msg = "".to_owned();
+          },
+          Err(why) => {
+            self.rotate_window.error_msg = why;
+          }
+        }
+      }
+
+      if self.replace_color_window.draw(ctx) {
+        let result = self.apply_operation(Operation::ReplaceColor {
+          target: self.replace_color_window.target,
+          replacement: self.replace_color_window.replacement,
+          tolerance: self.replace_color_window.tolerance,
+          metric: self.replace_color_window.metric,
+        });
+
+        match result {
+          Ok(_) => {
+            self.replace_color_window.error_msg = "".to_owned();
+          },
+          Err(why) => {
+            self.replace_color_window.error_msg = why;
+          }
+        }
+      }
+
+      if self.selective_color_window.draw(ctx) {
+        let result = self.apply_operation(Operation::AdjustColorRange {
+          center_hue: self.selective_color_window.center_hue,
+          hue_width: self.selective_color_window.hue_width,
+          saturation_delta: self.selective_color_window.saturation_delta,
+          value_delta: self.selective_color_window.value_delta,
+        });
+
+        match result {
+          Ok(_) => {
+            self.selective_color_window.error_msg = "".to_owned();
+          },
+          Err(why) => {
+            self.selective_color_window.error_msg = why;
           }
         }
       }
@@ -826,11 +2779,13 @@ impl eframe::App for ImageViewer {
       let new_viewport_width = ui.available_width() - VIEWPORT_WMARGIN;
 
       if new_viewport_height != self.viewport_height || 
-         new_viewport_width != self.viewport_width {
+         new_viewport_width != self.viewport_width ||
+         ctx.pixels_per_point() != self.pixels_per_point {
         resized = true;
 
         self.viewport_height = new_viewport_height;
         self.viewport_width = new_viewport_width;
+        self.pixels_per_point = ctx.pixels_per_point();
       }
 
       if resized {
@@ -841,11 +2796,50 @@ impl eframe::App for ImageViewer {
 
       /* #endregion */
 
+      let mut image_response: Option<Response> = None;
+
       ui.centered_and_justified(|ui| {
-        if let Some(buf) = &self.drawn_image {
-          buf.show(ui);
+        if let Some((path, _)) = &self.loading_image {
+          // a cached thumbnail stands in for the frame until it is read
+          match (&self.thumbnail_shown, &self.drawn_image) {
+            (Some(_), Some(buf)) => {
+              ui.add(egui::Image::new(buf.texture_id(ctx), self.drawn_size));
+            },
+            _ => {
+              ui.label(format!("Loading \"{}\"...", path));
+            },
+          }
+        } else if let Some(buf) = &self.drawn_image {
+          image_response = Some(ui.add(
+            egui::Image::new(buf.texture_id(ctx), self.drawn_size)
+              .sense(egui::Sense::click_and_drag())
+          ));
         }
       });
+
+      if let Some(response) = image_response {
+        if self.mask_brush_window.is_painting() {
+          self.update_brush(&response);
+        } else {
+          self.update_selection(&response);
+        }
+
+        if let Some(view) = self.current_view() {
+          self.clipping_overlay.draw(ui, response.rect, &view);
+          self.mask_brush_window.draw_overlay(ui, response.rect, &view);
+        }
+
+        self.histogram_overlay.draw(ui, response.rect);
+
+        // outline the selection on top of the drawn image
+        if let Some(selection) = self.selection {
+          if let Some(rect) = self.image_to_screen(response.rect, &selection) {
+            ui.painter().rect_stroke(
+              rect, 0., egui::Stroke::new(1., egui::Color32::YELLOW)
+            );
+          }
+        }
+      }
     });
   }
 }
@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+/// The file that the command history is kept in, next to the settings
+const HISTORY_FILE_NAME: &str = ".image-viewer-history";
+
+/// The most commands that are remembered. The oldest are dropped first.
+pub const MAX_HISTORY_LENGTH: usize = 100;
+
+/* #region CommandHistory */
+
+/**
+ * The commands entered into the command box, oldest first. Like a shell, up
+ * steps back through them and down steps forward again, ending on whatever
+ * was being typed before the history was opened.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandHistory {
+  entries: Vec<String>,
+  max_length: usize,
+  // the entry being shown, while stepping through the history
+  position: Option<usize>,
+  // what was in the command box when stepping started
+  draft: String,
+}
+
+impl CommandHistory {
+  pub fn new(max_length: usize) -> Self {
+    CommandHistory {
+      entries: Vec::<String>::new(),
+      max_length: max_length,
+      position: None,
+      draft: String::new(),
+    }
+  }
+
+  /// Adds a command that was entered. Blank commands and repeats of the last
+  /// command aren't added.
+  pub fn push(&mut self, command: &str) {
+    self.position = None;
+
+    let command = command.trim();
+    let is_repeat = self.entries.last().map_or(false, |last| last == command);
+    if command.is_empty() || is_repeat {
+      return;
+    }
+
+    self.entries.push(command.to_string());
+    if self.entries.len() > self.max_length {
+      let excess = self.entries.len() - self.max_length;
+      self.entries.drain(..excess);
+    }
+  }
+
+  /// Steps back to the command before the one shown. The current text of the
+  /// command box is kept, so that stepping forward again can restore it.
+  pub fn previous(&mut self, current: &str) -> Option<&str> {
+    let position = match self.position {
+      None if self.entries.is_empty() => return None,
+      None => {
+        self.draft = current.to_string();
+        self.entries.len() - 1
+      },
+      Some(position) => position.saturating_sub(1),
+    };
+
+    self.position = Some(position);
+    Some(&self.entries[position])
+  }
+
+  /// Steps forward to the command after the one shown, or back to the draft
+  /// once the newest command is passed
+  pub fn next(&mut self) -> Option<&str> {
+    let position = self.position?;
+
+    if position + 1 < self.entries.len() {
+      self.position = Some(position + 1);
+      Some(&self.entries[position + 1])
+    } else {
+      self.position = None;
+      Some(&self.draft)
+    }
+  }
+
+  /// Reads the history back from a file, one command per line. A file that
+  /// doesn't exist yet just means that nothing has been entered.
+  pub fn load_file(path: &PathBuf, max_length: usize) -> Result<Self, String> {
+    let mut history = CommandHistory::new(max_length);
+    if !path.exists() {
+      return Ok(history);
+    }
+
+    match std::fs::read_to_string(path) {
+      Ok(text) => {
+        for line in text.lines() {
+          history.push(line);
+        }
+        Ok(history)
+      },
+      Err(why) => Err(format!(
+        "Could not read command history from \"{}\": {}", path.display(), why
+      )),
+    }
+  }
+
+  pub fn write_file(&self, path: &PathBuf) -> Result<(), String> {
+    let mut text = self.entries.join("\n");
+    text.push('\n');
+
+    match std::fs::write(path, text) {
+      Ok(_) => Ok(()),
+      Err(why) => Err(format!(
+        "Could not write command history to \"{}\": {}", path.display(), why
+      )),
+    }
+  }
+}
+
+/// Where the command history is kept inside of the config directory, with
+/// the other preferences
+pub fn history_file_path(config_directory: &Path) -> PathBuf {
+  config_directory.join(HISTORY_FILE_NAME)
+}
+
+/* #endregion */
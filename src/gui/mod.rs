@@ -1,2 +1,8 @@
 pub mod gui;
-pub mod windows;
\ No newline at end of file
+pub mod windows;
+pub mod presets;
+pub mod preview;
+pub mod sequence;
+pub mod history;
+pub mod thumbnails;
+pub mod settings;
\ No newline at end of file
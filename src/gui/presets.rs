@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use eframe::egui;
+
+use crate::core::ppm::Padding;
+use super::gui::SPACING;
+
+/// The file that presets are kept in, inside of the config directory
+const PRESET_FILE_NAME: &str = ".image-viewer-presets";
+
+/// Separates the filter, name, and values of a preset on its line
+const FIELD_SEPARATOR: char = '\t';
+
+/// Separates the values of a preset from each other
+const VALUE_SEPARATOR: char = ';';
+
+/* #region Presets */
+
+/// A named set of parameters for one of the filter windows. The values are
+/// kept as strings, so that every filter can store whatever it needs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preset {
+  pub name: String,
+  pub values: BTreeMap<String, String>,
+}
+
+impl Preset {
+  pub fn new(name: &str) -> Self {
+    Preset {
+      name: clean_field(name),
+      values: BTreeMap::<String, String>::new(),
+    }
+  }
+
+  pub fn set<T: ToString>(&mut self, key: &str, value: T) {
+    self.values.insert(key.to_string(), value.to_string());
+  }
+
+  /// Gets a value of the preset, if it is there and can be parsed
+  pub fn get<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+    self.values.get(key).and_then(|value| value.parse::<T>().ok())
+  }
+
+  pub fn get_padding(&self, key: &str) -> Option<Padding> {
+    match self.values.get(key).map(|value| value.as_str()) {
+      Some("Zero") => Some(Padding::Zero),
+      Some("Repeat") => Some(Padding::Repeat),
+      Some("Mirror") => Some(Padding::Mirror),
+      Some("Wrap") => Some(Padding::Wrap),
+      _ => None,
+    }
+  }
+}
+
+/// Filter windows whose parameters can be saved to and loaded from presets
+pub trait PresetParameters {
+  /// The name that the presets of the window are filed under
+  fn filter_name(&self) -> &'static str;
+
+  /// Creates a preset out of the current parameters of the window
+  fn to_preset(&self, name: &str) -> Preset;
+
+  /// Sets the parameters of the window from a preset. Values that are
+  /// missing from the preset are left alone.
+  fn load_preset(&mut self, preset: &Preset);
+
+  fn preset_controls(&mut self) -> &mut PresetControls;
+}
+
+/// Saves the preset that a window has asked to save (if any), and writes the
+/// presets out to the preset file (if there is one)
+pub fn save_requested_preset<W: PresetParameters>(
+  store: &mut PresetStore, window: &mut W, path: Option<PathBuf>
+) {
+  let name = match window.preset_controls().take_save_request() {
+    Some(name) => name,
+    None => return,
+  };
+
+  let preset = window.to_preset(&name);
+  store.save(window.filter_name(), preset);
+  window.preset_controls().presets = store.presets(window.filter_name()).to_vec();
+
+  if let Some(path) = path {
+    if let Err(why) = store.write_file(&path) {
+      println!("{}", why);
+    }
+  }
+}
+
+/* #endregion */
+
+/* #region PresetStore */
+
+/// Every saved preset, grouped by the filter that they belong to
+#[derive(Debug, Clone, PartialEq)]
+pub struct PresetStore {
+  presets: BTreeMap<String, Vec<Preset>>,
+}
+
+impl PresetStore {
+  pub fn new() -> Self {
+    PresetStore {
+      presets: BTreeMap::<String, Vec<Preset>>::new(),
+    }
+  }
+
+  /// The presets saved for a filter
+  pub fn presets(&self, filter: &str) -> &[Preset] {
+    match self.presets.get(filter) {
+      Some(presets) => presets.as_slice(),
+      None => &[],
+    }
+  }
+
+  /// Saves a preset for a filter, replacing any preset with the same name
+  pub fn save(&mut self, filter: &str, preset: Preset) {
+    let presets = self.presets.entry(filter.to_string()).or_default();
+
+    match presets.iter_mut().find(|saved| saved.name == preset.name) {
+      Some(saved) => *saved = preset,
+      None => presets.push(preset),
+    }
+  }
+
+  /**
+   * Writes the presets out as text, one preset per line:
+   *
+   *   filter<TAB>name<TAB>key=value;key=value
+   */
+  pub fn serialize(&self) -> String {
+    let mut text = String::new();
+
+    for (filter, presets) in self.presets.iter() {
+      for preset in presets {
+        let values: Vec<String> = preset.values.iter()
+          .map(|(key, value)| format!("{}={}", key, value))
+          .collect();
+
+        text.push_str(&format!(
+          "{}{}{}{}{}\n",
+          filter, FIELD_SEPARATOR,
+          preset.name, FIELD_SEPARATOR,
+          values.join(&VALUE_SEPARATOR.to_string())
+        ));
+      }
+    }
+
+    text
+  }
+
+  /// Reads presets back from the text written by serialize. Blank lines and
+  /// lines starting with # are skipped.
+  pub fn deserialize(text: &str) -> Result<Self, String> {
+    let mut store = PresetStore::new();
+
+    for (line_number, line) in text.lines().enumerate() {
+      if line.trim().is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      let fields: Vec<&str> = line.split(FIELD_SEPARATOR).collect();
+      if fields.len() != 3 {
+        return Err(format!(
+          "Preset on line {} should have a filter, a name, and values",
+          line_number + 1
+        ));
+      }
+
+      let mut preset = Preset::new(fields[1]);
+
+      for pair in fields[2].split(VALUE_SEPARATOR).filter(|pair| !pair.is_empty()) {
+        match pair.split_once('=') {
+          Some((key, value)) => preset.set(key, value),
+          None => return Err(format!(
+            "\"{}\" on line {} is not a key=value pair", pair, line_number + 1
+          )),
+        }
+      }
+
+      store.save(fields[0], preset);
+    }
+
+    Ok(store)
+  }
+
+  /// Loads the presets from a file. A file that doesn't exist yet just means
+  /// that no presets have been saved.
+  pub fn load_file(path: &PathBuf) -> Result<Self, String> {
+    if !path.exists() {
+      return Ok(PresetStore::new());
+    }
+
+    match std::fs::read_to_string(path) {
+      Ok(text) => PresetStore::deserialize(&text),
+      Err(why) => Err(format!(
+        "Could not read presets from \"{}\": {}", path.display(), why
+      )),
+    }
+  }
+
+  pub fn write_file(&self, path: &PathBuf) -> Result<(), String> {
+    match std::fs::write(path, self.serialize()) {
+      Ok(_) => Ok(()),
+      Err(why) => Err(format!(
+        "Could not write presets to \"{}\": {}", path.display(), why
+      )),
+    }
+  }
+}
+
+/// Where the presets are kept inside of the config directory
+pub fn preset_file_path(config_directory: &Path) -> PathBuf {
+  config_directory.join(PRESET_FILE_NAME)
+}
+
+/// Takes the characters that separate fields out of a preset name
+fn clean_field(field: &str) -> String {
+  field.replace(|c: char| FIELD_SEPARATOR == c || c.is_control(), " ")
+    .trim()
+    .to_string()
+}
+
+/* #endregion */
+
+/* #region PresetControls */
+
+/// The preset picker shown at the top of a filter window
+pub struct PresetControls {
+  pub presets: Vec<Preset>,
+  // the name typed in for the next preset to save
+  pub name: String,
+  save_request: Option<String>,
+}
+
+impl PresetControls {
+  pub fn new() -> Self {
+    PresetControls {
+      presets: Vec::<Preset>::new(),
+      name: "".to_owned(),
+      save_request: None,
+    }
+  }
+
+  /// The name of the preset that the user asked to save, if they did
+  pub fn take_save_request(&mut self) -> Option<String> {
+    self.save_request.take()
+  }
+
+  /// Draws the controls, returning the preset picked to be loaded (if any)
+  pub fn draw(&mut self, ui: &mut egui::Ui, id: &str) -> Option<Preset> {
+    let mut picked: Option<Preset> = None;
+
+    ui.horizontal(|ui| {
+      egui::ComboBox::from_id_source(format!("{}_presets", id))
+        .selected_text("Load preset")
+        .show_ui(ui, |ui| {
+          for preset in self.presets.iter() {
+            if ui.selectable_label(false, preset.name.as_str()).clicked() {
+              picked = Some(preset.clone());
+              self.name = preset.name.clone();
+            }
+          }
+        });
+    });
+
+    ui.horizontal(|ui| {
+      ui.text_edit_singleline(&mut self.name);
+
+      let name = clean_field(&self.name);
+      if ui.add_enabled(
+        !name.is_empty(), egui::Button::new("Save preset")
+      ).clicked() {
+        self.save_request = Some(name);
+      }
+    });
+
+    ui.add_space(SPACING);
+
+    picked
+  }
+}
+
+/* #endregion */
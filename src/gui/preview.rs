@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+use crate::core::ppm::PpmImage;
+use crate::core::operations::{resize, Operation, OperationResult, ResizeAlgorithm};
+
+/// How long a slider has to sit still before the preview is recomputed
+pub const DEFAULT_PREVIEW_DELAY_MS: u64 = 300;
+
+/// Images with more pixels than this are previewed at a lower resolution
+pub const PREVIEW_MAX_PIXELS: u32 = 1_000_000;
+
+/* #region Debounce */
+
+/// Holds off on recomputing a preview until the parameters have stopped
+/// changing for a while, so that dragging a slider doesn't re-run the filter
+/// on every frame
+pub struct Debounce {
+  pub delay: Duration,
+  // when the parameters last changed, if the preview hasn't caught up yet
+  changed_at: Option<Instant>,
+}
+
+impl Debounce {
+  pub fn new(delay: Duration) -> Self {
+    Debounce {
+      delay: delay,
+      changed_at: None,
+    }
+  }
+
+  /// Marks the parameters as changed, restarting the wait
+  pub fn touch(&mut self, now: Instant) {
+    self.changed_at = Some(now);
+  }
+
+  pub fn is_pending(&self) -> bool {
+    self.changed_at.is_some()
+  }
+
+  /// Whether the parameters have settled and the preview should be updated.
+  /// This is only true once per change.
+  pub fn ready(&mut self, now: Instant) -> bool {
+    match self.changed_at {
+      Some(changed_at) if now.duration_since(changed_at) >= self.delay => {
+        self.changed_at = None;
+        true
+      },
+      _ => false,
+    }
+  }
+}
+
+/* #endregion */
+
+/**
+ * Renders a preview of an operation on an image. Large images are shrunk to
+ * about PREVIEW_MAX_PIXELS first, and the result is scaled back up to the
+ * size of the image, which keeps the preview quick at the cost of detail.
+ * The preview of a large image is only an approximation, as the filter runs
+ * on the smaller image with the same parameters.
+ */
+pub fn render_preview(image: &PpmImage, operation: &Operation) -> OperationResult {
+  let pixel_count = image.width() as u64 * image.height() as u64;
+
+  if pixel_count <= PREVIEW_MAX_PIXELS as u64 {
+    return operation.apply(image);
+  }
+
+  let scale = (PREVIEW_MAX_PIXELS as f64 / pixel_count as f64).sqrt();
+  let small_width = ((image.width() as f64 * scale) as u32).max(1);
+  let small_height = ((image.height() as f64 * scale) as u32).max(1);
+
+  let small = resize(
+    image, small_width, small_height, Some(ResizeAlgorithm::NearestNeighbor)
+  )?;
+  let filtered = operation.apply(&small)?;
+
+  resize(
+    &filtered, image.width(), image.height(),
+    Some(ResizeAlgorithm::NearestNeighbor)
+  )
+}
@@ -0,0 +1,210 @@
+use std::path::{Path, PathBuf};
+
+use crate::core::io::is_image_file;
+use crate::core::stacking::ImageStack;
+
+/// The file, kept in the folder of a sequence, that lists the names of the
+/// frames that were excluded from stacking (one per line)
+pub const EXCLUDED_FRAMES_FILE_NAME: &str = ".image-viewer-excluded";
+
+/* #region ImageSequence */
+
+/**
+ * The images of a folder, stepped through one at a time. This is used for
+ * reviewing a night's captures, where bad frames can be excluded before the
+ * rest are stacked.
+ */
+pub struct ImageSequence {
+  directory: PathBuf,
+  paths: Vec<PathBuf>,
+  // whether each frame is to be used when the sequence is stacked
+  included: Vec<bool>,
+  index: usize,
+}
+
+impl ImageSequence {
+  /// Reads the images of a folder in order of their file names. Master frames
+  /// written by the stacking code are skipped, as they aren't captures. Any
+  /// frames that were excluded the last time the folder was open stay 
+  /// excluded.
+  pub fn from_directory(directory: &Path) -> Result<Self, String> {
+    let entries = match std::fs::read_dir(directory) {
+      Ok(entries) => entries,
+      Err(why) => return Err(format!(
+        "Could not read folder \"{}\": {}", directory.display(), why
+      )),
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+      .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+      .filter(|path| path.is_file() && is_sequence_image(path))
+      .collect();
+    paths.sort();
+
+    if paths.is_empty() {
+      return Err(format!(
+        "There are no images in folder \"{}\"", directory.display()
+      ));
+    }
+
+    let mut sequence = ImageSequence {
+      directory: directory.to_path_buf(),
+      included: vec![true; paths.len()],
+      paths: paths,
+      index: 0,
+    };
+    sequence.load_excluded()?;
+
+    Ok(sequence)
+  }
+
+  fn excluded_frames_path(&self) -> PathBuf {
+    self.directory.join(EXCLUDED_FRAMES_FILE_NAME)
+  }
+
+  /// Excludes the frames listed in the folder's excluded frames file
+  fn load_excluded(&mut self) -> Result<(), String> {
+    let path = self.excluded_frames_path();
+    if !path.exists() {
+      return Ok(());
+    }
+
+    let text = match std::fs::read_to_string(&path) {
+      Ok(text) => text,
+      Err(why) => return Err(format!(
+        "Could not read excluded frames from \"{}\": {}", path.display(), why
+      )),
+    };
+
+    let excluded: Vec<&str> = text.lines()
+      .map(|line| line.trim())
+      .filter(|line| !line.is_empty())
+      .collect();
+
+    for (path, included) in self.paths.iter().zip(self.included.iter_mut()) {
+      if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+        *included = !excluded.contains(&name);
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Writes the names of the excluded frames into the folder, so that they 
+  /// are still excluded the next time it is opened
+  pub fn save_excluded(&self) -> Result<(), String> {
+    let path = self.excluded_frames_path();
+
+    let mut text = String::new();
+    for (frame, included) in self.paths.iter().zip(self.included.iter()) {
+      if !included {
+        if let Some(name) = frame.file_name().and_then(|name| name.to_str()) {
+          text.push_str(name);
+          text.push('\n');
+        }
+      }
+    }
+
+    // there is no need to leave an empty file behind
+    let result = if text.is_empty() {
+      if path.exists() { std::fs::remove_file(&path) } else { Ok(()) }
+    } else {
+      std::fs::write(&path, text)
+    };
+
+    match result {
+      Ok(_) => Ok(()),
+      Err(why) => Err(format!(
+        "Could not write excluded frames to \"{}\": {}", path.display(), why
+      )),
+    }
+  }
+
+  pub fn directory(&self) -> &Path {
+    &self.directory
+  }
+
+  pub fn len(&self) -> usize {
+    self.paths.len()
+  }
+
+  pub fn index(&self) -> usize {
+    self.index
+  }
+
+  pub fn current_path(&self) -> &Path {
+    &self.paths[self.index]
+  }
+
+  /// Moves on to the next frame, returning false if this is the last one
+  pub fn next(&mut self) -> bool {
+    if self.index + 1 < self.paths.len() {
+      self.index += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  /// Moves back to the previous frame, returning false if this is the first
+  pub fn previous(&mut self) -> bool {
+    if self.index > 0 {
+      self.index -= 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  pub fn is_included(&self, index: usize) -> bool {
+    self.included.get(index).copied().unwrap_or(false)
+  }
+
+  /// Includes the current frame in the stack if it was excluded, and the
+  /// other way around
+  pub fn toggle_included(&mut self) {
+    self.included[self.index] = !self.included[self.index];
+  }
+
+  /// The frames that haven't been excluded, in order
+  pub fn included_paths(&self) -> Vec<&Path> {
+    self.paths.iter()
+      .zip(self.included.iter())
+      .filter(|(_, included)| **included)
+      .map(|(path, _)| path.as_path())
+      .collect()
+  }
+
+  /// Adds every frame that hasn't been excluded to a stack
+  pub fn add_to_stack(&self, stack: &mut ImageStack) {
+    for path in self.included_paths() {
+      stack.add_image(path.to_str().unwrap());
+    }
+  }
+
+  /// The file name and position of the current frame, for showing in the GUI
+  pub fn status(&self) -> String {
+    let name = self.current_path().file_name()
+      .map(|name| name.to_string_lossy().to_string())
+      .unwrap_or_default();
+
+    format!(
+      "{} ({} / {}){}",
+      name,
+      self.index + 1,
+      self.len(),
+      if self.is_included(self.index) { "" } else { " - excluded" }
+    )
+  }
+}
+
+/// Whether a file looks like an image that can be reviewed
+fn is_sequence_image(path: &Path) -> bool {
+  let is_master = path.file_name()
+    .and_then(|name| name.to_str())
+    .map_or(false, |name| name.contains("master"));
+
+  !is_master && is_image_file(path)
+}
+
+/* #endregion */
@@ -0,0 +1,143 @@
+use std::path::{Path, PathBuf};
+use strum::IntoEnumIterator;
+
+use crate::core::io::{ColorSpace, MaxValue, SaveFormat};
+use crate::core::threads::available_threads;
+
+/// The file that the settings are kept in, inside of the user's home
+/// directory
+const SETTINGS_FILE_NAME: &str = ".image-viewer-settings";
+
+/* #region Settings */
+
+/**
+ * The choices made in the settings menu that are kept between sessions. They
+ * are written one to a line as key=value, with each value written the way it
+ * is shown in the menu.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+  pub default_save_format: SaveFormat,
+  pub export_color_space: ColorSpace,
+  pub export_max_value: MaxValue,
+  // how many threads stacking and filtering use
+  pub thread_count: usize,
+}
+
+impl Settings {
+  pub fn new() -> Self {
+    Settings {
+      default_save_format: SaveFormat::Ppm,
+      export_color_space: ColorSpace::Srgb,
+      export_max_value: MaxValue::Full,
+      thread_count: available_threads(),
+    }
+  }
+
+  pub fn serialize(&self) -> String {
+    let mut text = String::new();
+
+    text.push_str(&format!(
+      "default_save_format={}\n", self.default_save_format
+    ));
+    text.push_str(&format!(
+      "export_color_space={}\n", self.export_color_space
+    ));
+    text.push_str(&format!("export_max_value={}\n", self.export_max_value));
+    text.push_str(&format!("thread_count={}\n", self.thread_count));
+
+    text
+  }
+
+  /// Reads settings back from the text written by serialize. Blank lines and
+  /// lines starting with # are skipped, and a setting that isn't in the text
+  /// keeps its default. Unknown keys are ignored, so that a settings file
+  /// from a newer version can still be read.
+  pub fn deserialize(text: &str) -> Result<Self, String> {
+    let mut settings = Settings::new();
+
+    for (line_number, line) in text.lines().enumerate() {
+      if line.trim().is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      let (key, value) = match line.split_once('=') {
+        Some((key, value)) => (key.trim(), value.trim()),
+        None => return Err(format!(
+          "\"{}\" on line {} is not a key=value pair", line, line_number + 1
+        )),
+      };
+
+      let unknown_value = || format!(
+        "\"{}\" on line {} is not a valid {}", value, line_number + 1, key
+      );
+
+      match key {
+        "default_save_format" => {
+          settings.default_save_format = parse_choice(value)
+            .ok_or_else(unknown_value)?;
+        },
+        "export_color_space" => {
+          settings.export_color_space = parse_choice(value)
+            .ok_or_else(unknown_value)?;
+        },
+        "export_max_value" => {
+          settings.export_max_value = parse_choice(value)
+            .ok_or_else(unknown_value)?;
+        },
+        "thread_count" => {
+          settings.thread_count = value.parse::<usize>().ok()
+            .filter(|&threads| threads > 0)
+            .ok_or_else(unknown_value)?;
+        },
+        _ => {},
+      }
+    }
+
+    Ok(settings)
+  }
+
+  /// Loads the settings from a file. A file that doesn't exist yet just means
+  /// that nothing has been changed from the defaults.
+  pub fn load_file(path: &PathBuf) -> Result<Self, String> {
+    if !path.exists() {
+      return Ok(Settings::new());
+    }
+
+    match std::fs::read_to_string(path) {
+      Ok(text) => Settings::deserialize(&text),
+      Err(why) => Err(format!(
+        "Could not read settings from \"{}\": {}", path.display(), why
+      )),
+    }
+  }
+
+  pub fn write_file(&self, path: &PathBuf) -> Result<(), String> {
+    match std::fs::write(path, self.serialize()) {
+      Ok(_) => Ok(()),
+      Err(why) => Err(format!(
+        "Could not write settings to \"{}\": {}", path.display(), why
+      )),
+    }
+  }
+}
+
+/// The directory that the preferences (the settings, presets, and command 
+/// history) are kept together in, which is the user's home directory
+pub fn config_directory() -> Option<PathBuf> {
+  std::env::var_os("HOME")
+    .or_else(|| std::env::var_os("USERPROFILE"))
+    .map(PathBuf::from)
+}
+
+/// Where the settings are kept inside of the config directory
+pub fn settings_file_path(config_directory: &Path) -> PathBuf {
+  config_directory.join(SETTINGS_FILE_NAME)
+}
+
+/// The choice of a menu whose name is the value
+fn parse_choice<T: IntoEnumIterator + ToString>(value: &str) -> Option<T> {
+  T::iter().find(|choice| choice.to_string() == value)
+}
+
+/* #endregion */
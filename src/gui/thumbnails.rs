@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::core::operations::area_average;
+use crate::core::ppm::PpmImage;
+use crate::core::PIXEL_SIZE;
+use super::gui::FitMode;
+
+/// The longest side of a thumbnail
+pub const THUMBNAIL_MAX_DIMENSION: u32 = 1024;
+
+/// How much memory the thumbnails of a folder can take up, before the least
+/// recently viewed are dropped
+pub const DEFAULT_THUMBNAIL_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+/* #region ThumbnailCache */
+
+/// A shrunken copy of an image, along with the size of the image it was made
+/// from so that it can stand in for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thumbnail {
+  pub image: PpmImage,
+  pub full_width: u32,
+  pub full_height: u32,
+}
+
+impl Thumbnail {
+  /// Shrinks the image so that its longest side is at most max_dimension.
+  /// This takes a while for a large image, so it is done on the thread that
+  /// read the image rather than on the GUI thread.
+  pub fn new(image: &PpmImage, max_dimension: u32) -> Self {
+    let (width, height) = FitMode::FitNoEnlarge.fitted_size(
+      image.width(),
+      image.height(),
+      max_dimension as f32,
+      max_dimension as f32
+    );
+
+    Thumbnail {
+      image: area_average(image, width.max(1), height.max(1)),
+      full_width: image.width(),
+      full_height: image.height(),
+    }
+  }
+
+  fn bytes(&self) -> usize {
+    (self.image.width() * self.image.height()) as usize * PIXEL_SIZE
+  }
+}
+
+/**
+ * Thumbnails of the images that have been viewed, so that stepping back
+ * through a folder can show something straight away instead of waiting for
+ * the image to be decoded again. A thumbnail is only used while its file
+ * hasn't been modified since it was made. Once the thumbnails take up more
+ * than max_bytes, the least recently used are dropped.
+ */
+pub struct ThumbnailCache {
+  max_bytes: usize,
+  max_dimension: u32,
+  // least recently used first
+  entries: VecDeque<(PathBuf, SystemTime, Thumbnail)>,
+}
+
+impl ThumbnailCache {
+  pub fn new(max_bytes: usize, max_dimension: u32) -> Self {
+    ThumbnailCache {
+      max_bytes: max_bytes,
+      max_dimension: max_dimension,
+      entries: VecDeque::new(),
+    }
+  }
+
+  /// The longest side that thumbnails are made with
+  pub fn max_dimension(&self) -> u32 {
+    self.max_dimension
+  }
+
+  /// How much memory the thumbnails are taking up
+  pub fn total_bytes(&self) -> usize {
+    self.entries.iter().map(|(_, _, thumbnail)| thumbnail.bytes()).sum()
+  }
+
+  /// The thumbnail of the file, if there is one that is still up to date
+  pub fn get(&mut self, path: &Path) -> Option<&Thumbnail> {
+    let position = self.entries.iter()
+      .position(|(entry_path, _, _)| entry_path == path)?;
+    let entry = self.entries.remove(position)?;
+
+    // a file that was changed (or can't be read) needs a new thumbnail
+    if Some(entry.1) != modified_time(path) {
+      return None;
+    }
+
+    self.entries.push_back(entry);
+    self.entries.back().map(|(_, _, thumbnail)| thumbnail)
+  }
+
+  /// Keeps the thumbnail of the file, which was made from the file as it was
+  /// at the given modification time. That time should be read before the
+  /// image itself, so that a file changed in between isn't cached with the
+  /// thumbnail of its old contents.
+  pub fn insert(
+    &mut self, path: &Path, modified: SystemTime, thumbnail: Thumbnail
+  ) {
+    self.entries.retain(|(entry_path, _, _)| entry_path != path);
+    self.entries.push_back((path.to_path_buf(), modified, thumbnail));
+
+    // the newest thumbnail is kept even if it is too big on its own
+    let mut total_bytes = self.total_bytes();
+    while total_bytes > self.max_bytes && self.entries.len() > 1 {
+      if let Some((_, _, dropped)) = self.entries.pop_front() {
+        total_bytes -= dropped.bytes();
+      }
+    }
+  }
+}
+
+/// When the file was last modified, if that can be read
+pub fn modified_time(path: &Path) -> Option<SystemTime> {
+  std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/* #endregion */
@@ -1,12 +1,23 @@
 use egui::plot::{Bar, BarChart};
 use eframe::{egui::{self}, epaint::{Vec2, Color32}};
-use crate::core::{ppm::{Padding, PpmImage}, 
-operations::{Histogram, histogram_equalization}
+use crate::core::{ppm::{Padding, PpmImage, Selection}, PIXEL_SIZE, PixelBytes,
+color::ColorMetric, mask::{BinaryMask, merge_runs},
+operations::{
+  Histogram, Sample, histogram_equalization, 
+  DISPLAY_HISTOGRAM_BINS, DISPLAY_HISTOGRAM_STRIDE
+},
+filters::{MIN_KERNEL_SIZE, MIN_GAUSSIAN_SIGMA, odd_kernel_size},
+batch::DEFAULT_BATCH_TEMPLATE
 };
 use super::gui::{ImageViewer, BUTTON_PADDING, SPACING};
+use super::presets::{Preset, PresetControls, PresetParameters};
+use super::preview::{Debounce, DEFAULT_PREVIEW_DELAY_MS};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use strum_macros::{EnumIter, Display};
 
 pub fn gamma_window(app: &mut ImageViewer, ctx:&egui::Context) {
-  use crate::core::operations::gamma_transform;
+  use crate::core::operations::Operation;
 
   if app.show_gamma_controls {
     egui::Window::new("Gamma Transform Options")
@@ -19,14 +30,7 @@ pub fn gamma_window(app: &mut ImageViewer, ctx:&egui::Context) {
           0.1..=5.0).text("gamma")
         );
         if ui.button("Apply").clicked() {
-          if let Some(image) = app.get_image().as_mut() {
-            if let Ok(transform) = gamma_transform(
-              image, 
-              app.gamma, 
-              None) {
-              app.set_image(Some(transform));
-            }
-          }
+          let _ = app.apply_operation(Operation::Gamma { gamma: app.gamma });
         }
       });
     });
@@ -37,7 +41,7 @@ pub fn gamma_window(app: &mut ImageViewer, ctx:&egui::Context) {
 }
 
 pub fn log_window(app: &mut ImageViewer, ctx:&egui::Context) {
-  use crate::core::operations::log_transform;
+  use crate::core::operations::Operation;
   if app.show_log_controls {
     egui::Window::new("Log Transform Options")
       .collapsible(true)
@@ -56,15 +60,7 @@ pub fn log_window(app: &mut ImageViewer, ctx:&egui::Context) {
             0.0..=10.).text("b")
           );
           if ui.button("Apply").clicked() {
-            if let Some(new_image) = app.get_image().as_mut() {
-              if let Ok(transform) = log_transform(
-                new_image,
-                None,
-                Some(app.log_b)
-              ) {
-                app.set_image(Some(transform))
-              }
-            }
+            let _ = app.apply_operation(Operation::Log { base: app.log_b });
           }
         })
       });
@@ -72,10 +68,14 @@ pub fn log_window(app: &mut ImageViewer, ctx:&egui::Context) {
   }
 }
 
+/// The largest minimum area (in pixels) that the CCL window can be set to
+const MAX_CCL_MIN_AREA: usize = 100;
+
 /// Shows the Connected Component Label window
 pub fn ccl_window(app: &mut ImageViewer, ctx:&egui::Context) {
   use crate::core::ccl;
   use crate::core::ccl::Connectivity;
+  use strum::IntoEnumIterator;
   if app.show_ccl_controls {
     egui::Window::new("Connected Component Labeling")
       .collapsible(true)
@@ -88,36 +88,77 @@ pub fn ccl_window(app: &mut ImageViewer, ctx:&egui::Context) {
             BUTTON_PADDING
           );
 
+          // 0 only connects identical colors, 1 connects everything
           ui.add(egui::Slider::new(
             &mut app.ccl_tolerance, 
-            0.95..=1.0).text("tolerance")
+            0.0..=1.0).text("tolerance")
+          );
+
+          // smaller regions (such as hot pixels) aren't colored in
+          ui.add(egui::Slider::new(
+            &mut app.ccl_min_area, 
+            0..=MAX_CCL_MIN_AREA).text("minimum area")
           );
+
+          // how far apart two colors are, for the tolerance
+          ui.horizontal(|ui| {
+            for metric in ColorMetric::iter() {
+              ui.radio_value(&mut app.ccl_metric, metric, metric.to_string());
+            }
+          });
           
           if ui.button("8-Connected").clicked() {
+            let start = Instant::now();
             app.ccl_image_mask = Some(ccl::make_ccl_mask(
               app.get_image().as_mut().unwrap(), 
-              Connectivity::EIGHT,
-              app.ccl_tolerance)
-            );
+              Connectivity::EIGHT, 
+              app.ccl_tolerance, 
+              app.ccl_metric, 
+              app.ccl_min_area
+            ));
+            app.report_duration("CCL (8-connected)", start);
             app.redraw_image("ccl changed to 8-connected".to_string());
           }
           ui.add_space(SPACING);
           if ui.button("4-Connected").clicked() {
+            let start = Instant::now();
             app.ccl_image_mask = Some(ccl::make_ccl_mask(
               app.get_image().as_mut().unwrap(), 
-              Connectivity::FOUR, app.ccl_tolerance)
-            );
+              Connectivity::FOUR, 
+              app.ccl_tolerance, 
+              app.ccl_metric, 
+              app.ccl_min_area
+            ));
+            app.report_duration("CCL (4-connected)", start);
             app.redraw_image("ccl changed to 4-connected".to_string());
           }
           ui.add_space(SPACING);
           if ui.button("NOS Connected").clicked() {
+            let start = Instant::now();
             app.ccl_image_mask = Some(ccl::make_ccl_mask(
               app.get_image().as_mut().unwrap(), 
-              Connectivity::NOS, app.ccl_tolerance)
-            );
+              Connectivity::NOS, 
+              app.ccl_tolerance, 
+              app.ccl_metric, 
+              app.ccl_min_area
+            ));
+            app.report_duration("CCL (NOS connected)", start);
             app.redraw_image("ccl changed to NOS connected".to_string());
           }
           ui.add_space(SPACING);
+          if ui.button("Diagonal Connected").clicked() {
+            let start = Instant::now();
+            app.ccl_image_mask = Some(ccl::make_ccl_mask(
+              app.get_image().as_mut().unwrap(), 
+              Connectivity::DIAGONAL, 
+              app.ccl_tolerance, 
+              app.ccl_metric, 
+              app.ccl_min_area
+            ));
+            app.report_duration("CCL (diagonal connected)", start);
+            app.redraw_image("ccl changed to diagonal connected".to_string());
+          }
+          ui.add_space(SPACING);
           if ui.button("Clear").clicked() {
             app.ccl_image_mask = None;
             app.redraw_image("ccl was explicitly cleared".to_string());
@@ -132,6 +173,58 @@ pub fn ccl_window(app: &mut ImageViewer, ctx:&egui::Context) {
 
 /* #region Window Data Structures */
 
+/* #region KernelSizeControl */
+
+/// The kernel size slider shared by the filter windows, which keeps the size
+/// odd and within what makes sense for the current image
+pub struct KernelSizeControl {
+  pub size: i32,
+  pub max_size: i32,
+}
+
+impl KernelSizeControl {
+  pub fn new(size: i32) -> Self {
+    KernelSizeControl {
+      size: size,
+      max_size: 25,
+    }
+  }
+
+  /// Limits the kernel size to what makes sense for the current image
+  pub fn set_max_size(&mut self, max_size: i32) {
+    self.max_size = max_size;
+    self.fix();
+  }
+
+  /// Rounds the kernel size to the nearest usable (odd) value
+  pub fn fix(&mut self) {
+    self.size = odd_kernel_size(self.size, self.max_size);
+  }
+
+  /// Pulls a gaussian sigma into the range that the kernel can hold, the
+  /// same way that the size itself is fixed
+  pub fn fix_sigma(&self, sigma: f32) -> f32 {
+    sigma.clamp(MIN_GAUSSIAN_SIGMA, self.size as f32)
+  }
+
+  pub fn draw(&mut self, ui: &mut egui::Ui, text: &str) {
+    ui.add(egui::Slider::new(
+      &mut self.size, MIN_KERNEL_SIZE..=self.max_size
+    ).text(text));
+    self.fix();
+  }
+
+  /// A sigma slider for a gaussian of this kernel size
+  pub fn draw_sigma(&self, ui: &mut egui::Ui, sigma: &mut f32) {
+    ui.add(egui::Slider::new(
+      sigma, MIN_GAUSSIAN_SIGMA..=self.size as f32
+    ).text("sigma"));
+    *sigma = self.fix_sigma(*sigma);
+  }
+}
+
+/* #endregion */
+
 /* #region UnsharpMaskWindow */
 pub struct UnsharpMaskWindow {
   title: String,
@@ -139,8 +232,12 @@ pub struct UnsharpMaskWindow {
   pub error_msg: String,
   pub padding: Padding,
   pub sigma: f32,
-  pub kernel_size: i32,
+  pub kernel: KernelSizeControl,
   pub scaling_factor: f32,
+  pub preset_controls: PresetControls,
+  // whether the filter is previewed on the image as the sliders move
+  pub live_preview: bool,
+  pub preview_debounce: Debounce,
 }
 
 impl UnsharpMaskWindow {
@@ -152,7 +249,12 @@ impl UnsharpMaskWindow {
       error_msg: "".to_owned(),
       is_open: false,
       sigma: 1.,
-      kernel_size: 3
+      kernel: KernelSizeControl::new(3),
+      preset_controls: PresetControls::new(),
+      live_preview: false,
+      preview_debounce: Debounce::new(
+        Duration::from_millis(DEFAULT_PREVIEW_DELAY_MS)
+      ),
     }
   }
 
@@ -160,9 +262,19 @@ impl UnsharpMaskWindow {
     self.is_open = !self.is_open;
   }
 
+  /// Whether the filter is being previewed on the image
+  pub fn is_previewing(&self) -> bool {
+    self.is_open && self.live_preview
+  }
+
   pub fn draw(&mut self, ctx:&egui::Context) -> bool {
     let mut clicked = false;
     if self.is_open {
+      let parameters = (
+        self.sigma, self.kernel.size, self.scaling_factor, self.padding
+      );
+      let mut preview_toggled = false;
+
       egui::Window::new(self.title.as_str())
         .collapsible(true)
         .resizable(false)
@@ -171,6 +283,9 @@ impl UnsharpMaskWindow {
             if !self.error_msg.is_empty() {
               ui.colored_label(Color32::DARK_RED, self.error_msg.as_str());
             }
+            if let Some(preset) = self.preset_controls.draw(ui, &self.title) {
+              self.load_preset(&preset);
+            }
             ui.vertical(|ui2| {
               ui2.add_space(SPACING);
               ui2.radio_value(
@@ -185,27 +300,76 @@ impl UnsharpMaskWindow {
                 "Zero"
               );
               ui2.add_space(SPACING);
+              ui2.radio_value(
+                &mut self.padding, 
+                Padding::Mirror, 
+                "Mirror"
+              );
+              ui2.add_space(SPACING);
+              ui2.radio_value(
+                &mut self.padding, 
+                Padding::Wrap, 
+                "Wrap"
+              );
+              ui2.add_space(SPACING);
             });
             ui.add(egui::Slider::new(
               &mut self.scaling_factor, 0.0..=20.
             ).text("scaling factor k"));
             
-            ui.add(egui::Slider::new(
-              &mut self.sigma, 0.0..=8.0
-            ).text("sigma"));
-
-            ui.add(egui::Slider::new(
-              &mut self.kernel_size, 0..=25
-            ).text("kernel size"));
+            self.kernel.draw(ui, "kernel size");
+            self.kernel.draw_sigma(ui, &mut self.sigma);
+            preview_toggled = ui.checkbox(
+              &mut self.live_preview, "Live preview"
+            ).changed();
+            if self.live_preview && self.preview_debounce.is_pending() {
+              ui.label("Updating preview...");
+            }
             clicked = ui.button("Apply").clicked()
           });
         }); 
+
+      // the preview is only recomputed once the sliders settle
+      if preview_toggled || parameters != (
+        self.sigma, self.kernel.size, self.scaling_factor, self.padding
+      ) {
+        self.preview_debounce.touch(Instant::now());
+      }
     }
 
     clicked
   }
 }
 
+impl PresetParameters for UnsharpMaskWindow {
+  fn filter_name(&self) -> &'static str {
+    "unsharp_mask"
+  }
+
+  fn to_preset(&self, name: &str) -> Preset {
+    let mut preset = Preset::new(name);
+    preset.set("sigma", self.sigma);
+    preset.set("kernel_size", self.kernel.size);
+    preset.set("scaling_factor", self.scaling_factor);
+    preset.set("padding", format!("{:?}", self.padding));
+    preset
+  }
+
+  fn load_preset(&mut self, preset: &Preset) {
+    self.sigma = preset.get("sigma").unwrap_or(self.sigma);
+    self.kernel.size = preset.get("kernel_size").unwrap_or(self.kernel.size);
+    self.scaling_factor = preset.get("scaling_factor")
+      .unwrap_or(self.scaling_factor);
+    self.padding = preset.get_padding("padding").unwrap_or(self.padding);
+    self.kernel.fix();
+    self.sigma = self.kernel.fix_sigma(self.sigma);
+  }
+
+  fn preset_controls(&mut self) -> &mut PresetControls {
+    &mut self.preset_controls
+  }
+}
+
 /* #endregion */
 
 /* #region GausianBlurWindow */
@@ -215,7 +379,11 @@ pub struct GaussianBlurWindow {
   pub padding: Padding,
   pub error_msg: String,
   pub sigma: f32,
-  pub kernel_size: i32,
+  pub kernel: KernelSizeControl,
+  pub preset_controls: PresetControls,
+  // whether the filter is previewed on the image as the sliders move
+  pub live_preview: bool,
+  pub preview_debounce: Debounce,
 }
 
 impl GaussianBlurWindow {
@@ -226,7 +394,12 @@ impl GaussianBlurWindow {
       error_msg: "".to_owned(),
       is_open: false,
       sigma: 1.,
-      kernel_size: 3
+      kernel: KernelSizeControl::new(3),
+      preset_controls: PresetControls::new(),
+      live_preview: false,
+      preview_debounce: Debounce::new(
+        Duration::from_millis(DEFAULT_PREVIEW_DELAY_MS)
+      ),
     }
   }
 
@@ -234,9 +407,17 @@ impl GaussianBlurWindow {
     self.is_open = !self.is_open;
   }
 
+  /// Whether the filter is being previewed on the image
+  pub fn is_previewing(&self) -> bool {
+    self.is_open && self.live_preview
+  }
+
   pub fn draw(&mut self, ctx:&egui::Context) -> bool {
     let mut clicked = false;
     if self.is_open {
+      let parameters = (self.sigma, self.kernel.size, self.padding);
+      let mut preview_toggled = false;
+
       egui::Window::new(self.title.as_str())
         .collapsible(true)
         .resizable(false)
@@ -245,6 +426,9 @@ impl GaussianBlurWindow {
             if !self.error_msg.is_empty() {
               ui.colored_label(Color32::DARK_RED, self.error_msg.as_str());
             }
+            if let Some(preset) = self.preset_controls.draw(ui, &self.title) {
+              self.load_preset(&preset);
+            }
             ui.vertical(|ui| {
               ui.add_space(SPACING);
               
@@ -263,145 +447,713 @@ impl GaussianBlurWindow {
               );
               
               ui.add_space(SPACING);
+              
+              ui.radio_value(
+                &mut self.padding,
+                Padding::Mirror,
+                "Mirror"
+              );
+              
+              ui.add_space(SPACING);
+              
+              ui.radio_value(
+                &mut self.padding,
+                Padding::Wrap,
+                "Wrap"
+              );
+              
+              ui.add_space(SPACING);
             });
-            ui.add(egui::Slider::new(
-              &mut self.sigma, 0.0..=8.0).text("sigma"
-            ));
-            ui.add(egui::Slider::new(
-              &mut self.kernel_size, 0..=25).text("kernel size"
-            ));
+            self.kernel.draw(ui, "kernel size");
+            self.kernel.draw_sigma(ui, &mut self.sigma);
+            preview_toggled = ui.checkbox(
+              &mut self.live_preview, "Live preview"
+            ).changed();
+            if self.live_preview && self.preview_debounce.is_pending() {
+              ui.label("Updating preview...");
+            }
             clicked = ui.button("Apply").clicked()
           });
         }); 
+
+      // the preview is only recomputed once the sliders settle
+      if preview_toggled || 
+         parameters != (self.sigma, self.kernel.size, self.padding) {
+        self.preview_debounce.touch(Instant::now());
+      }
     }
 
     clicked
   }
 }
 
+impl PresetParameters for GaussianBlurWindow {
+  fn filter_name(&self) -> &'static str {
+    "gaussian_blur"
+  }
+
+  fn to_preset(&self, name: &str) -> Preset {
+    let mut preset = Preset::new(name);
+    preset.set("sigma", self.sigma);
+    preset.set("kernel_size", self.kernel.size);
+    preset.set("padding", format!("{:?}", self.padding));
+    preset
+  }
+
+  fn load_preset(&mut self, preset: &Preset) {
+    self.sigma = preset.get("sigma").unwrap_or(self.sigma);
+    self.kernel.size = preset.get("kernel_size").unwrap_or(self.kernel.size);
+    self.padding = preset.get_padding("padding").unwrap_or(self.padding);
+    self.kernel.fix();
+    self.sigma = self.kernel.fix_sigma(self.sigma);
+  }
+
+  fn preset_controls(&mut self) -> &mut PresetControls {
+    &mut self.preset_controls
+  }
+}
+
 /* #endregion */
 
-/* #region HistogramWindow */
-pub struct HistogramWindow {
+/* #region DeconvolutionWindow */
+pub struct DeconvolutionWindow {
   title: String,
-  pub histogram: Option<Histogram>,
-  bars: Vec<Bar>,
-  id: String,
-  image_file: Option<String>,
-  pub has_been_equalized: bool,
-  pub should_equalize_current: bool,
-  pub apply_to_current: bool,
-  pub is_open: bool,
+  is_open: bool,
+  pub error_msg: String,
+  pub sigma: f32,
+  pub kernel: KernelSizeControl,
+  pub iterations: u32,
+  pub preset_controls: PresetControls,
 }
 
-impl HistogramWindow {
-  
-  pub fn new(title: String, id: String) -> Self {
-    HistogramWindow {
-      title: title, 
-      histogram: None,
-      bars: Vec::<Bar>::new(),
-      id: id,
-      image_file: None,
-      has_been_equalized: false,
-      should_equalize_current: false,
-      apply_to_current: false,
+impl DeconvolutionWindow {
+  pub fn new(title: String) -> Self {
+    DeconvolutionWindow {
+      title: title,
+      error_msg: "".to_owned(),
       is_open: false,
+      sigma: 1.,
+      kernel: KernelSizeControl::new(5),
+      iterations: 10,
+      preset_controls: PresetControls::new(),
     }
   }
 
-  pub fn set_filepath(&mut self, filepath:String) {
-    self.has_been_equalized = false;
-    self.image_file = Some(filepath);
-  }
-
   pub fn toggle(&mut self) {
     self.is_open = !self.is_open;
   }
 
-  pub fn update(&mut self, image:&PpmImage) {
-    // given the order of operations, if this is true at the beginning of the
-    // update loop, it has already been handled, and we can clear it.
-    if self.apply_to_current {
-      self.apply_to_current = false;
+  pub fn draw(&mut self, ctx:&egui::Context) -> bool {
+    let mut clicked = false;
+    if self.is_open {
+      egui::Window::new(self.title.as_str())
+        .collapsible(true)
+        .resizable(false)
+        .show(ctx, |ui| {
+          ui.vertical(|ui| {
+            if !self.error_msg.is_empty() {
+              ui.colored_label(Color32::DARK_RED, self.error_msg.as_str());
+            }
+            if let Some(preset) = self.preset_controls.draw(ui, &self.title) {
+              self.load_preset(&preset);
+            }
+            ui.add(egui::Slider::new(
+              &mut self.sigma, 0.1..=8.0).text("psf sigma"
+            ));
+            self.kernel.draw(ui, "psf size");
+            ui.add(egui::Slider::new(
+              &mut self.iterations, 1..=100).text("iterations"
+            ));
+            clicked = ui.button("Apply").clicked()
+          });
+        }); 
     }
 
-    let hist = Histogram::from_image(image);
+    clicked
+  }
+}
 
-    self.bars.clear();
+impl PresetParameters for DeconvolutionWindow {
+  fn filter_name(&self) -> &'static str {
+    "deconvolution"
+  }
 
-    for value in hist.intensities() {
-      self.bars.push(Bar::new(
-        *value as f64,
-        *hist.data.get(value).unwrap() as f64
-      ));
-    }
+  fn to_preset(&self, name: &str) -> Preset {
+    let mut preset = Preset::new(name);
+    preset.set("sigma", self.sigma);
+    preset.set("kernel_size", self.kernel.size);
+    preset.set("iterations", self.iterations);
+    preset
+  }
 
-    self.has_been_equalized = false;
-    self.histogram = Some(hist);
+  fn load_preset(&mut self, preset: &Preset) {
+    self.sigma = preset.get("sigma").unwrap_or(self.sigma);
+    self.kernel.size = preset.get("kernel_size").unwrap_or(self.kernel.size);
+    self.iterations = preset.get("iterations").unwrap_or(self.iterations);
+    self.kernel.fix();
   }
 
-  pub fn draw(&mut self, ctx:&egui::Context) {
-    use egui::plot::Plot;
+  fn preset_controls(&mut self) -> &mut PresetControls {
+    &mut self.preset_controls
+  }
+}
 
-    if self.is_open && None != self.histogram {
+/* #endregion */
 
-      let filepath = self.image_file.clone();
-      let mut should_equalize = false;
-      let mut should_apply = false;
+/* #region RotateWindow */
+pub struct RotateWindow {
+  title: String,
+  is_open: bool,
+  pub error_msg: String,
+  pub degrees: f32,
+}
+
+impl RotateWindow {
+  pub fn new(title: String) -> Self {
+    RotateWindow {
+      title: title,
+      error_msg: "".to_owned(),
+      is_open: false,
+      degrees: 0.,
+    }
+  }
 
+  pub fn toggle(&mut self) {
+    self.is_open = !self.is_open;
+  }
+
+  pub fn draw(&mut self, ctx:&egui::Context) -> bool {
+    let mut clicked = false;
+    if self.is_open {
       egui::Window::new(self.title.as_str())
         .collapsible(true)
-        .resizable(true)
-        .min_height(150.)
-        .min_width(300.)
+        .resizable(false)
         .show(ctx, |ui| {
           ui.vertical(|ui| {
-            
-            let hist_plot = BarChart::new(self.bars.clone());
-            
-            let plotter = Plot::new(self.id.as_str())
-              .allow_boxed_zoom(false)
-              .allow_scroll(false)
-              .allow_zoom(false)
-              .allow_drag(false);
-
-            plotter.view_aspect(2.0).show(
-                ui,
-                |plot_ui| 
-                plot_ui.bar_chart(hist_plot)
-              );
-
-            if let Some(path) = filepath {
-              ui.label(path);
+            if !self.error_msg.is_empty() {
+              ui.colored_label(Color32::DARK_RED, self.error_msg.as_str());
             }
+            ui.add(egui::Slider::new(
+              &mut self.degrees, -180.0..=180.0).text("degrees"
+            ));
+            clicked = ui.button("Apply").clicked()
+          });
+        }); 
+    }
 
-            ui.vertical_centered_justified(|ui| {
-              ui.spacing_mut().button_padding = Vec2::new(
-                BUTTON_PADDING, BUTTON_PADDING
-              );
-              
-              if ui.add_enabled(
-                !self.has_been_equalized, 
-                egui::Button::new("Equalize Histogram")
-              ).clicked() {
-                should_equalize = true;
-              }
+    clicked
+  }
+}
 
-              if ui.button("Apply to current image").clicked() {
-                should_apply = true;
-              }
-            })
-          });
-      });
+/* #endregion */
 
-      if should_equalize {
-        use crate::core::io::open_image;
-        // open the image that this histogram is made for
-        if let Some(image_path) = &self.image_file {
-          if let Ok(image) = open_image(image_path.as_str()) {
-            // if we actually have a histogram (not gauranteed)
-            if let Some(hist) = self.histogram.clone() {
+/* #region DifferenceOfGaussiansWindow */
+pub struct DifferenceOfGaussiansWindow {
+  title: String,
+  is_open: bool,
+  pub error_msg: String,
+  // the finer of the two blurs, structure smaller than this is dropped
+  pub sigma1: f32,
+  // the coarser of the two blurs, structure larger than this is dropped
+  pub sigma2: f32,
+}
+
+impl DifferenceOfGaussiansWindow {
+  pub fn new(title: String) -> Self {
+    DifferenceOfGaussiansWindow {
+      title: title,
+      error_msg: "".to_owned(),
+      is_open: false,
+      sigma1: 1.,
+      sigma2: 4.,
+    }
+  }
+
+  pub fn toggle(&mut self) {
+    self.is_open = !self.is_open;
+  }
+
+  pub fn draw(&mut self, ctx:&egui::Context) -> bool {
+    let mut clicked = false;
+    if self.is_open {
+      egui::Window::new(self.title.as_str())
+        .collapsible(true)
+        .resizable(false)
+        .show(ctx, |ui| {
+          ui.vertical(|ui| {
+            if !self.error_msg.is_empty() {
+              ui.colored_label(Color32::DARK_RED, self.error_msg.as_str());
+            }
+            ui.add(egui::Slider::new(
+              &mut self.sigma1, 0.33..=20.0
+            ).text("fine sigma"));
+            ui.add(egui::Slider::new(
+              &mut self.sigma2, 0.33..=20.0
+            ).text("coarse sigma"));
+            clicked = ui.button("Apply").clicked()
+          });
+        }); 
+    }
+
+    clicked
+  }
+}
+
+/* #endregion */
+
+/* #region HighPassWindow */
+pub struct HighPassWindow {
+  title: String,
+  is_open: bool,
+  pub error_msg: String,
+  pub sigma: f32,
+  // how much of the blended high-pass is mixed back into the image
+  pub strength: f32,
+  // apply just the high-pass, rather than sharpening with it
+  pub high_pass_only: bool,
+}
+
+impl HighPassWindow {
+  pub fn new(title: String) -> Self {
+    HighPassWindow {
+      title: title,
+      error_msg: "".to_owned(),
+      is_open: false,
+      sigma: 2.,
+      strength: 0.5,
+      high_pass_only: false,
+    }
+  }
+
+  pub fn toggle(&mut self) {
+    self.is_open = !self.is_open;
+  }
+
+  pub fn draw(&mut self, ctx:&egui::Context) -> bool {
+    let mut clicked = false;
+    if self.is_open {
+      egui::Window::new(self.title.as_str())
+        .collapsible(true)
+        .resizable(false)
+        .show(ctx, |ui| {
+          ui.vertical(|ui| {
+            if !self.error_msg.is_empty() {
+              ui.colored_label(Color32::DARK_RED, self.error_msg.as_str());
+            }
+            ui.add(egui::Slider::new(
+              &mut self.sigma, 0.33..=20.0
+            ).text("sigma"));
+            ui.add_enabled(!self.high_pass_only, egui::Slider::new(
+              &mut self.strength, 0.0..=1.0
+            ).text("strength"));
+            ui.checkbox(&mut self.high_pass_only, "High-pass only");
+            clicked = ui.button("Apply").clicked()
+          });
+        }); 
+    }
+
+    clicked
+  }
+}
+
+/* #endregion */
+
+/* #region ReplaceColorWindow */
+pub struct ReplaceColorWindow {
+  title: String,
+  is_open: bool,
+  pub error_msg: String,
+  // the color to replace, and what to replace it with
+  pub target: PixelBytes<u8>,
+  pub replacement: PixelBytes<u8>,
+  pub tolerance: f32,
+  pub metric: ColorMetric,
+}
+
+impl ReplaceColorWindow {
+  pub fn new(title: String) -> Self {
+    ReplaceColorWindow {
+      title: title,
+      error_msg: "".to_owned(),
+      is_open: false,
+      target: [255, 255, 255],
+      replacement: [0, 0, 0],
+      tolerance: 0.1,
+      metric: ColorMetric::Redmean,
+    }
+  }
+
+  pub fn toggle(&mut self) {
+    self.is_open = !self.is_open;
+  }
+
+  pub fn draw(&mut self, ctx:&egui::Context) -> bool {
+    use strum::IntoEnumIterator;
+
+    let mut clicked = false;
+    if self.is_open {
+      egui::Window::new(self.title.as_str())
+        .collapsible(true)
+        .resizable(false)
+        .show(ctx, |ui| {
+          ui.vertical(|ui| {
+            if !self.error_msg.is_empty() {
+              ui.colored_label(Color32::DARK_RED, self.error_msg.as_str());
+            }
+            ui.horizontal(|ui| {
+              ui.color_edit_button_srgb(&mut self.target);
+              ui.label("color to replace");
+            });
+            ui.horizontal(|ui| {
+              ui.color_edit_button_srgb(&mut self.replacement);
+              ui.label("replacement");
+            });
+            ui.add(egui::Slider::new(
+              &mut self.tolerance, 0.0..=1.0
+            ).text("tolerance"));
+            ui.horizontal(|ui| {
+              for metric in ColorMetric::iter() {
+                ui.radio_value(&mut self.metric, metric, metric.to_string());
+              }
+            });
+            clicked = ui.button("Apply").clicked()
+          });
+        }); 
+    }
+
+    clicked
+  }
+}
+
+/* #endregion */
+
+/* #region SelectiveColorWindow */
+pub struct SelectiveColorWindow {
+  title: String,
+  is_open: bool,
+  pub error_msg: String,
+  // the middle of the hues that are adjusted, and how far either side of it
+  pub center_hue: f32,
+  pub hue_width: f32,
+  pub saturation_delta: f32,
+  pub value_delta: f32,
+}
+
+impl SelectiveColorWindow {
+  pub fn new(title: String) -> Self {
+    SelectiveColorWindow {
+      title: title,
+      error_msg: "".to_owned(),
+      is_open: false,
+      center_hue: 220.,
+      hue_width: 30.,
+      saturation_delta: 0.,
+      value_delta: 0.,
+    }
+  }
+
+  pub fn toggle(&mut self) {
+    self.is_open = !self.is_open;
+  }
+
+  pub fn draw(&mut self, ctx:&egui::Context) -> bool {
+    let mut clicked = false;
+    if self.is_open {
+      egui::Window::new(self.title.as_str())
+        .collapsible(true)
+        .resizable(false)
+        .show(ctx, |ui| {
+          ui.vertical(|ui| {
+            if !self.error_msg.is_empty() {
+              ui.colored_label(Color32::DARK_RED, self.error_msg.as_str());
+            }
+            ui.add(egui::Slider::new(
+              &mut self.center_hue, 0.0..=360.0
+            ).text("hue"));
+            ui.add(egui::Slider::new(
+              &mut self.hue_width, 0.0..=180.0
+            ).text("hue width"));
+            ui.add(egui::Slider::new(
+              &mut self.saturation_delta, -1.0..=1.0
+            ).text("saturation"));
+            ui.add(egui::Slider::new(
+              &mut self.value_delta, -1.0..=1.0
+            ).text("value"));
+            clicked = ui.button("Apply").clicked()
+          });
+        }); 
+    }
+
+    clicked
+  }
+}
+
+/* #endregion */
+
+/* #region ClarityWindow */
+pub struct ClarityWindow {
+  title: String,
+  is_open: bool,
+  pub error_msg: String,
+  // the sigma of the blur, which is the size of the areas given contrast
+  pub radius: f32,
+  pub strength: f32,
+}
+
+impl ClarityWindow {
+  pub fn new(title: String) -> Self {
+    ClarityWindow {
+      title: title,
+      error_msg: "".to_owned(),
+      is_open: false,
+      radius: 20.,
+      strength: 0.3,
+    }
+  }
+
+  pub fn toggle(&mut self) {
+    self.is_open = !self.is_open;
+  }
+
+  pub fn draw(&mut self, ctx:&egui::Context) -> bool {
+    let mut clicked = false;
+    if self.is_open {
+      egui::Window::new(self.title.as_str())
+        .collapsible(true)
+        .resizable(false)
+        .show(ctx, |ui| {
+          ui.vertical(|ui| {
+            if !self.error_msg.is_empty() {
+              ui.colored_label(Color32::DARK_RED, self.error_msg.as_str());
+            }
+            ui.add(egui::Slider::new(
+              &mut self.radius, 1.0..=50.0
+            ).text("radius"));
+            ui.add(egui::Slider::new(
+              &mut self.strength, -1.0..=2.0
+            ).text("strength"));
+            clicked = ui.button("Apply").clicked()
+          });
+        }); 
+    }
+
+    clicked
+  }
+}
+
+/* #endregion */
+
+/* #region BatchWindow */
+pub struct BatchWindow {
+  title: String,
+  is_open: bool,
+  pub error_msg: String,
+  // the command applied to each image, like "gblur -s 2 -k 7"
+  pub command: String,
+  // what the results are named, like "{name}_{algo}.{ext}"
+  pub template: String,
+  pub input_directory: Option<PathBuf>,
+  pub output_directory: Option<PathBuf>,
+}
+
+impl BatchWindow {
+  pub fn new(title: String) -> Self {
+    BatchWindow {
+      title: title,
+      error_msg: "".to_owned(),
+      is_open: false,
+      command: "".to_owned(),
+      template: DEFAULT_BATCH_TEMPLATE.to_owned(),
+      input_directory: None,
+      output_directory: None,
+    }
+  }
+
+  pub fn toggle(&mut self) {
+    self.is_open = !self.is_open;
+  }
+
+  /// Draws the window. Returns true when the batch should be started, which
+  /// can only happen once both folders have been chosen.
+  pub fn draw(&mut self, ctx:&egui::Context, is_running: bool) -> bool {
+    let mut clicked = false;
+    if self.is_open {
+      egui::Window::new(self.title.as_str())
+        .collapsible(true)
+        .resizable(false)
+        .show(ctx, |ui| {
+          ui.vertical(|ui| {
+            if !self.error_msg.is_empty() {
+              ui.colored_label(Color32::DARK_RED, self.error_msg.as_str());
+            }
+            ui.add(egui::TextEdit::singleline(&mut self.command)
+              .code_editor()
+              .hint_text("command, like gblur -s 2 -k 7"));
+            ui.horizontal(|ui| {
+              ui.label("Save as");
+              ui.add(egui::TextEdit::singleline(&mut self.template)
+                .code_editor()
+                .hint_text(DEFAULT_BATCH_TEMPLATE));
+            }).response.on_hover_text(
+              "{name}, {algo}, {params} and {ext} are filled in for each image"
+            );
+
+            for (label, directory) in [
+              ("Input folder", &mut self.input_directory),
+              ("Output folder", &mut self.output_directory),
+            ] {
+              ui.horizontal(|ui| {
+                if ui.button(label).clicked() {
+                  if let Some(picked) = rfd::FileDialog::new().pick_folder() {
+                    *directory = Some(picked);
+                  }
+                }
+                ui.label(directory.as_ref().map_or(
+                  "(none)".to_string(), |path| path.display().to_string()
+                ));
+              });
+            }
+
+            let ready = !is_running &&
+              self.input_directory.is_some() &&
+              self.output_directory.is_some();
+            clicked = ui.add_enabled(ready, egui::Button::new("Run")).clicked()
+          });
+        });
+    }
+
+    clicked
+  }
+}
+
+/* #endregion */
+
+/* #region HistogramWindow */
+pub struct HistogramWindow {
+  title: String,
+  pub histogram: Option<Histogram>,
+  bars: Vec<Bar>,
+  id: String,
+  image_file: Option<String>,
+  pub has_been_equalized: bool,
+  pub should_equalize_current: bool,
+  pub apply_to_current: bool,
+  pub is_open: bool,
+}
+
+impl HistogramWindow {
+  
+  pub fn new(title: String, id: String) -> Self {
+    HistogramWindow {
+      title: title, 
+      histogram: None,
+      bars: Vec::<Bar>::new(),
+      id: id,
+      image_file: None,
+      has_been_equalized: false,
+      should_equalize_current: false,
+      apply_to_current: false,
+      is_open: false,
+    }
+  }
+
+  pub fn set_filepath(&mut self, filepath:String) {
+    self.has_been_equalized = false;
+    self.image_file = Some(filepath);
+  }
+
+  pub fn toggle(&mut self) {
+    self.is_open = !self.is_open;
+  }
+
+  pub fn update(&mut self, image:&PpmImage) {
+    // given the order of operations, if this is true at the beginning of the
+    // update loop, it has already been handled, and we can clear it.
+    if self.apply_to_current {
+      self.apply_to_current = false;
+    }
+
+    // the full precision histogram is kept for equalization, but showing it
+    // would mean thousands of thin, mostly empty bars
+    let hist = Histogram::from_image(image);
+    let display_hist = Histogram::from_image_strided(
+      image, DISPLAY_HISTOGRAM_BINS, DISPLAY_HISTOGRAM_STRIDE
+    );
+
+    self.bars.clear();
+
+    for key in display_hist.intensities() {
+      self.bars.push(Bar::new(
+        display_hist.to_value(*key as f32) as f64,
+        *display_hist.data.get(key).unwrap() as f64
+      ));
+    }
+
+    self.has_been_equalized = false;
+    self.histogram = Some(hist);
+  }
+
+  pub fn draw(&mut self, ctx:&egui::Context) {
+    use egui::plot::Plot;
+
+    if self.is_open && None != self.histogram {
+
+      let filepath = self.image_file.clone();
+      let mut should_equalize = false;
+      let mut should_apply = false;
+
+      egui::Window::new(self.title.as_str())
+        .collapsible(true)
+        .resizable(true)
+        .min_height(150.)
+        .min_width(300.)
+        .show(ctx, |ui| {
+          ui.vertical(|ui| {
+            
+            let hist_plot = BarChart::new(self.bars.clone());
+            
+            let plotter = Plot::new(self.id.as_str())
+              .allow_boxed_zoom(false)
+              .allow_scroll(false)
+              .allow_zoom(false)
+              .allow_drag(false);
+
+            plotter.view_aspect(2.0).show(
+                ui,
+                |plot_ui| 
+                plot_ui.bar_chart(hist_plot)
+              );
+
+            if let Some(path) = filepath {
+              ui.label(path);
+            }
+
+            ui.vertical_centered_justified(|ui| {
+              ui.spacing_mut().button_padding = Vec2::new(
+                BUTTON_PADDING, BUTTON_PADDING
+              );
+              
+              if ui.add_enabled(
+                !self.has_been_equalized, 
+                egui::Button::new("Equalize Histogram")
+              ).clicked() {
+                should_equalize = true;
+              }
+
+              if ui.button("Apply to current image").clicked() {
+                should_apply = true;
+              }
+            })
+          });
+      });
+
+      if should_equalize {
+        use crate::core::io::open_image;
+        // open the image that this histogram is made for
+        if let Some(image_path) = &self.image_file {
+          if let Ok(image) = open_image(image_path.as_str()) {
+            // if we actually have a histogram (not gauranteed)
+            if let Some(hist) = self.histogram.clone() {
               // if we successfully equalized the histogram
               if let Ok(equalized) = histogram_equalization(
                 &image, Some(hist)
@@ -428,4 +1180,405 @@ impl HistogramWindow {
 
 /* #endregion */
 
+/* #region HistogramOverlay */
+pub const HISTOGRAM_OVERLAY_BINS: usize = 64;
+const HISTOGRAM_OVERLAY_SIZE: Vec2 = Vec2::new(192., 64.);
+
+/// A small value channel histogram painted over a corner of the image, for a
+/// quick check of the exposure
+pub struct HistogramOverlay {
+  pub is_open: bool,
+  // the height of each bin, relative to the tallest one (0..1)
+  pub bins: Vec<f32>,
+}
+
+impl HistogramOverlay {
+  pub fn new() -> Self {
+    HistogramOverlay {
+      is_open: false,
+      bins: Vec::<f32>::new(),
+    }
+  }
+
+  pub fn update(&mut self, image:&PpmImage) {
+    // no need to do the work if the overlay isn't shown, it is updated again
+    // when it gets turned on
+    if !self.is_open {
+      self.bins.clear();
+      return;
+    }
+
+    let histogram = Histogram::from_image_strided(
+      image, HISTOGRAM_OVERLAY_BINS as u32, DISPLAY_HISTOGRAM_STRIDE
+    );
+
+    let mut bins = vec![0.; HISTOGRAM_OVERLAY_BINS];
+    for (key, count) in histogram.data.iter() {
+      bins[*key as usize] = *count;
+    }
+
+    let tallest = bins.iter().cloned().fold(0., f32::max);
+    if tallest > 0. {
+      for bin in bins.iter_mut() {
+        *bin /= tallest;
+      }
+    }
+
+    self.bins = bins;
+  }
+
+  /// Paints the histogram into the bottom right corner of the given rect
+  pub fn draw(&self, ui:&egui::Ui, rect:egui::Rect) {
+    if !self.is_open || self.bins.is_empty() {
+      return;
+    }
+
+    let overlay_rect = egui::Rect::from_min_size(
+      rect.right_bottom() - HISTOGRAM_OVERLAY_SIZE - Vec2::new(SPACING, SPACING),
+      HISTOGRAM_OVERLAY_SIZE
+    );
+
+    let painter = ui.painter();
+    painter.rect_filled(
+      overlay_rect, 2., Color32::from_rgba_unmultiplied(0, 0, 0, 160)
+    );
+
+    let bar_width = overlay_rect.width() / self.bins.len() as f32;
+    for (i, bin) in self.bins.iter().enumerate() {
+      let left = overlay_rect.left() + i as f32 * bar_width;
+      let top = overlay_rect.bottom() - bin * overlay_rect.height();
+      painter.rect_filled(
+        egui::Rect::from_min_max(
+          egui::pos2(left, top),
+          egui::pos2(left + bar_width, overlay_rect.bottom())
+        ),
+        0.,
+        Color32::from_rgba_unmultiplied(220, 220, 220, 200)
+      );
+    }
+  }
+}
+
+/* #endregion */
+
+/* #region ClippingOverlay */
+
+/// Crushed shadows (every channel at 0) are highlighted in blue
+const CLIPPED_SHADOW_COLOR: Color32 = Color32::from_rgba_premultiplied(0, 0, 160, 160);
+
+/// Blown highlights (any channel at 255) are highlighted in red
+const CLIPPED_HIGHLIGHT_COLOR: Color32 = Color32::from_rgba_premultiplied(160, 0, 0, 160);
+
+/// A horizontal run of clipped pixels on a single row of the image
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ClippedRun {
+  pub y: u32,
+  pub x_start: u32,
+  // one past the last clipped pixel
+  pub x_end: u32,
+  // whether the pixels are blown highlights (or else crushed shadows)
+  pub highlight: bool,
+}
+
+/// Highlights the pixels of the image that have been clipped to black or 
+/// white (the "blinkies" of a camera's review screen). The image itself is 
+/// never touched, the clipped pixels are painted over it.
+pub struct ClippingOverlay {
+  pub is_open: bool,
+  pub runs: Vec<ClippedRun>,
+}
+
+impl ClippingOverlay {
+  pub fn new() -> Self {
+    ClippingOverlay {
+      is_open: false,
+      runs: Vec::<ClippedRun>::new(),
+    }
+  }
+
+  pub fn update(&mut self, image:&PpmImage) {
+    // no need to do the work if the overlay isn't shown, it is updated again
+    // when it gets turned on
+    self.runs.clear();
+    if !self.is_open {
+      return;
+    }
+
+    // neighbouring clipped pixels are merged into runs, so that there is a 
+    // lot less to paint
+    let mut current: Option<ClippedRun> = None;
+    for (index, pixel) in image.get_data().chunks_exact(PIXEL_SIZE).enumerate() {
+      let x = index as u32 % image.width();
+      let y = index as u32 / image.width();
+
+      let clipped = if pixel.iter().any(|ch| ch.is_saturated()) {
+        Some(true)
+      } else if pixel.iter().all(|ch| 0 == *ch) {
+        Some(false)
+      } else {
+        None
+      };
+
+      if let Some(run) = current.as_mut() {
+        if Some(run.highlight) == clipped && run.y == y {
+          run.x_end = x + 1;
+          continue;
+        }
+
+        self.runs.push(*run);
+        current = None;
+      }
+
+      if let Some(highlight) = clipped {
+        current = Some(ClippedRun {
+          y: y,
+          x_start: x,
+          x_end: x + 1,
+          highlight: highlight,
+        });
+      }
+    }
+
+    if let Some(run) = current {
+      self.runs.push(run);
+    }
+  }
+
+  /// Paints the clipped pixels over the image drawn in rect, where view is the
+  /// region of the image that is currently shown
+  pub fn draw(&self, ui:&egui::Ui, rect:egui::Rect, view:&Selection) {
+    if !self.is_open || 0 == view.width || 0 == view.height {
+      return;
+    }
+
+    let scale_x = rect.width() / view.width as f32;
+    let scale_y = rect.height() / view.height as f32;
+
+    let painter = ui.painter();
+    for run in self.runs.iter() {
+      // skip anything outside of the zoomed in region
+      if run.y < view.y || run.y >= view.y + view.height ||
+         run.x_end <= view.x || run.x_start >= view.x + view.width {
+        continue;
+      }
+
+      let min = egui::pos2(
+        rect.min.x + (run.x_start as f32 - view.x as f32) * scale_x,
+        rect.min.y + (run.y as f32 - view.y as f32) * scale_y,
+      );
+      let max = egui::pos2(
+        rect.min.x + (run.x_end as f32 - view.x as f32) * scale_x,
+        min.y + scale_y,
+      );
+
+      let color = if run.highlight {
+        CLIPPED_HIGHLIGHT_COLOR
+      } else {
+        CLIPPED_SHADOW_COLOR
+      };
+
+      painter.rect_filled(
+        egui::Rect::from_min_max(min, max).intersect(rect), 0., color
+      );
+    }
+  }
+}
+
+/* #endregion */
+
+/* #region MaskBrushWindow */
+
+/// The painted mask is shown in green over the image
+const PAINTED_MASK_COLOR: Color32 =
+  Color32::from_rgba_premultiplied(0, 140, 0, 120);
+
+/// Whether the brush turns the pixels of the mask on or off
+#[derive(PartialEq, EnumIter, Display, Clone, Copy, Debug)]
+pub enum BrushMode {
+  Add,
+  Erase,
+}
+
+/**
+ * Lets the user paint a mask over the image with a round brush, for the
+ * operations that only work on part of the image. While the window is open,
+ * dragging in the viewport paints rather than selecting.
+ *
+ * The mask is shown by painting over the image, like the clipping overlay.
+ * Its runs are kept row by row, and only the rows under the brush are worked
+ * out again while painting, so that it stays responsive on large images.
+ */
+pub struct MaskBrushWindow {
+  title: String,
+  is_open: bool,
+  pub error_msg: String,
+  // the radius of the brush, in pixels of the image
+  pub brush_size: u32,
+  pub mode: BrushMode,
+  pub mask: Option<BinaryMask>,
+  // the runs of painted pixels on each row of the mask
+  row_runs: Vec<Vec<(u32, u32)>>,
+  // where the brush was on the last frame of the current stroke
+  last_point: Option<(u32, u32)>,
+}
+
+impl MaskBrushWindow {
+  pub fn new(title: String) -> Self {
+    MaskBrushWindow {
+      title: title,
+      error_msg: "".to_owned(),
+      is_open: false,
+      brush_size: 10,
+      mode: BrushMode::Add,
+      mask: None,
+      row_runs: Vec::<Vec<(u32, u32)>>::new(),
+      last_point: None,
+    }
+  }
+
+  pub fn toggle(&mut self) {
+    self.is_open = !self.is_open;
+    self.last_point = None;
+  }
+
+  /// Whether dragging in the viewport should paint
+  pub fn is_painting(&self) -> bool {
+    self.is_open
+  }
+
+  /// Whether anything has been painted
+  pub fn has_mask(&self) -> bool {
+    self.row_runs.iter().any(|runs| !runs.is_empty())
+  }
+
+  /// Starts over with an empty mask the size of the image, unless the mask
+  /// already is that size
+  pub fn fit_to(&mut self, width: u32, height: u32) {
+    if let Some(mask) = &self.mask {
+      if mask.width() == width && mask.height() == height {
+        return;
+      }
+    }
+
+    self.mask = Some(BinaryMask::new(width, height));
+    self.row_runs = vec![Vec::<(u32, u32)>::new(); height as usize];
+    self.last_point = None;
+  }
+
+  pub fn clear(&mut self) {
+    if let Some(mask) = &self.mask {
+      let (width, height) = (mask.width(), mask.height());
+      self.mask = None;
+      self.fit_to(width, height);
+    }
+  }
+
+  /// Paints at a pixel of the image, joining it up to the last point painted
+  /// in the same stroke
+  pub fn paint(&mut self, x: u32, y: u32) {
+    let mask = match self.mask.as_mut() {
+      Some(mask) => mask,
+      None => return,
+    };
+
+    let from = self.last_point.unwrap_or((x, y));
+    let on = BrushMode::Add == self.mode;
+    mask.paint_line(from, (x, y), self.brush_size, on);
+    self.last_point = Some((x, y));
+
+    // only the rows that the brush went over have changed
+    let first_row = from.1.min(y).saturating_sub(self.brush_size);
+    let last_row = (from.1.max(y) + self.brush_size)
+      .min(mask.height().saturating_sub(1));
+    for row in first_row..=last_row {
+      self.row_runs[row as usize] = mask.row_runs(row);
+    }
+  }
+
+  /// Ends the current stroke, so that the next one isn't joined onto it
+  pub fn end_stroke(&mut self) {
+    self.last_point = None;
+  }
+
+  pub fn draw(&mut self, ctx:&egui::Context) -> bool {
+    use strum::IntoEnumIterator;
+
+    let mut clicked = false;
+    if self.is_open {
+      egui::Window::new(self.title.as_str())
+        .collapsible(true)
+        .resizable(false)
+        .show(ctx, |ui| {
+          ui.vertical(|ui| {
+            if !self.error_msg.is_empty() {
+              ui.colored_label(Color32::DARK_RED, self.error_msg.as_str());
+            }
+            ui.add(egui::Slider::new(
+              &mut self.brush_size, 1..=200
+            ).text("brush size"));
+            ui.horizontal(|ui| {
+              for mode in BrushMode::iter() {
+                ui.radio_value(&mut self.mode, mode, mode.to_string());
+              }
+            });
+            ui.horizontal(|ui| {
+              if ui.button("Clear").clicked() {
+                self.clear();
+              }
+              clicked = ui.add_enabled(
+                self.has_mask(), egui::Button::new("Fill masked area")
+              ).clicked();
+            });
+          });
+        });
+    }
+
+    clicked
+  }
+
+  /// Paints the mask over the image drawn in rect while the window is open,
+  /// where view is the region of the image that is currently shown
+  pub fn draw_overlay(&self, ui:&egui::Ui, rect:egui::Rect, view:&Selection) {
+    if !self.is_open || 0 == view.width || 0 == view.height {
+      return;
+    }
+
+    let scale_x = rect.width() / view.width as f32;
+    let scale_y = rect.height() / view.height as f32;
+
+    // when zoomed out, several rows of the image land on the same row of the
+    // screen, so each band of them is merged and painted once
+    let row_step = ((1. / scale_y).floor() as usize).max(1);
+
+    let view_end = ((view.y + view.height) as usize).min(self.row_runs.len());
+    let painter = ui.painter();
+    for y in (view.y as usize..view_end).step_by(row_step) {
+      let band = &self.row_runs[y..(y + row_step).min(view_end)];
+
+      for (x_start, x_end) in merge_runs(band).iter() {
+        if *x_end <= view.x || *x_start >= view.x + view.width {
+          continue;
+        }
+
+        let min = egui::pos2(
+          rect.min.x + (*x_start as f32 - view.x as f32) * scale_x,
+          rect.min.y + (y as f32 - view.y as f32) * scale_y,
+        );
+        let max = egui::pos2(
+          rect.min.x + (*x_end as f32 - view.x as f32) * scale_x,
+          min.y + scale_y * band.len() as f32,
+        );
+
+        painter.rect_filled(
+          egui::Rect::from_min_max(min, max).intersect(rect),
+          0.,
+          PAINTED_MASK_COLOR
+        );
+      }
+    }
+  }
+}
+
+/* #endregion */
+
 /* #endregion */
\ No newline at end of file
@@ -1,12 +1,13 @@
-use crate::core::{stacking::{StackOperation, ClippingStrategy, ImageStack}, io::open_image, io::write_image, color, V_CH};
+use crate::core::{stacking::{StackOperation, BrightnessMetric, ClippingStrategy, ImageStack}, io::open_image, io::write_image, color, V_CH};
 use crate::core::cr2::read_cr2;
 use crate::core::io::read_raw;
 use crate::core::fourier::dft_rows;
 use crate::core::fourier::make_complex;
 use crate::core::fourier::fast_fourier;
 use std::{collections::HashMap, fs, io::Cursor, iter::Map};
+use std::sync::{Arc, atomic::AtomicBool};
 
-use crate::core::{args::parse_arguments, stacking};
+use crate::core::{args::parse_arguments, batch, stacking};
 use byteorder::{LittleEndian, ReadBytesExt};
 use eframe::{NativeOptions, run_native, epaint::Vec2};
 use fft2d::slice::fft_2d;
@@ -45,7 +46,7 @@ fn main() {
   
   //let test_image = open_image("samples\\man8.ppm").ok();
 
-  //let complex = fast_fourier(test_image.unwrap());
+  //let complex = fast_fourier(test_image.unwrap(), MagnitudeScale::Linear);
   
   //app.set_image(Some(complex));
 
@@ -62,13 +63,20 @@ fn main() {
 
 fn create_master_images(directory: &str) {
 
+  let folder_name = directory.rsplit('\\').next().unwrap_or_default();
   let path_helper = move |stack_name: &str| -> String {
-    format!("{}\\master.{}.tiff", directory, stack_name)
+    let file_name = batch::expand_template(
+      batch::DEFAULT_MASTER_TEMPLATE,
+      &batch::OutputName { 
+        name: folder_name, algo: stack_name, params: "", ext: "tiff" 
+      }
+    );
+    format!("{}\\{}", directory, file_name)
   };
 
   let mut stack = ImageStack::new();
   stack.add_algorithm(Box::new(
-    stacking::Maximum::new()), 
+    stacking::Maximum::new(BrightnessMetric::Value)), 
     path_helper("maximum")
   );
   stack.add_algorithm(Box::new(
@@ -79,6 +87,10 @@ fn create_master_images(directory: &str) {
     Box::new(stacking::Average::new()), 
     path_helper("average")
   );
+  stack.add_algorithm(
+    Box::new(stacking::PercentileClipAverage::new(10., 90.)), 
+    path_helper("percentile_10_90")
+  );
   
   let ksc_params = [(10, 0.74), (10, 1.0), (10, 2.0), (10, 0.5), (5, 0.74)];
   
@@ -130,6 +142,8 @@ fn create_master_images(directory: &str) {
       }
     }
 
-    stack.process_stack();
+    if let Err(why) = stack.process_stack(&Arc::new(AtomicBool::new(false))) {
+      println!("{}", why);
+    }
   }
 }
\ No newline at end of file
@@ -31,357 +31,4093 @@ fn test_sobel() {
     &input, SOBEL_V, Padding::Repeat
   );
 
-  if h_filtered != h_check {
-    assert!(is_reasonably_similar(
-      &h_filtered, 
-      &h_check, 
-      2));
+  assert_images_similar(&h_filtered, &h_check, 2);
+  assert_images_similar(&v_filtered, &v_check, 2);
+}
+
+#[test]
+fn test_richardson_lucy() {
+  use crate::core::ppm::Padding;
+  use crate::core::filters::{
+    gaussian_blur, richardson_lucy, get_gaussian_weight_matrix
+  };
+
+  // a single bright point in the middle of a dark image
+  let mut point = PpmImage::create_color(0, 0, 0, 15, 15);
+  point.set_pixel_by_coord(7, 7, &[255, 255, 255]);
+
+  let blurred = gaussian_blur(&point, 1.5, 5, Padding::Repeat).unwrap();
+  let blurred_peak = blurred.get_pixel_by_coord(7, 7).unwrap()[0];
+
+  let psf = get_gaussian_weight_matrix(5, 1.5);
+  let deconvolved = richardson_lucy(&blurred, &psf, 20).unwrap();
+  let deconvolved_peak = deconvolved.get_pixel_by_coord(7, 7).unwrap()[0];
+
+  assert!(deconvolved_peak > blurred_peak);
+
+  // the neighbors of the peak should have been pulled back into it
+  assert!(
+    deconvolved.get_pixel_by_coord(8, 7).unwrap()[0] <
+    blurred.get_pixel_by_coord(8, 7).unwrap()[0]
+  );
+
+  assert!(richardson_lucy(&blurred, &vec![1.; 4], 1).is_err());
+}
+
+#[test]
+fn test_padding_at_corner() {
+  use crate::core::ppm::Padding;
+
+  // every pixel gets its own value, 10 * y + x
+  let mut image = PpmImage::new(4, 3);
+  for y in 0..3 {
+    for x in 0..4 {
+      image.set_pixel_by_coord(x, y, &[(10 * y + x) as u8; 3]);
+    }
+  }
+
+  // the 3x3 neighborhood of the top left pixel, in the order that 
+  // get_matrix_at returns it (column by column)
+  let neighborhood = |padding: Padding| -> Vec<u8> {
+    image.get_matrix_at(0, 0, 3, padding).iter().map(|pixel| pixel[0]).collect()
+  };
+
+  assert_eq!(neighborhood(Padding::Zero), vec![
+    0, 0, 0, 
+    0, 0, 10, 
+    0, 1, 11,
+  ]);
+  assert_eq!(neighborhood(Padding::Repeat), vec![
+    0, 0, 10, 
+    0, 0, 10, 
+    1, 1, 11,
+  ]);
+  assert_eq!(neighborhood(Padding::Mirror), vec![
+    11, 1, 11, 
+    10, 0, 10, 
+    11, 1, 11,
+  ]);
+  assert_eq!(neighborhood(Padding::Wrap), vec![
+    23, 3, 13, 
+    20, 0, 10, 
+    21, 1, 11,
+  ]);
+
+  // a single pixel wide image mirrors onto itself
+  assert_eq!(Padding::Mirror.resolve(-2, 1), Some(0));
+  assert_eq!(Padding::Mirror.resolve(5, 4), Some(1));
+  assert_eq!(Padding::Wrap.resolve(-5, 4), Some(3));
+  assert_eq!(Padding::Zero.resolve(4, 4), None);
+}
+
+#[test]
+fn test_neighborhood() {
+  use crate::core::ppm::{neighborhood, neighborhood_coords, Padding};
+
+  // every pixel gets its own value, 10 * y + x + 1 (so that the zero padding
+  // stands out)
+  let mut image = PpmImage::new(4, 3);
+  for y in 0..3 {
+    for x in 0..4 {
+      image.set_pixel_by_coord(x, y, &[(10 * y + x + 1) as u8; 3]);
+    }
+  }
+
+  // the 3x3 window, column by column
+  let window = |x, y, padding| -> Vec<u8> {
+    neighborhood(&image, x, y, 1, padding).map(|pixel| pixel[0]).collect()
+  };
+
+  // away from the edges, the padding makes no difference
+  for padding in [
+    Padding::Zero, Padding::Repeat, Padding::Mirror, Padding::Wrap
+  ] {
+    assert_eq!(window(1, 1, padding), vec![1, 11, 21, 2, 12, 22, 3, 13, 23]);
+  }
+
+  // the top left corner
+  assert_eq!(
+    window(0, 0, Padding::Zero), vec![0, 0, 0, 0, 1, 11, 0, 2, 12]
+  );
+  assert_eq!(
+    window(0, 0, Padding::Repeat), vec![1, 1, 11, 1, 1, 11, 2, 2, 12]
+  );
+  assert_eq!(
+    window(0, 0, Padding::Mirror), vec![12, 2, 12, 11, 1, 11, 12, 2, 12]
+  );
+  assert_eq!(
+    window(0, 0, Padding::Wrap), vec![24, 4, 14, 21, 1, 11, 22, 2, 12]
+  );
+
+  // the bottom right corner
+  assert_eq!(
+    window(3, 2, Padding::Zero), vec![13, 23, 0, 14, 24, 0, 0, 0, 0]
+  );
+  assert_eq!(
+    window(3, 2, Padding::Repeat), vec![13, 23, 23, 14, 24, 24, 14, 24, 24]
+  );
+  assert_eq!(
+    window(3, 2, Padding::Mirror), vec![13, 23, 13, 14, 24, 14, 13, 23, 13]
+  );
+  assert_eq!(
+    window(3, 2, Padding::Wrap), vec![13, 23, 3, 14, 24, 4, 11, 21, 1]
+  );
+
+  // column by column, with zero padding leaving out what is off the image
+  let coords: Vec<_> = neighborhood_coords(0, 0, 1, 4, 3, Padding::Zero)
+    .collect();
+  assert_eq!(coords.len(), 9);
+  assert_eq!(coords[0], ((-1, -1), None));
+  assert_eq!(coords[4], ((0, 0), Some((0, 0))));
+  assert_eq!(coords[5], ((0, 1), Some((0, 1))));
+  let inside = coords.iter().filter(|(_, position)| position.is_some());
+  assert_eq!(inside.count(), 4);
+
+  // a radius of 0 is just the pixel itself
+  let center: Vec<&[u8]> = neighborhood(&image, 3, 2, 0, Padding::Zero)
+    .collect();
+  assert_eq!(center, vec![&[24u8; 3][..]]);
+}
+
+#[test]
+fn test_convolve() {
+  use crate::core::ppm::Padding;
+  use crate::core::filters::convolve;
+  use crate::core::args::apply_command;
+
+  let mut image = PpmImage::create_color(100, 100, 100, 5, 5);
+  image.set_pixel_by_coord(2, 2, &[200, 200, 200]);
+  image.set_pixel_by_coord(4, 1, &[30, 60, 90]);
+
+  // the identity kernel leaves the image as it is
+  let identity = [0., 0., 0., 0., 1., 0., 0., 0., 0.];
+  assert_eq!(convolve(&image, &identity, 3, Padding::Zero).unwrap(), image);
+
+  // sharpening boosts the bright pixel and darkens the ones around it, while
+  // flat areas are left alone
+  let sharpen = [0., -1., 0., -1., 5., -1., 0., -1., 0.];
+  let sharpened = convolve(&image, &sharpen, 3, Padding::Repeat).unwrap();
+  assert_eq!(sharpened.get_pixel_by_coord(2, 2).unwrap(), [255; 3]);
+  assert_eq!(sharpened.get_pixel_by_coord(2, 1).unwrap(), [0; 3]);
+  assert_eq!(sharpened.get_pixel_by_coord(0, 4).unwrap(), [100; 3]);
+
+  // the kernel is laid over the image row by row, so this takes the value of
+  // the pixel to the right
+  let right = [0., 0., 0., 0., 0., 1., 0., 0., 0.];
+  let shifted = convolve(&image, &right, 3, Padding::Repeat).unwrap();
+  assert_eq!(shifted.get_pixel_by_coord(1, 2).unwrap(), [200; 3]);
+  assert_eq!(shifted.get_pixel_by_coord(3, 1).unwrap(), [30, 60, 90]);
+
+  // kernels have to be odd sized squares
+  assert!(convolve(&image, &[1.; 4], 2, Padding::Zero).is_err());
+  assert!(convolve(&image, &[1.; 8], 3, Padding::Zero).is_err());
+
+  // and the same kernel can be given from the command box
+  let from_command = apply_command("conv -m 0,-1,0,-1,5,-1,0,-1,0", Some(&image));
+  assert_eq!(from_command.unwrap(), Some(sharpened));
+  assert!(apply_command("conv -m 1,2,3", Some(&image)).is_err());
+}
+
+#[test]
+fn test_estimate_noise() {
+  use rand::{Rng, SeedableRng, rngs::StdRng};
+  use crate::core::filters::{estimate_noise, NOISE_TILE_SIZE};
+
+  const SIGMA: f64 = 5.;
+  let mut rng = StdRng::seed_from_u64(7);
+
+  // gaussian noise, by the Box-Muller transform
+  let mut gaussian = || {
+    let u1: f64 = rng.gen_range(f64::EPSILON..1.);
+    let u2: f64 = rng.gen_range(0. ..1.);
+    (-2. * u1.ln()).sqrt() * (2. * std::f64::consts::PI * u2).cos() * SIGMA
+  };
+
+  // the left half is flat gray, while the right half is a checkerboard that
+  // should not be mistaken for noise
+  let mut image = PpmImage::new(128, 64);
+  for y in 0..64 {
+    for x in 0..128 {
+      let base = if x >= 64 && 0 == (x / 4 + y / 4) % 2 { 170. } else { 110. };
+      image.set_pixel_by_coord(x, y, &[
+        (base + gaussian()).round() as u8,
+        (base + gaussian()).round() as u8,
+        (base + gaussian()).round() as u8,
+      ]);
+    }
+  }
+
+  let noise = estimate_noise(&image, NOISE_TILE_SIZE).unwrap();
+  assert!((noise - SIGMA).abs() < 0.5, "estimated {} instead of {}", noise, SIGMA);
+
+  // an image without any noise has none to measure
+  let flat = PpmImage::create_color(90, 90, 90, 32, 32);
+  assert_eq!(estimate_noise(&flat, NOISE_TILE_SIZE).unwrap(), 0.);
+
+  // images smaller than a tile can't be measured
+  assert!(estimate_noise(&PpmImage::new(8, 8), NOISE_TILE_SIZE).is_err());
+}
+
+#[test]
+fn test_snr_map() {
+  use rand::{Rng, SeedableRng, rngs::StdRng};
+  use crate::core::filters::snr_map;
+
+  let mut rng = StdRng::seed_from_u64(11);
+
+  // the left half is a bright signal with a little noise on it, and the 
+  // right half is nothing but noise
+  let mut image = PpmImage::new(40, 20);
+  for y in 0..20 {
+    for x in 0..40 {
+      let value: u8 = if x < 20 {
+        rng.gen_range(190..=210)
+      } else {
+        rng.gen_range(0..=20)
+      };
+      image.set_pixel_by_coord(x, y, &[value; 3]);
+    }
+  }
+
+  let map = snr_map(&image, 5).unwrap();
+  assert_eq!((map.width(), map.height()), (40, 20));
+
+  let brightness = |x0: u32, x1: u32| {
+    let mut total = 0_u32;
+    for y in 0..20 {
+      for x in x0..x1 {
+        total += map.get_pixel_by_coord(x, y).unwrap().iter()
+          .map(|ch| *ch as u32).sum::<u32>();
+      }
+    }
+    total
+  };
+
+  // away from the boundary between the two halves
+  assert!(brightness(2, 15) > 4 * brightness(25, 38));
+
+  assert!(snr_map(&image, 4).is_err());
+}
+
+#[test]
+fn test_difference_of_gaussians() {
+  use crate::core::ppm::Padding;
+  use crate::core::filters::{difference_of_gaussians, MID_GRAY};
+
+  // a bright square on a dark background
+  let mut image = PpmImage::create_color(20, 20, 20, 30, 30);
+  for x in 10..20 {
+    for y in 10..20 {
+      image.set_pixel_by_coord(x, y, &[220, 200, 180]);
+    }
+  }
+
+  // blurring twice at the same scale leaves nothing behind but mid-gray
+  let flat = difference_of_gaussians(&image, 1.5, 1.5, Padding::Repeat).unwrap();
+  assert_eq!((flat.width(), flat.height()), (30, 30));
+  assert!(flat.get_data().iter().all(|value| MID_GRAY == *value));
+
+  // different scales bring out the edges of the square, which sit either side
+  // of mid-gray, while flat areas far from the square stay at mid-gray
+  let band = difference_of_gaussians(&image, 1., 3., Padding::Repeat).unwrap();
+  let inside_edge = band.get_pixel_by_coord(10, 15).unwrap();
+  let outside_edge = band.get_pixel_by_coord(9, 15).unwrap();
+  assert!(inside_edge[0] > MID_GRAY + 10);
+  assert!(outside_edge[0] < MID_GRAY - 5);
+  assert_eq!(band.get_pixel_by_coord(0, 0).unwrap(), [MID_GRAY; 3]);
+
+  assert!(difference_of_gaussians(&image, 0., 3., Padding::Repeat).is_err());
+}
+
+#[test]
+fn test_high_pass() {
+  use crate::core::ppm::Padding;
+  use crate::core::filters::{high_pass, high_pass_sharpen, MID_GRAY};
+
+  // a uniform image has no detail, so its high-pass is flat mid-gray and 
+  // sharpening leaves it alone
+  let uniform = PpmImage::create_color(90, 140, 200, 16, 16);
+  let flat = high_pass(&uniform, 2., Padding::Repeat).unwrap();
+  assert!(flat.get_data().iter().all(|value| MID_GRAY == *value));
+  assert_eq!(high_pass_sharpen(&uniform, 2., 1., Padding::Repeat).unwrap(), uniform);
+
+  // a vertical edge gets more contrast across it
+  let mut image = PpmImage::create_color(60, 60, 60, 16, 16);
+  for x in 8..16 {
+    for y in 0..16 {
+      image.set_pixel_by_coord(x, y, &[180, 180, 180]);
+    }
+  }
+
+  let sharpened = high_pass_sharpen(&image, 1.5, 1., Padding::Repeat).unwrap();
+  assert!(sharpened.get_pixel_by_coord(7, 8).unwrap()[0] < 60);
+  assert!(sharpened.get_pixel_by_coord(8, 8).unwrap()[0] > 180);
+
+  // and no strength leaves the image alone
+  assert_eq!(high_pass_sharpen(&image, 1.5, 0., Padding::Repeat).unwrap(), image);
+  assert!(high_pass_sharpen(&image, 1.5, 2., Padding::Repeat).is_err());
+}
+
+#[test]
+fn test_clarity() {
+  use crate::core::color;
+  use crate::core::filters::clarity;
+  use crate::core::{H_CH, V_CH};
+
+  // a brighter orange square on a dim orange background
+  let mut image = PpmImage::create_color(120, 60, 20, 24, 24);
+  for y in 8..16 {
+    for x in 8..16 {
+      image.set_pixel_by_coord(x, y, &[180, 90, 30]);
+    }
+  }
+
+  // no strength leaves the image exactly as it was
+  assert_eq!(clarity(&image, 3., 0.).unwrap(), image);
+
+  let clarified = clarity(&image, 3., 1.).unwrap();
+
+  // the edge of the square gets brighter, and the background next to it 
+  // darker, while the hue stays the same
+  let inside = clarified.get_pixel_by_coord(8, 12).unwrap();
+  let outside = clarified.get_pixel_by_coord(7, 12).unwrap();
+  assert!(color::rgb_to_hsv(inside)[V_CH] > 180.);
+  assert!(color::rgb_to_hsv(outside)[V_CH] < 120.);
+
+  let hue = color::rgb_to_hsv([180u8, 90, 30])[H_CH];
+  for pixel in [inside, outside] {
+    assert!((color::rgb_to_hsv(pixel)[H_CH] - hue).abs() < 3.);
+  }
+
+  // far from the square there is no local contrast to boost
+  assert_eq!(
+    clarified.get_pixel_by_coord(0, 0), image.get_pixel_by_coord(0, 0)
+  );
+
+  assert!(clarity(&image, 3., f32::NAN).is_err());
+}
+
+#[test]
+fn test_gaussian_kernel_size_correction() {
+  use crate::core::ppm::Padding;
+  use crate::core::filters::{gaussian_blur, max_kernel_size};
+  use crate::gui::windows::GaussianBlurWindow;
+
+  let image = PpmImage::create_color(40, 80, 120, 20, 12);
+
+  // the kernel can be at most half of the shortest side, rounded down to odd
+  assert_eq!(max_kernel_size(image.width(), image.height()), 5);
+  assert_eq!(max_kernel_size(4, 4), 3);
+
+  // an even value from the slider is corrected before filtering
+  let mut window = GaussianBlurWindow::new("Gaussian Blur".to_string());
+  window.kernel.set_max_size(max_kernel_size(image.width(), image.height()));
+  window.kernel.size = 4;
+  let blurred = gaussian_blur(&image, 1., window.kernel.size, Padding::Zero);
+  assert!(blurred.is_err());
+
+  window.kernel.fix();
+  assert_eq!(window.kernel.size, 5);
+  assert!(gaussian_blur(&image, 1., window.kernel.size, Padding::Zero).is_ok());
+
+  // values past the cap are pulled back within it
+  window.kernel.size = 24;
+  window.kernel.fix();
+  assert_eq!(window.kernel.size, 5);
+
+  // sigma is pulled into the range that the kernel can hold, so that it is
+  // always accepted by the filter
+  window.kernel.size = 3;
+  for (sigma, fixed) in [(4., 3.), (0.1, 0.33), (1.5, 1.5)] {
+    assert_eq!(window.kernel.fix_sigma(sigma), fixed);
+    assert!(gaussian_blur(&image, fixed, 3, Padding::Zero).is_ok());
+  }
+
+  // a degenerate sigma for the kernel is reported rather than applied
+  assert!(gaussian_blur(&image, 0.1, 5, Padding::Zero).is_err());
+  assert!(gaussian_blur(&image, 8., 3, Padding::Zero).is_err());
+}
+
+#[test]
+fn test_rotate() {
+  use crate::core::ppm::Padding;
+  use crate::core::operations::{rotate, rotate_90};
+
+  let mut image = PpmImage::create_color(0, 0, 0, 5, 8);
+  for x in 0..8 {
+    for y in 0..5 {
+      image.set_pixel_by_coord(x, y, &[(x * 30) as u8, (y * 50) as u8, 100]);
+    }
+  }
+
+  // rotating by nothing gives back the same image
+  assert_eq!(rotate(&image, 0., Padding::Zero, None).unwrap(), image);
+  assert_eq!(rotate(&image, 360., Padding::Zero, None).unwrap(), image);
+
+  // a right angle matches the integer rotation
+  let rotated = rotate(&image, 90., Padding::Zero, None).unwrap();
+  let quarter_turn = rotate_90(&image).unwrap();
+  assert_eq!(rotated.width(), 5);
+  assert_eq!(rotated.height(), 8);
+  assert_eq!(rotated, quarter_turn);
+
+  // anything else expands the canvas to fit the rotated corners
+  let rotated = rotate(&image, 45., Padding::Zero, None).unwrap();
+  assert!(rotated.width() > image.width());
+  assert!(rotated.height() > image.width());
+  assert_eq!(rotated.get_pixel_by_coord(0, 0).unwrap(), [0, 0, 0]);
+}
+
+#[test]
+fn test_rotate_interpolation() {
+  use std::collections::HashSet;
+  use crate::core::ppm::Padding;
+  use crate::core::operations::{rotate, ResizeAlgorithm};
+
+  // a checkerboard of 2x2 squares
+  let mut checkerboard = PpmImage::create_color(0, 0, 0, 16, 16);
+  for x in 0..16 {
+    for y in 0..16 {
+      if (x / 2 + y / 2) % 2 == 0 {
+        checkerboard.set_pixel_by_coord(x, y, &[255, 255, 255]);
+      }
+    }
+  }
+
+  let values = |image: &PpmImage| {
+    image.get_data().iter().cloned().collect::<HashSet<u8>>()
+  };
+
+  let nearest = rotate(
+    &checkerboard, 45., Padding::Zero, Some(ResizeAlgorithm::NearestNeighbor)
+  ).unwrap();
+  let bilinear = rotate(
+    &checkerboard, 45., Padding::Zero, Some(ResizeAlgorithm::BilinearInterpolation)
+  ).unwrap();
+
+  assert_eq!(nearest.width(), bilinear.width());
+  assert_eq!(nearest.height(), bilinear.height());
+
+  // nearest neighbor only ever copies pixels, so the edges stay hard
+  assert_eq!(values(&nearest), HashSet::from([0, 255]));
+
+  // while bilinear blends the squares into each other along the diagonals
+  assert!(values(&bilinear).iter().any(|v| *v != 0 && *v != 255));
+}
+
+#[test]
+fn test_alignment_error() {
+  use crate::core::ppm::{Padding, Selection};
+  use crate::core::operations::{
+    alignment_error, translate, translated_region
+  };
+
+  // a smooth gradient, so that the pixels lost off the edges are close to
+  // the padding that replaces them
+  let mut image = PpmImage::new(32, 24);
+  for y in 0..24 {
+    for x in 0..32 {
+      image.set_pixel_by_coord(x, y, &[(x * 4) as u8, (y * 5) as u8, 100]);
+    }
+  }
+
+  let full = Selection::new(0, 0, 32, 24);
+  assert_eq!(alignment_error(&image, &image, &full), 0.);
+
+  let shifted = translate(&image, 3, -2, Padding::Repeat).unwrap();
+  let shifted_region = translated_region(32, 24, 3, -2).unwrap();
+  assert_eq!(shifted_region, Selection::new(3, 0, 29, 22));
+  assert!(alignment_error(&image, &shifted, &shifted_region) > 5.);
+
+  // moving the frame back lines up every pixel that made it through both
+  // moves, and only the padded strips differ
+  let realigned = translate(&shifted, -3, 2, Padding::Repeat).unwrap();
+  let realigned_region = translated_region(32, 24, -3, 2).unwrap();
+  assert_eq!(alignment_error(&image, &realigned, &realigned_region), 0.);
+  assert!(alignment_error(&image, &realigned, &full) > 0.);
+
+  // regions that don't overlap can't be compared
+  assert_eq!(translated_region(32, 24, 40, 0), None);
+  let left = Selection::new(0, 0, 10, 24);
+  assert_eq!(left.intersect(&Selection::new(10, 0, 5, 5)), None);
+  assert_eq!(
+    left.intersect(&shifted_region), Some(Selection::new(3, 0, 7, 22))
+  );
+}
+
+#[test]
+fn test_ccl_tolerance_merges_monotonically() {
+  use std::collections::HashSet;
+  use crate::core::ccl::{ccl, Connectivity, LabelNumbering};
+
+  // a dark blue background with a row of slowly brightening red patches,
+  // each of them two pixels wide
+  let mut image = PpmImage::create_color(0, 0, 40, 4, 20);
+  for x in 0..20 {
+    let red = 40 + (x / 2) * 20;
+    image.set_pixel_by_coord(x, 1, &[red as u8, 0, 0]);
+    image.set_pixel_by_coord(x, 2, &[red as u8, 0, 0]);
+  }
+
+  let region_count = |tolerance: f32| {
+    let (labels, _) = ccl(
+      &image, Connectivity::FOUR, tolerance, LabelNumbering::AsFound
+    );
+    labels.iter().filter(|l| **l != 0).collect::<HashSet<_>>().len()
+  };
+
+  // exact matching keeps every patch separate, and the full tolerance joins
+  // them all
+  assert_eq!(region_count(0.), 10);
+  assert_eq!(region_count(1.), 1);
+
+  // in between, raising the tolerance never splits regions apart
+  let mut last_count = region_count(0.);
+  for step in 1..=20 {
+    let count = region_count(step as f32 / 20.);
+    assert!(count <= last_count);
+    last_count = count;
+  }
+}
+
+#[test]
+fn test_create_color_background() {
+  // every pixel of a new black image is counted as black, so a pixel drawn
+  // over it doesn't take over as the background
+  let mut image = PpmImage::create_color(0, 0, 0, 4, 5);
+  assert_eq!(image.get_background(), [0, 0, 0]);
+
+  image.set_pixel_by_coord(2, 1, &[255, 0, 0]);
+  assert_eq!(image.get_background(), [0, 0, 0]);
+
+  let image = PpmImage::create_color(255, 0, 0, 4, 5);
+  assert_eq!(image.get_background(), [255, 0, 0]);
+}
+
+#[test]
+fn test_ccl_sequential_labels() {
+  use std::collections::BTreeSet;
+  use crate::core::ccl::{ccl, Connectivity, LabelNumbering};
+
+  // a V whose arms are labeled separately until they meet at the bottom, and
+  // two dots, on a black background
+  let mut image = PpmImage::create_color(0, 0, 0, 7, 9);
+  for (x, y) in [(0, 1), (1, 2), (2, 3), (4, 1), (3, 2), (3, 3), (7, 1), (7, 5)] {
+    image.set_pixel_by_coord(x, y, &[255, 255, 255]);
+  }
+
+  let (labels, count) = ccl(
+    &image, Connectivity::EIGHT, 0., LabelNumbering::Sequential
+  );
+  let distinct: BTreeSet<u64> = labels.iter().copied().filter(|l| 0 != *l).collect();
+
+  assert_eq!(count, 3);
+  assert_eq!(distinct, BTreeSet::from([1, 2, 3]));
+  // regions are numbered in the order that they are first seen
+  assert_eq!(labels[9], 1);
+  assert_eq!(labels[9 + 4], 1);
+  assert_eq!(labels[9 + 7], 2);
+  assert_eq!(labels[5 * 9 + 7], 3);
+
+  // the same regions are found either way, only their numbers differ
+  let (as_found, _) = ccl(
+    &image, Connectivity::EIGHT, 0., LabelNumbering::AsFound
+  );
+  for (lhs, rhs) in labels.iter().zip(as_found.iter()) {
+    assert_eq!(0 == *lhs, 0 == *rhs);
+  }
+  let max_label = *as_found.iter().max().unwrap() as usize;
+  assert!(max_label > distinct.len());
+}
+
+#[test]
+fn test_ccl_top_row() {
+  use std::collections::BTreeSet;
+  use crate::core::ccl::{ccl, Connectivity, LabelNumbering};
+
+  // an upside down U along the top edge, whose legs only meet through row 0
+  // (with enough black around it that black is the background)
+  let mut image = PpmImage::create_color(0, 0, 0, 6, 8);
+  for x in 0..6 {
+    image.set_pixel_by_coord(x, 0, &[255, 255, 255]);
+  }
+  for y in 1..4 {
+    image.set_pixel_by_coord(0, y, &[255, 255, 255]);
+    image.set_pixel_by_coord(5, y, &[255, 255, 255]);
+  }
+
+  for c_type in [Connectivity::FOUR, Connectivity::EIGHT] {
+    let (labels, count) = ccl(&image, c_type, 0., LabelNumbering::Sequential);
+    let distinct: BTreeSet<u64> = labels.iter()
+      .copied()
+      .filter(|label| 0 != *label)
+      .collect();
+
+    assert_eq!(count, 1);
+    assert_eq!(distinct, BTreeSet::from([1]));
+    assert_eq!(labels[3 * 8 + 5], 1);
+  }
+}
+
+#[test]
+fn test_ccl_with_stats() {
+  use crate::core::ccl::{ccl_with_stats, Connectivity};
+
+  // a 3x2 block, and an L of three pixels, on a black background
+  let mut image = PpmImage::create_color(0, 0, 0, 8, 10);
+  for y in 1..3 {
+    for x in 1..4 {
+      image.set_pixel_by_coord(x, y, &[255, 255, 255]);
+    }
+  }
+  for (x, y) in [(7, 4), (7, 5), (8, 5)] {
+    image.set_pixel_by_coord(x, y, &[255, 255, 255]);
+  }
+
+  let (labels, stats) = ccl_with_stats(&image, Connectivity::EIGHT, 0.);
+  assert_eq!(stats.len(), 2);
+
+  let block = stats[&labels[1 * 10 + 1]];
+  assert_eq!(block.area, 6);
+  assert_eq!(
+    (block.min_x, block.min_y, block.max_x, block.max_y), (1, 1, 3, 2)
+  );
+  assert_eq!(block.centroid, (2., 1.5));
+
+  let l_shape = stats[&labels[4 * 10 + 7]];
+  assert_eq!(l_shape.area, 3);
+  assert_eq!(
+    (l_shape.min_x, l_shape.min_y, l_shape.max_x, l_shape.max_y), 
+    (7, 4, 8, 5)
+  );
+  assert!((l_shape.centroid.0 - 22. / 3.).abs() < 1e-5);
+  assert!((l_shape.centroid.1 - 14. / 3.).abs() < 1e-5);
+
+  // small regions (like hot pixels) can be picked out by their area
+  let large: Vec<_> = stats.values().filter(|region| region.area > 4).collect();
+  assert_eq!(large, vec![&block]);
+}
+
+#[test]
+fn test_ccl_mask_min_area() {
+  use crate::core::ccl::{make_ccl_mask, Connectivity};
+  use crate::core::color::ColorMetric;
+
+  // a hot pixel, and a 10x5 star, on a black background
+  let mut image = PpmImage::create_color(0, 0, 0, 12, 16);
+  image.set_pixel_by_coord(1, 1, &[255, 255, 255]);
+  for y in 5..10 {
+    for x in 4..14 {
+      image.set_pixel_by_coord(x, y, &[200, 200, 200]);
+    }
+  }
+
+  let mask = |min_area| make_ccl_mask(
+    &image, Connectivity::EIGHT, 0., ColorMetric::Redmean, min_area
+  );
+  let background = [0, 0, 0];
+
+  // the hot pixel is painted over with the background, but the star isn't
+  let filtered = mask(10);
+  assert_eq!(filtered.get_pixel_by_coord(1, 1).unwrap(), background);
+  let star = filtered.get_pixel_by_coord(4, 5).unwrap();
+  assert_ne!(star, background);
+  for y in 5..10 {
+    for x in 4..14 {
+      assert_eq!(filtered.get_pixel_by_coord(x, y).unwrap(), star);
+    }
+  }
+
+  // a star exactly at the minimum area is kept
+  assert_ne!(mask(50).get_pixel_by_coord(4, 5).unwrap(), background);
+  assert_eq!(mask(51).get_pixel_by_coord(4, 5).unwrap(), background);
+
+  // with no minimum area every region is colored in
+  let unfiltered = mask(0);
+  assert_ne!(unfiltered.get_pixel_by_coord(1, 1).unwrap(), background);
+  assert_ne!(unfiltered.get_pixel_by_coord(4, 5).unwrap(), background);
+}
+
+#[test]
+fn test_ccl_merges_transitively() {
+  use std::collections::HashSet;
+  use crate::core::ccl::{ccl, Connectivity, LabelNumbering};
+
+  // a U whose right arm forks at the top. The prongs of the fork meet first
+  // (tying their labels together), and only further down does the bottom of
+  // the U tie them to the left arm, so the whole shape is only one region if
+  // the equivalences are followed through
+  let rows = [
+    ".....",
+    "X.X.X",
+    "X.X.X",
+    "X.XXX",
+    "X.X..",
+    "XXX..",
+  ];
+
+  // wide enough that black is still the background color
+  let mut image = PpmImage::create_color(0, 0, 0, 6, 10);
+  for (y, row) in rows.iter().enumerate() {
+    for (x, cell) in row.chars().enumerate() {
+      if 'X' == cell {
+        image.set_pixel_by_coord(x as u32, y as u32, &[255, 255, 255]);
+      }
+    }
+  }
+
+  for connectivity in [Connectivity::FOUR, Connectivity::EIGHT] {
+    let (labels, _) = ccl(&image, connectivity, 0., LabelNumbering::AsFound);
+    let regions: HashSet<u64> = labels.iter().copied().filter(|l| 0 != *l).collect();
+    assert_eq!(regions.len(), 1);
+  }
+
+  let (labels, count) = ccl(
+    &image, Connectivity::FOUR, 0., LabelNumbering::Sequential
+  );
+  assert_eq!(count, 1);
+  assert_eq!(labels.iter().filter(|l| 1 == **l).count(), 15);
+}
+
+#[test]
+fn test_ccl_diagonal_connectivity() {
+  use std::collections::HashSet;
+  use crate::core::ccl::{ccl, Connectivity, LabelNumbering};
+
+  // an 8x8 red and blue checkerboard in the middle of a black background
+  let mut image = PpmImage::create_color(0, 0, 0, 20, 20);
+  for y in 0..8 {
+    for x in 0..8 {
+      let color = if 0 == (x + y) % 2 { [255, 0, 0] } else { [0, 0, 255] };
+      image.set_pixel_by_coord(x + 6, y + 6, &color);
+    }
+  }
+
+  let region_count = |connectivity: Connectivity| {
+    let (labels, count) = ccl(
+      &image, connectivity, 0., LabelNumbering::Sequential
+    );
+    let distinct: HashSet<&u64> = labels.iter().filter(|l| 0 != **l).collect();
+    assert_eq!(distinct.len(), count);
+    count
+  };
+
+  // each color touches itself only at the corners, so diagonal connectivity
+  // finds one region per color, while 4-connectivity leaves every square on
+  // its own
+  assert_eq!(region_count(Connectivity::DIAGONAL), 2);
+  assert_eq!(region_count(Connectivity::FOUR), 64);
+
+  // a straight line is the other way around
+  let mut line = PpmImage::create_color(0, 0, 0, 10, 10);
+  for x in 2..8 {
+    line.set_pixel_by_coord(x, 4, &[255, 255, 255]);
+  }
+  let (_, count) = ccl(&line, Connectivity::DIAGONAL, 0., LabelNumbering::Sequential);
+  assert_eq!(count, 6);
+  let (_, count) = ccl(&line, Connectivity::FOUR, 0., LabelNumbering::Sequential);
+  assert_eq!(count, 1);
+}
+
+#[test]
+fn test_ccl_color_metric() {
+  use crate::core::ccl::{ccl_with_metric, Connectivity, LabelNumbering};
+  use crate::core::color::ColorMetric;
+
+  // black next to a very dark blue, on a white background. Redmean puts 
+  // them close together, but in CIELAB the blue is a lot more colorful.
+  let mut image = PpmImage::create_color(255, 255, 255, 3, 6);
+  image.set_pixel_by_coord(2, 1, &[0, 0, 0]);
+  image.set_pixel_by_coord(3, 1, &[0, 0, 40]);
+
+  let region_count = |metric: ColorMetric| {
+    ccl_with_metric(
+      &image, Connectivity::FOUR, 0.15, metric, LabelNumbering::Sequential
+    ).1
+  };
+
+  assert_eq!(region_count(ColorMetric::Redmean), 1);
+  assert_eq!(region_count(ColorMetric::Cie76), 2);
+}
+
+#[test]
+fn test_binary_mask() {
+  use crate::core::mask::BinaryMask;
+
+  // big enough that the bits span more than one word
+  let mut mask = BinaryMask::new(13, 7);
+  assert_eq!(mask.count(), 0);
+
+  mask.set(0, 0, true);
+  mask.set(12, 6, true);
+  mask.set(5, 4, true);
+  mask.set(5, 4, false);
+  mask.set(20, 20, true);
+
+  assert!(mask.get(0, 0));
+  assert!(mask.get(12, 6));
+  assert!(!mask.get(5, 4));
+  assert!(!mask.get(20, 20));
+  assert_eq!(mask.count(), 2);
+
+  // the mask comes back out as black and white pixels
+  let image = mask.to_ppm();
+  assert_eq!(image.width(), 13);
+  assert_eq!(image.height(), 7);
+  assert_eq!(image.get_pixel_by_coord(0, 0).unwrap(), [255, 255, 255]);
+  assert_eq!(image.get_pixel_by_coord(12, 6).unwrap(), [255, 255, 255]);
+  assert_eq!(image.get_pixel_by_coord(5, 4).unwrap(), [0, 0, 0]);
+
+  // and thresholding that image gives back the same mask
+  assert_eq!(BinaryMask::from_threshold(&image, 127.), mask);
+
+  assert_eq!(mask.dilate().count(), 4 + 4);
+}
+
+#[test]
+fn test_mask_brush() {
+  use crate::core::mask::{BinaryMask, merge_runs};
+
+  // a brush of radius 1 is a plus sign
+  let mut mask = BinaryMask::new(10, 6);
+  mask.paint_circle(4, 2, 1, true);
+  assert_eq!(mask.count(), 5);
+  assert_eq!(mask.row_runs(1), vec![(4, 5)]);
+  assert_eq!(mask.row_runs(2), vec![(3, 6)]);
+  assert!(mask.row_runs(0).is_empty());
+
+  // clipped at the edges of the mask
+  mask.paint_circle(0, 0, 1, true);
+  assert_eq!(mask.row_runs(0), vec![(0, 2)]);
+
+  // a quick drag still paints a solid line
+  let mut line = BinaryMask::new(10, 6);
+  line.paint_line((0, 5), (9, 5), 0, true);
+  assert_eq!(line.row_runs(5), vec![(0, 10)]);
+  line.paint_line((3, 5), (5, 5), 0, false);
+  assert_eq!(line.row_runs(5), vec![(0, 3), (6, 10)]);
+
+  // a band of rows drawn as one covers everything painted in any of them
+  let band = [mask.row_runs(0), mask.row_runs(1), mask.row_runs(2)];
+  assert_eq!(merge_runs(&band), vec![(0, 2), (3, 6)]);
+  assert_eq!(merge_runs(&[vec![(0, 3)], vec![(3, 4), (8, 9)]]), vec![
+    (0, 4), (8, 9)
+  ]);
+  assert!(merge_runs(&[]).is_empty());
+}
+
+#[test]
+fn test_find_local_maxima() {
+  use crate::core::ccl::find_local_maxima;
+
+  // two stars close enough that their halos touch, on a dim background
+  let mut image = PpmImage::create_color(10, 10, 10, 15, 20);
+  for (cx, cy, peak) in [(6u32, 7u32, 250u8), (11, 7, 200)] {
+    for dy in -2i32..=2 {
+      for dx in -2i32..=2 {
+        let falloff = 40 * (dx.abs() + dy.abs()) as u8;
+        let value = peak.saturating_sub(falloff).max(10);
+        image.set_pixel_by_coord(
+          (cx as i32 + dx) as u32, (cy as i32 + dy) as u32, &[value; 3]
+        );
+      }
+    }
+  }
+
+  // a flat, dim plateau that is below the threshold
+  image.set_pixel_by_coord(1, 1, &[40, 40, 40]);
+  image.set_pixel_by_coord(2, 1, &[40, 40, 40]);
+
+  let maxima = find_local_maxima(&image, 5, 100.);
+  assert_eq!(maxima, vec![(6, 7), (11, 7)]);
+
+  // with the threshold dropped, the plateau is only reported once
+  let maxima = find_local_maxima(&image, 5, 30.);
+  assert_eq!(maxima, vec![(1, 1), (6, 7), (11, 7)]);
+}
+
+#[test]
+fn test_drawing() {
+  use crate::core::ppm::Selection;
+
+  const RED: [u8; 3] = [255, 0, 0];
+  const BLACK: [u8; 3] = [0, 0, 0];
+
+  let mut image = PpmImage::create_color(0, 0, 0, 10, 12);
+  image.draw_line((2, 4), (8, 4), &RED);
+
+  for x in 0..12 {
+    for y in 0..10 {
+      let expected = if y == 4 && (2..=8).contains(&x) { RED } else { BLACK };
+      assert_eq!(image.get_pixel_by_coord(x, y).unwrap(), expected);
+    }
+  }
+
+  // lines can run off of the image
+  let mut image = PpmImage::create_color(0, 0, 0, 10, 12);
+  image.draw_line((-5, 0), (20, 0), &RED);
+  assert!((0..12).all(|x| image.get_pixel_by_coord(x, 0).unwrap() == RED));
+
+  // the corners of a rect, and the edges of a circle
+  let mut image = PpmImage::create_color(0, 0, 0, 10, 12);
+  image.draw_rect(&Selection::new(1, 2, 4, 3), &RED);
+  assert_eq!(image.get_pixel_by_coord(1, 2).unwrap(), RED);
+  assert_eq!(image.get_pixel_by_coord(4, 4).unwrap(), RED);
+  assert_eq!(image.get_pixel_by_coord(2, 3).unwrap(), BLACK);
+
+  image.draw_circle((7, 5), 3, &RED);
+  for (x, y) in [(10, 5), (4, 5), (7, 2), (7, 8)] {
+    assert_eq!(image.get_pixel_by_coord(x, y).unwrap(), RED);
+  }
+  assert_eq!(image.get_pixel_by_coord(7, 5).unwrap(), BLACK);
+}
+
+#[test]
+fn test_draw_text() {
+  const WHITE: [u8; 3] = [255, 255, 255];
+
+  // the 'T' glyph is a bar across the top with a stem down the middle
+  let mut image = PpmImage::create_color(0, 0, 0, 9, 7);
+  image.draw_text("T", 1, 1, &WHITE);
+
+  for x in 0..7 {
+    for y in 0..9 {
+      let is_bar = y == 1 && (1..=5).contains(&x);
+      let is_stem = x == 3 && (1..=7).contains(&y);
+      let expected = if is_bar || is_stem { WHITE } else { [0, 0, 0] };
+      assert_eq!(image.get_pixel_by_coord(x, y).unwrap(), expected);
+    }
+  }
+
+  // lower case letters share the upper case glyphs, and each character moves
+  // the cursor along by the glyph width plus a pixel of spacing
+  let mut upper = PpmImage::create_color(0, 0, 0, 7, 12);
+  let mut lower = PpmImage::create_color(0, 0, 0, 7, 12);
+  upper.draw_text("II", 0, 0, &WHITE);
+  lower.draw_text("ii", 0, 0, &WHITE);
+  assert_eq!(upper, lower);
+  assert_eq!(upper.get_pixel_by_coord(2, 0).unwrap(), WHITE);
+  assert_eq!(upper.get_pixel_by_coord(8, 0).unwrap(), WHITE);
+  assert_eq!(upper.get_pixel_by_coord(5, 0).unwrap(), [0, 0, 0]);
+}
+
+#[test]
+fn test_thread_count_does_not_change_results() {
+  use crate::core::ppm::Padding;
+  use crate::core::filters::gaussian_blur;
+  use crate::core::threads::{set_thread_count, available_threads};
+  use rayon::ThreadPoolBuilder;
+
+  let mut image = PpmImage::create_color(30, 60, 90, 24, 32);
+  for x in 0..32 {
+    image.set_pixel_by_coord(x, (x * 7) % 24, &[250, (x * 8) as u8, 20]);
+  }
+
+  // each blur runs on a pool of its own, so that the shared pool (and the
+  // other tests using it) are left alone
+  let blur_with_threads = |threads: usize| -> PpmImage {
+    ThreadPoolBuilder::new().num_threads(threads).build().unwrap()
+      .install(|| gaussian_blur(&image, 1.5, 5, Padding::Repeat).unwrap())
+  };
+
+  let single = blur_with_threads(1);
+  let many = blur_with_threads(available_threads().max(4));
+
+  assert_eq!(single, many);
+  assert!(set_thread_count(0).is_err());
+}
+
+#[test]
+fn test_remove_stars() {
+  use crate::core::ccl::remove_stars;
+
+  // a smooth "nebula" gradient as the background
+  let mut image = PpmImage::create_color(0, 0, 0, 40, 40);
+  for y in 0..40 {
+    for x in 0..40 {
+      let value = 20 + x as u8;
+      image.set_pixel_by_coord(x, y, &[value, value / 2, value]);
+    }
+  }
+
+  // small stars
+  let stars = [(5, 5), (30, 8), (12, 33)];
+  for (x, y) in stars {
+    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+      image.set_pixel_by_coord(x + dx, y + dy, &[255, 255, 255]);
+    }
+  }
+
+  // a large bright region that should not be mistaken for a star
+  for y in 20..30 {
+    for x in 20..30 {
+      image.set_pixel_by_coord(x, y, &[255, 255, 255]);
+    }
+  }
+
+  let starless = remove_stars(&image, 10).unwrap();
+
+  for (x, y) in stars {
+    let pixel = starless.get_pixel_by_coord(x, y).unwrap();
+    let background = image.get_pixel_by_coord(x, y + 4).unwrap();
+    for ch in 0..3 {
+      assert!(pixel[ch].abs_diff(background[ch]) <= 3);
+    }
+  }
+
+  assert_eq!(starless.get_pixel_by_coord(25, 25), Some([255, 255, 255]));
+}
+
+/* #endregion */
+
+/* #region Transform Tests */
+
+#[test]
+pub fn test_gamma_correction() {
+
+  use crate::core::operations::gamma_transform;
+  
+  use crate::core::io::open_image;
+
+  let gamma_values = [
+    0.3, 2.8, 5.0
+  ];
+
+  let input = open_image(
+    "tests\\gamma_correction\\1.ppm")
+    .unwrap();
+
+  for gamma_value in gamma_values {
+    let gamma_correct_result = gamma_transform(
+      &input, gamma_value, None
+    );
+
+    assert!(gamma_correct_result.is_ok());
+
+    let check_path = format!(
+      "tests\\gamma_correction\\check.{:.1}.ppm", gamma_value
+    );
+
+    let check = open_image(check_path.as_str()).unwrap();
+
+    // a precision of 4 means to pass this test the images must be 99.99% 
+    // similar
+    assert_images_similar(gamma_correct_result.as_ref().unwrap(), &check, 4);
+  }
+}
+
+/**
+ * This test is a little more subjective. What I did was find an image that
+ * had been log transformed already, then I performed my own log transform and
+ * compared it to the expected result, measuring similarity between the images
+ * by getting the average percentage that all the pixels were different
+ * between the two images.
+ */
+#[test]
+pub fn test_log_transform() {
+  use crate::core::io::open_image;
+  use crate::core::operations::log_transform;
+
+  let base_dir = "tests\\log_transformation";
+
+  let test_images = [1, 2];
+
+  for image in test_images {
+    let input = open_image(
+      format!("{}\\{}.ppm", base_dir, image).as_str()
+    ).unwrap();
+  
+    let log_transformed = log_transform(
+      &input, None, None
+    ).unwrap();
+  
+    let check = open_image(
+      format!("{}\\check{}.ppm", base_dir, image).as_str()
+    ).unwrap();
+  
+    assert_images_similar(&log_transformed, &check, 4);
+  }
+}
+
+#[test]
+pub fn test_luminance_gamma_preserves_color() {
+  use crate::core::color::rgb_to_hsv;
+  use crate::core::operations::apply_on_luminance;
+  use crate::core::{H_CH, S_CH, V_CH};
+
+  let colors = [[200, 100, 50], [30, 120, 200], [90, 200, 40], [180, 40, 160]];
+
+  let mut image = PpmImage::create_color(0, 0, 0, 1, colors.len() as u32);
+  for (x, color) in colors.iter().enumerate() {
+    image.set_pixel_by_coord(x as u32, 0, color);
+  }
+
+  let gamma = 2.2;
+  let corrected = apply_on_luminance(&image, |value| {
+    (255. * (value as f32 / 255.).powf(1. / gamma)).round() as u8
+  }).unwrap();
+
+  for x in 0..colors.len() as u32 {
+    let before = rgb_to_hsv(image.get_pixel_by_coord(x, 0).unwrap());
+    let after = rgb_to_hsv(corrected.get_pixel_by_coord(x, 0).unwrap());
+
+    // only rounding to 8 bits should move the hue and saturation
+    assert!((before[H_CH] - after[H_CH]).abs() < 1.5);
+    assert!((before[S_CH] - after[S_CH]).abs() < 0.02);
+    assert!(after[V_CH] > before[V_CH]);
+  }
+}
+
+#[test]
+fn test_fourier_magnitude_scaling() {
+  use crate::core::fourier::{magnitude_to_u8, MagnitudeScale};
+
+  // large magnitudes are clamped, instead of wrapping around
+  assert_eq!(magnitude_to_u8(0.5, MagnitudeScale::Linear), 128);
+  assert_eq!(magnitude_to_u8(1., MagnitudeScale::Linear), 255);
+  assert_eq!(magnitude_to_u8(1000., MagnitudeScale::Linear), 255);
+  assert_eq!(magnitude_to_u8(-1000., MagnitudeScale::Linear), 255);
+  assert_eq!(magnitude_to_u8(f32::INFINITY, MagnitudeScale::Linear), 255);
+  assert_eq!(magnitude_to_u8(f32::NAN, MagnitudeScale::Linear), 0);
+
+  // a log scale fits the largest finite magnitude, and lifts the small ones
+  let magnitudes = [0., 10., 1000., f32::NAN, f32::INFINITY];
+  let scale = MagnitudeScale::log_fitted(&magnitudes);
+  assert_eq!(scale, MagnitudeScale::Log { max_magnitude: 1000. });
+
+  assert_eq!(magnitude_to_u8(0., scale), 0);
+  assert_eq!(magnitude_to_u8(1000., scale), 255);
+  assert_eq!(magnitude_to_u8(5000., scale), 255);
+  assert_eq!(magnitude_to_u8(f32::NAN, scale), 0);
+  assert!(magnitude_to_u8(10., scale) > 80);
+
+  // with nothing to fit, everything is dark rather than NaN
+  let empty = MagnitudeScale::log_fitted(&[]);
+  assert_eq!(magnitude_to_u8(10., empty), 0);
+}
+
+#[test]
+fn test_fft_2d_round_trip() {
+  use crate::core::fourier::{fft_2d_forward, fft_2d_inverse};
+
+  // a grayscale image, since only the intensity goes through the transform
+  let mut original = PpmImage::create_color(0, 0, 0, 6, 10);
+  for y in 0..6 {
+    for x in 0..10 {
+      let value = ((x * 25 + y * 40) % 256) as u8;
+      original.set_pixel_by_coord(x, y, &[value, value, value]);
+    }
+  }
+
+  let coefficients = fft_2d_forward(&original);
+  assert_eq!(coefficients.len(), 60);
+
+  // the DC component is the sum of the intensities
+  let sum: f32 = (0..original.width() * original.height())
+    .map(|i| original.get_pixel_at(i as usize)[0] as f32)
+    .sum();
+  assert!((coefficients[0].re - sum).abs() < 0.5);
+  assert!(coefficients[0].im.abs() < 0.5);
+
+  let round_trip = fft_2d_inverse(&coefficients, 10, 6);
+  assert_images_similar(&round_trip, &original, 4);
+}
+
+#[test]
+fn test_magnitude_spectrum() {
+  use std::f32::consts::PI;
+  use crate::core::fourier::magnitude_spectrum;
+
+  // 4 cycles of a cosine across the image
+  let mut stripes = PpmImage::create_color(0, 0, 0, 32, 32);
+  for y in 0..32 {
+    for x in 0..32 {
+      let angle = 2. * PI * 4. * x as f32 / 32.;
+      let value = (128. + 100. * angle.cos()).round() as u8;
+      stripes.set_pixel_by_coord(x, y, &[value, value, value]);
+    }
+  }
+
+  let spectrum = magnitude_spectrum(&stripes);
+  let brightness = |x, y| spectrum.get_pixel_by_coord(x, y).unwrap()[0];
+
+  // the DC component is in the middle, with the frequency of the stripes on
+  // either side of it
+  assert_eq!(brightness(16, 16), 255);
+  assert!(brightness(20, 16) > 200);
+  assert_eq!(brightness(20, 16), brightness(12, 16));
+
+  // and there is nothing anywhere else
+  assert!(brightness(16, 20) < 30);
+  assert!(brightness(5, 9) < 30);
+  assert!(brightness(0, 0) < 30);
+}
+
+#[test]
+fn test_fast_fourier_1d() {
+  use std::f32::consts::PI;
+  use rustfft::num_complex::Complex32;
+  use crate::core::fourier::{
+    fast_fourier, fast_fourier_1d, pad_to_power_of_two, MagnitudeScale
+  };
+
+  let signal: Vec<Complex32> = [1., 2., 0., -1., 3., 0.5, -2., 4.].iter()
+    .map(|&re| Complex32 { re: re, im: 0. })
+    .collect();
+
+  // the discrete fourier transform, straight from its definition
+  let size = signal.len();
+  let expected: Vec<Complex32> = (0..size).map(|k| {
+    signal.iter().enumerate().map(|(n, sample)| {
+      let angle = -2. * PI * (k * n) as f32 / size as f32;
+      sample * Complex32 { re: angle.cos(), im: angle.sin() }
+    }).sum()
+  }).collect();
+
+  let mut transformed = signal.clone();
+  fast_fourier_1d(&mut transformed);
+  for (actual, expected) in transformed.iter().zip(expected.iter()) {
+    assert!((actual - expected).norm() < 1e-4, "{} != {}", actual, expected);
+  }
+
+  // other lengths are padded up to a power of two
+  let mut odd_length = signal[..5].to_vec();
+  pad_to_power_of_two(&mut odd_length);
+  assert_eq!(odd_length.len(), 8);
+  assert_eq!(odd_length[7], Complex32 { re: 0., im: 0. });
+
+  let image = PpmImage::create_color(100, 150, 200, 5, 3);
+  let spectrum = fast_fourier(image, MagnitudeScale::Linear);
+  assert_eq!((spectrum.width(), spectrum.height()), (3, 5));
+}
+
+/* #endregion */
+
+/* #region Operation Tests */
+
+/**
+ * The following uses a helper function to test the expected outcomes of
+ * product operations on images, then makes sure that they are correct
+ */
+#[test]
+pub fn test_multiplication() {
+  // tests for "normal" circumstances
+  test_multiplication_helper(2, 2, 4);
+  test_multiplication_helper(3, 4, 12);
+  test_multiplication_helper(4, 3, 12);
+  test_multiplication_helper(4, 4, 16);
+
+  // test the zero / zero case
+  test_multiplication_helper(0, 0, 0);
+
+  // test the zero / 255 case
+  test_multiplication_helper(0, 255, 0);
+  
+  // test the one / 255 case
+  test_multiplication_helper(1, 255, 255);
+
+  // test the overflow case
+  test_multiplication_helper(30, 30, 255);
+}
+
+#[test]
+pub fn test_division() {
+  // tests for "normal" circumstances
+  test_division_helper(12, 4, 3);
+  test_division_helper(255, 1, 255);
+  test_division_helper(0, 7, 0);
+
+  // the quotient is rounded
+  test_division_helper(7, 2, 4);
+  test_division_helper(10, 4, 3);
+  test_division_helper(1, 3, 0);
+
+  // test the divide by zero cases
+  test_division_helper(5, 0, 255);
+  test_division_helper(0, 0, 255);
+}
+
+#[test]
+fn test_lighten_darken() {
+  use crate::core::operations::{combine_samples, perform_operation};
+
+  let mut image = PpmImage::new(8, 4);
+  for y in 0..4 {
+    for x in 0..8 {
+      image.set_pixel_by_coord(x, y, &[(x * 30) as u8, (y * 60) as u8, 90]);
+    }
+  }
+  let black = PpmImage::create_color(0, 0, 0, 4, 8);
+  let white = PpmImage::create_color(255, 255, 255, 4, 8);
+
+  // black never wins a lighten, and white never wins a darken
+  let blend = |rhs: &PpmImage, optype| {
+    perform_operation(&image, rhs, optype).unwrap()
+  };
+  assert_eq!(blend(&black, OpType::Lighten), image);
+  assert_eq!(blend(&white, OpType::Darken), image);
+  assert_eq!(blend(&white, OpType::Lighten), white);
+  assert_eq!(blend(&black, OpType::Darken), black);
+
+  // each channel is picked on its own
+  let lhs: [u16; 3] = [100, 5000, 65535];
+  let rhs: [u16; 3] = [200, 4000, 0];
+  assert_eq!(
+    combine_samples(&lhs, &rhs, OpType::Lighten), vec![200, 5000, 65535]
+  );
+  assert_eq!(combine_samples(&lhs, &rhs, OpType::Darken), vec![100, 4000, 0]);
+}
+
+#[test]
+fn test_operation_size_matching() {
+  use crate::core::operations::{
+    perform_operation, perform_operation_with_matching, SizeMatching
+  };
+  use crate::core::ppm::Selection;
+
+  // a large gradient, and a small flat image to add onto its corner
+  let mut large = PpmImage::new(12, 8);
+  for y in 0..8 {
+    for x in 0..12 {
+      large.set_pixel_by_coord(x, y, &[(x * 10) as u8, (y * 20) as u8, 50]);
+    }
+  }
+  let small = PpmImage::create_color(5, 5, 5, 3, 4);
+
+  let matched = |matching| {
+    perform_operation_with_matching(&large, &small, OpType::Add, matching)
+      .unwrap()
+  };
+
+  // keeping the first size adds onto the matching pixels, and leaves the rest
+  let composited = matched(SizeMatching::FirstOperand);
+  assert_eq!((composited.width(), composited.height()), (12, 8));
+  for y in 0..8 {
+    for x in 0..12 {
+      let original = large.get_pixel_by_coord(x, y).unwrap();
+      let expected = match x < 4 && y < 3 {
+        true => original.map(|sample| sample + 5),
+        false => original,
+      };
+      assert_eq!(composited.get_pixel_by_coord(x, y).unwrap(), expected);
+    }
+  }
+
+  // cropping keeps only the overlap, which is the same as the corner above
+  let cropped = matched(SizeMatching::Intersection);
+  assert_eq!((cropped.width(), cropped.height()), (4, 3));
+  assert_eq!(
+    cropped, composited.crop(&Selection::new(0, 0, 4, 3)).unwrap()
+  );
+
+  // rescaling (the default) stretches both images to the average size
+  let rescaled = matched(SizeMatching::Rescale);
+  assert_eq!((rescaled.width(), rescaled.height()), (8, 5));
+  assert_eq!(rescaled, perform_operation(&large, &small, OpType::Add).unwrap());
+}
+
+/// The safe math saturates at the max value of the sample type, so 16 bit
+/// samples clip at 65535 instead of 255
+#[test]
+fn test_sample_saturation() {
+  use crate::core::operations::{
+    add_safe, sub_safe, mult_safe, combine_samples, OpType, Sample
+  };
+
+  assert_eq!(add_safe(200u16, 100), 300);
+  assert_eq!(add_safe(65000u16, 1000), 65535);
+  assert_eq!(sub_safe(10u16, 20), 0);
+  assert_eq!(mult_safe(300u16, 300), 65535);
+  assert_eq!(mult_safe(255u16, 2), 510);
+
+  // 8 bit samples still clip at 255
+  assert_eq!(add_safe(200u8, 100), 255);
+  assert_eq!(mult_safe(16u8, 16), 255);
+
+  let lhs: [u16; 3] = [65535, 40000, 5];
+  let rhs: [u16; 3] = [1, 40000, 10];
+  assert_eq!(combine_samples(&lhs, &rhs, OpType::Add), vec![65535, 65535, 15]);
+  assert_eq!(combine_samples(&lhs, &rhs, OpType::Subtract), vec![65534, 0, 0]);
+  assert_eq!(combine_samples(&lhs, &rhs, OpType::Multiply), vec![65535, 65535, 50]);
+
+  assert!(65535u16.is_saturated());
+  assert!(!255u16.is_saturated());
+  assert!(255u8.is_saturated());
+}
+
+#[test]
+fn test_clamp_to_range() {
+  use crate::core::operations::clamp_to_range;
+
+  // 8 bit samples, at and around either end of the range
+  assert_eq!(clamp_to_range(-1., u8::MAX), 0u8);
+  assert_eq!(clamp_to_range(0., u8::MAX), 0u8);
+  assert_eq!(clamp_to_range(254.9, u8::MAX), 254u8);
+  assert_eq!(clamp_to_range(255., u8::MAX), 255u8);
+  assert_eq!(clamp_to_range(256., u8::MAX), 255u8);
+  assert_eq!(clamp_to_range(1e9, u8::MAX), 255u8);
+  assert_eq!(clamp_to_range(f32::NAN, u8::MAX), 0u8);
+
+  // 16 bit samples go well past where 8 bit ones stop
+  assert_eq!(clamp_to_range(-1., u16::MAX), 0u16);
+  assert_eq!(clamp_to_range(256., u16::MAX), 256u16);
+  assert_eq!(clamp_to_range(65535., u16::MAX), 65535u16);
+  assert_eq!(clamp_to_range(65536., u16::MAX), 65535u16);
+  assert_eq!(clamp_to_range(f32::INFINITY, u16::MAX), 65535u16);
+  assert_eq!(clamp_to_range(f32::NAN, u16::MAX), 0u16);
+
+  // a lower max clamps there instead
+  assert_eq!(clamp_to_range(200., 100u8), 100u8);
+  assert_eq!(clamp_to_range(5000., 4095u16), 4095u16);
+}
+
+/// Takes an image with a "1" in white in the upper lefthand corner, and adds
+/// it to an image that has a "3" in the lower lefthand corner, then compares
+/// the sum with a check image
+#[test]
+pub fn test_add(){
+  use crate::core::operations::OpType;
+
+  test_op(
+    "tests\\addition\\1.ppm",
+    "tests\\addition\\2.ppm",
+    OpType::Add,
+    "tests\\addition\\check.ppm"
+  );
+}
+
+#[test]
+pub fn test_subtract() {
+  use crate::core::operations::OpType;
+
+  test_op(
+    "tests\\subtraction\\1.ppm",
+    "tests\\subtraction\\2.ppm",
+    OpType::Subtract,
+    "tests\\subtraction\\check.ppm",
+  )
+}
+
+#[test]
+pub fn test_negatation() {
+
+  use crate::core::io::open_image;
+  use crate::core::operations::negate;
+
+  let input = open_image("tests\\negation\\1.ppm").unwrap();
+  let negated_result = negate(&input);
+  assert!(negated_result.is_ok());
+
+  let check = open_image("tests\\negation\\check.ppm").unwrap();
+
+  assert_eq!(negated_result.unwrap(), check);
+}
+
+#[test]
+fn test_negate_in_place() {
+  use crate::core::operations::negate;
+
+  let mut original = PpmImage::new(6, 4);
+  for y in 0..4 {
+    for x in 0..6 {
+      original.set_pixel_by_coord(x, y, &[(x * 40) as u8, (y * 60) as u8, 255]);
+    }
+  }
+
+  // the same result as the allocating negate (the image uses the full range,
+  // so both of them flip the values around 255)
+  let mut image = original.clone();
+  image.negate_in_place();
+  assert_images_equal(&image, &negate(&original).unwrap());
+  assert_eq!(image.get_pixel_by_coord(1, 2).unwrap(), [215, 135, 0]);
+
+  // and negating twice gives back the original
+  image.negate_in_place();
+  assert_images_equal(&image, &original);
+
+  // a dim image is flipped around its brightest value, just like negate does
+  let original = PpmImage::create_color(10, 20, 30, 3, 3);
+  let mut dim = original.clone();
+  dim.negate_in_place();
+  assert_images_equal(&dim, &negate(&original).unwrap());
+  assert_eq!(dim.get_pixel_by_coord(2, 2).unwrap(), [20, 10, 0]);
+}
+
+#[test]
+fn test_replace_color() {
+  use crate::core::color::ColorMetric;
+  use crate::core::operations::{replace_color, Operation};
+
+  // a green background, with red, almost red, and blue patches
+  let mut image = PpmImage::create_color(0, 160, 0, 4, 6);
+  image.set_pixel_by_coord(0, 0, &[255, 0, 0]);
+  image.set_pixel_by_coord(5, 3, &[255, 0, 0]);
+  image.set_pixel_by_coord(2, 1, &[250, 5, 5]);
+  image.set_pixel_by_coord(3, 2, &[0, 0, 255]);
+
+  // with no tolerance, only the exact color is replaced (wherever it is)
+  let exact = replace_color(
+    &image, [255, 0, 0], [255, 255, 0], 0., ColorMetric::Redmean
+  ).unwrap();
+  assert_eq!(exact.get_pixel_by_coord(0, 0).unwrap(), [255, 255, 0]);
+  assert_eq!(exact.get_pixel_by_coord(5, 3).unwrap(), [255, 255, 0]);
+  assert_eq!(exact.get_pixel_by_coord(2, 1).unwrap(), [250, 5, 5]);
+
+  // a little tolerance takes in the almost red pixel too, but nothing else
+  for metric in [ColorMetric::Redmean, ColorMetric::Cie76] {
+    let operation = Operation::ReplaceColor {
+      target: [255, 0, 0],
+      replacement: [255, 255, 0],
+      tolerance: 0.05,
+      metric: metric,
+    };
+    let loose = operation.apply(&image).unwrap();
+
+    for y in 0..4 {
+      for x in 0..6 {
+        let expected = match image.get_pixel_by_coord(x, y).unwrap() {
+          [255, 0, 0] | [250, 5, 5] => [255, 255, 0],
+          pixel => pixel,
+        };
+        assert_eq!(loose.get_pixel_by_coord(x, y).unwrap(), expected);
+      }
+    }
+  }
+
+  assert!(replace_color(
+    &image, [255, 0, 0], [0, 0, 0], -1., ColorMetric::Redmean
+  ).is_err());
+}
+
+#[test]
+fn test_adjust_color_range() {
+  use crate::core::color;
+  use crate::core::operations::adjust_color_range;
+  use crate::core::{H_CH, S_CH, V_CH};
+
+  // two blues (either side of 240 degrees), a red, a green, and a gray
+  let mut image = PpmImage::create_color(128, 128, 128, 2, 6);
+  image.set_pixel_by_coord(0, 0, &[40, 60, 200]);
+  image.set_pixel_by_coord(1, 0, &[90, 40, 200]);
+  image.set_pixel_by_coord(2, 0, &[200, 40, 40]);
+  image.set_pixel_by_coord(3, 0, &[40, 200, 40]);
+
+  // desaturate and darken everything within 30 degrees of blue
+  let adjusted = adjust_color_range(&image, 240., 30., -0.2, -0.1).unwrap();
+
+  for x in 0..2 {
+    let before = color::rgb_to_hsv(image.get_pixel_by_coord(x, 0).unwrap());
+    let after = color::rgb_to_hsv(adjusted.get_pixel_by_coord(x, 0).unwrap());
+
+    assert!((after[H_CH] - before[H_CH]).abs() < 2.);
+    assert!((after[S_CH] - (before[S_CH] - 0.2)).abs() < 0.01);
+    assert!((after[V_CH] - (before[V_CH] - 25.5)).abs() < 1.);
+  }
+
+  // the red, green, and gray pixels are left exactly as they were
+  for (x, y) in [(2, 0), (3, 0), (5, 1)] {
+    assert_eq!(
+      adjusted.get_pixel_by_coord(x, y), image.get_pixel_by_coord(x, y)
+    );
+  }
+
+  // the range wraps around, so reds either side of 0 degrees are both in it
+  let mut reds = PpmImage::create_color(128, 128, 128, 1, 2);
+  reds.set_pixel_by_coord(0, 0, &[200, 40, 60]);
+  reds.set_pixel_by_coord(1, 0, &[200, 60, 40]);
+  let adjusted = adjust_color_range(&reds, 0., 15., 0., -1.).unwrap();
+  assert_eq!(adjusted.get_pixel_by_coord(0, 0).unwrap(), [0, 0, 0]);
+  assert_eq!(adjusted.get_pixel_by_coord(1, 0).unwrap(), [0, 0, 0]);
+
+  assert!(adjust_color_range(&image, 240., -1., 0., 0.).is_err());
+}
+
+
+#[test]
+fn test_strided_histogram() {
+  use crate::core::operations::{Histogram, DISPLAY_HISTOGRAM_STRIDE};
+
+  // noise (from a fixed seed), so that every bin gets a good number of pixels
+  let mut image = PpmImage::new(97, 61);
+  let mut state: u32 = 12345;
+  for y in 0..61 {
+    for x in 0..97 {
+      state = state.wrapping_mul(1103515245).wrapping_add(12345);
+      image.set_pixel_by_coord(x, y, &[(state >> 16) as u8; 3]);
+    }
+  }
+
+  let full = Histogram::from_image_with_bins(&image, 16);
+  let strided = Histogram::from_image_strided(
+    &image, 16, DISPLAY_HISTOGRAM_STRIDE
+  );
+
+  // about a quarter of the pixels are looked at
+  let expected_count = (97 * 61 + DISPLAY_HISTOGRAM_STRIDE - 1) 
+    / DISPLAY_HISTOGRAM_STRIDE;
+  assert_eq!(strided.pixel_count as usize, expected_count);
+
+  // but each bin holds about the same share of them
+  for (key, count) in full.data.iter() {
+    let full_share = count / full.pixel_count as f32;
+    let strided_share = strided.data.get(key).cloned().unwrap_or(0.) 
+      / strided.pixel_count as f32;
+    assert!((full_share - strided_share).abs() < 0.02);
+  }
+
+  // a stride of one is the full histogram
+  assert!(Histogram::from_image_strided(&image, 16, 1) == full);
+}
+
+#[test]
+fn test_coarse_histogram_equalization() {
+  use crate::core::operations::{
+    histogram_equalization, Histogram, DISPLAY_HISTOGRAM_BINS
+  };
+
+  // a gray ramp from dark to bright
+  let mut image = PpmImage::new(64, 4);
+  for y in 0..4 {
+    for x in 0..64 {
+      image.set_pixel_by_coord(x, y, &[(x * 2 + 40) as u8; 3]);
+    }
+  }
+
+  let display = Histogram::from_image_with_bins(&image, DISPLAY_HISTOGRAM_BINS);
+  let coarse = Histogram::from_image_with_bins(&image, 16);
+
+  assert!(display.data.len() <= DISPLAY_HISTOGRAM_BINS as usize);
+  assert!(coarse.data.len() <= 16);
+  assert_eq!(coarse.pixel_count, display.pixel_count);
+
+  for histogram in [None, Some(display), Some(coarse)] {
+    let equalized = histogram_equalization(&image, histogram).unwrap();
+
+    // brighter pixels never come out darker than dimmer ones
+    let mut last_value = 0;
+    for x in 0..64 {
+      let value = equalized.get_pixel_by_coord(x, 0).unwrap()[0];
+      assert!(value >= last_value);
+      last_value = value;
+    }
+
+    // and the dark end of the ramp gets stretched down towards black
+    assert!(equalized.get_pixel_by_coord(0, 0).unwrap()[0] < 40);
+  }
+}
+
+#[test]
+fn test_histogram_matching() {
+  use crate::core::args::parse_arguments;
+  use crate::core::io::{open_image, write_image};
+
+  // a dark ramp, and a bright one to match it to
+  let mut dark = PpmImage::new(32, 2);
+  let mut bright = PpmImage::new(32, 2);
+  for y in 0..2 {
+    for x in 0..32 {
+      dark.set_pixel_by_coord(x, y, &[(x + 20) as u8; 3]);
+      bright.set_pixel_by_coord(x, y, &[(x * 3 + 150) as u8; 3]);
+    }
+  }
+
+  let directory = std::env::temp_dir();
+  let [source, reference, output] = [
+    "histmatch_source.ppm", "histmatch_reference.ppm", "histmatch_output.ppm"
+  ].map(|name| directory.join(name).to_str().unwrap().to_string());
+  write_image(&dark, &source).unwrap();
+  write_image(&bright, &reference).unwrap();
+
+  let args = [
+    "arglist", "histmatch", "-i", &source, "-ref", &reference, "-o", &output
+  ].map(|arg| arg.to_string()).to_vec();
+  let matched = parse_arguments(Some(args)).unwrap().unwrap();
+  assert_eq!(open_image(&output).unwrap(), matched);
+
+  let mean_value = |image: &PpmImage| {
+    image.get_data().iter().map(|sample| *sample as f32).sum::<f32>() / 
+      image.get_data().len() as f32
+  };
+  assert!(mean_value(&matched) > mean_value(&dark) + 100.);
+
+  // the matched ramp spans the range of the reference, in the same order
+  let first = matched.get_pixel_by_coord(0, 0).unwrap()[0];
+  let last = matched.get_pixel_by_coord(31, 0).unwrap()[0];
+  assert!(first >= 149 && last >= 240);
+  for x in 1..32 {
+    assert!(
+      matched.get_pixel_by_coord(x, 0).unwrap()[0] >= 
+      matched.get_pixel_by_coord(x - 1, 0).unwrap()[0]
+    );
+  }
+
+  // the flags have to be in their places
+  let misplaced = [
+    "arglist", "histmatch", "-i", &source, "-o", &reference, "-ref", &output
+  ].map(|arg| arg.to_string()).to_vec();
+  assert!(parse_arguments(Some(misplaced)).is_err());
+
+  for path in [source, reference, output] {
+    std::fs::remove_file(path).unwrap();
+  }
+}
+
+#[test]
+fn test_rgb_histogram_equalization() {
+  use crate::core::color::rgb_to_hsv;
+  use crate::core::operations::{
+    histogram_equalization, histogram_equalization_rgb
+  };
+  use crate::core::{H_CH, R_CH, G_CH, B_CH};
+
+  // an orange gradient, with red and green ramping at different rates
+  let mut image = PpmImage::new(40, 1);
+  for x in 0..40 {
+    image.set_pixel_by_coord(x, 0, &[(x * 5 + 40) as u8, (x + 30) as u8, 10]);
+  }
+
+  let value_only = histogram_equalization(&image, None).unwrap();
+  let per_channel = histogram_equalization_rgb(&image).unwrap();
+
+  // the darkest pixels are skipped, as rounding them to 8 bits after they've
+  // been darkened throws their hue off
+  let hue_shift = |equalized: &PpmImage| {
+    (8..40).map(|x| {
+      let before = rgb_to_hsv(image.get_pixel_by_coord(x, 0).unwrap())[H_CH];
+      let after = rgb_to_hsv(equalized.get_pixel_by_coord(x, 0).unwrap())[H_CH];
+      (before - after).abs()
+    }).fold(0., f32::max)
+  };
+
+  // equalizing the value channel keeps the hues (up to rounding), while 
+  // equalizing the channels separately moves them
+  assert!(hue_shift(&value_only) < 2.);
+  assert!(hue_shift(&per_channel) > 5.);
+
+  // every channel of the RGB result still ramps upwards, and the constant 
+  // blue channel is left alone
+  for x in 1..40 {
+    let last = per_channel.get_pixel_by_coord(x - 1, 0).unwrap();
+    let pixel = per_channel.get_pixel_by_coord(x, 0).unwrap();
+    assert!(pixel[R_CH] >= last[R_CH]);
+    assert!(pixel[G_CH] >= last[G_CH]);
+    assert_eq!(pixel[B_CH], 10);
+  }
+}
+
+#[test]
+fn test_auto_white_balance() {
+  use crate::core::operations::{auto_white_balance, channel_histograms};
+  use crate::core::{R_CH, G_CH, B_CH};
+
+  // a mostly gray scene with a strongly blue patch, under a warm cast that 
+  // boosts red and cuts blue
+  let mut image = PpmImage::new(20, 10);
+  for x in 0..20 {
+    for y in 0..10 {
+      let neutral = (x * 8 + y * 4 + 40) as f32;
+      let scene = if x >= 18 { [30., 40., 200.] } else { [neutral; 3] };
+      image.set_pixel_by_coord(x, y, &[
+        (scene[R_CH] * 1.25_f32).min(255.) as u8,
+        scene[G_CH] as u8,
+        (scene[B_CH] * 0.7) as u8,
+      ]);
+    }
+  }
+
+  let balanced = auto_white_balance(&image).unwrap();
+
+  let spread = |pixel: [u8; 3]| {
+    *pixel.iter().max().unwrap() as i32 - *pixel.iter().min().unwrap() as i32
+  };
+
+  // a gray pixel of the scene comes back out close to gray
+  let cast = image.get_pixel_by_coord(5, 5).unwrap();
+  let gray = balanced.get_pixel_by_coord(5, 5).unwrap();
+  assert!(spread(cast) > 50);
+  assert!(spread(gray) < 15, "{:?}", gray);
+
+  // which is closer than plain gray world (scaling the channel means) gets,
+  // as the blue patch drags the mean of the blue channel up
+  let mut means = [0_f32; 3];
+  for pixel in image.get_data().chunks_exact(3) {
+    for ch in 0..3 {
+      means[ch] += pixel[ch] as f32 / 200.;
+    }
+  }
+  let gray_world_target = means.iter().sum::<f32>() / 3.;
+  let gray_world = [0, 1, 2].map(|ch| {
+    (cast[ch] as f32 * gray_world_target / means[ch]).round() as u8
+  });
+  assert!(spread(gray) < spread(gray_world), "{:?}", gray_world);
+
+  // and the medians of the channels are lined up
+  let median = |counts: &[u32; 256]| {
+    let mut running = 0;
+    counts.iter().position(|count| { running += count; running >= 100 })
+      .unwrap() as i32
+  };
+  let counts = channel_histograms(&balanced);
+  assert!((median(&counts[R_CH]) - median(&counts[G_CH])).abs() <= 1);
+  assert!((median(&counts[B_CH]) - median(&counts[G_CH])).abs() <= 1);
+
+  // the blue patch stays blue
+  let patch = balanced.get_pixel_by_coord(19, 5).unwrap();
+  assert!(patch[B_CH] > patch[R_CH] + 100);
+
+  assert!(auto_white_balance(&PpmImage::new(0, 0)).is_err());
+}
+
+#[test]
+fn test_poisson_blend() {
+  use crate::core::blend::poisson_blend;
+  use crate::core::mask::BinaryMask;
+
+  // a horizontal gradient, with a flat patch pasted into the middle of it
+  let mut dest = PpmImage::create_color(0, 0, 0, 10, 16);
+  for y in 0..10 {
+    for x in 0..16 {
+      let value = (10 + 12 * x) as u8;
+      dest.set_pixel_by_coord(x, y, &[value, value, value]);
+    }
+  }
+  let src = PpmImage::create_color(200, 200, 200, 4, 6);
+  let mut mask = BinaryMask::new(6, 4);
+  for y in 0..4 {
+    for x in 0..6 {
+      mask.set(x, y, true);
+    }
+  }
+
+  // a patch with no detail of its own takes on the gradient around it
+  let blended = poisson_blend(&dest, &src, &mask, 5, 3).unwrap();
+  for y in 0..10 {
+    for x in 0..16 {
+      let pixel = blended.get_pixel_by_coord(x, y).unwrap();
+      let original = dest.get_pixel_by_coord(x, y).unwrap();
+      let inside = (5..11).contains(&x) && (3..7).contains(&y);
+      match inside {
+        true => assert!((pixel[0] as i32 - original[0] as i32).abs() <= 1),
+        false => assert_eq!(pixel, original),
+      }
+    }
+  }
+
+  assert!(poisson_blend(&dest, &src, &BinaryMask::new(3, 3), 0, 0).is_err());
+}
+
+#[test]
+fn test_masked_operation() {
+  use crate::core::mask::BinaryMask;
+  use crate::core::operations::Operation;
+
+  let mut image = PpmImage::create_color(40, 80, 120, 5, 8);
+  image.set_pixel_by_coord(1, 1, &[200, 10, 0]);
+  let mut mask = BinaryMask::new(8, 5);
+  for y in 1..3 {
+    for x in 1..4 {
+      mask.set(x, y, true);
+    }
+  }
+
+  // only the pixels inside of the mask are negated
+  let negated = Operation::Negate.apply_masked(&image, &mask).unwrap();
+  let everywhere = Operation::Negate.apply(&image).unwrap();
+  for y in 0..5 {
+    for x in 0..8 {
+      let expected = match mask.get(x, y) {
+        true => everywhere.get_pixel_by_coord(x, y).unwrap(),
+        false => image.get_pixel_by_coord(x, y).unwrap(),
+      };
+      assert_eq!(negated.get_pixel_by_coord(x, y).unwrap(), expected);
+    }
+  }
+
+  // anything that isn't a point operation can't be masked yet, and neither
+  // can an image with a mask of the wrong size
+  assert!(Operation::Rotate90.apply_masked(&image, &mask).is_err());
+  assert!(
+    Operation::Negate.apply_masked(&image, &BinaryMask::new(5, 8)).is_err()
+  );
+}
+
+#[test]
+fn test_inpaint() {
+  use crate::core::blend::inpaint;
+  use crate::core::mask::BinaryMask;
+
+  // a smooth diagonal gradient with a square hole punched in it
+  let mut image = PpmImage::create_color(0, 0, 0, 12, 12);
+  for y in 0..12 {
+    for x in 0..12 {
+      image.set_pixel_by_coord(x, y, &[
+        (20 + 10 * x) as u8, (30 + 8 * y) as u8, (5 * (x + y)) as u8
+      ]);
+    }
+  }
+  let mut damaged = image.clone();
+  let mut mask = BinaryMask::new(12, 12);
+  for y in 4..8 {
+    for x in 3..7 {
+      mask.set(x, y, true);
+      damaged.set_pixel_by_coord(x, y, &[255, 255, 255]);
+    }
+  }
+
+  let filled = inpaint(&damaged, &mask).unwrap();
+  for y in 0..12 {
+    for x in 0..12 {
+      let pixel = filled.get_pixel_by_coord(x, y).unwrap();
+      let original = image.get_pixel_by_coord(x, y).unwrap();
+      for ch in 0..3 {
+        assert!((pixel[ch] as i32 - original[ch] as i32).abs() <= 1);
+      }
+    }
+  }
+
+  assert!(inpaint(&image, &BinaryMask::new(3, 3)).is_err());
+
+  let mut everything = BinaryMask::new(12, 12);
+  for y in 0..12 {
+    for x in 0..12 {
+      everything.set(x, y, true);
+    }
+  }
+  assert!(inpaint(&image, &everything).is_err());
+}
+
+/* #endregion */
+
+/* #region Scaling Tests   */
+
+#[test]
+fn test_build_pyramid() {
+  use crate::core::operations::build_pyramid;
+
+  let mut image = PpmImage::new(37, 20);
+  for x in 0..37 {
+    for y in 0..20 {
+      image.set_pixel_by_coord(x, y, &[(x * 6) as u8, (y * 12) as u8, 77]);
+    }
+  }
+
+  let pyramid = build_pyramid(&image, 4);
+  assert_eq!(pyramid.len(), 4);
+  assert_eq!(pyramid[0], image);
+
+  for level in 1..pyramid.len() {
+    assert_eq!(pyramid[level].width(), pyramid[level - 1].width() / 2);
+    assert_eq!(pyramid[level].height(), pyramid[level - 1].height() / 2);
+  }
+
+  // each pixel is the average of the block above it
+  let top_left = pyramid[1].get_pixel_by_coord(0, 0).unwrap();
+  assert_eq!(top_left, [3, 6, 77]);
+
+  // the odd last column is folded into the last block rather than dropped
+  let last = pyramid[1].get_pixel_by_coord(17, 0).unwrap();
+  assert_eq!(last[0], 210);
+
+  // the pyramid stops once it gets down to a single pixel
+  let pyramid = build_pyramid(&image, 20);
+  assert_eq!(pyramid.len(), 6);
+  let smallest = pyramid.last().unwrap();
+  assert_eq!((smallest.width(), smallest.height()), (1, 1));
+
+  assert!(build_pyramid(&image, 0).is_empty());
+}
+
+#[test]
+fn test_resize_prefilter() {
+  use crate::core::operations::{
+    resize, resize_with_prefilter, prefilter_sigma, ResizeAlgorithm
+  };
+
+  // stripes three pixels apart are too fine to survive a 4x downscale
+  let mut image = PpmImage::new(96, 96);
+  for x in 0..96 {
+    for y in 0..96 {
+      let value = if 0 == x % 3 { 255 } else { 0 };
+      image.set_pixel_by_coord(x, y, &[value; 3]);
+    }
+  }
+
+  // the energy of the differences between neighbouring pixels, which is all
+  // high frequency detail (or aliasing)
+  let high_frequency_energy = |image: &PpmImage| -> f64 {
+    let mut energy = 0.;
+    for y in 0..image.height() {
+      for x in 1..image.width() {
+        let lhs = image.get_pixel_by_coord(x - 1, y).unwrap()[0] as f64;
+        let rhs = image.get_pixel_by_coord(x, y).unwrap()[0] as f64;
+        energy += (lhs - rhs).powi(2);
+      }
+    }
+    energy
+  };
+
+  let algorithm = Some(ResizeAlgorithm::NearestNeighbor);
+  let naive = resize(&image, 24, 24, algorithm).unwrap();
+  let filtered = resize_with_prefilter(&image, 24, 24, algorithm, true).unwrap();
+
+  // the naive resize samples the stripes into a coarser (aliased) pattern,
+  // while the prefiltered one is close to flat gray
+  assert!(high_frequency_energy(&naive) > 0.);
+  assert!(
+    high_frequency_energy(&filtered) < high_frequency_energy(&naive) / 10.,
+    "{} vs {}", high_frequency_energy(&filtered), high_frequency_energy(&naive)
+  );
+
+  // without the flag (or when enlarging) it is the same as resize
+  assert_eq!(
+    resize_with_prefilter(&image, 24, 24, algorithm, false).unwrap(), naive
+  );
+  assert_eq!(
+    resize_with_prefilter(&image, 120, 120, algorithm, true).unwrap(),
+    resize(&image, 120, 120, algorithm).unwrap()
+  );
+  assert_eq!(prefilter_sigma(1.), 0.);
+  assert_eq!(prefilter_sigma(4.), 1.5);
+}
+
+#[test]
+pub fn test_nearest_neighbor_scaling() {
+ 
+  use crate::core::operations::{resize, ResizeAlgorithm};
+  use crate::core::io::open_image;
+
+  let base_dir = "tests\\resize\\nearest_neighbor";
+
+  let input = open_image(
+    format!("{}\\1.ppm", base_dir).as_str()
+  ).unwrap();
+
+  let sizes: [[u32;2];5] = [
+    [128,64],  
+    [128,128],
+    [256,256],
+    [64,128],
+    [1024,1024],
+  ];
+
+  for size in sizes {
+    let resized = resize(
+      &input, size[0], size[1], 
+      Some(ResizeAlgorithm::NearestNeighbor)
+    ).unwrap();
+
+    let check = open_image(
+      format!(
+        "{}\\1.{}x{}.ppm", base_dir, size[0], size[1]
+      ).as_str()
+    ).unwrap();
+
+    assert_eq!(resized, check);
+  }
+}
+
+#[test]
+pub fn test_bilinear_iterpolation_scaling() {
+  
+  use crate::core::operations::{resize, ResizeAlgorithm};
+  use crate::core::io::open_image;
+
+  let base_dir = "tests\\resize\\bilinear_interpolation";
+
+  let input = open_image(
+    format!("{}\\1.ppm", base_dir).as_str()
+  ).unwrap();
+
+  let sizes: [[u32;2];5] = [
+    [128,64],  
+    [128,128],
+    [256,256],
+    [64,128],
+    [1024,1024],
+  ];
+
+  for size in sizes {
+    let resized = resize(
+      &input, size[0], size[1], 
+      Some(ResizeAlgorithm::BilinearInterpolation)
+    ).unwrap();
+
+    let check = open_image(
+      format!(
+        "{}\\1.{}x{}.ppm", base_dir, size[0], size[1]
+      ).as_str()
+    ).unwrap();
+
+    // similarity of 3 means 99.9% similarity
+    assert_images_similar(&resized, &check, 3);
+  }
+
+}
+
+#[test]
+pub fn test_bicubic_scaling() {
+  use crate::core::operations::{resize, ResizeAlgorithm};
+  use crate::core::io::open_image;
+
+  let base_dir = "tests/resize/bilinear_interpolation";
+
+  let input = open_image(
+    format!("{}/1.ppm", base_dir).as_str()
+  ).unwrap();
+
+  // bicubic is a little sharper than bilinear, but should still come out
+  // very close to it
+  let sizes: [[u32;2];5] = [
+    [128,64],  
+    [128,128],
+    [256,256],
+    [64,128],
+    [1024,1024],
+  ];
+
+  for size in sizes {
+    let resized = resize(
+      &input, size[0], size[1], Some(ResizeAlgorithm::Bicubic)
+    ).unwrap();
+
+    let check = open_image(
+      format!("{}/1.{}x{}.ppm", base_dir, size[0], size[1]).as_str()
+    ).unwrap();
+
+    // similarity of 2 means 99% similarity
+    assert!(is_reasonably_similar(&resized, &check, 2));
+  }
+
+  // a flat image stays flat all the way to its borders, rather than being 
+  // darkened by samples from past the edge
+  let flat = PpmImage::create_color(200, 100, 50, 5, 5);
+  let enlarged = resize(
+    &flat, 17, 13, Some(ResizeAlgorithm::Bicubic)
+  ).unwrap();
+  assert_eq!(enlarged, PpmImage::create_color(200, 100, 50, 13, 17));
+}
+
+#[test]
+pub fn test_crop_to_selection() {
+  use crate::core::ppm::Selection;
+
+  let mut image = PpmImage::create_color(10, 20, 30, 8, 8);
+  image.set_pixel_by_coord(3, 2, &[255, 255, 255]);
+
+  // corners given in reverse order should still produce the same selection
+  let selection = Selection::from_corners(5, 4, 2, 1);
+  assert_eq!(selection, Selection::new(2, 1, 4, 4));
+
+  let cropped = image.crop(&selection).unwrap();
+  assert_eq!(cropped.width(), 4);
+  assert_eq!(cropped.height(), 4);
+  assert_eq!(cropped.get_pixel_by_coord(1, 1), Some([255, 255, 255]));
+  assert_eq!(cropped.get_pixel_by_coord(0, 0), Some([10, 20, 30]));
+
+  // selections hanging off the edge of the image are clamped
+  let clamped = image.crop(&Selection::new(6, 6, 10, 10)).unwrap();
+  assert_eq!((clamped.width(), clamped.height()), (2, 2));
+
+  assert!(image.crop(&Selection::new(8, 0, 1, 1)).is_none());
+}
+
+/* #endregion */
+
+/* #region Color Tests */
+
+#[test]
+fn test_hsv_to_rgb() {
+  use crate::core::io::open_image;
+  use crate::core::PIXEL_SIZE;
+  use crate::core::ppm::PpmImage;
+  use crate::core::color;
+  use crate::core::{H_CH, S_CH, V_CH};
+
+  let base_dir = "tests\\colorspace_conversion";
+
+  let open_image_result = open_image(
+    format!("{}\\1.ppm", base_dir).as_str()
+  );
+
+  assert!(open_image_result.is_ok());
+
+  let image = open_image_result.ok().unwrap();
+  
+  let mut image_hsv_and_back = PpmImage::new(
+    image.width(), image.height()
+  );
+
+  let mut abs_diff:usize = 0;
+
+  for y in 0..image.height() {
+    for x in 0..image.width() {
+
+      if let Some(rgb_pixel) = image.get_pixel_by_coord(x, y) {
+        let hsv_pixel = color::rgb_to_hsv(rgb_pixel);
+
+        let back_to_rgb = color::hsv_to_rgb(
+          hsv_pixel[H_CH], 
+   hsv_pixel[S_CH], 
+        hsv_pixel[V_CH]
+        );
+
+        image_hsv_and_back.set_pixel_by_coord(x, y, &back_to_rgb);
+        for i in 0..PIXEL_SIZE {
+          abs_diff += (rgb_pixel[i]).abs_diff(back_to_rgb[i]) as usize;
+        }
+      }
+    }
+  }
+
+  if 0 != abs_diff {
+    assert!(
+      is_reasonably_similar(
+        &image, 
+        &image_hsv_and_back, 
+        2
+      )
+    )
+  } else {
+    assert_eq!(0, abs_diff);
+  }
+}
+
+#[test]
+fn test_srgb_linear_round_trip() {
+  use crate::core::color;
+
+  // every 8 bit value comes back as itself
+  for value in 0..=255u8 {
+    let linear = color::srgb_to_linear(value);
+    assert_eq!(color::linear_to_srgb(linear), value);
+
+    // and the lookup table matches the curve it was made from
+    let decoded = color::decode_srgb(value as f32 / 255.);
+    assert!((linear - decoded).abs() < 1e-6);
+  }
+
+  // known values of the curve, either side of where it turns linear
+  assert_eq!(color::srgb_to_linear(0), 0.);
+  assert_eq!(color::srgb_to_linear(255), 1.);
+  assert!((color::srgb_to_linear(10) - 0.003035).abs() < 1e-5);
+  assert!((color::srgb_to_linear(128) - 0.215861).abs() < 1e-5);
+
+  // the linear light half way point is well above the middle of sRGB
+  assert_eq!(color::linear_to_srgb(0.5), 188);
+  assert_eq!(color::linear_to_srgb(-1.), 0);
+  assert_eq!(color::linear_to_srgb(2.), 255);
+}
+
+#[test]
+fn test_cielab_conversion() {
+  use crate::core::color::{self, ColorMetric};
+
+  let close = |lab: [f32; 3], expected: [f32; 3]| {
+    lab.iter().zip(expected.iter()).all(|(a, b)| (a - b).abs() < 0.05)
+  };
+
+  // reference values for the sRGB primaries and white (D65)
+  assert!(close(color::rgb_to_lab([0, 0, 0]), [0., 0., 0.]));
+  assert!(close(color::rgb_to_lab([255, 255, 255]), [100., 0., 0.]));
+  assert!(close(color::rgb_to_lab([255, 0, 0]), [53.24, 80.09, 67.20]));
+  assert!(close(color::rgb_to_lab([0, 255, 0]), [87.73, -86.18, 83.18]));
+  assert!(close(color::rgb_to_lab([0, 0, 255]), [32.30, 79.19, -107.86]));
+
+  let white = color::rgb_to_xyz([255, 255, 255]);
+  assert!(white.iter().zip(color::D65_WHITE.iter())
+    .all(|(a, b)| (a - b).abs() < 1e-3));
+
+  // black to white is 100 apart, and a color is no distance from itself
+  let black_to_white = color::delta_e_cie76([0, 0, 0], [255, 255, 255]);
+  assert!((black_to_white - 100.).abs() < 0.05);
+  assert_eq!(color::delta_e_cie76([12, 200, 99], [12, 200, 99]), 0.);
+
+  // red to green (the reference difference is about 170.6)
+  let red_to_green = color::delta_e_cie76([255, 0, 0], [0, 255, 0]);
+  assert!((red_to_green - 170.57).abs() < 0.1);
+
+  // both metrics put black and white 1 apart
+  for metric in [ColorMetric::Redmean, ColorMetric::Cie76] {
+    let distance = metric.distance([0, 0, 0], [255, 255, 255]);
+    assert!((distance - 1.).abs() < 1e-3);
+    assert_eq!(metric.distance([40, 50, 60], [40, 50, 60]), 0.);
+  }
+}
+
+#[test]
+fn test_grayscale_modes() {
+  use crate::core::color;
+  use crate::core::operations::{to_grayscale, GrayscaleMode};
+
+  // grays come out the same either way, as the weights add up to one
+  for value in [0u8, 64, 200, 255] {
+    assert_eq!(color::srgb_luma([value; 3]), value);
+    assert_eq!(color::linear_luminance([value; 3]), value);
+  }
+
+  // but a saturated red is a lot brighter in linear light: 0.2126 of the 
+  // light of white, which is encoded as about half way up the range, rather
+  // than 0.2126 of the way up
+  let red = PpmImage::create_color(255, 0, 0, 2, 3);
+  let fast = to_grayscale(&red, GrayscaleMode::Srgb).unwrap();
+  let linear = to_grayscale(&red, GrayscaleMode::LinearLight).unwrap();
+
+  assert_eq!(fast.get_pixel_by_coord(1, 1).unwrap(), [54; 3]);
+  assert_eq!(linear.get_pixel_by_coord(1, 1).unwrap(), [127; 3]);
+
+  // linear light is never darker, since the transfer curve bows upwards
+  let blue = PpmImage::create_color(30, 60, 255, 2, 2);
+  let fast = to_grayscale(&blue, GrayscaleMode::Srgb).unwrap();
+  let linear = to_grayscale(&blue, GrayscaleMode::LinearLight).unwrap();
+  assert!(linear.get_pixel_by_coord(0, 0).unwrap()[0] 
+    > fast.get_pixel_by_coord(0, 0).unwrap()[0]);
+}
+
+/* #endregion */
+
+/* #region IO Tests        */
+
+#[test]
+fn test_read_write() {
+  use std::fs::{read_dir, remove_file};
+  use crate::core::io::{open_image, write_image};
+
+  let paths = read_dir(
+    ".\\samples\\OfficialTestImages\\"
+  ).unwrap();
+
+  let mut failed_count = 0;
+
+  const SAMPLE_FILE:&str = "samples\\TEMP.ppm";
+  for path in paths {
+    let mut image = open_image(
+      path.unwrap().path().to_str().unwrap()
+    ).unwrap();
+
+    match write_image(&mut image, SAMPLE_FILE) {
+      Err(why) => panic!("Could not write file: {}", why),
+      Ok(_) => {},
+    }
+
+    let read_image = open_image(SAMPLE_FILE).unwrap();
+
+    println!("Type of PPM Image: {}", image.ppm_type());
+
+    if read_image != image {
+      failed_count += 1
+    } else {
+      println!("Image successfully read, written, and read!");
+    }
+
+    remove_file(SAMPLE_FILE).expect("Could not delete sample file.");
+  }
+
+  assert_eq!(0, failed_count);
+}
+
+#[test]
+fn test_png_color_space_tag() {
+  use crate::core::io::{open_image, write_png, ColorSpace};
+
+  let mut image = PpmImage::create_color(200, 100, 50, 6, 5);
+  image.set_pixel_by_coord(2, 3, &[1, 2, 3]);
+
+  let contains = |bytes: &[u8], marker: &[u8]| {
+    bytes.windows(marker.len()).any(|window| window == marker)
+  };
+
+  for (color_space, marker) in [
+    (ColorSpace::Srgb, &b"sRGB"[..]), 
+    (ColorSpace::Linear, &b"gAMA"[..])
+  ] {
+    let path = std::env::temp_dir().join(format!("tagged_{}.png", color_space));
+    let path = path.to_str().unwrap();
+    write_png(&image, path, color_space, &[]).unwrap();
+
+    let bytes = std::fs::read(path).unwrap();
+    assert!(contains(&bytes, marker));
+
+    // the chunk has to come before the image data
+    let marker_at = bytes.windows(4).position(|window| window == marker).unwrap();
+    let data_at = bytes.windows(4).position(|window| window == b"IDAT").unwrap();
+    assert!(marker_at < data_at);
+
+    // and the file still decodes (so the chunk's checksum is right)
+    assert_eq!(open_image(path).unwrap(), image);
+    std::fs::remove_file(path).unwrap();
+  }
+
+  let path = std::env::temp_dir().join("untagged.png");
+  let path = path.to_str().unwrap();
+  write_png(&image, path, ColorSpace::Untagged, &[]).unwrap();
+  assert!(!contains(&std::fs::read(path).unwrap(), b"sRGB"));
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_write_image_by_extension() {
+  use crate::core::io::{open_image, write_image};
+
+  let mut image = PpmImage::create_color(0, 0, 0, 16, 16);
+  for y in 0..16 {
+    for x in 0..16 {
+      image.set_pixel_by_coord(x, y, &[(x * 16) as u8, (y * 16) as u8, 128]);
+    }
+  }
+
+  // PNG is lossless, so the image comes back exactly as it was
+  let path = std::env::temp_dir().join("write_by_extension.png");
+  let path = path.to_str().unwrap();
+  write_image(&image, path).unwrap();
+  assert!(std::fs::read(path).unwrap().starts_with(&[137, 80, 78, 71]));
+  let reopened = open_image(path).unwrap();
+  assert_eq!(measure_similarity(&image, &reopened), 1.0);
+  std::fs::remove_file(path).unwrap();
+
+  // JPEG is lossy, so it only has to come back close
+  for name in ["write_by_extension.jpg", "write_by_extension.JPEG"] {
+    let path = std::env::temp_dir().join(name);
+    let path = path.to_str().unwrap();
+    write_image(&image, path).unwrap();
+    assert!(std::fs::read(path).unwrap().starts_with(&[0xFF, 0xD8]));
+    let reopened = open_image(path).unwrap();
+    assert!(measure_similarity(&image, &reopened) > 0.99);
+    std::fs::remove_file(path).unwrap();
+  }
+
+  // BMP and TIFF are lossless too
+  let lossless: [(&str, &[u8]); 2] = [
+    ("write_by_extension.bmp", b"BM"), ("write_by_extension.tiff", b"II*\0")
+  ];
+  for (name, signature) in lossless {
+    let path = std::env::temp_dir().join(name);
+    let path = path.to_str().unwrap();
+    write_image(&image, path).unwrap();
+    assert!(std::fs::read(path).unwrap().starts_with(signature));
+    let reopened = open_image(path).unwrap();
+    assert_eq!(measure_similarity(&image, &reopened), 1.0);
+    std::fs::remove_file(path).unwrap();
+  }
+
+  // anything else is still written as a PPM
+  let path = std::env::temp_dir().join("write_by_extension.ppm");
+  let path = path.to_str().unwrap();
+  write_image(&image, path).unwrap();
+  assert!(std::fs::read(path).unwrap().starts_with(b"P6"));
+  assert_eq!(open_image(path).unwrap(), image);
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_read_ppm_mapped() {
+  use std::fs::remove_file;
+  use crate::core::io::{open_image, read_ppm_mapped, write_image};
+
+  let mut image = PpmImage::create_color(10, 20, 30, 9, 13);
+  for x in 0..13 {
+    image.set_pixel_by_coord(x, x % 9, &[255, (x * 19) as u8, 7]);
+  }
+
+  let path = std::env::temp_dir().join("image_viewer_mapped.ppm");
+  let path = path.to_str().unwrap();
+  write_image(&image, path).unwrap();
+
+  let regular = open_image(path).unwrap();
+  let mut mapped = read_ppm_mapped(path).unwrap();
+
+  assert!(mapped.is_mapped());
+  assert!(!regular.is_mapped());
+  assert_eq!(mapped, regular);
+  assert_eq!(mapped.get_data(), regular.get_data());
+  assert_eq!(mapped.max_value(), regular.max_value());
+
+  // writing to a mapped image copies the pixels out of the file first
+  mapped.set_pixel_by_coord(0, 0, &[1, 2, 3]);
+  assert!(!mapped.is_mapped());
+  assert_eq!(mapped.get_pixel_by_coord(0, 0).unwrap(), [1, 2, 3]);
+  assert_eq!(read_ppm_mapped(path).unwrap(), regular);
+
+  remove_file(path).expect("Could not delete sample file.");
+}
+
+#[test]
+fn test_write_max_value() {
+  use std::fs::{read, remove_file};
+  use crate::core::io::{open_image, write_ppm, MaxValue};
+
+  // a dim image, which never uses the top of the range
+  let mut image = PpmImage::create_color(10, 20, 30, 4, 5);
+  image.set_pixel_by_coord(2, 1, &[90, 0, 45]);
+
+  let path = std::env::temp_dir().join("image_viewer_max_value.ppm");
+  let path = path.to_str().unwrap();
+
+  write_ppm(&image, path, MaxValue::Full, &[]).unwrap();
+  assert!(read(path).unwrap().starts_with(b"P6\n5 4\n255\n"));
+
+  let reread = open_image(path).unwrap();
+  assert_eq!(reread.get_data(), image.get_data());
+  assert_eq!(reread.get_pixel_by_coord(2, 1).unwrap(), [90, 0, 45]);
+
+  // the computed value is never below the brightest sample
+  let computed = MaxValue::Computed.for_image(&image);
+  assert!(computed >= 90);
+  write_ppm(&image, path, MaxValue::Computed, &[]).unwrap();
+  let header = format!("P6\n5 4\n{}\n", computed);
+  assert!(read(path).unwrap().starts_with(header.as_bytes()));
+
+  remove_file(path).expect("Could not delete sample file.");
+}
+
+#[test]
+fn test_operation_log_comments() {
+  use std::fs::{read, remove_file};
+  use crate::core::io::{open_image, write_ppm, write_png, MaxValue, ColorSpace};
+  use crate::core::operations::Operation;
+  use crate::core::provenance::{Edit, OperationLog};
+
+  let original = PpmImage::create_color(200, 100, 50, 4, 6);
+
+  // a session that opens an image, and then negates and rotates it
+  let mut log = OperationLog::new();
+  log.record(Edit::Opened("original.ppm".to_string()));
+
+  let mut image = original.clone();
+  for operation in [Operation::Negate, Operation::Rotate90] {
+    image = operation.apply(&image).unwrap();
+    log.record(Edit::Other);
+    log.amend(Edit::Operation(operation));
+  }
+
+  // an undone edit is forgotten
+  log.record(Edit::Other);
+  log.undo();
+
+  let comments = log.comments();
+  assert_eq!(comments, vec![
+    "Opened from original.ppm".to_string(),
+    "Step 1: Negate".to_string(),
+    "Step 2: Rotate 90 degrees".to_string(),
+  ]);
+
+  let path = std::env::temp_dir().join("image_viewer_provenance.ppm");
+  let path = path.to_str().unwrap();
+  write_ppm(&image, path, MaxValue::Full, &comments).unwrap();
+
+  let bytes = read(path).unwrap();
+  assert!(bytes.starts_with(
+    b"P6\n# Opened from original.ppm\n# Step 1: Negate\n\
+      # Step 2: Rotate 90 degrees\n4 6\n255\n"
+  ));
+
+  // the comments don't get in the way of reading the image back
+  assert_eq!(open_image(path).unwrap().get_data(), image.get_data());
+  remove_file(path).expect("Could not delete sample file.");
+
+  // PNGs keep them in text chunks
+  let path = std::env::temp_dir().join("image_viewer_provenance.png");
+  let path = path.to_str().unwrap();
+  write_png(&image, path, ColorSpace::Srgb, &comments).unwrap();
+
+  let bytes = read(path).unwrap();
+  let text = b"tEXtComment\0Step 2: Rotate 90 degrees";
+  assert!(bytes.windows(text.len()).any(|window| window == text));
+  assert_eq!(open_image(path).unwrap(), image);
+  remove_file(path).expect("Could not delete sample file.");
+
+  // opening another image starts the log over
+  log.record(Edit::Opened("other.ppm".to_string()));
+  assert_eq!(log.comments(), vec!["Opened from other.ppm".to_string()]);
+}
+
+#[test]
+fn test_read_ascii_ppm() {
+  use crate::core::io::open_image;
+
+  // in a bitmap 1 is black and 0 is white
+  let bitmap = open_image("tests/ascii/bitmap.pbm").unwrap();
+  assert_eq!((bitmap.width(), bitmap.height()), (4, 3));
+
+  let rows = ["0110", "1001", "0110"];
+  for (y, row) in rows.iter().enumerate() {
+    for (x, bit) in row.chars().enumerate() {
+      let value = if '1' == bit { 0 } else { 255 };
+      assert_eq!(
+        bitmap.get_pixel_by_coord(x as u32, y as u32).unwrap(), [value; 3]
+      );
+    }
+  }
+
+  // gray values are scaled by the max value and copied into each channel
+  let gray = open_image("tests/ascii/gray.pgm").unwrap();
+  assert_eq!((gray.width(), gray.height()), (3, 2));
+
+  let expected = [0, 64, 128, 191, 255, 0];
+  for (index, value) in expected.iter().enumerate() {
+    let (x, y) = ((index % 3) as u32, (index / 3) as u32);
+    assert_eq!(gray.get_pixel_by_coord(x, y).unwrap(), [*value; 3]);
+  }
+}
+
+#[test]
+fn test_read_binary_bitmap() {
+  use crate::core::io::open_image;
+
+  // the width isn't a multiple of 8, and the padding at the end of each row
+  // is set, so any bits bleeding into the next row would show
+  let bitmap = open_image("tests/bitmap/1.pbm").unwrap();
+  let check = open_image("tests/bitmap/check.ppm").unwrap();
+
+  assert_eq!((bitmap.width(), bitmap.height()), (10, 3));
+  assert_eq!(bitmap.get_data(), check.get_data());
+}
+
+#[test]
+fn test_read_ascii_ppm_streaming() {
+  use std::fs::{remove_file, write};
+  use crate::core::io::{open_image, write_image};
+
+  let image = open_image("tests/ascii/color.ppm").unwrap();
+  assert_eq!((image.width(), image.height()), (2, 2));
+  assert_eq!(
+    image.get_data(), &[255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255]
+  );
+
+  // reading stops once the image is full, so anything after the last pixel
+  // is never parsed
+  let path = std::env::temp_dir().join("image_viewer_streaming.ppm");
+  let path = path.to_str().unwrap();
+  let contents = std::fs::read_to_string("tests/ascii/color.ppm").unwrap();
+  write(path, contents + "not a sample\n").unwrap();
+  assert_eq!(open_image(path).unwrap(), image);
+
+  // the ASCII image matches the same image read back as binary
+  write_image(&image, path).unwrap();
+  assert_eq!(open_image(path).unwrap().get_data(), image.get_data());
+
+  remove_file(path).expect("Could not delete sample file.");
+}
+
+#[test]
+fn test_read_16_bit_ppm() {
+  use crate::core::io::{open_image, read_ppm_mapped};
+
+  // a 2x1 P6 file with 16 bit samples, stored most significant byte first
+  let mut bytes = b"P6\n2 1\n65535\n".to_vec();
+  for sample in [0u16, 257, 65535, 32896, 12850, 514] {
+    bytes.extend_from_slice(&sample.to_be_bytes());
+  }
+  let path = std::env::temp_dir().join("image_viewer_16_bit.ppm");
+  std::fs::write(&path, &bytes).unwrap();
+
+  // every sample is scaled down to 8 bits (divided by 257)
+  let image = open_image(path.to_str().unwrap()).unwrap();
+  assert_eq!(image.width(), 2);
+  assert_eq!(image.height(), 1);
+  assert_eq!(image.get_pixel_by_coord(0, 0).unwrap(), [0, 1, 255]);
+  assert_eq!(image.get_pixel_by_coord(1, 0).unwrap(), [128, 50, 2]);
+
+  // a memory mapped read can't use the samples as they are
+  assert_eq!(read_ppm_mapped(path.to_str().unwrap()).unwrap(), image);
+
+  // and a file that ends early is an error rather than a crash
+  std::fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+  assert!(open_image(path.to_str().unwrap()).is_err());
+
+  std::fs::remove_file(&path).expect("Could not delete sample file.");
+}
+
+#[test]
+fn test_open_missing_image() {
+  use crate::core::error::CoreError;
+  use crate::core::io::{open_image, read_raw};
+
+  let path = std::env::temp_dir().join("image_viewer_missing.png");
+  let _ = std::fs::remove_file(&path);
+  let path = path.to_str().unwrap();
+
+  // a missing file is an error rather than a panic
+  assert!(matches!(open_image(path), Err(CoreError::Io(_))));
+  assert!(read_raw(path).is_none());
+
+  // and so is a file that isn't really an image
+  let not_an_image = std::env::temp_dir().join("image_viewer_not_an_image.png");
+  std::fs::write(&not_an_image, "not an image").unwrap();
+  assert!(matches!(
+    open_image(not_an_image.to_str().unwrap()), Err(CoreError::Decode(_))
+  ));
+  assert!(read_raw(not_an_image.to_str().unwrap()).is_none());
+  std::fs::remove_file(&not_an_image).unwrap();
+}
+
+#[test]
+fn test_cr2_tag_lookup() {
+  use crate::core::cr2::{parse_cr2, MAKE, MODEL, DATE_TIME};
+
+  let cr2 = parse_cr2("tests/cr2/1.cr2").unwrap();
+
+  assert_eq!(cr2.get_tag_string(MODEL), Some("Canon EOS 600D"));
+  assert_eq!(cr2.get_tag_string(MAKE), Some("Canon"));
+  assert_eq!(cr2.get_tag(MODEL).unwrap().entry_label(), "Model");
+  assert!(cr2.get_tag(DATE_TIME).is_none());
+}
+
+#[test]
+fn test_core_error_variants() {
+  use crate::core::error::CoreError;
+  use crate::core::io::{open_image, write_image};
+  use crate::core::filters::validate_gaussian;
+  use crate::core::operations::{resize, ResizeAlgorithm};
+  use crate::core::args::{apply_command, run_chain};
+
+  let image = PpmImage::create_color(10, 60, 120, 4, 4);
+
+  match open_image("tests/does_not_exist.ppm") {
+    Err(CoreError::Io(message)) => assert_eq!(
+      message, "Could not find file: \"tests/does_not_exist.ppm\""
+    ),
+    other => panic!("expected an io error, got {:?}", other),
+  }
+
+  assert!(matches!(
+    open_image("tests/ascii"), Err(CoreError::UnsupportedFormat(_))
+  ));
+
+  // a PPM that can't be created is an error rather than a panic
+  match write_image(&image, "tests/does_not_exist/image.ppm") {
+    Err(CoreError::Io(message)) => assert!(message.starts_with(
+      "Could not create \"tests/does_not_exist/image.ppm\""
+    )),
+    other => panic!("expected an io error, got {:?}", other),
+  }
+
+  assert!(matches!(
+    validate_gaussian(0., 3), Err(CoreError::InvalidParameter(_))
+  ));
+  assert!(matches!(
+    resize(&image, 0, 4, Some(ResizeAlgorithm::NearestNeighbor)),
+    Err(CoreError::InvalidDimensions(_))
+  ));
+  assert!(matches!(
+    apply_command("bogus", Some(&image)), Err(CoreError::InvalidCommand(_))
+  ));
+
+  // a failing step of a chain keeps the kind of error, with the step added to
+  // its message
+  let error = run_chain(&vec!["gblur -s 0 -k 3".to_string()], Some(&image))
+    .unwrap_err();
+  assert!(matches!(error, CoreError::InvalidParameter(_)));
+  assert_eq!(
+    error.to_string(),
+    "Step 1 (\"gblur -s 0 -k 3\") failed: \
+     Sigma value must be greater than 0, cannot be: 0.000"
+  );
+}
+
+#[test]
+fn test_malformed_ppm_never_panics() {
+  use std::panic::catch_unwind;
+  use rand::{Rng, SeedableRng, rngs::StdRng};
+  use crate::core::io::open_image;
+
+  let path = std::env::temp_dir().join("malformed.ppm");
+  let path = path.to_str().unwrap();
+  let open_bytes = |bytes: &[u8]| {
+    std::fs::write(path, bytes).unwrap();
+    let result = catch_unwind(|| open_image(path));
+    assert!(result.is_ok(), "Opening {:?} panicked", bytes);
+    result.unwrap()
+  };
+
+  // broken headers are errors, as is a header for more pixels than the file
+  // could hold (which would otherwise be allocated)
+  let corpus: [&[u8]; 12] = [
+    b"",
+    b"P",
+    b"Q6 2 2 255\n",
+    b"P6",
+    b"P6 \n",
+    b"P6 two 2 255\n............",
+    b"P6 0 2 255\n",
+    b"P6 2 2 0\n............",
+    b"P6 2 2 99999\n............",
+    b"P6 100000 100000 255\n....",
+    b"P6 4294967295 4294967295 255\n....",
+    b"P3 2 2 255\n1 2 3",
+  ];
+  for bytes in corpus {
+    assert!(open_bytes(bytes).is_err(), "{:?} was opened", bytes);
+  }
+
+  // extra data after the pixels is an error rather than a panic
+  assert!(open_bytes(b"P6 1 1 255\n\x01\x02\x03\x04\x05\x06").is_err());
+
+  // every truncation of a valid file
+  for valid in ["tests/ascii/color.ppm", "tests/ascii/gray.pgm"] {
+    let bytes = std::fs::read(valid).unwrap();
+    assert!(open_bytes(&bytes).is_ok());
+    for len in 0..bytes.len() {
+      let _ = open_bytes(&bytes[..len]);
+    }
   }
 
-  if v_filtered != v_check {
-    assert!(is_reasonably_similar(
-      &v_filtered, 
-      &v_check, 
-      2));
+  // random bytes, with and without a valid magic number in front of them
+  let mut rng = StdRng::seed_from_u64(13);
+  let alphabet = b"0123456789 \n\t#P-+.x";
+  for attempt in 0..500 {
+    let len = rng.gen_range(0..48);
+    let mut bytes: Vec<u8> = match attempt % 2 {
+      0 => (0..len).map(|_| rng.gen()).collect(),
+      _ => (0..len)
+        .map(|_| alphabet[rng.gen_range(0..alphabet.len())])
+        .collect(),
+    };
+    if 0 == attempt % 3 {
+      let magic = [b'P', b'1' + rng.gen_range(0..6)];
+      bytes.splice(0..0, magic);
+    }
+
+    let _ = open_bytes(&bytes);
   }
 
+  std::fs::remove_file(path).unwrap();
 }
 
 /* #endregion */
 
-/* #region Transform Tests */
+/* #region Command Tests   */
 
 #[test]
-pub fn test_gamma_correction() {
+fn test_command_chain() {
+  use crate::core::args::split_chain;
+  use crate::core::operations::{negate, histogram_equalization};
+  use crate::gui::gui::ImageViewer;
 
-  use crate::core::operations::gamma_transform;
-  
+  assert_eq!(
+    split_chain("chain \"gblur -s 1 -k 3\" \"histeq\""),
+    Some(vec!["gblur -s 1 -k 3".to_string(), "histeq".to_string()])
+  );
+  assert_eq!(
+    split_chain("inv | histeq"),
+    Some(vec!["inv".to_string(), "histeq".to_string()])
+  );
+  assert_eq!(split_chain("inv -i in.ppm -o out.ppm"), None);
+
+  let mut original = PpmImage::create_color(10, 60, 120, 8, 8);
+  for x in 0..8 {
+    original.set_pixel_by_coord(x, 0, &[200, 30, 90]);
+  }
+
+  let mut viewer = ImageViewer::with_config_directory(None);
+  viewer.set_image(Some(original.clone()));
+  viewer.run_command_chain(&split_chain("inv | histeq").unwrap()).unwrap();
+
+  let negated = negate(&original).unwrap();
+  let equalized = histogram_equalization(&negated, None).unwrap();
+
+  // each step of the chain can be undone on its own
+  assert_eq!(viewer.get_image(), Some(&equalized));
+  viewer.undo();
+  assert_eq!(viewer.get_image(), Some(&negated));
+  viewer.undo();
+  assert_eq!(viewer.get_image(), Some(&original));
+
+  // a failing step leaves the image alone
+  assert!(viewer.run_command_chain(&split_chain("inv | bogus").unwrap()).is_err());
+  assert_eq!(viewer.get_image(), Some(&original));
+}
+
+#[test]
+fn test_repeat_last_operation() {
+  use crate::core::operations::Operation;
+  use crate::gui::gui::ImageViewer;
+
+  let mut original = PpmImage::create_color(0, 0, 0, 8, 8);
+  for x in 0..8 {
+    original.set_pixel_by_coord(x, x, &[255, 255, 255]);
+  }
+
+  let mut viewer = ImageViewer::with_config_directory(None);
+  viewer.set_image(Some(original.clone()));
+
+  // nothing to repeat yet
+  viewer.repeat_last_operation();
+  assert_eq!(viewer.get_image(), Some(&original));
+
+  viewer.apply_operation(Operation::Negate).unwrap();
+  assert_ne!(viewer.get_image(), Some(&original));
+
+  // negating twice gives back the original
+  viewer.repeat_last_operation();
+  assert_eq!(viewer.get_image(), Some(&original));
+}
+
+#[test]
+fn test_fit_modes() {
+  use crate::gui::gui::FitMode;
+
+  // a small image is blown up by Fit, but left alone by FitNoEnlarge
+  assert_eq!(FitMode::Fit.fitted_size(50, 25, 400., 300.), (400, 200));
+  assert_eq!(FitMode::FitNoEnlarge.fitted_size(50, 25, 400., 300.), (50, 25));
+  assert_eq!(FitMode::Fill.fitted_size(50, 25, 400., 300.), (400, 300));
+
+  // a large image is shrunk the same way by both
+  assert_eq!(FitMode::Fit.fitted_size(800, 1200, 400., 300.), (200, 300));
+  assert_eq!(FitMode::FitNoEnlarge.fitted_size(800, 1200, 400., 300.), (200, 300));
+}
+
+#[test]
+fn test_operation_timing() {
+  use crate::core::operations::Operation;
+  use crate::gui::gui::ImageViewer;
+
+  let mut viewer = ImageViewer::with_config_directory(None);
+  viewer.set_image(Some(PpmImage::create_color(10, 20, 30, 8, 8)));
+  viewer.apply_operation(Operation::Gamma { gamma: 1.5 }).unwrap();
+
+  let response = &viewer.command_resp;
+  assert!(response.starts_with("Gamma (1.50) completed in "), "{}", response);
+  assert!(response.ends_with("ms"));
+}
+
+#[test]
+fn test_undo_coalescing() {
+  use crate::core::operations::Operation;
+  use crate::gui::gui::ImageViewer;
+
+  let original = PpmImage::create_color(10, 60, 200, 8, 8);
+  let mut viewer = ImageViewer::with_config_directory(None);
+  viewer.set_image(Some(original.clone()));
+  viewer.apply_operation(Operation::Negate).unwrap();
+  let negated = viewer.get_image().unwrap().clone();
+
+  // three gammas in a row are one undo step
+  for gamma in [1.2, 1.5, 2.0] {
+    viewer.apply_operation(Operation::Gamma { gamma: gamma }).unwrap();
+  }
+  assert_ne!(viewer.get_image(), Some(&negated));
+
+  viewer.undo();
+  assert_eq!(viewer.get_image(), Some(&negated));
+  viewer.undo();
+  assert_eq!(viewer.get_image(), Some(&original));
+
+  // a different operation in between starts a new step
+  viewer.apply_operation(Operation::Gamma { gamma: 1.5 }).unwrap();
+  viewer.apply_operation(Operation::Negate).unwrap();
+  viewer.apply_operation(Operation::Gamma { gamma: 1.5 }).unwrap();
+  viewer.undo();
+  viewer.undo();
+  assert_ne!(viewer.get_image(), Some(&original));
+  viewer.undo();
+  assert_eq!(viewer.get_image(), Some(&original));
+}
+
+#[test]
+fn test_undo_proxy_stop() {
+  use crate::core::operations::Operation;
+  use crate::core::proxy::PROXY_MAX_PIXELS;
+  use crate::gui::gui::ImageViewer;
+
+  let size = |viewer: &ImageViewer| -> (u32, u32) {
+    let image = viewer.get_image().unwrap();
+    (image.width(), image.height())
+  };
+
+  // just over the size that needs a proxy
+  let original = PpmImage::create_color(10, 60, 200, 1001, 2000);
+  assert!(2000 * 1001 > PROXY_MAX_PIXELS);
+
+  let mut viewer = ImageViewer::with_config_directory(None);
+  viewer.set_image(Some(original.clone()));
+  viewer.set_proxy_mode(true);
+  let proxy_size = size(&viewer);
+  assert!(proxy_size.0 < 2000);
+
+  viewer.apply_operation(Operation::Negate).unwrap();
+  viewer.set_proxy_mode(false);
+  assert_eq!(size(&viewer), (2000, 1001));
+
+  // undoing the stop goes back to the proxy, which still replays at full
+  // resolution
+  viewer.undo();
+  assert_eq!(size(&viewer), proxy_size);
+  viewer.set_proxy_mode(false);
+  let negated = Operation::Negate.apply(&original).unwrap();
+  assert_eq!(viewer.get_image(), Some(&negated));
+}
+
+#[test]
+fn test_load_image_in_background() {
+  use std::time::{Duration, Instant};
   use crate::core::io::open_image;
+  use crate::gui::gui::ImageViewer;
 
-  let gamma_values = [
-    0.3, 2.8, 5.0
-  ];
+  let wait_for_load = |viewer: &mut ImageViewer| {
+    let start = Instant::now();
+    while viewer.is_loading() {
+      assert!(start.elapsed() < Duration::from_secs(10));
+      std::thread::sleep(Duration::from_millis(5));
+      viewer.poll_loading_image();
+    }
+  };
 
-  let input = open_image(
-    "tests\\gamma_correction\\1.ppm")
-    .unwrap();
+  let mut viewer = ImageViewer::with_config_directory(None);
+  viewer.load_image("tests/ascii/color.ppm");
+  assert!(viewer.is_loading());
 
-  for gamma_value in gamma_values {
-    let gamma_correct_result = gamma_transform(
-      &input, gamma_value, None
-    );
+  wait_for_load(&mut viewer);
+  let expected = open_image("tests/ascii/color.ppm").unwrap();
+  assert_eq!(viewer.get_image(), Some(&expected));
 
-    assert!(gamma_correct_result.is_ok());
+  // a failed load leaves the current image alone
+  viewer.load_image("tests/ascii/missing.ppm");
+  wait_for_load(&mut viewer);
+  assert_eq!(viewer.get_image(), Some(&expected));
+}
 
-    let check_path = format!(
-      "tests\\gamma_correction\\check.{:.1}.ppm", gamma_value
-    );
+#[test]
+fn test_content_aware_fill_in_background() {
+  use std::time::{Duration, Instant};
+  use crate::core::blend::inpaint;
+  use crate::core::mask::BinaryMask;
+  use crate::gui::gui::ImageViewer;
 
-    let check = open_image(check_path.as_str()).unwrap();
+  let mut image = PpmImage::create_color(40, 80, 120, 16, 16);
+  for y in 6..10 {
+    for x in 6..10 {
+      image.set_pixel_by_coord(x, y, &[255, 0, 0]);
+    }
+  }
+  let mut mask = BinaryMask::new(16, 16);
+  for y in 5..11 {
+    for x in 5..11 {
+      mask.set(x, y, true);
+    }
+  }
 
-    if *gamma_correct_result.as_ref().unwrap() != check {
-      // a precision of 4 means to pass this test the images must be 
-      // 99.99% similar
-      assert!(is_reasonably_similar(
-        gamma_correct_result.as_ref().unwrap(), 
-        &check, 4)
-      );
-    } else {
-      assert_eq!(gamma_correct_result.unwrap(), check);
+  let mut viewer = ImageViewer::with_config_directory(None);
+  viewer.set_image(Some(image.clone()));
+  viewer.content_aware_fill(&mask).unwrap();
+
+  // one fill at a time
+  assert!(viewer.is_editing_in_background());
+  assert!(viewer.content_aware_fill(&mask).is_err());
+
+  let start = Instant::now();
+  while viewer.is_editing_in_background() {
+    assert!(start.elapsed() < Duration::from_secs(10));
+    std::thread::sleep(Duration::from_millis(5));
+    viewer.poll_background_edit();
+  }
+
+  let expected = inpaint(&image, &mask).unwrap();
+  assert_eq!(viewer.get_image(), Some(&expected));
+
+  // the fill is a single undo step
+  viewer.undo();
+  assert_eq!(viewer.get_image(), Some(&image));
+}
+
+#[test]
+fn test_image_sequence() {
+  use crate::core::io::write_image;
+  use crate::core::stacking::ImageStack;
+  use crate::gui::sequence::ImageSequence;
+
+  let directory = std::env::temp_dir().join("image_viewer_sequence");
+  let _ = std::fs::remove_dir_all(&directory);
+  std::fs::create_dir_all(&directory).unwrap();
+
+  for (name, value) in [("b.ppm", 20), ("a.ppm", 10), ("c.ppm", 30)] {
+    let image = PpmImage::create_color(value, value, value, 4, 4);
+    write_image(&image, directory.join(name).to_str().unwrap()).unwrap();
+  }
+  std::fs::write(directory.join("notes.txt"), "not an image").unwrap();
+  std::fs::write(directory.join("master.median.tiff"), "").unwrap();
+
+  // frames are in order of their names, and other files are skipped
+  let mut sequence = ImageSequence::from_directory(&directory).unwrap();
+  assert_eq!(sequence.len(), 3);
+  assert!(sequence.current_path().ends_with("a.ppm"));
+  assert_eq!(sequence.status(), "a.ppm (1 / 3)");
+
+  assert!(!sequence.previous());
+  assert!(sequence.next());
+  assert!(sequence.next());
+  assert!(!sequence.next());
+  assert!(sequence.previous());
+  assert_eq!(sequence.index(), 1);
+
+  // excluded frames are left out of the stack
+  sequence.toggle_included();
+  assert_eq!(sequence.status(), "b.ppm (2 / 3) - excluded");
+  let included = sequence.included_paths();
+  assert_eq!(included.len(), 2);
+  assert!(included[0].ends_with("a.ppm") && included[1].ends_with("c.ppm"));
+
+  let mut stack = ImageStack::new();
+  sequence.add_to_stack(&mut stack);
+  assert_eq!(stack.data.len(), 2);
+
+  sequence.toggle_included();
+  assert_eq!(sequence.included_paths().len(), 3);
+
+  let empty = directory.join("empty");
+  std::fs::create_dir_all(&empty).unwrap();
+  assert!(ImageSequence::from_directory(&empty).is_err());
+  assert!(ImageSequence::from_directory(&directory.join("missing")).is_err());
+
+  std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_excluded_frames_persist() {
+  use crate::core::io::write_image;
+  use crate::core::stacking::ImageStack;
+  use crate::gui::sequence::{ImageSequence, EXCLUDED_FRAMES_FILE_NAME};
+
+  let directory = std::env::temp_dir().join("image_viewer_culling");
+  let _ = std::fs::remove_dir_all(&directory);
+  std::fs::create_dir_all(&directory).unwrap();
+
+  for name in ["sub_1.ppm", "sub_2.ppm", "sub_3.ppm", "sub_4.ppm"] {
+    let image = PpmImage::create_color(50, 50, 50, 4, 4);
+    write_image(&image, directory.join(name).to_str().unwrap()).unwrap();
+  }
+
+  // reject the second and last frames
+  let mut sequence = ImageSequence::from_directory(&directory).unwrap();
+  sequence.next();
+  sequence.toggle_included();
+  sequence.next();
+  sequence.next();
+  sequence.toggle_included();
+  sequence.save_excluded().unwrap();
+
+  // the rejections are still there when the folder is opened again, and the
+  // rejected frames never make it into the stack
+  let reopened = ImageSequence::from_directory(&directory).unwrap();
+  let mut stack = ImageStack::new();
+  reopened.add_to_stack(&mut stack);
+
+  assert_eq!(stack.data.len(), 2);
+  assert!(stack.data[0].ends_with("sub_1.ppm"));
+  assert!(stack.data[1].ends_with("sub_3.ppm"));
+  assert!(!reopened.is_included(1) && !reopened.is_included(3));
+
+  // including every frame again cleans up the file
+  let mut sequence = reopened;
+  sequence.next();
+  sequence.toggle_included();
+  sequence.next();
+  sequence.next();
+  sequence.toggle_included();
+  sequence.save_excluded().unwrap();
+  assert!(!directory.join(EXCLUDED_FRAMES_FILE_NAME).exists());
+  assert_eq!(ImageSequence::from_directory(&directory).unwrap().included_paths().len(), 4);
+
+  std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_thumbnail_cache() {
+  use std::time::SystemTime;
+  use crate::core::io::write_image;
+  use crate::gui::thumbnails::{modified_time, Thumbnail, ThumbnailCache};
+
+  // this covers the cache itself: lookups, eviction of the least recently
+  // used thumbnail, and the modification time check
+
+  let directory = std::env::temp_dir().join("image_viewer_thumbnails");
+  let _ = std::fs::remove_dir_all(&directory);
+  std::fs::create_dir_all(&directory).unwrap();
+
+  let (first, second) = (directory.join("a.ppm"), directory.join("b.ppm"));
+  let mut images = Vec::<PpmImage>::new();
+  for (path, value) in [(&first, 40), (&second, 200)] {
+    let image = PpmImage::create_color(value, value, value, 20, 40);
+    write_image(&image, path.to_str().unwrap()).unwrap();
+    images.push(image);
+  }
+
+  // room for one 16x8 thumbnail
+  let mut cache = ThumbnailCache::new(16 * 8 * 3, 16);
+  assert!(cache.get(&first).is_none());
+
+  let thumbnail = Thumbnail::new(&images[0], cache.max_dimension());
+  assert_eq!((thumbnail.image.width(), thumbnail.image.height()), (16, 8));
+  assert_eq!((thumbnail.full_width, thumbnail.full_height), (40, 20));
+  assert_eq!(thumbnail.image.get_pixel_by_coord(3, 3).unwrap(), [40, 40, 40]);
+
+  cache.insert(&first, modified_time(&first).unwrap(), thumbnail.clone());
+  assert_eq!(cache.get(&first), Some(&thumbnail));
+  assert_eq!(cache.total_bytes(), 16 * 8 * 3);
+
+  // the least recently used thumbnail makes way for a new one
+  cache.insert(
+    &second,
+    modified_time(&second).unwrap(),
+    Thumbnail::new(&images[1], cache.max_dimension())
+  );
+  assert!(cache.get(&first).is_none());
+  assert!(cache.get(&second).is_some());
+
+  // a thumbnail made before the file was last changed isn't used
+  cache.insert(&first, SystemTime::UNIX_EPOCH, thumbnail.clone());
+  assert!(cache.get(&first).is_none());
+
+  // and neither is one of a file that is gone
+  std::fs::remove_file(&second).unwrap();
+  assert!(cache.get(&second).is_none());
+
+  std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_batch() {
+  use crate::core::batch::{
+    batch_images, run_batch, BatchProgress, DEFAULT_BATCH_TEMPLATE
+  };
+  use crate::core::error::CoreError;
+  use crate::core::io::{open_image, write_image};
+  use crate::core::operations::negate;
+
+  let input = std::env::temp_dir().join("image_viewer_batch_in");
+  let output = std::env::temp_dir().join("image_viewer_batch_out");
+  for directory in [&input, &output] {
+    let _ = std::fs::remove_dir_all(directory);
+    std::fs::create_dir_all(directory).unwrap();
+  }
+
+  let image = PpmImage::create_color(10, 100, 200, 4, 4);
+  for name in ["a.ppm", "b.ppm", "c.ppm"] {
+    write_image(&image, input.join(name).to_str().unwrap()).unwrap();
+  }
+  // a file that isn't an image is left out, and one that can't be read fails
+  // without stopping the rest
+  std::fs::write(input.join("notes.txt"), "not an image").unwrap();
+  std::fs::write(input.join("broken.png"), "not a png").unwrap();
+
+  let paths = batch_images(&input).unwrap();
+  assert_eq!(paths.len(), 4);
+
+  let mut updates = Vec::<BatchProgress>::new();
+  let summary = run_batch(
+    &paths, &output, "inv", DEFAULT_BATCH_TEMPLATE, |progress| {
+      updates.push(progress)
     }
+  ).unwrap();
+
+  assert_eq!(
+    updates.iter().map(|progress| progress.completed).collect::<Vec<_>>(),
+    vec![1, 2, 3, 4]
+  );
+  assert_eq!(updates[1].remaining(), 2);
+  assert_eq!(updates[3].fraction(), 1.);
+
+  assert_eq!((summary.total, summary.succeeded()), (4, 3));
+  assert!(summary.failures[0].0.ends_with("broken.png"));
+  assert!(matches!(summary.failures[0].1, CoreError::Decode(_)));
+  assert!(summary.report().starts_with("Processed 3 of 4 images\nbroken.png: "));
+
+  let negated = negate(&image).unwrap();
+  for name in ["a.ppm", "b.ppm", "c.ppm"] {
+    assert_eq!(open_image(output.join(name).to_str().unwrap()).unwrap(), negated);
+  }
+
+  // commands that read and write their own files can't be batched
+  assert!(matches!(
+    run_batch(
+      &paths, &output, "inv -i a.ppm -o b.ppm", DEFAULT_BATCH_TEMPLATE, |_| {}
+    ),
+    Err(CoreError::InvalidCommand(_))
+  ));
+
+  // an image whose result would overwrite that of another one fails, while
+  // the first of them is still written
+  std::fs::remove_file(input.join("broken.png")).unwrap();
+  let other = PpmImage::create_color(200, 100, 10, 4, 4);
+  write_image(&other, input.join("a.png").to_str().unwrap()).unwrap();
+  std::fs::remove_dir_all(&output).unwrap();
+  std::fs::create_dir_all(&output).unwrap();
+
+  let paths = batch_images(&input).unwrap();
+  let summary = run_batch(
+    &paths, &output, "inv", DEFAULT_BATCH_TEMPLATE, |_| {}
+  ).unwrap();
+  assert_eq!((summary.total, summary.succeeded()), (4, 3));
+  assert!(summary.failures[0].0.ends_with("a.ppm"));
+  assert!(matches!(summary.failures[0].1, CoreError::InvalidParameter(_)));
+  // a.png comes first, so a.ppm holds its result
+  assert_eq!(
+    open_image(output.join("a.ppm").to_str().unwrap()).unwrap(),
+    negate(&other).unwrap()
+  );
+
+  for directory in [&input, &output] {
+    std::fs::remove_dir_all(directory).unwrap();
   }
 }
 
-/**
- * This test is a little more subjective. What I did was find an image that
- * had been log transformed already, then I performed my own log transform and
- * compared it to the expected result, measuring similarity between the images
- * by getting the average percentage that all the pixels were different
- * between the two images.
- */
 #[test]
-pub fn test_log_transform() {
-  use crate::core::io::open_image;
-  use crate::core::operations::log_transform;
+fn test_output_name_template() {
+  use crate::core::batch::{
+    command_name_parts, expand_template, run_batch, OutputName, 
+    DEFAULT_BATCH_TEMPLATE, DEFAULT_MASTER_TEMPLATE
+  };
+  use crate::core::error::CoreError;
+  use crate::core::io::{open_image, write_image};
 
-  let base_dir = "tests\\log_transformation";
+  let (algo, params) = command_name_parts("gblur -s 2 -k 7");
+  assert_eq!((algo.as_str(), params.as_str()), ("gblur", "s_2_k_7"));
 
-  let test_images = [1, 2];
+  let output = OutputName { 
+    name: "m31", algo: &algo, params: &params, ext: "png" 
+  };
+  assert_eq!(
+    expand_template("{name}_{algo}_{params}.{ext}", &output), 
+    "m31_gblur_s_2_k_7.png"
+  );
+  assert_eq!(expand_template(DEFAULT_BATCH_TEMPLATE, &output), "m31.png");
+  assert_eq!(
+    expand_template(DEFAULT_MASTER_TEMPLATE, &output), "master.gblur.png"
+  );
+  // placeholders can repeat, and anything else in braces is left alone
+  assert_eq!(
+    expand_template("{algo}/{algo}-{date}", &output), "gblur/gblur-{date}"
+  );
 
-  for image in test_images {
-    let input = open_image(
-      format!("{}\\{}.ppm", base_dir, image).as_str()
-    ).unwrap();
-  
-    let log_transformed = log_transform(
-      &input, None, None
-    ).unwrap();
-  
-    let check = open_image(
-      format!("{}\\check{}.ppm", base_dir, image).as_str()
-    ).unwrap();
-  
-    if log_transformed != check {
-      assert!(is_reasonably_similar(
-        &log_transformed, &check, 4
-      ));
+  // a batch names its results with the template
+  let input = std::env::temp_dir().join("image_viewer_template_in");
+  let output = std::env::temp_dir().join("image_viewer_template_out");
+  for directory in [&input, &output] {
+    let _ = std::fs::remove_dir_all(directory);
+    std::fs::create_dir_all(directory).unwrap();
+  }
+
+  let image = PpmImage::create_color(10, 100, 200, 4, 4);
+  let path = input.join("m31.ppm");
+  write_image(&image, path.to_str().unwrap()).unwrap();
+
+  let summary = run_batch(
+    &[path.clone()], &output, "inv", "{name}_{algo}.png", |_| {}
+  ).unwrap();
+  assert_eq!(summary.succeeded(), 1);
+  let written = output.join("m31_inv.png");
+  assert!(open_image(written.to_str().unwrap()).is_ok());
+
+  // a template without the name would write every result to the same file
+  assert!(matches!(
+    run_batch(&[path], &output, "inv", "{algo}.{ext}", |_| {}),
+    Err(CoreError::InvalidParameter(_))
+  ));
+
+  for directory in [&input, &output] {
+    std::fs::remove_dir_all(directory).unwrap();
+  }
+}
+
+#[test]
+fn test_preset_round_trip() {
+  use crate::core::ppm::Padding;
+  use crate::gui::presets::{PresetStore, PresetParameters};
+  use crate::gui::windows::{GaussianBlurWindow, DeconvolutionWindow};
+
+  let mut window = GaussianBlurWindow::new("Gaussian Blur".to_string());
+  window.sigma = 2.5;
+  window.kernel.size = 7;
+  window.padding = Padding::Mirror;
+
+  let mut store = PresetStore::new();
+  store.save(window.filter_name(), window.to_preset("soft\tfocus"));
+
+  // presets are kept per filter
+  let mut deconvolution = DeconvolutionWindow::new("Deconvolution".to_string());
+  deconvolution.iterations = 42;
+  store.save(deconvolution.filter_name(), deconvolution.to_preset("soft focus"));
+
+  let text = store.serialize();
+  let read_back = PresetStore::deserialize(&text).unwrap();
+  assert_eq!(read_back, store);
+
+  let presets = read_back.presets(window.filter_name());
+  assert_eq!(presets.len(), 1);
+  assert_eq!(presets[0].name, "soft focus");
+
+  let mut loaded = GaussianBlurWindow::new("Gaussian Blur".to_string());
+  loaded.load_preset(&presets[0]);
+  assert_eq!(loaded.sigma, 2.5);
+  assert_eq!(loaded.kernel.size, 7);
+  assert_eq!(loaded.padding, Padding::Mirror);
+
+  // saving under the same name replaces the preset
+  window.sigma = 1.5;
+  store.save(window.filter_name(), window.to_preset("soft focus"));
+  assert_eq!(store.presets(window.filter_name()).len(), 1);
+  assert_eq!(
+    store.presets(window.filter_name())[0].get::<f32>("sigma"), Some(1.5)
+  );
+
+  assert!(PresetStore::deserialize("gaussian_blur\tbroken").is_err());
+  assert_eq!(PresetStore::deserialize("# nothing here\n\n"), Ok(PresetStore::new()));
+}
+
+#[test]
+fn test_settings_round_trip() {
+  use crate::core::io::{ColorSpace, MaxValue, SaveFormat};
+  use crate::gui::settings::Settings;
+
+  let mut settings = Settings::new();
+  settings.default_save_format = SaveFormat::Tiff;
+  settings.export_color_space = ColorSpace::Linear;
+  settings.export_max_value = MaxValue::Computed;
+  settings.thread_count = 3;
+
+  let read_back = Settings::deserialize(&settings.serialize()).unwrap();
+  assert_eq!(read_back, settings);
+
+  // settings that aren't in the file keep their defaults, and unknown keys
+  // are skipped
+  let read_back = Settings::deserialize(
+    "# settings\ndefault_save_format=PNG\nsomething_new=1\n"
+  ).unwrap();
+  assert_eq!(read_back.default_save_format, SaveFormat::Png);
+  assert_eq!(read_back.export_max_value, Settings::new().export_max_value);
+
+  assert!(Settings::deserialize("default_save_format=GIF").is_err());
+  assert!(Settings::deserialize("default_save_format").is_err());
+  assert!(Settings::deserialize("thread_count=0").is_err());
+}
+
+#[test]
+fn test_command_history() {
+  use crate::gui::history::CommandHistory;
+
+  let mut history = CommandHistory::new(3);
+
+  // nothing to step back to yet
+  assert_eq!(history.previous("draft"), None);
+
+  for command in ["inv", "histeq", "histeq", " ", "sobel", "gblur -s 2 -k 7"] {
+    history.push(command);
+  }
+
+  // the repeat and the blank command are skipped, and the oldest command is
+  // dropped once there are more than three
+  assert_eq!(history.previous("typing"), Some("gblur -s 2 -k 7"));
+  assert_eq!(history.previous("ignored"), Some("sobel"));
+  assert_eq!(history.previous("ignored"), Some("histeq"));
+  assert_eq!(history.previous("ignored"), Some("histeq"));
+
+  // stepping forward past the newest command gives back the draft
+  assert_eq!(history.next(), Some("sobel"));
+  assert_eq!(history.next(), Some("gblur -s 2 -k 7"));
+  assert_eq!(history.next(), Some("typing"));
+  assert_eq!(history.next(), None);
+
+  // the history is kept across sessions
+  let path = std::env::temp_dir().join("image_viewer_history");
+  history.write_file(&path).unwrap();
+  let mut loaded = CommandHistory::load_file(&path, 3).unwrap();
+  assert_eq!(loaded.previous(""), Some("gblur -s 2 -k 7"));
+  assert_eq!(loaded.previous(""), Some("sobel"));
+  assert_eq!(loaded.previous(""), Some("histeq"));
+  std::fs::remove_file(&path).unwrap();
+
+  assert_eq!(
+    CommandHistory::load_file(&path, 3).unwrap(), CommandHistory::new(3)
+  );
+}
+
+#[test]
+fn test_preview_debounce() {
+  use std::time::{Duration, Instant};
+  use crate::core::ppm::Padding;
+  use crate::core::operations::Operation;
+  use crate::gui::preview::{Debounce, render_preview, PREVIEW_MAX_PIXELS};
+
+  let mut debounce = Debounce::new(Duration::from_millis(300));
+  let start = Instant::now();
+  assert!(!debounce.ready(start));
+
+  // every change restarts the wait
+  debounce.touch(start);
+  assert!(!debounce.ready(start + Duration::from_millis(200)));
+  debounce.touch(start + Duration::from_millis(200));
+  assert!(!debounce.ready(start + Duration::from_millis(400)));
+  assert!(debounce.ready(start + Duration::from_millis(500)));
+
+  // and the preview is only updated once per change
+  assert!(!debounce.is_pending());
+  assert!(!debounce.ready(start + Duration::from_millis(900)));
+
+  // large images are previewed at a lower resolution, but at the same size
+  let width = 1200;
+  let height = PREVIEW_MAX_PIXELS / width + 10;
+  let image = PpmImage::create_color(40, 80, 120, height, width);
+  let operation = Operation::GaussianBlur {
+    sigma: 1.0,
+    kernel_size: 3,
+    padding: Padding::Repeat,
+  };
+
+  let preview = render_preview(&image, &operation).unwrap();
+  assert_eq!(preview.width(), width);
+  assert_eq!(preview.height(), height);
+  assert_eq!(preview, image);
+}
+
+#[test]
+fn test_proxy_replay() {
+  use crate::core::ppm::Padding;
+  use crate::core::operations::Operation;
+  use crate::core::proxy::ProxyEdit;
+
+  let mut image = PpmImage::create_color(30, 60, 90, 30, 40);
+  for x in 0..40 {
+    for y in 0..30 {
+      image.set_pixel_by_coord(x, y, &[(x * 6) as u8, (y * 8) as u8, 200]);
     }
   }
+
+  let mut proxy = ProxyEdit::new(image.clone());
+  let small = proxy.create_proxy(300).unwrap();
+  assert!(small.width() * small.height() <= 300);
+  assert!(!ProxyEdit::is_needed(&small, 300));
+
+  let operations = [
+    Operation::Negate,
+    Operation::Gamma { gamma: 0.8 },
+    Operation::GaussianBlur { sigma: 1.0, kernel_size: 3, padding: Padding::Repeat },
+  ];
+
+  let mut direct = image.clone();
+  for operation in operations.iter() {
+    proxy.record(*operation);
+    direct = operation.apply(&direct).unwrap();
+  }
+
+  // the replay matches applying the operations to the full image directly
+  let full = proxy.render_full().unwrap();
+  assert_eq!(full.width(), 40);
+  assert_eq!(full.height(), 30);
+  assert_eq!(full, direct);
+
+  // edits that aren't operations can't be replayed, until they are undone
+  proxy.record_unreplayable();
+  assert!(proxy.render_full().is_err());
+  assert!(proxy.undo());
+  assert_eq!(proxy.render_full().unwrap(), direct);
+
+  for _ in 0..operations.len() {
+    assert!(proxy.undo());
+  }
+  assert!(!proxy.undo());
+  assert_eq!(proxy.render_full().unwrap(), image);
+}
+
+#[test]
+fn test_clipping_overlay() {
+  use crate::gui::windows::{ClippingOverlay, ClippedRun};
+
+  let mut image = PpmImage::create_color(100, 100, 100, 2, 6);
+  image.set_pixel_by_coord(1, 0, &[255, 20, 20]);
+  image.set_pixel_by_coord(2, 0, &[20, 255, 20]);
+  image.set_pixel_by_coord(3, 0, &[0, 0, 0]);
+  image.set_pixel_by_coord(5, 0, &[0, 0, 0]);
+  image.set_pixel_by_coord(0, 1, &[0, 0, 0]);
+  image.set_pixel_by_coord(1, 1, &[0, 0, 1]);
+  let original = image.clone();
+
+  let mut overlay = ClippingOverlay::new();
+
+  // nothing is worked out while the overlay is hidden
+  overlay.update(&image);
+  assert!(overlay.runs.is_empty());
+
+  overlay.is_open = true;
+  overlay.update(&image);
+
+  // runs never wrap around onto the next row
+  let run = |y, x_start, x_end, highlight| ClippedRun {
+    y: y, x_start: x_start, x_end: x_end, highlight: highlight
+  };
+  assert_eq!(overlay.runs, vec![
+    run(0, 1, 3, true),
+    run(0, 3, 4, false),
+    run(0, 5, 6, false),
+    run(1, 0, 1, false),
+  ]);
+
+  assert_eq!(image, original);
 }
 
-/* #endregion */
+/* #endregion */
+
+/* #region Stacking Tests  */
+
+#[test]
+fn test_chunk_halo() {
+  use image::{DynamicImage, ImageBuffer, Rgb};
+  use crate::core::filters::gaussian_blur;
+  use crate::core::io::dynamic_to_ppm;
+  use crate::core::ppm::Padding;
+  use crate::core::stacking::crop_with_halo;
+
+  const KERNEL_SIZE: i32 = 7;
+  const CHUNK_SIZE: u32 = 10;
+
+  let buffer = ImageBuffer::from_fn(30, 20, |x, y| {
+    let value = ((x * 7919 + y * 104729) % 251) as u16 * 257;
+    Rgb::<u16>::from([value, value / 2, u16::MAX - value])
+  });
+  let image = DynamicImage::ImageRgb16(buffer);
+
+  let blur = |image: &DynamicImage| gaussian_blur(
+    &dynamic_to_ppm(image), 2., KERNEL_SIZE, Padding::Repeat
+  ).unwrap();
+  let whole = blur(&image);
+
+  // filters each chunk on its own, and checks it against the whole image
+  let chunks_match = |halo: u32| {
+    (0..2).all(|chunk_row| (0..3).all(|chunk_col| {
+      let (x, y) = (chunk_col * CHUNK_SIZE, chunk_row * CHUNK_SIZE);
+      let chunk = crop_with_halo(&image, x, y, CHUNK_SIZE, CHUNK_SIZE, halo);
+      let filtered = blur(&DynamicImage::ImageRgb16(chunk.buffer.clone()));
 
-/* #region Operation Tests */
+      (0..CHUNK_SIZE).all(|inner_y| (0..CHUNK_SIZE).all(|inner_x| {
+        filtered.get_pixel_by_coord(
+          chunk.inner_x + inner_x, chunk.inner_y + inner_y
+        ) == whole.get_pixel_by_coord(x + inner_x, y + inner_y)
+      }))
+    }))
+  };
+
+  // a halo as wide as the kernel's radius hides the seams, none leaves them
+  assert!(chunks_match(KERNEL_SIZE as u32 / 2));
+  assert!(!chunks_match(0));
+}
 
-/**
- * The following uses a helper function to test the expected outcomes of
- * product operations on images, then makes sure that they are correct
- */
 #[test]
-pub fn test_multiplication() {
-  // tests for "normal" circumstances
-  test_multiplication_helper(2, 2, 4);
-  test_multiplication_helper(3, 4, 12);
-  test_multiplication_helper(4, 3, 12);
-  test_multiplication_helper(4, 4, 16);
+fn test_running_stats() {
+  use crate::core::stats::RunningStats;
 
-  // test the zero / zero case
-  test_multiplication_helper(0, 0, 0);
+  let mut stats = RunningStats::new();
+  assert_eq!(stats.mean(), 0.);
+  assert_eq!(stats.variance(), 0.);
 
-  // test the zero / 255 case
-  test_multiplication_helper(0, 255, 0);
-  
-  // test the one / 255 case
-  test_multiplication_helper(1, 255, 255);
+  for value in [2., 4., 4., 4., 5., 5., 7., 9.] {
+    stats.push(value);
+  }
+  assert!((stats.mean() - 5.).abs() < 1e-12);
+  assert!((stats.variance() - 4.).abs() < 1e-12);
+  assert!((stats.stddev() - 2.).abs() < 1e-12);
 
-  // test the overflow case
-  test_multiplication_helper(30, 30, 255);
+  // large values close together don't lose their spread
+  let mut offset = RunningStats::new();
+  for value in [1e9 + 4., 1e9 + 7., 1e9 + 13., 1e9 + 16.] {
+    offset.push(value);
+  }
+  assert!((offset.mean() - (1e9 + 10.)).abs() < 1e-6);
+  assert!((offset.variance() - 22.5).abs() < 1e-6);
 }
 
-/// Takes an image with a "1" in white in the upper lefthand corner, and adds
-/// it to an image that has a "3" in the lower lefthand corner, then compares
-/// the sum with a check image
 #[test]
-pub fn test_add(){
-  use crate::core::operations::OpType;
+fn test_average_rounds() {
+  use image::Rgb;
+  use crate::core::stacking::{Average, StackOperation};
 
-  test_op(
-    "tests\\addition\\1.ppm",
-    "tests\\addition\\2.ppm",
-    OpType::Add,
-    "tests\\addition\\check.ppm"
-  );
+  // the mean of each channel is rounded to the nearest value
+  let average = Average::new();
+  let pixels = vec![Rgb([1, 2, 10]), Rgb([2, 2, 11]), Rgb([2, 2, 11])];
+  assert_eq!((average.get_function())(&pixels), Rgb([2, 2, 11]));
+
+  let pixels = vec![Rgb([1, 4, 10]), Rgb([2, 5, 13])];
+  assert_eq!((average.get_function())(&pixels), Rgb([2, 5, 12]));
 }
 
 #[test]
-pub fn test_subtract() {
-  use crate::core::operations::OpType;
+fn test_kappa_sigma_clipping() {
+  use image::Rgb;
+  use crate::core::stacking::{
+    ClippingStrategy, KappaSigmaClipping, StackOperation
+  };
 
-  test_op(
-    "tests\\subtraction\\1.ppm",
-    "tests\\subtraction\\2.ppm",
-    OpType::Subtract,
-    "tests\\subtraction\\check.ppm",
-  )
+  let gray = |value: u16| Rgb([value; 3]);
+  let clipping = KappaSigmaClipping::new(1, 2., ClippingStrategy::Remove);
+  let stack = clipping.get_function();
+
+  // 60000 is more than two standard deviations (about 21989) from the mean
+  // (about 10833), so it is clipped and the next pixel takes its place
+  let pixels = vec![
+    gray(60000), gray(1000), gray(1000), gray(1000), gray(1000), gray(1000)
+  ];
+  assert_eq!(stack(&pixels), gray(1000));
+
+  // bright pixels that are close together aren't outliers, no matter how
+  // bright they are
+  let pixels = vec![gray(50010), gray(50000), gray(49990), gray(50000)];
+  assert_eq!(stack(&pixels), gray(50010));
 }
 
 #[test]
-pub fn test_negatation() {
+fn test_normalize_master() {
+  use image::Rgb;
+  use crate::core::stacking::{ChunkBuffer, normalize_master};
 
-  use crate::core::io::open_image;
-  use crate::core::operations::negate;
+  let mut master = ChunkBuffer::new(4, 4);
+  for (x, y, pixel) in master.enumerate_pixels_mut() {
+    let value = 1000 + (x + y * 4) as u16 * 100;
+    *pixel = Rgb::<u16>::from([value, value / 2 + 500, value]);
+  }
 
-  let input = open_image("tests\\negation\\1.ppm").unwrap();
-  let negated_result = negate(&input);
-  assert!(negated_result.is_ok());
+  normalize_master(&mut master);
 
-  let check = open_image("tests\\negation\\check.ppm").unwrap();
+  let lowest = master.pixels().flat_map(|p| p.0).min().unwrap();
+  let highest = master.pixels().flat_map(|p| p.0).max().unwrap();
 
-  assert_eq!(negated_result.unwrap(), check);
+  assert_eq!(lowest, 0);
+  assert_eq!(highest, u16::MAX);
 }
 
+#[test]
+fn test_drizzle_identical_frames() {
+  use image::Rgb;
+  use crate::core::stacking::{ChunkBuffer, Drizzle, DrizzleSettings};
 
-/* #endregion */
+  let mut frame = ChunkBuffer::new(6, 4);
+  for (x, y, pixel) in frame.enumerate_pixels_mut() {
+    let value = (x * 1000 + y * 7000) as u16;
+    *pixel = Rgb::<u16>::from([value, value / 2, 60000 - value]);
+  }
 
-/* #region Scaling Tests   */
+  let settings = DrizzleSettings { scale: 2, pixel_fraction: 1. };
+  let mut drizzle = Drizzle::new(frame.width(), frame.height(), settings);
+  for _ in 0..3 {
+    drizzle.add_frame(&frame, (0., 0.));
+  }
+
+  let master = drizzle.finish();
+
+  assert_eq!(master.width(), 12);
+  assert_eq!(master.height(), 8);
+
+  // the aligned frames land exactly on top of each other, so the result is
+  // the input scaled up by two
+  for (x, y, pixel) in master.enumerate_pixels() {
+    assert_eq!(pixel, frame.get_pixel(x / 2, y / 2));
+  }
+}
 
 #[test]
-pub fn test_nearest_neighbor_scaling() {
- 
-  use crate::core::operations::{resize, ResizeAlgorithm};
-  use crate::core::io::open_image;
+fn test_process_all_and_label() {
+  use std::sync::{Arc, atomic::AtomicBool};
+  use image::Rgb;
+  use crate::core::stacking::{
+    self, BrightnessMetric, ChunkBuffer, ClippingStrategy, ImageStack
+  };
 
-  let base_dir = "tests\\resize\\nearest_neighbor";
+  let directory = std::env::temp_dir().join("image_viewer_label_stack");
+  std::fs::create_dir_all(&directory).unwrap();
 
-  let input = open_image(
-    format!("{}\\1.ppm", base_dir).as_str()
-  ).unwrap();
+  let mut stack = ImageStack::new();
+  for (i, value) in [1000, 2000, 6000].iter().enumerate() {
+    let path = directory.join(format!("{}.png", i));
+    ChunkBuffer::from_pixel(4, 4, Rgb::<u16>::from([*value; 3]))
+      .save(&path)
+      .unwrap();
+    stack.add_image(path.to_str().unwrap());
+  }
 
-  let sizes: [[u32;2];5] = [
-    [128,64],  
-    [128,128],
-    [256,256],
-    [64,128],
-    [1024,1024],
-  ];
+  stack.add_algorithm(
+    Box::new(stacking::Maximum::new(BrightnessMetric::Value)), "maximum".into()
+  );
+  stack.add_algorithm(Box::new(stacking::Average::new()), "average".into());
+  stack.add_algorithm(
+    Box::new(stacking::KappaSigmaClipping::new(
+      5, 1.0, ClippingStrategy::Remove
+    )),
+    "ksc".into()
+  );
 
-  for size in sizes {
-    let resized = resize(
-      &input, size[0], size[1], 
-      Some(ResizeAlgorithm::NearestNeighbor)
-    ).unwrap();
+  // one master per algorithm, kept in memory rather than written out
+  let masters = stack.process_all_and_label(
+    &Arc::new(AtomicBool::new(false))
+  ).unwrap();
 
-    let check = open_image(
-      format!(
-        "{}\\1.{}x{}.ppm", base_dir, size[0], size[1]
-      ).as_str()
-    ).unwrap();
+  assert_eq!(masters.len(), 3);
+  assert_eq!(masters["maximum"].get_pixel_by_coord(0, 0).unwrap(), [23; 3]);
+  assert_eq!(masters["average"].get_pixel_by_coord(0, 0).unwrap(), [12; 3]);
+  assert_eq!(masters["ksc"].width(), 4);
+  assert!(!directory.join("maximum").exists());
 
-    assert_eq!(resized, check);
-  }
+  std::fs::remove_dir_all(&directory).unwrap();
 }
 
 #[test]
-pub fn test_bilinear_iterpolation_scaling() {
-  
-  use crate::core::operations::{resize, ResizeAlgorithm};
-  use crate::core::io::open_image;
+fn test_maximum_brightness_metric() {
+  use image::Rgb;
+  use crate::core::stacking::{BrightnessMetric, Maximum, StackOperation};
 
-  let base_dir = "tests\\resize\\bilinear_interpolation";
+  // a saturated red, a dim white, and a bright green
+  let pixels = vec![
+    Rgb::<u16>::from([60000, 0, 0]),
+    Rgb::<u16>::from([30000, 30000, 30000]),
+    Rgb::<u16>::from([0, 50000, 10000]),
+  ];
 
-  let input = open_image(
-    format!("{}\\1.ppm", base_dir).as_str()
-  ).unwrap();
+  let stack = |metric| (Maximum::new(metric).get_function())(&pixels);
 
-  let sizes: [[u32;2];5] = [
-    [128,64],  
-    [128,128],
-    [256,256],
-    [64,128],
-    [1024,1024],
-  ];
+  // the red has the highest value, but the green looks the brightest
+  assert_eq!(stack(BrightnessMetric::Value), pixels[0]);
+  assert_eq!(stack(BrightnessMetric::Luminance), pixels[2]);
 
-  for size in sizes {
-    let resized = resize(
-      &input, size[0], size[1], 
-      Some(ResizeAlgorithm::BilinearInterpolation)
-    ).unwrap();
+  // each channel takes its own maximum, which none of the frames had
+  let per_channel = stack(BrightnessMetric::PerChannel);
+  assert_eq!(per_channel, Rgb::<u16>::from([60000, 50000, 30000]));
+  assert!(!pixels.contains(&per_channel));
+}
 
-    let check = open_image(
-      format!(
-        "{}\\1.{}x{}.ppm", base_dir, size[0], size[1]
-      ).as_str()
-    ).unwrap();
+#[test]
+fn test_percentile_clip_average() {
+  use image::Rgb;
+  use crate::core::stacking::{PercentileClipAverage, StackOperation};
 
-    if resized != check {
-      // similarity of 3 means 99.9% similarity
-      assert!(is_reasonably_similar(&resized, &check, 3));
-    }
+  let mut pixels = vec![Rgb::<u16>::from([1000, 2000, 3000]); 8];
+  pixels[1] = Rgb::<u16>::from([1010, 1990, 3000]);
+  pixels[2] = Rgb::<u16>::from([990, 2010, 3000]);
+  // a satellite in red and a dead pixel in green, in different frames
+  pixels.push(Rgb::<u16>::from([u16::MAX, 2000, u16::MAX]));
+  pixels.push(Rgb::<u16>::from([1000, 0, 0]));
+
+  let stacker = PercentileClipAverage::new(10., 90.);
+  let stacked = (stacker.get_function())(&pixels);
+  for (ch, expected) in [1000, 2000, 3000].iter().enumerate() {
+    assert!((stacked.0[ch] as i32 - expected).abs() <= 2);
   }
 
-}
+  // without clipping, the outliers pull the average away
+  let unclipped = PercentileClipAverage::new(0., 100.);
+  let stacked = (unclipped.get_function())(&pixels);
+  assert!(stacked.0[0] > 7000);
+  assert!(stacked.0[1] < 1900);
 
-/* #endregion */
+  // clipping everything still leaves a sample to go by
+  let single = PercentileClipAverage::new(50., 50.);
+  assert_eq!((single.get_function())(&pixels).0[2], 3000);
 
-/* #region Color Tests */
+  // clipping everything below 100% keeps the brightest sample
+  let top = PercentileClipAverage::new(100., 100.);
+  assert_eq!(
+    (top.get_function())(&pixels), Rgb::<u16>::from([u16::MAX, 2010, u16::MAX])
+  );
+
+  // an empty stack is black
+  assert_eq!(
+    (stacker.get_function())(&Vec::new()), Rgb::<u16>::from([0, 0, 0])
+  );
+}
 
 #[test]
-fn test_hsv_to_rgb() {
-  use crate::core::io::open_image;
-  use crate::core::PIXEL_SIZE;
-  use crate::core::ppm::PpmImage;
-  use crate::core::color;
-  use crate::core::{H_CH, S_CH, V_CH};
+fn test_write_tiff_16() {
+  use image::Rgb;
+  use crate::core::error::CoreError;
+  use crate::core::io::write_tiff_16;
+  use crate::core::stacking::ChunkBuffer;
 
-  let base_dir = "tests\\colorspace_conversion";
+  // values that don't fit into 8 bits, and the extremes
+  let buffer = ChunkBuffer::from_fn(5, 3, |x, y| {
+    Rgb::<u16>::from([x as u16 * 16000, y as u16 * 30000 + 1, u16::MAX])
+  });
 
-  let open_image_result = open_image(
-    format!("{}\\1.ppm", base_dir).as_str()
-  );
+  let path = std::env::temp_dir().join("write_tiff_16.tiff");
+  let path = path.to_str().unwrap();
+  write_tiff_16(&buffer, path).unwrap();
 
-  assert!(open_image_result.is_ok());
+  let reread = image::open(path).unwrap();
+  assert_eq!(reread.color(), image::ColorType::Rgb16);
+  assert_eq!(reread.into_rgb16(), buffer);
+  std::fs::remove_file(path).unwrap();
 
-  let image = open_image_result.ok().unwrap();
-  
-  let mut image_hsv_and_back = PpmImage::new(
-    image.width(), image.height()
-  );
+  // a folder that doesn't exist can't be written to
+  let missing = std::env::temp_dir().join("no_such_folder").join("a.tiff");
+  assert!(matches!(
+    write_tiff_16(&buffer, missing.to_str().unwrap()),
+    Err(CoreError::Io(_))
+  ));
+}
 
-  let mut abs_diff:usize = 0;
+#[test]
+fn test_parallel_stack_matches_serial() {
+  use std::sync::{Arc, atomic::AtomicBool};
+  use image::Rgb;
+  use crate::core::stacking::{
+    self, BrightnessMetric, ChunkBuffer, ImageStack
+  };
+  use crate::core::threads::available_threads;
+  use rayon::ThreadPoolBuilder;
 
-  for y in 0..image.height() {
-    for x in 0..image.width() {
+  let directory = std::env::temp_dir().join("image_viewer_parallel_stack");
+  let _ = std::fs::remove_dir_all(&directory);
+  std::fs::create_dir_all(&directory).unwrap();
 
-      if let Some(rgb_pixel) = image.get_pixel_by_coord(x, y) {
-        let hsv_pixel = color::rgb_to_hsv(rgb_pixel);
+  // three frames that differ from pixel to pixel, and from each other
+  let mut frames = Vec::<String>::new();
+  for i in 0..3u32 {
+    let frame = ChunkBuffer::from_fn(8, 8, |x, y| {
+      let value = ((x * 977 + y * 3571 + i * 12007) % 65536) as u16;
+      Rgb::<u16>::from([value, value / 2, 65535 - value])
+    });
+    let path = directory.join(format!("{}.png", i));
+    frame.save(&path).unwrap();
+    frames.push(path.to_str().unwrap().to_string());
+  }
 
-        let back_to_rgb = color::hsv_to_rgb(
-          hsv_pixel[H_CH], 
-   hsv_pixel[S_CH], 
-        hsv_pixel[V_CH]
-        );
+  // each stack runs on a pool of its own, so that the shared pool is left
+  // alone
+  let stack_with_threads = |threads: usize| -> Vec<ChunkBuffer> {
+    let pool = ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
 
-        image_hsv_and_back.set_pixel_by_coord(x, y, &back_to_rgb);
-        for i in 0..PIXEL_SIZE {
-          abs_diff += (rgb_pixel[i]).abs_diff(back_to_rgb[i]) as usize;
-        }
-      }
+    let mut stack = ImageStack::new();
+    for frame in frames.iter() {
+      stack.add_image(frame);
     }
-  }
+    // split into chunks, so that the chunk offsets are covered too
+    stack.memory_limit = Some(4 * 4 * 3 * 6);
 
-  if 0 != abs_diff {
-    assert!(
-      is_reasonably_similar(
-        &image, 
-        &image_hsv_and_back, 
-        2
-      )
-    )
-  } else {
-    assert_eq!(0, abs_diff);
-  }
-}
+    let paths: Vec<String> = ["median", "average", "maximum"].iter()
+      .map(|name| directory.join(format!("{}.{}.tiff", name, threads)))
+      .map(|path| path.to_str().unwrap().to_string())
+      .collect();
+    stack.add_algorithm(Box::new(stacking::Median::new()), paths[0].clone());
+    stack.add_algorithm(Box::new(stacking::Average::new()), paths[1].clone());
+    stack.add_algorithm(
+      Box::new(stacking::Maximum::new(BrightnessMetric::Luminance)),
+      paths[2].clone()
+    );
 
-/* #endregion */
+    pool.install(|| {
+      stack.process_stack(&Arc::new(AtomicBool::new(false))).unwrap()
+    });
+    paths.iter()
+      .map(|path| image::open(path).unwrap().into_rgb16())
+      .collect()
+  };
 
-/* #region IO Tests        */
+  let serial = stack_with_threads(1);
+  let parallel = stack_with_threads(available_threads().max(4));
+  assert_eq!(serial.len(), 3);
+  assert!(serial == parallel);
+
+  std::fs::remove_dir_all(&directory).unwrap();
+}
 
 #[test]
-fn test_read_write() {
-  use std::fs::{read_dir, remove_file};
-  use crate::core::io::{open_image, write_image};
+fn test_stack_save_failure() {
+  use std::sync::{Arc, atomic::AtomicBool};
+  use image::Rgb;
+  use crate::core::error::CoreError;
+  use crate::core::stacking::{
+    self, BrightnessMetric, ChunkBuffer, ImageStack
+  };
 
-  let paths = read_dir(
-    ".\\samples\\OfficialTestImages\\"
-  ).unwrap();
+  let directory = std::env::temp_dir().join("image_viewer_stack_save");
+  let _ = std::fs::remove_dir_all(&directory);
+  std::fs::create_dir_all(&directory).unwrap();
 
-  let mut failed_count = 0;
+  let mut stack = ImageStack::new();
+  for i in 0..2 {
+    let path = directory.join(format!("{}.png", i));
+    ChunkBuffer::from_pixel(4, 4, Rgb::<u16>::from([1000; 3]))
+      .save(&path)
+      .unwrap();
+    stack.add_image(path.to_str().unwrap());
+  }
 
-  const SAMPLE_FILE:&str = "samples\\TEMP.ppm";
-  for path in paths {
-    let mut image = open_image(
-      path.unwrap().path().to_str().unwrap()
-    ).unwrap();
+  // one master goes into a folder that doesn't exist
+  let missing = directory.join("missing").join("master.maximum.tiff");
+  let written = directory.join("master.average.tiff");
+  stack.add_algorithm(
+    Box::new(stacking::Maximum::new(BrightnessMetric::Value)), 
+    missing.to_str().unwrap().into()
+  );
+  stack.add_algorithm(
+    Box::new(stacking::Average::new()), written.to_str().unwrap().into()
+  );
 
-    match write_image(&mut image, SAMPLE_FILE) {
-      Err(why) => panic!("Could not write file: {}", why),
-      Ok(_) => {},
-    }
+  let result = stack.process_stack(&Arc::new(AtomicBool::new(false)));
+  match result {
+    Err(CoreError::Io(why)) => {
+      assert!(why.starts_with("1 of 2 master frames could not be saved"));
+      assert!(why.contains("master.maximum.tiff"));
+    },
+    other => panic!("Expected the failed save to be reported: {:?}", other),
+  }
 
-    let read_image = open_image(SAMPLE_FILE).unwrap();
+  // the failure doesn't stop the other master from being written
+  assert!(written.exists());
 
-    println!("Type of PPM Image: {}", image.ppm_type());
+  std::fs::remove_dir_all(&directory).unwrap();
+}
 
-    if read_image != image {
-      failed_count += 1
-    } else {
-      println!("Image successfully read, written, and read!");
+#[test]
+fn test_cancel_stack() {
+  use std::sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}};
+  use image::Rgb;
+  use crate::core::stacking::{
+    ChunkBuffer, ImageStack, StackOperation, StackingFunction, StackStatus
+  };
+
+  // a stacking algorithm that asks for the stack to be cancelled as soon as
+  // it is used, and counts how many pixels it was asked to stack
+  struct CancelOnFirstPixel {
+    stacking_function: Box<StackingFunction>,
+  }
+
+  impl StackOperation for CancelOnFirstPixel {
+    fn get_function(&self) -> &Box<StackingFunction> {
+      &self.stacking_function
     }
+  }
 
-    remove_file(SAMPLE_FILE).expect("Could not delete sample file.");
+  let cancel = Arc::new(AtomicBool::new(false));
+  let pixels_stacked = Arc::new(AtomicUsize::new(0));
+
+  let (flag, counter) = (cancel.clone(), pixels_stacked.clone());
+  let algorithm = CancelOnFirstPixel {
+    stacking_function: Box::new(move |pixels: &Vec<Rgb<u16>>| {
+      flag.store(true, Ordering::Relaxed);
+      counter.fetch_add(1, Ordering::Relaxed);
+      pixels[0]
+    }),
+  };
+
+  let directory = std::env::temp_dir().join("image_viewer_cancel_stack");
+  std::fs::create_dir_all(&directory).unwrap();
+  let output = directory.join("master.tiff");
+
+  let mut stack = ImageStack::new();
+  for i in 0..2 {
+    let path = directory.join(format!("{}.png", i));
+    ChunkBuffer::from_pixel(8, 8, Rgb::<u16>::from([1000, 2000, 3000]))
+      .save(&path)
+      .unwrap();
+    stack.add_image(path.to_str().unwrap());
   }
 
-  assert_eq!(0, failed_count);
+  // small enough that the 8x8 frames are split into 2x2 chunks
+  stack.memory_limit = Some(2 * 2 * 2 * 6);
+  stack.add_algorithm(Box::new(algorithm), output.to_str().unwrap().to_string());
+
+  let status = stack.process_stack(&cancel).unwrap();
+
+  // only the first chunk is stacked, and no master is written
+  assert_eq!(status, StackStatus::Cancelled { chunks_processed: 1, chunk_count: 16 });
+  assert_eq!(pixels_stacked.load(Ordering::Relaxed), 4);
+  assert!(!output.exists());
+
+  std::fs::remove_dir_all(&directory).unwrap();
 }
 
 /* #endregion */
@@ -412,6 +4148,62 @@ fn is_reasonably_similar(
   return precision == precision_test
 }
 
+/// Fails the test unless the images are exactly the same, saying where they
+/// first differ
+#[cfg(test)]
+fn assert_images_equal(actual: &PpmImage, expected: &PpmImage) {
+  if actual != expected {
+    panic!("The images are not equal: {}", first_difference(actual, expected));
+  }
+}
+
+/// Fails the test unless the images are the same, or reasonably similar to
+/// the given precision (see is_reasonably_similar)
+#[cfg(test)]
+fn assert_images_similar(
+  actual: &PpmImage,
+  expected: &PpmImage,
+  precision: u8
+) {
+  if actual == expected || is_reasonably_similar(actual, expected, precision) {
+    return;
+  }
+
+  panic!(
+    "The images are only {:.4}% similar, short of a precision of {}: {}",
+    measure_similarity(actual, expected) * 100.,
+    precision,
+    first_difference(actual, expected)
+  );
+}
+
+/// Describes the first way in which two images differ, for a failed test
+#[cfg(test)]
+fn first_difference(actual: &PpmImage, expected: &PpmImage) -> String {
+  if actual.width() != expected.width() || 
+     actual.height() != expected.height() {
+    return format!(
+      "the sizes differ ({}x{} != {}x{})",
+      actual.width(), actual.height(), expected.width(), expected.height()
+    );
+  }
+
+  for y in 0..actual.height() {
+    for x in 0..actual.width() {
+      let actual_pixel = actual.get_pixel_by_coord_ref(x, y);
+      let expected_pixel = expected.get_pixel_by_coord_ref(x, y);
+      if actual_pixel != expected_pixel {
+        return format!(
+          "the first differing pixel is ({}, {}), {:?} != {:?}",
+          x, y, actual_pixel, expected_pixel
+        );
+      }
+    }
+  }
+
+  "the pixels are the same, but the headers differ".to_string()
+}
+
 #[cfg(test)]
 pub fn measure_similarity(image_one: &PpmImage, image_two: &PpmImage) -> f64 {
   use std::collections::BTreeSet;
@@ -501,4 +4293,18 @@ pub fn test_multiplication_helper(rgb1: u8, rgb2: u8, check: u8) {
   assert_eq!(mult_result.ok().unwrap(), check_image);
 }
 
+#[cfg(test)]
+pub fn test_division_helper(rgb1: u8, rgb2: u8, check: u8) {
+  use crate::core::operations::perform_operation;
+
+  let lhs = PpmImage::create_color(rgb1, rgb1, rgb1, 10, 10);
+  let rhs = PpmImage::create_color(rgb2, rgb2, rgb2, 10, 10);
+  let check_image = PpmImage::create_color(check, check, check, 10, 10);
+
+  let div_result = perform_operation(&lhs, &rhs, OpType::Divide);
+
+  assert!(div_result.is_ok());
+  assert_eq!(div_result.ok().unwrap(), check_image);
+}
+
 /* #endregion */
\ No newline at end of file